@@ -2,6 +2,8 @@ import asyncio
 import json
 import logging
 import os
+import threading
+from collections import OrderedDict
 from datetime import datetime, timezone
 from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
 from typing import Dict, Any
@@ -11,6 +13,7 @@ import httpx
 from guardian.actions.alert_human import AlertHumanAction
 from guardian.actions.block_ip import BlockIPAction
 from guardian.actions.quarantine import QuarantineAction
+from guardian.capability import build_capability_issuer
 from guardian.config import guardian_id, guardian_tags
 
 logger = logging.getLogger(__name__)
@@ -26,6 +29,42 @@ ACTION_MAP = {
 
 GUARDIAN_ID = guardian_id()
 GUARDIAN_TAGS = guardian_tags()
+_issuer = None
+
+# The portal retries deliveries with the same Idempotency-Key; remember recent
+# results so a retried action is not executed twice.
+IDEMPOTENCY_CACHE_SIZE = 1024
+_results: "OrderedDict[str, Dict[str, Any]]" = OrderedDict()
+_in_flight: set = set()
+_results_lock = threading.Lock()
+
+
+def _claim(key: str):
+    """Return a cached result for `key`, `None` if the caller should run the
+    action, or an in-progress marker if another request is running it."""
+    with _results_lock:
+        if key in _results:
+            _results.move_to_end(key)
+            return _results[key]
+        if key in _in_flight:
+            return {"status": "in_progress"}
+        _in_flight.add(key)
+        return None
+
+
+def _remember(key: str, result: Dict[str, Any]):
+    with _results_lock:
+        _in_flight.discard(key)
+        _results[key] = result
+        while len(_results) > IDEMPOTENCY_CACHE_SIZE:
+            _results.popitem(last=False)
+
+
+def _auth_header() -> Dict[str, str]:
+    global _issuer
+    if _issuer is None:
+        _issuer = build_capability_issuer()
+    return {"Authorization": f"Bearer {_issuer.token()}"}
 
 
 async def send_update(req: Dict[str, Any], status: str, details: Dict[str, Any]):
@@ -43,7 +82,8 @@ async def send_update(req: Dict[str, Any], status: str, details: Dict[str, Any])
     url = f"{PORTAL_URL}/offsec/action/update"
     async with httpx.AsyncClient(timeout=5.0) as client:
         try:
-            await client.post(url, json=payload)
+            resp = await client.post(url, json=payload, headers=_auth_header())
+            resp.raise_for_status()
         except Exception as exc:
             logger.error("Failed to send action update: %s", exc)
 
@@ -95,7 +135,21 @@ class ActionHandler(BaseHTTPRequestHandler):
             self._json_response(400, {"error": "invalid_json"})
             return
 
-        result = asyncio.run(process_action(data))
+        key = self.headers.get("Idempotency-Key")
+        if key:
+            cached = _claim(key)
+            if cached is not None:
+                self._json_response(200, {"status": "accepted", "duplicate": True, **cached})
+                return
+        try:
+            result = asyncio.run(process_action(data))
+        except Exception:
+            if key:
+                with _results_lock:
+                    _in_flight.discard(key)
+            raise
+        if key:
+            _remember(key, result)
         self._json_response(200, {"status": "accepted", **result})
 
 
@@ -8,6 +8,9 @@ import jwt
 
 from guardian.config import config, guardian_id as resolve_guardian_id
 
+# Every guardian reports the results of its own actions.
+REPORT_ACTION = "action:report"
+
 
 class CapabilityIssuer:
     def __init__(
@@ -31,7 +34,7 @@ class CapabilityIssuer:
         self._cached_token: Optional[str] = None
         self._expires_at: int = 0
 
-    def _issue(self, lifetime_sec: int = 300) -> str:
+    def _encode(self, lifetime_sec: int) -> tuple[str, int]:
         now = int(time.time())
         exp = now + lifetime_sec
         payload = {
@@ -39,15 +42,16 @@ class CapabilityIssuer:
             "aud": self.audience,
             "iat": now,
             "exp": exp,
-            "actions": self.allowed_actions,
+            "actions": list(dict.fromkeys([*self.allowed_actions, REPORT_ACTION])),
             "nonce": token_hex(8),
         }
 
         if self.private_key:
-            token = jwt.encode(payload, self.private_key, algorithm="EdDSA")
-        else:
-            token = jwt.encode(payload, self.hs256_secret, algorithm="HS256")
+            return jwt.encode(payload, self.private_key, algorithm="EdDSA"), exp
+        return jwt.encode(payload, self.hs256_secret, algorithm="HS256"), exp
 
+    def _issue(self, lifetime_sec: int = 300) -> str:
+        token, exp = self._encode(lifetime_sec)
         self._cached_token = token
         self._expires_at = exp
         return token
@@ -58,6 +62,11 @@ class CapabilityIssuer:
             return self._issue()
         return self._cached_token
 
+    def single_use(self, lifetime_sec: int = 60) -> str:
+        """Fresh token for one destructive action; the portal rejects replays."""
+        token, _ = self._encode(lifetime_sec)
+        return token
+
 
 def load_private_key() -> Optional[str]:
     env_key = os.getenv("GUARDIAN_JWT_PRIVATE_KEY")
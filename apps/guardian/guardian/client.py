@@ -18,8 +18,8 @@ class PortalClient:
         self.guardian_tags = guardian_tags()
         self.source_host = socket.gethostname()
 
-    def _auth_header(self) -> dict:
-        token = self.issuer.token()
+    def _auth_header(self, single_use: bool = False) -> dict:
+        token = self.issuer.single_use() if single_use else self.issuer.token()
         return {"Authorization": f"Bearer {token}"}
 
     async def ingest_event(self, event: ThreatEvent) -> bool:
@@ -52,7 +52,7 @@ class PortalClient:
             response = await self.client.post(
                 f"{self.api_url}/offsec/action",
                 json=enriched.dict(exclude_none=True),
-                headers=self._auth_header(),
+                headers=self._auth_header(single_use=True),
             )
             return response.status_code == 200
         except Exception as exc:
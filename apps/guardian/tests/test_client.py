@@ -10,3 +10,13 @@ async def test_client_uses_capability_token():
         assert token and isinstance(token, str)
     finally:
         await client.close()
+
+
+@pytest.mark.asyncio
+async def test_single_use_tokens_are_not_cached():
+    client = PortalClient()
+    try:
+        assert client.issuer.single_use() != client.issuer.single_use()
+        assert client.issuer.token() == client.issuer.token()
+    finally:
+        await client.close()
@@ -0,0 +1,879 @@
+//! Persisted action records. Every action gets one row keyed by
+//! `action_id`, moved through `requested → approved → dispatched →
+//! executed / failed / rolled_back`, with each transition kept alongside
+//! the receipt that caused it.
+
+use std::fmt;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{
+    sqlite::{SqliteConnection, SqlitePool},
+    Row,
+};
+
+use crate::{models::ErrorResponse, AppState};
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS actions (
+        action_id TEXT PRIMARY KEY,
+        action_type TEXT NOT NULL,
+        target TEXT NOT NULL,
+        guardian_id TEXT,
+        requested_by TEXT,
+        state TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS idx_actions_state ON actions (state)",
+    "CREATE TABLE IF NOT EXISTS action_history (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        action_id TEXT NOT NULL,
+        from_state TEXT,
+        to_state TEXT NOT NULL,
+        actor TEXT NOT NULL,
+        receipt_id TEXT,
+        at TEXT NOT NULL,
+        details TEXT
+    )",
+    "CREATE INDEX IF NOT EXISTS idx_action_history_action ON action_history (action_id, seq)",
+    "CREATE TABLE IF NOT EXISTS action_approvals (
+        action_id TEXT PRIMARY KEY,
+        required INTEGER NOT NULL,
+        expires_at TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS approval_decisions (
+        action_id TEXT NOT NULL,
+        approver TEXT NOT NULL,
+        decision TEXT NOT NULL,
+        reason TEXT,
+        receipt_id TEXT,
+        at TEXT NOT NULL,
+        PRIMARY KEY (action_id, approver)
+    )",
+    "CREATE TABLE IF NOT EXISTS action_receipts (
+        action_id TEXT NOT NULL,
+        receipt_id TEXT NOT NULL,
+        at TEXT NOT NULL,
+        PRIMARY KEY (action_id, receipt_id)
+    )",
+];
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionState {
+    Requested,
+    Approved,
+    Dispatched,
+    Executed,
+    Failed,
+    RolledBack,
+}
+
+impl ActionState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Requested => "requested",
+            Self::Approved => "approved",
+            Self::Dispatched => "dispatched",
+            Self::Executed => "executed",
+            Self::Failed => "failed",
+            Self::RolledBack => "rolled_back",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<Self> {
+        Some(match state {
+            "requested" => Self::Requested,
+            "approved" => Self::Approved,
+            "dispatched" => Self::Dispatched,
+            "executed" => Self::Executed,
+            "failed" => Self::Failed,
+            "rolled_back" => Self::RolledBack,
+            _ => return None,
+        })
+    }
+
+    /// Whether the lifecycle allows moving from `self` to `to`. Any live
+    /// action may fail; only an executed one may be rolled back.
+    pub fn can_transition(self, to: Self) -> bool {
+        use ActionState::*;
+        matches!(
+            (self, to),
+            (Requested, Approved)
+                | (Approved, Dispatched)
+                | (Dispatched, Executed)
+                | (Requested | Approved | Dispatched, Failed)
+                | (Executed, RolledBack)
+        )
+    }
+
+    /// The state a Guardian result `status` moves an action to.
+    pub fn from_result_status(status: &str) -> Option<Self> {
+        Some(match status {
+            "applied" | "executed" => Self::Executed,
+            "failed" => Self::Failed,
+            "rolled_back" => Self::RolledBack,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for ActionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One step in an action's history. The first step has no `from`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionTransition {
+    pub from: Option<ActionState>,
+    pub to: ActionState,
+    pub actor: String,
+    pub receipt_id: Option<String>,
+    pub at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Approve,
+    Deny,
+}
+
+impl Decision {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Approve => "approve",
+            Self::Deny => "deny",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalDecision {
+    pub approver: String,
+    pub decision: Decision,
+    pub reason: Option<String>,
+    pub receipt_id: Option<String>,
+    pub at: String,
+}
+
+/// Sign-off an action is waiting for, or got.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalStatus {
+    pub required: u32,
+    pub expires_at: String,
+    pub decisions: Vec<ApprovalDecision>,
+}
+
+impl ApprovalStatus {
+    pub fn approvers(&self) -> Vec<&str> {
+        self.decisions
+            .iter()
+            .filter(|d| d.decision == Decision::Approve)
+            .map(|d| d.approver.as_str())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionRecord {
+    pub action_id: String,
+    pub action_type: String,
+    pub target: Value,
+    pub guardian_id: Option<String>,
+    pub requested_by: Option<String>,
+    pub state: ActionState,
+    pub created_at: String,
+    pub updated_at: String,
+    pub history: Vec<ActionTransition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approval: Option<ApprovalStatus>,
+    /// Receipts linked to the action, oldest first.
+    pub receipt_ids: Vec<String>,
+}
+
+impl ActionRecord {
+    /// Whoever first requested the action.
+    pub fn requester(&self) -> Option<&str> {
+        self.history.first().map(|h| h.actor.as_str())
+    }
+}
+
+/// An action about to be recorded as `requested`.
+#[derive(Debug, Clone)]
+pub struct NewAction {
+    pub action_id: String,
+    pub action_type: String,
+    pub target: Value,
+    pub guardian_id: Option<String>,
+    pub requested_by: Option<String>,
+    /// Sign-off to hold the action for, recorded with it.
+    pub approval: Option<PendingApproval>,
+}
+
+/// Hold a `requested` action until `required` approvers sign off or
+/// `expires_at` passes.
+#[derive(Debug, Clone)]
+pub struct PendingApproval {
+    pub required: u32,
+    pub expires_at: String,
+}
+
+/// One lifecycle step, for [`ActionStore::transition_in`].
+#[derive(Debug, Clone, Copy)]
+pub struct Transition<'a> {
+    pub from: ActionState,
+    pub to: ActionState,
+    pub actor: &'a str,
+    pub receipt_id: Option<&'a str>,
+    pub details: Option<&'a Value>,
+}
+
+#[derive(Debug)]
+pub enum TransitionError {
+    Unknown(String),
+    Invalid {
+        action_id: String,
+        from: ActionState,
+        to: ActionState,
+    },
+    Store(anyhow::Error),
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(id) => write!(f, "no action {id:?}"),
+            Self::Invalid {
+                action_id,
+                from,
+                to,
+            } => write!(f, "action {action_id:?} cannot move from {from} to {to}"),
+            Self::Store(e) => write!(f, "action store error: {e}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for TransitionError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Store(e.into())
+    }
+}
+
+pub fn transition_response(err: TransitionError) -> (StatusCode, Json<ErrorResponse>) {
+    let (code, error) = match &err {
+        TransitionError::Unknown(_) => (StatusCode::NOT_FOUND, "unknown_action"),
+        TransitionError::Invalid { .. } => (StatusCode::CONFLICT, "invalid_transition"),
+        TransitionError::Store(_) => (StatusCode::INTERNAL_SERVER_ERROR, "action_store_failed"),
+    };
+    (
+        code,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            details: Some(err.to_string()),
+        }),
+    )
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ActionFilter {
+    pub state: Option<ActionState>,
+    pub guardian_id: Option<String>,
+    pub action_type: Option<String>,
+}
+
+/// Action records and their transition history, kept in `receipts.db`.
+#[derive(Clone)]
+pub struct ActionStore {
+    pool: SqlitePool,
+}
+
+impl ActionStore {
+    pub async fn open(pool: SqlitePool) -> anyhow::Result<Self> {
+        for stmt in SCHEMA {
+            sqlx::query(stmt).execute(&pool).await?;
+        }
+        Ok(Self { pool })
+    }
+
+    /// Record a new action as `requested` by `actor`, with its approval
+    /// requirement if it has one. Returns `None` if the `action_id` is
+    /// already taken. The request receipt is attached afterwards with
+    /// [`ActionStore::set_request_receipt`].
+    pub async fn create(
+        &self,
+        action: &NewAction,
+        actor: &str,
+    ) -> anyhow::Result<Option<ActionRecord>> {
+        let now = Utc::now().to_rfc3339();
+        let mut tx = self.pool.begin().await?;
+        let inserted = sqlx::query(
+            "INSERT INTO actions
+                (action_id, action_type, target, guardian_id, requested_by, state, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT (action_id) DO NOTHING",
+        )
+        .bind(&action.action_id)
+        .bind(&action.action_type)
+        .bind(action.target.to_string())
+        .bind(&action.guardian_id)
+        .bind(&action.requested_by)
+        .bind(ActionState::Requested.as_str())
+        .bind(&now)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(None);
+        }
+        sqlx::query(
+            "INSERT INTO action_history (action_id, from_state, to_state, actor, at)
+             VALUES (?, NULL, ?, ?, ?)",
+        )
+        .bind(&action.action_id)
+        .bind(ActionState::Requested.as_str())
+        .bind(actor)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+        if let Some(approval) = &action.approval {
+            sqlx::query(
+                "INSERT INTO action_approvals (action_id, required, expires_at) VALUES (?, ?, ?)",
+            )
+            .bind(&action.action_id)
+            .bind(approval.required as i64)
+            .bind(&approval.expires_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        self.get(&action.action_id).await
+    }
+
+    /// Move `action_id` from `from` to `to`. Fails if the lifecycle does
+    /// not allow it or the action is no longer in `from`.
+    pub async fn transition(
+        &self,
+        action_id: &str,
+        from: ActionState,
+        to: ActionState,
+        actor: &str,
+        receipt_id: Option<&str>,
+        details: Option<&Value>,
+    ) -> Result<ActionRecord, TransitionError> {
+        let mut tx = self.pool.begin().await?;
+        self.transition_in(
+            &mut tx,
+            action_id,
+            Transition {
+                from,
+                to,
+                actor,
+                receipt_id,
+                details,
+            },
+        )
+        .await?;
+        tx.commit().await?;
+
+        self.get(action_id)
+            .await
+            .map_err(TransitionError::Store)?
+            .ok_or_else(|| TransitionError::Unknown(action_id.to_string()))
+    }
+
+    /// [`Self::transition`] on the caller's connection, so it can share a
+    /// transaction with other writes. Takes effect once that commits.
+    pub async fn transition_in(
+        &self,
+        conn: &mut SqliteConnection,
+        action_id: &str,
+        step: Transition<'_>,
+    ) -> Result<(), TransitionError> {
+        let invalid = |from| TransitionError::Invalid {
+            action_id: action_id.to_string(),
+            from,
+            to: step.to,
+        };
+        if !step.from.can_transition(step.to) {
+            return Err(invalid(step.from));
+        }
+
+        let now = Utc::now().to_rfc3339();
+        let updated = sqlx::query(
+            "UPDATE actions SET state = ?, updated_at = ? WHERE action_id = ? AND state = ?",
+        )
+        .bind(step.to.as_str())
+        .bind(&now)
+        .bind(action_id)
+        .bind(step.from.as_str())
+        .execute(&mut *conn)
+        .await?;
+        if updated.rows_affected() == 0 {
+            let current = sqlx::query("SELECT state FROM actions WHERE action_id = ?")
+                .bind(action_id)
+                .fetch_optional(&mut *conn)
+                .await?;
+            return Err(match current {
+                Some(row) => {
+                    let state: String = row.get("state");
+                    invalid(ActionState::parse(&state).ok_or_else(|| {
+                        TransitionError::Store(anyhow::anyhow!("unknown action state {state:?}"))
+                    })?)
+                }
+                None => TransitionError::Unknown(action_id.to_string()),
+            });
+        }
+        sqlx::query(
+            "INSERT INTO action_history
+                (action_id, from_state, to_state, actor, receipt_id, at, details)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(action_id)
+        .bind(step.from.as_str())
+        .bind(step.to.as_str())
+        .bind(step.actor)
+        .bind(step.receipt_id)
+        .bind(&now)
+        .bind(step.details.map(Value::to_string))
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Record `approver`'s decision on an action still `requested`, before
+    /// it is receipted. A denial fails the action in the same transaction,
+    /// so a concurrent decision cannot overtake it. Returns the record as
+    /// of the claim, `None` if the approver already decided, or
+    /// [`TransitionError::Invalid`] once the action is decided. The
+    /// receipt is attached afterwards with
+    /// [`ActionStore::set_decision_receipt`].
+    pub async fn claim_decision(
+        &self,
+        action_id: &str,
+        approver: &str,
+        decision: Decision,
+        reason: Option<&str>,
+    ) -> Result<Option<ActionRecord>, TransitionError> {
+        let mut tx = self.pool.begin().await?;
+        let inserted = sqlx::query(
+            "INSERT INTO approval_decisions (action_id, approver, decision, reason, at)
+             SELECT ?, ?, ?, ?, ? WHERE EXISTS
+                (SELECT 1 FROM actions WHERE action_id = ? AND state = ?)
+             ON CONFLICT (action_id, approver) DO NOTHING",
+        )
+        .bind(action_id)
+        .bind(approver)
+        .bind(decision.as_str())
+        .bind(reason)
+        .bind(Utc::now().to_rfc3339())
+        .bind(action_id)
+        .bind(ActionState::Requested.as_str())
+        .execute(&mut *tx)
+        .await?;
+        let to = match decision {
+            Decision::Approve => ActionState::Approved,
+            Decision::Deny => ActionState::Failed,
+        };
+        if inserted.rows_affected() == 0 {
+            let decided = sqlx::query(
+                "SELECT 1 FROM approval_decisions WHERE action_id = ? AND approver = ?",
+            )
+            .bind(action_id)
+            .bind(approver)
+            .fetch_optional(&mut *tx)
+            .await?;
+            if decided.is_some() {
+                return Ok(None);
+            }
+            let current = sqlx::query("SELECT state FROM actions WHERE action_id = ?")
+                .bind(action_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+            return Err(match current {
+                Some(row) => {
+                    let state: String = row.get("state");
+                    TransitionError::Invalid {
+                        action_id: action_id.to_string(),
+                        from: ActionState::parse(&state).ok_or_else(|| {
+                            TransitionError::Store(anyhow::anyhow!(
+                                "unknown action state {state:?}"
+                            ))
+                        })?,
+                        to,
+                    }
+                }
+                None => TransitionError::Unknown(action_id.to_string()),
+            });
+        }
+        if decision == Decision::Deny {
+            let details = serde_json::json!({ "denied_by": approver, "reason": reason });
+            self.transition_in(
+                &mut tx,
+                action_id,
+                Transition {
+                    from: ActionState::Requested,
+                    to,
+                    actor: approver,
+                    receipt_id: None,
+                    details: Some(&details),
+                },
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        self.get(action_id)
+            .await
+            .map_err(TransitionError::Store)?
+            .ok_or_else(|| TransitionError::Unknown(action_id.to_string()))
+            .map(Some)
+    }
+
+    /// Attach the receipt of `actor`'s decision to the decision and to the
+    /// state change it made, if any.
+    pub async fn set_decision_receipt(
+        &self,
+        action_id: &str,
+        actor: &str,
+        receipt_id: &str,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "UPDATE approval_decisions SET receipt_id = ?
+             WHERE action_id = ? AND approver = ? AND receipt_id IS NULL",
+        )
+        .bind(receipt_id)
+        .bind(action_id)
+        .bind(actor)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "UPDATE action_history SET receipt_id = ?
+             WHERE action_id = ? AND actor = ? AND from_state = ? AND receipt_id IS NULL",
+        )
+        .bind(receipt_id)
+        .bind(action_id)
+        .bind(actor)
+        .bind(ActionState::Requested.as_str())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Drop an approval claimed with [`ActionStore::claim_decision`] whose
+    /// receipt could not be written, so the approver can try again.
+    pub async fn release_decision(&self, action_id: &str, approver: &str) {
+        let released = sqlx::query(
+            "DELETE FROM approval_decisions
+             WHERE action_id = ? AND approver = ? AND receipt_id IS NULL",
+        )
+        .bind(action_id)
+        .bind(approver)
+        .execute(&self.pool)
+        .await;
+        if let Err(e) = released {
+            tracing::warn!("could not release decision on {}: {}", action_id, e);
+        }
+    }
+
+    /// Fail an action whose request could not be receipted, so the id is
+    /// not left `requested` with nothing on record for it.
+    pub async fn abandon(&self, action_id: &str, actor: &str, error: &str) {
+        let details = serde_json::json!({ "error": error });
+        if let Err(e) = self
+            .transition(
+                action_id,
+                ActionState::Requested,
+                ActionState::Failed,
+                actor,
+                None,
+                Some(&details),
+            )
+            .await
+        {
+            tracing::warn!("could not fail unreceipted action {}: {:?}", action_id, e);
+        }
+    }
+
+    /// Attach the receipt of the request that created `action_id` to its
+    /// `requested` history entry.
+    pub async fn set_request_receipt(
+        &self,
+        action_id: &str,
+        receipt_id: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "UPDATE action_history SET receipt_id = ?
+             WHERE action_id = ? AND from_state IS NULL",
+        )
+        .bind(receipt_id)
+        .bind(action_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Link a receipt that did not change the action's state, such as a
+    /// dispatch outcome.
+    pub async fn link_receipt(&self, action_id: &str, receipt_id: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO action_receipts (action_id, receipt_id, at) VALUES (?, ?, ?)
+             ON CONFLICT (action_id, receipt_id) DO NOTHING",
+        )
+        .bind(action_id)
+        .bind(receipt_id)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Actions still waiting for approval at `now` whose window has passed.
+    pub async fn expired_approvals(&self, now: &str) -> anyhow::Result<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT a.action_id FROM actions a
+             JOIN action_approvals p ON p.action_id = a.action_id
+             WHERE a.state = ? AND p.expires_at <= ?
+             ORDER BY p.expires_at ASC",
+        )
+        .bind(ActionState::Requested.as_str())
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| r.get("action_id")).collect())
+    }
+
+    pub async fn get(&self, action_id: &str) -> anyhow::Result<Option<ActionRecord>> {
+        let row = sqlx::query("SELECT * FROM actions WHERE action_id = ?")
+            .bind(action_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        match row {
+            Some(row) => Ok(Some(self.with_history(row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Most recently updated actions first.
+    pub async fn list(
+        &self,
+        filter: &ActionFilter,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ActionRecord>> {
+        let rows = sqlx::query(
+            "SELECT * FROM actions
+             WHERE (?1 IS NULL OR state = ?1)
+               AND (?2 IS NULL OR guardian_id = ?2)
+               AND (?3 IS NULL OR action_type = ?3)
+             ORDER BY updated_at DESC, action_id ASC
+             LIMIT ?4",
+        )
+        .bind(filter.state.map(ActionState::as_str))
+        .bind(&filter.guardian_id)
+        .bind(&filter.action_type)
+        .bind(limit.clamp(1, MAX_LIMIT) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut records = Vec::with_capacity(rows.len());
+        for row in rows {
+            records.push(self.with_history(row).await?);
+        }
+        Ok(records)
+    }
+
+    async fn with_history(&self, row: sqlx::sqlite::SqliteRow) -> anyhow::Result<ActionRecord> {
+        let action_id: String = row.get("action_id");
+        let state: String = row.get("state");
+        let history = sqlx::query(
+            "SELECT from_state, to_state, actor, receipt_id, at, details
+             FROM action_history WHERE action_id = ? ORDER BY seq ASC",
+        )
+        .bind(&action_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|h| {
+            let to: String = h.get("to_state");
+            Ok(ActionTransition {
+                from: h
+                    .get::<Option<String>, _>("from_state")
+                    .as_deref()
+                    .and_then(ActionState::parse),
+                to: ActionState::parse(&to)
+                    .ok_or_else(|| anyhow::anyhow!("unknown action state {to:?}"))?,
+                actor: h.get("actor"),
+                receipt_id: h.get("receipt_id"),
+                at: h.get("at"),
+                details: h
+                    .get::<Option<String>, _>("details")
+                    .map(|d| serde_json::from_str(&d))
+                    .transpose()?,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let approval = self.approval(&action_id).await?;
+
+        let other: Vec<(String, String)> =
+            sqlx::query("SELECT at, receipt_id FROM action_receipts WHERE action_id = ?")
+                .bind(&action_id)
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|r| (r.get("at"), r.get("receipt_id")))
+                .collect();
+        let mut linked: Vec<(&str, &String)> = history
+            .iter()
+            .filter_map(|h| Some((h.at.as_str(), h.receipt_id.as_ref()?)))
+            .collect();
+        if let Some(approval) = &approval {
+            linked.extend(
+                approval
+                    .decisions
+                    .iter()
+                    .filter_map(|d| Some((d.at.as_str(), d.receipt_id.as_ref()?))),
+            );
+        }
+        linked.extend(other.iter().map(|(at, id)| (at.as_str(), id)));
+        linked.sort_by_key(|(at, _)| *at);
+        let mut receipt_ids: Vec<String> = Vec::new();
+        for (_, id) in linked {
+            if !receipt_ids.contains(id) {
+                receipt_ids.push(id.clone());
+            }
+        }
+        Ok(ActionRecord {
+            action_type: row.get("action_type"),
+            target: serde_json::from_str(&row.get::<String, _>("target"))?,
+            guardian_id: row.get("guardian_id"),
+            requested_by: row.get("requested_by"),
+            state: ActionState::parse(&state)
+                .ok_or_else(|| anyhow::anyhow!("unknown action state {state:?}"))?,
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+            action_id,
+            history,
+            approval,
+            receipt_ids,
+        })
+    }
+
+    async fn approval(&self, action_id: &str) -> anyhow::Result<Option<ApprovalStatus>> {
+        let Some(row) =
+            sqlx::query("SELECT required, expires_at FROM action_approvals WHERE action_id = ?")
+                .bind(action_id)
+                .fetch_optional(&self.pool)
+                .await?
+        else {
+            return Ok(None);
+        };
+        let decisions = sqlx::query(
+            "SELECT approver, decision, reason, receipt_id, at FROM approval_decisions
+             WHERE action_id = ? ORDER BY at ASC",
+        )
+        .bind(action_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|d| ApprovalDecision {
+            approver: d.get("approver"),
+            decision: if d.get::<String, _>("decision") == "deny" {
+                Decision::Deny
+            } else {
+                Decision::Approve
+            },
+            reason: d.get("reason"),
+            receipt_id: d.get("receipt_id"),
+            at: d.get("at"),
+        })
+        .collect();
+        Ok(Some(ApprovalStatus {
+            required: row.get::<i64, _>("required") as u32,
+            expires_at: row.get("expires_at"),
+            decisions,
+        }))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActionQuery {
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub guardian_id: Option<String>,
+    #[serde(default)]
+    pub action_type: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+fn store_error(e: anyhow::Error) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: "action_store_failed".to_string(),
+            details: Some(e.to_string()),
+        }),
+    )
+}
+
+/// Action records with their history, most recently updated first.
+pub async fn list_actions(
+    State(state): State<AppState>,
+    Query(params): Query<ActionQuery>,
+) -> Result<Json<Vec<ActionRecord>>, (StatusCode, Json<ErrorResponse>)> {
+    let action_state = params
+        .state
+        .as_deref()
+        .map(|s| {
+            ActionState::parse(s).ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "invalid action query".to_string(),
+                        details: Some(format!("unknown state {s:?}")),
+                    }),
+                )
+            })
+        })
+        .transpose()?;
+    let filter = ActionFilter {
+        state: action_state,
+        guardian_id: params.guardian_id,
+        action_type: params.action_type,
+    };
+    state
+        .actions
+        .list(&filter, params.limit.unwrap_or(DEFAULT_LIMIT))
+        .await
+        .map(Json)
+        .map_err(store_error)
+}
+
+pub async fn get_action(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ActionRecord>, (StatusCode, Json<ErrorResponse>)> {
+    state
+        .actions
+        .get(&id)
+        .await
+        .map_err(store_error)?
+        .map(Json)
+        .ok_or_else(|| transition_response(TransitionError::Unknown(id)))
+}
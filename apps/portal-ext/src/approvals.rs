@@ -0,0 +1,377 @@
+//! Human sign-off for actions listed in `[approvals]`. Such actions stay
+//! `requested` until the configured number of distinct approvers holding
+//! `approve:<action>` sign off, one of them denies, or the window passes.
+//! Every decision is receipted as `offsec.action.approval`.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    actions::{transition_response, ActionRecord, ActionState, Decision, TransitionError},
+    auth::{AuthRejection, Caller},
+    capabilities::denial_payload,
+    models::ErrorResponse,
+    outbox::dispatch,
+    receipts::write_receipt,
+    routes::action_apply::ActionRequestPayload,
+    AppState,
+};
+
+/// How often pending actions are checked for expiry.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Actor recorded for decisions the portal makes itself.
+const PORTAL_ACTOR: &str = "portal";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DecisionPayload {
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// The capability an approver needs for `action`.
+pub fn approve_action(action: &str) -> String {
+    format!("approve:{action}")
+}
+
+fn reject(code: StatusCode, error: &str, details: String) -> AuthRejection {
+    (
+        code,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            details: Some(details),
+        }),
+    )
+}
+
+fn store_failed(e: anyhow::Error) -> AuthRejection {
+    reject(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "action_store_failed",
+        e.to_string(),
+    )
+}
+
+/// `POST /offsec/actions/:id/approve`.
+pub async fn approve(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    caller: Caller,
+    body: Option<Json<DecisionPayload>>,
+) -> Result<Json<Value>, AuthRejection> {
+    let body = body.map(|Json(b)| b).unwrap_or_default();
+    decide(&state, &id, caller, Decision::Approve, body.reason).await
+}
+
+/// `POST /offsec/actions/:id/deny`.
+pub async fn deny(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    caller: Caller,
+    body: Option<Json<DecisionPayload>>,
+) -> Result<Json<Value>, AuthRejection> {
+    let body = body.map(|Json(b)| b).unwrap_or_default();
+    decide(&state, &id, caller, Decision::Deny, body.reason).await
+}
+
+async fn decide(
+    state: &AppState,
+    action_id: &str,
+    caller: Caller,
+    decision: Decision,
+    reason: Option<String>,
+) -> Result<Json<Value>, AuthRejection> {
+    let record = state
+        .actions
+        .get(action_id)
+        .await
+        .map_err(store_failed)?
+        .ok_or_else(|| transition_response(TransitionError::Unknown(action_id.to_string())))?;
+    let principal = caller.permit(state, &approve_action(&record.action_type))?;
+
+    let Some(approval) = record.approval.as_ref() else {
+        return Err(reject(
+            StatusCode::CONFLICT,
+            "approval_not_required",
+            format!("{} actions do not need approval", record.action_type),
+        ));
+    };
+    if record.state != ActionState::Requested {
+        return Err(reject(
+            StatusCode::CONFLICT,
+            "not_pending_approval",
+            format!("action {action_id:?} is {}", record.state),
+        ));
+    }
+    if approval.expires_at.as_str() <= Utc::now().to_rfc3339().as_str() {
+        expire(state, &record).await.map_err(store_failed)?;
+        return Err(reject(
+            StatusCode::GONE,
+            "approval_expired",
+            format!("approval window closed at {}", approval.expires_at),
+        ));
+    }
+    if record.requester() == Some(principal.sub.as_str()) {
+        state.ws.send_json(&denial_payload(
+            Some(&principal.sub),
+            &approve_action(&record.action_type),
+            "requester cannot approve",
+        ));
+        return Err(reject(
+            StatusCode::FORBIDDEN,
+            "self_approval",
+            "the requester of an action cannot decide on it".to_string(),
+        ));
+    }
+    if approval
+        .decisions
+        .iter()
+        .any(|d| d.approver == principal.sub)
+    {
+        return Err(already_decided(&principal.sub));
+    }
+
+    // Claim the decision, and for a denial the action, before receipting
+    // it: a decision that loses a race is refused without a receipt.
+    let claimed = state
+        .actions
+        .claim_decision(action_id, &principal.sub, decision, reason.as_deref())
+        .await
+        .map_err(transition_response)?
+        .ok_or_else(|| already_decided(&principal.sub))?;
+    let claimed_approval = claimed.approval.as_ref().unwrap_or(approval);
+    let approvers = claimed_approval.approvers();
+    let receipt = match receipt_decision(
+        state,
+        &record,
+        &principal.sub,
+        decision.as_str(),
+        reason.as_deref(),
+        &approvers,
+        approval.required,
+    )
+    .await
+    {
+        Ok(receipt) => receipt,
+        Err(rejection) => {
+            if decision == Decision::Approve {
+                state
+                    .actions
+                    .release_decision(action_id, &principal.sub)
+                    .await;
+            }
+            return Err(rejection);
+        }
+    };
+    state
+        .actions
+        .set_decision_receipt(action_id, &principal.sub, &receipt)
+        .await
+        .map_err(store_failed)?;
+
+    let (status, action_state) = match decision {
+        Decision::Deny => ("denied", claimed.state),
+        Decision::Approve if approvers.len() as u32 >= approval.required => {
+            match state
+                .actions
+                .transition(
+                    action_id,
+                    ActionState::Requested,
+                    ActionState::Approved,
+                    &principal.sub,
+                    Some(&receipt),
+                    Some(&json!({ "approved_by": approvers })),
+                )
+                .await
+            {
+                Ok(_) => {
+                    let request = request_payload(state, &record).await?;
+                    let action_state = dispatch(state, &request)
+                        .await
+                        .map_err(transition_response)?;
+                    ("approved", action_state)
+                }
+                // A concurrent approval reached the threshold first and
+                // dispatched it; this decision is recorded all the same.
+                Err(TransitionError::Invalid { from, .. }) if from != ActionState::Failed => {
+                    ("approved", from)
+                }
+                Err(TransitionError::Invalid { from, .. }) => ("recorded", from),
+                Err(e) => return Err(transition_response(e)),
+            }
+        }
+        Decision::Approve => ("pending_approval", ActionState::Requested),
+    };
+
+    Ok(Json(json!({
+        "status": status,
+        "state": action_state,
+        "approvals": approvers.len(),
+        "required": approval.required,
+        "receipt_id": receipt,
+    })))
+}
+
+fn already_decided(approver: &str) -> AuthRejection {
+    reject(
+        StatusCode::CONFLICT,
+        "already_decided",
+        format!("{approver} has already decided on this action"),
+    )
+}
+
+/// Receipt and broadcast one decision. Returns the receipt id.
+async fn receipt_decision(
+    state: &AppState,
+    record: &ActionRecord,
+    approver: &str,
+    decision: &str,
+    reason: Option<&str>,
+    approvers: &[&str],
+    required: u32,
+) -> Result<String, AuthRejection> {
+    let payload = json!({
+        "action_id": record.action_id,
+        "action_type": record.action_type,
+        "decision": decision,
+        "approver": approver,
+        "reason": reason,
+        "approvers": approvers,
+        "required": required,
+        "ts": Utc::now().to_rfc3339(),
+    });
+    let receipt = write_receipt(
+        state,
+        "offsec.action.approval",
+        record.guardian_id.as_deref(),
+        &[],
+        &payload,
+    )
+    .await
+    .map_err(|e| reject(StatusCode::INTERNAL_SERVER_ERROR, "receipt_write_failed", e))?;
+
+    let mut frame = json!({ "type": "offsec.action.approval", "data": payload });
+    frame["data"]["receipt_id"] = json!(receipt.id);
+    state.ws.send_json(&frame);
+    Ok(receipt.id)
+}
+
+/// The request an action was created from, as receipted by
+/// `/offsec/action/apply`.
+async fn request_payload(
+    state: &AppState,
+    record: &ActionRecord,
+) -> Result<ActionRequestPayload, AuthRejection> {
+    let receipt_id = record
+        .history
+        .first()
+        .and_then(|h| h.receipt_id.as_deref())
+        .ok_or_else(|| store_failed(anyhow::anyhow!("action has no request receipt")))?;
+    let payload = state
+        .store
+        .get(receipt_id)
+        .await
+        .map_err(store_failed)?
+        .and_then(|r| r.payload)
+        .ok_or_else(|| store_failed(anyhow::anyhow!("request receipt {receipt_id} not found")))?;
+    serde_json::from_value(payload).map_err(|e| store_failed(e.into()))
+}
+
+/// Fail a pending action whose approval window has passed. Returns
+/// `false` if it was decided first, in which case nothing is receipted.
+async fn expire(state: &AppState, record: &ActionRecord) -> anyhow::Result<bool> {
+    let Some(approval) = &record.approval else {
+        return Ok(false);
+    };
+    let expired = match state
+        .actions
+        .transition(
+            &record.action_id,
+            ActionState::Requested,
+            ActionState::Failed,
+            PORTAL_ACTOR,
+            None,
+            Some(&json!({ "expired": true, "expires_at": approval.expires_at })),
+        )
+        .await
+    {
+        Ok(expired) => expired,
+        Err(TransitionError::Invalid { .. }) => return Ok(false),
+        Err(e) => return Err(anyhow::anyhow!(e.to_string())),
+    };
+    let approvers = expired
+        .approval
+        .as_ref()
+        .map(|a| a.approvers())
+        .unwrap_or_default();
+    let receipt = receipt_decision(
+        state,
+        record,
+        PORTAL_ACTOR,
+        "expired",
+        None,
+        &approvers,
+        approval.required,
+    )
+    .await
+    .map_err(|(_, Json(e))| anyhow::anyhow!(e.details.unwrap_or(e.error)))?;
+    state
+        .actions
+        .set_decision_receipt(&record.action_id, PORTAL_ACTOR, &receipt)
+        .await?;
+    Ok(true)
+}
+
+/// Fail every action whose approval window has passed. Returns how many
+/// were expired.
+pub async fn expire_pending(state: &AppState) -> usize {
+    let ids = match state
+        .actions
+        .expired_approvals(&Utc::now().to_rfc3339())
+        .await
+    {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::warn!("listing expired approvals: {}", e);
+            return 0;
+        }
+    };
+    let mut expired = 0;
+    for id in ids {
+        let record = match state.actions.get(&id).await {
+            Ok(Some(record)) => record,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!("loading action {}: {}", id, e);
+                continue;
+            }
+        };
+        match expire(state, &record).await {
+            Ok(true) => expired += 1,
+            Ok(false) => {}
+            Err(e) => tracing::warn!("expiring action {}: {}", id, e),
+        }
+    }
+    expired
+}
+
+pub fn spawn_expiry_worker(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            let expired = expire_pending(&state).await;
+            if expired > 0 {
+                tracing::info!("expired {} actions awaiting approval", expired);
+            }
+        }
+    });
+}
@@ -0,0 +1,326 @@
+//! One verification path for both capability formats: Ed25519/HS256 JWTs
+//! checked against the keyring, and base64 ledger capabilities signed by a
+//! trusted issuer. Either yields a [`Principal`] with the same grant and
+//! scope model, and routes take it through the [`Caller`] and
+//! [`Authorized`] extractors.
+
+use std::{collections::BTreeMap, marker::PhantomData};
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use ed25519_dalek::VerifyingKey;
+use serde::Serialize;
+
+use crate::{
+    capabilities::{
+        denial_payload, error_response, extract_token, verify_token, CapabilityError, Claims,
+    },
+    keyring::Keyring,
+    models::ErrorResponse,
+    offsec_ledger,
+    replay::{enforce_single_use, is_destructive},
+    scope::{count_scoped_use, enforce_scope, ActionScope, ScopeTarget},
+    AppState,
+};
+
+pub type AuthRejection = (StatusCode, Json<ErrorResponse>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenFormat {
+    Jwt,
+    Ledger,
+}
+
+/// A verified caller, whatever token format it presented.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub sub: String,
+    pub format: TokenFormat,
+    /// Granted actions: exact names, `prefix:*`, or `*`.
+    pub grants: Vec<String>,
+    /// Constraints per granted action (see [`ActionScope`]).
+    pub scopes: BTreeMap<String, ActionScope>,
+    /// `jti`/`nonce` for JWTs, the signature for ledger capabilities.
+    pub token_id: Option<String>,
+    /// Expiry as a Unix timestamp.
+    pub exp: i64,
+    pub tags: Vec<String>,
+}
+
+/// Whether `grant` covers `action`: an exact match, `*`, or `prefix:*`
+/// for any `prefix:...` action.
+pub fn grant_matches(grant: &str, action: &str) -> bool {
+    grant == action
+        || grant == "*"
+        || grant
+            .strip_suffix(":*")
+            .and_then(|prefix| action.strip_prefix(prefix))
+            .is_some_and(|rest| rest.starts_with(':'))
+}
+
+impl Principal {
+    pub fn allows(&self, action: &str) -> bool {
+        self.grants.iter().any(|g| grant_matches(g, action))
+    }
+
+    /// The scope for `action`; an exact key wins over a wildcard one.
+    pub fn scope(&self, action: &str) -> Option<&ActionScope> {
+        self.scopes.get(action).or_else(|| {
+            self.scopes
+                .iter()
+                .find(|(grant, _)| grant_matches(grant, action))
+                .map(|(_, scope)| scope)
+        })
+    }
+
+    pub fn ensure_action(&self, action: &str) -> Result<(), CapabilityError> {
+        if self.allows(action) {
+            Ok(())
+        } else {
+            Err(CapabilityError::NotAllowed(action.to_string()))
+        }
+    }
+
+    /// Scope constraints and single-use rules for `action` on `target`.
+    /// A replayed token is refused before it counts towards `max_count`.
+    /// What this spends is given back by [`release`](Self::release) if the
+    /// request fails before it is receipted.
+    pub async fn enforce(
+        &self,
+        state: &AppState,
+        action: &str,
+        target: &ScopeTarget,
+    ) -> Result<(), AuthRejection> {
+        enforce_scope(state, self, action, target)?;
+        enforce_single_use(state, self, action).await?;
+        if let Err(rejection) = count_scoped_use(state, self, action).await {
+            if is_destructive(action) {
+                if let Err(e) = state.replay.release(self, action).await {
+                    tracing::warn!("releasing token of {} for {}: {:#}", self.sub, action, e);
+                }
+            }
+            return Err(rejection);
+        }
+        Ok(())
+    }
+
+    /// Give back the single-use spend and `max_count` use taken by
+    /// [`enforce`](Self::enforce), for a request refused or failed before
+    /// anything was receipted.
+    pub async fn release(&self, state: &AppState, action: &str) {
+        let mut result = Ok(());
+        if self.scope(action).is_some_and(|s| s.max_count.is_some()) {
+            result = state.replay.uncount_use(self, action).await;
+        }
+        if is_destructive(action) {
+            result = result.and(state.replay.release(self, action).await);
+        }
+        if let Err(e) = result {
+            tracing::warn!("releasing token of {} for {}: {:#}", self.sub, action, e);
+        }
+    }
+}
+
+impl From<Claims> for Principal {
+    fn from(claims: Claims) -> Self {
+        let tags = claims
+            .extra
+            .get("tags")
+            .and_then(|v| v.as_array())
+            .map(|tags| {
+                tags.iter()
+                    .filter_map(|v| v.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            token_id: claims.jti.or(claims.nonce).filter(|id| !id.is_empty()),
+            sub: claims.sub,
+            format: TokenFormat::Jwt,
+            grants: claims.actions,
+            scopes: claims.scopes,
+            exp: claims.exp as i64,
+            tags,
+        }
+    }
+}
+
+/// Turns a bearer token into a [`Principal`].
+pub trait CapabilityVerifier: Send + Sync {
+    /// Whether the token looks like this verifier's format.
+    fn accepts(&self, token: &str) -> bool;
+    fn verify(&self, token: &str) -> Result<Principal, CapabilityError>;
+}
+
+/// Capability JWTs (three dot-separated segments).
+pub struct JwtVerifier<'a> {
+    pub keyring: &'a Keyring,
+    pub audience: &'a str,
+}
+
+impl CapabilityVerifier for JwtVerifier<'_> {
+    fn accepts(&self, token: &str) -> bool {
+        token.split('.').count() == 3
+    }
+
+    fn verify(&self, token: &str) -> Result<Principal, CapabilityError> {
+        verify_token(token, self.keyring, self.audience).map(Principal::from)
+    }
+}
+
+/// Base64 ledger capabilities from `trusted_issuers.json` issuers. Their
+/// `constraints` use the same per-action form as the JWT `scopes` claim.
+pub struct LedgerVerifier<'a> {
+    pub issuers: &'a BTreeMap<String, VerifyingKey>,
+}
+
+impl CapabilityVerifier for LedgerVerifier<'_> {
+    fn accepts(&self, token: &str) -> bool {
+        !token.contains('.')
+    }
+
+    fn verify(&self, token: &str) -> Result<Principal, CapabilityError> {
+        let cap = offsec_ledger::decode_capability(token, self.issuers)
+            .map_err(|e| CapabilityError::Invalid(e.to_string()))?;
+        let scopes = match &cap.constraints {
+            serde_json::Value::Null => BTreeMap::new(),
+            serde_json::Value::Object(map) if map.is_empty() => BTreeMap::new(),
+            constraints => serde_json::from_value(constraints.clone())
+                .map_err(|e| CapabilityError::Invalid(format!("invalid constraints: {e}")))?,
+        };
+        Ok(Principal {
+            sub: cap.sub,
+            format: TokenFormat::Ledger,
+            grants: cap.scopes,
+            scopes,
+            token_id: Some(cap.signature),
+            exp: cap.exp.timestamp(),
+            tags: Vec::new(),
+        })
+    }
+}
+
+/// Verify `token` with the first verifier that accepts its format.
+pub fn verify_any(
+    token: &str,
+    verifiers: &[&dyn CapabilityVerifier],
+) -> Result<Principal, CapabilityError> {
+    verifiers
+        .iter()
+        .find(|v| v.accepts(token))
+        .ok_or_else(|| CapabilityError::Invalid("unrecognised token format".to_string()))?
+        .verify(token)
+}
+
+/// Verify against the current trust snapshot.
+pub fn verify_bearer(state: &AppState, token: &str) -> Result<Principal, CapabilityError> {
+    let trust = state.trust.snapshot();
+    verify_any(
+        token,
+        &[
+            &JwtVerifier {
+                keyring: &trust.keyring,
+                audience: &state.config.capability_audience,
+            },
+            &LedgerVerifier {
+                issuers: &trust.issuers,
+            },
+        ],
+    )
+}
+
+/// Extractor for routes whose action comes from the request body: the
+/// verified caller, or why verification failed, to be checked with
+/// [`Caller::permit`] once the action is known.
+pub struct Caller(Result<Principal, CapabilityError>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Caller {
+    type Rejection = AuthRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Caller(match extract_token(&parts.headers) {
+            Some(token) => verify_bearer(state, &token),
+            None => Err(CapabilityError::Missing),
+        }))
+    }
+}
+
+impl Caller {
+    /// The principal, if it may perform `action`. Denials are broadcast as
+    /// `capability_denied` frames.
+    pub fn permit(self, state: &AppState, action: &str) -> Result<Principal, AuthRejection> {
+        let denied = |guardian: Option<&str>, reason: &str, err: CapabilityError| {
+            state
+                .ws
+                .send_json(&denial_payload(guardian, action, reason));
+            let (code, err) = error_response(err);
+            (code, Json(err))
+        };
+        match self.0 {
+            Err(CapabilityError::Missing) => {
+                Err(denied(None, "missing token", CapabilityError::Missing))
+            }
+            Err(err) => Err(denied(None, "invalid token", err)),
+            Ok(principal) => match principal.ensure_action(action) {
+                Ok(()) => Ok(principal),
+                Err(err) => Err(denied(Some(&principal.sub), "action not allowed", err)),
+            },
+        }
+    }
+}
+
+/// A fixed action checked by [`Authorized`].
+pub trait RequiredAction {
+    const ACTION: &'static str;
+}
+
+/// Extractor for routes with a fixed action: rejects unless the caller
+/// holds `A::ACTION`.
+pub struct Authorized<A>(pub Principal, pub PhantomData<A>);
+
+#[async_trait]
+impl<A: RequiredAction> FromRequestParts<AppState> for Authorized<A> {
+    type Rejection = AuthRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let caller = Caller::from_request_parts(parts, state).await?;
+        Ok(Authorized(caller.permit(state, A::ACTION)?, PhantomData))
+    }
+}
+
+macro_rules! required_actions {
+    ($($(#[$doc:meta])* $name:ident => $action:expr;)*) => {$(
+        $(#[$doc])*
+        pub struct $name;
+        impl RequiredAction for $name {
+            const ACTION: &'static str = $action;
+        }
+    )*};
+}
+
+required_actions! {
+    /// `POST /offsec/ingest`.
+    Ingest => "ingest";
+    /// `POST /offsec/admin/reload`.
+    AdminReload => crate::trust::RELOAD_ACTION;
+    /// `POST /api/offsec/events`.
+    InfrastructureWrite => "infrastructure:write";
+    /// `POST /offsec/action/update`.
+    ActionReport => "action:report";
+    /// `POST /offsec/anchor`.
+    AnchorWrite => "anchor:write";
+    /// Creating, assigning, linking and transitioning incidents.
+    IncidentWrite => "incident:write";
+}
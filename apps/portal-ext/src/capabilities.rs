@@ -1,9 +1,15 @@
 use axum::http::{HeaderMap, StatusCode};
 use chrono::Utc;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::{decode, decode_header, Validation};
 use serde::{Deserialize, Serialize};
 
-use crate::{config::OffsecConfig, models::ErrorResponse};
+use std::collections::BTreeMap;
+
+use crate::{
+    keyring::{JwtKey, KeyStatus, Keyring},
+    models::ErrorResponse,
+    scope::{ActionScope, ScopeViolation},
+};
 
 const BEARER_PREFIX: &str = "Bearer ";
 
@@ -17,6 +23,11 @@ pub struct Claims {
     pub actions: Vec<String>,
     #[serde(default)]
     pub nonce: Option<String>,
+    #[serde(default)]
+    pub jti: Option<String>,
+    /// Constraints on actions listed in `actions`, keyed by action.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scopes: BTreeMap<String, ActionScope>,
     #[serde(flatten)]
     pub extra: serde_json::Value,
 }
@@ -26,6 +37,12 @@ pub enum CapabilityError {
     Missing,
     Invalid(String),
     NotAllowed(String),
+    /// Signed by a key past its `not_after`; carries the key id.
+    RetiredKey(String),
+    /// A single-use token presented again; carries the token id.
+    Replayed(String),
+    /// The action is allowed, but not for this target.
+    OutOfScope(ScopeViolation),
 }
 
 pub fn extract_token(headers: &HeaderMap) -> Option<String> {
@@ -36,51 +53,70 @@ pub fn extract_token(headers: &HeaderMap) -> Option<String> {
         .map(|v| v.trim().to_string())
 }
 
-fn decoding_key(config: &OffsecConfig) -> Option<(DecodingKey, Algorithm)> {
-    if let Some(pem) = &config.jwt_public_key_pem {
-        if let Ok(key) = DecodingKey::from_ed_pem(pem.as_bytes()) {
-            return Some((key, Algorithm::EdDSA));
-        }
-    }
-
-    if let Some(secret) = &config.jwt_hs256_secret {
-        return Some((
-            DecodingKey::from_secret(secret.as_bytes()),
-            Algorithm::HS256,
-        ));
-    }
-
-    None
-}
-
-pub fn verify_token(token: &str, config: &OffsecConfig) -> Result<Claims, CapabilityError> {
-    let Some((key, alg)) = decoding_key(config) else {
+/// Verify a capability JWT against the keyring. Tokens with a `kid` are
+/// checked against that key only; tokens without one against every key of
+/// the header's algorithm. A token whose signature checks out against a key
+/// past its `not_after` fails with [`CapabilityError::RetiredKey`].
+pub fn verify_token(
+    token: &str,
+    keyring: &Keyring,
+    audience: &str,
+) -> Result<Claims, CapabilityError> {
+    if keyring.is_empty() {
         return Err(CapabilityError::Invalid(
             "no capability verification key configured".to_string(),
         ));
-    };
-
-    let mut validation = Validation::new(alg);
-    validation.set_audience(&[config.capability_audience.as_str()]);
-
-    let token_data = decode::<Claims>(token, &key, &validation)
-        .map_err(|e| CapabilityError::Invalid(e.to_string()))?;
-
-    let claims = token_data.claims;
-    let now = Utc::now().timestamp() as usize;
-    if claims.exp <= now {
-        return Err(CapabilityError::Invalid("token expired".to_string()));
     }
+    let header = decode_header(token).map_err(|e| CapabilityError::Invalid(e.to_string()))?;
+    let candidates: Vec<&JwtKey> = match &header.kid {
+        Some(kid) => vec![keyring
+            .get(kid)
+            .ok_or_else(|| CapabilityError::Invalid(format!("unknown key id {kid:?}")))?],
+        None => keyring
+            .keys()
+            .values()
+            .filter(|k| k.alg == header.alg)
+            .collect(),
+    };
 
-    Ok(claims)
-}
-
-pub fn ensure_action(claims: &Claims, action: &str) -> Result<(), CapabilityError> {
-    if claims.actions.iter().any(|a| a == action) {
-        Ok(())
-    } else {
-        Err(CapabilityError::NotAllowed(action.to_string()))
+    let now = Utc::now();
+    let mut failure = CapabilityError::Invalid(format!("no {:?} key configured", header.alg));
+    for key in candidates {
+        if key.alg != header.alg {
+            failure = CapabilityError::Invalid(format!(
+                "key {:?} is {:?}, token is {:?}",
+                key.kid, key.alg, header.alg
+            ));
+            continue;
+        }
+        let mut validation = Validation::new(key.alg);
+        validation.set_audience(&[audience]);
+        let claims = match decode::<Claims>(token, key.decoding_key(), &validation) {
+            Ok(data) => data.claims,
+            Err(e) => {
+                failure = CapabilityError::Invalid(e.to_string());
+                continue;
+            }
+        };
+        match key.status(now) {
+            KeyStatus::Active => {
+                if claims.exp <= now.timestamp() as usize {
+                    return Err(CapabilityError::Invalid("token expired".to_string()));
+                }
+                return Ok(claims);
+            }
+            KeyStatus::Retired => {
+                failure = CapabilityError::RetiredKey(key.kid.clone());
+            }
+            KeyStatus::Pending => {
+                if !matches!(failure, CapabilityError::RetiredKey(_)) {
+                    failure =
+                        CapabilityError::Invalid(format!("key {:?} is not active yet", key.kid));
+                }
+            }
+        }
     }
+    Err(failure)
 }
 
 pub fn denial_payload(guardian: Option<&str>, action: &str, reason: &str) -> serde_json::Value {
@@ -118,6 +154,27 @@ pub fn error_response(err: CapabilityError) -> (StatusCode, ErrorResponse) {
                 details: Some(reason),
             },
         ),
+        CapabilityError::RetiredKey(kid) => (
+            StatusCode::UNAUTHORIZED,
+            ErrorResponse {
+                error: "retired_capability_key".to_string(),
+                details: Some(format!("token is signed by retired key {kid:?}")),
+            },
+        ),
+        CapabilityError::Replayed(token_id) => (
+            StatusCode::UNAUTHORIZED,
+            ErrorResponse {
+                error: "replayed_capability_token".to_string(),
+                details: Some(format!("token {token_id:?} has already been used")),
+            },
+        ),
+        CapabilityError::OutOfScope(violation) => (
+            StatusCode::FORBIDDEN,
+            ErrorResponse {
+                error: "action_out_of_scope".to_string(),
+                details: Some(violation.to_string()),
+            },
+        ),
         CapabilityError::NotAllowed(reason) => (
             StatusCode::FORBIDDEN,
             ErrorResponse {
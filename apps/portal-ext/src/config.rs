@@ -1,7 +1,17 @@
-use serde::Deserialize;
-use std::env;
+use std::{
+    collections::{BTreeMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+};
 
-#[derive(Debug, Clone, Deserialize)]
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::correlation::is_known_key;
+use crate::keyring::Keyring;
+use crate::mesh::util::{load_signing_key, parse_pubkey, public_key_b64};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct MeshPeer {
     pub id: String,
     pub url: String,
@@ -25,6 +35,122 @@ impl MeshConfig {
     }
 }
 
+/// What to do when a receipt cannot be written for an ingested event or
+/// action.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DurabilityMode {
+    /// Fail the request with a 5xx and broadcast nothing.
+    #[default]
+    Strict,
+    /// Accept the request, broadcast, and queue the receipt for retry.
+    BestEffort,
+}
+
+impl DurabilityMode {
+    fn from_env_value(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "best-effort" | "best_effort" => Ok(DurabilityMode::BestEffort),
+            "strict" => Ok(DurabilityMode::Strict),
+            other => bail!("OFFSEC_DURABILITY {other:?} is not \"strict\" or \"best-effort\""),
+        }
+    }
+}
+
+/// Human sign-off for high-risk actions (`[approvals]`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApprovalPolicy {
+    /// Distinct approvers needed per action type. Unlisted actions are
+    /// approved by the requesting capability alone.
+    pub required: BTreeMap<String, u32>,
+    /// How long an action waits for approval before it fails.
+    pub timeout_seconds: u64,
+}
+
+impl Default for ApprovalPolicy {
+    fn default() -> Self {
+        Self {
+            required: BTreeMap::new(),
+            timeout_seconds: 3600,
+        }
+    }
+}
+
+impl ApprovalPolicy {
+    pub fn required_for(&self, action: &str) -> u32 {
+        self.required.get(action).copied().unwrap_or(0)
+    }
+}
+
+/// Delivery of approved actions to Guardian (`[dispatch]`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DispatchConfig {
+    /// Attempts before an action is dead-lettered and marked failed.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after.
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Timeout for one call to Guardian, connect included.
+    pub timeout_ms: u64,
+}
+
+impl Default for DispatchConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            base_delay_ms: 1_000,
+            max_delay_ms: 300_000,
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+/// Identity of the civilization-ledger adapter (`[ledger]`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LedgerConfig {
+    /// DID that infrastructure receipts and proofs are issued under.
+    pub issuer_id: String,
+    /// Raw 32-byte Ed25519 key, generated on first boot if missing.
+    /// Defaults to `<data_dir>/ledger/node.key`.
+    pub key_file: Option<String>,
+}
+
+impl Default for LedgerConfig {
+    fn default() -> Self {
+        Self {
+            issuer_id: "did:vm:node:offsec-shield".to_string(),
+            key_file: None,
+        }
+    }
+}
+
+/// Grouping of ThreatEvents into incidents (`[correlation]`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorrelationConfig {
+    pub enabled: bool,
+    /// Events sharing a key this close together join the same incident;
+    /// each one extends the window.
+    pub window_seconds: u64,
+    /// Fields that together make up the correlation key: `source_ip` (IPs
+    /// in `affected`), `event_type`, `guardian_id`, `source`, `source_host`
+    /// or `metadata.<field>`.
+    pub keys: Vec<String>,
+}
+
+impl Default for CorrelationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_seconds: 900,
+            keys: vec!["source_ip".to_string()],
+        }
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct OffsecConfig {
     pub listen: String,
@@ -32,35 +158,441 @@ pub struct OffsecConfig {
     pub capability_audience: String,
     pub jwt_public_key_pem: Option<String>,
     pub jwt_hs256_secret: Option<String>,
+    /// Accept `jwt_hs256_secret` even when a PEM key or JWKS file is set.
+    /// Off by default so an old shared secret does not linger next to them.
+    #[serde(default)]
+    pub jwt_allow_hs256: bool,
+    /// JWKS-style file of additional capability keys (see `keyring`).
+    #[serde(default)]
+    pub jwks_file: Option<String>,
     pub data_dir: String,
     pub guardian_url: Option<String>,
+    /// Ed25519 key used to sign receipts and tree heads; falls back to
+    /// `mesh.privkey_file` when unset.
+    #[serde(default)]
+    pub signing_key_file: Option<String>,
+    #[serde(default)]
+    pub durability: DurabilityMode,
     #[serde(default)]
     pub mesh: Option<MeshConfig>,
+    #[serde(default)]
+    pub approvals: ApprovalPolicy,
+    #[serde(default)]
+    pub dispatch: DispatchConfig,
+    #[serde(default)]
+    pub ledger: LedgerConfig,
+    #[serde(default)]
+    pub correlation: CorrelationConfig,
+    /// TOML file this config was read from; re-read when trust stores are
+    /// reloaded.
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
+}
+
+/// Environment variable naming the TOML config file.
+pub const CONFIG_ENV: &str = "OFFSEC_CONFIG";
+
+/// Layout of the TOML config file. Sections used only by other components
+/// (`[guardian]`, `[detectors]`, `[actions]`, `[logging]`) are ignored, so
+/// `config/*.example.toml` can be shared.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    server: ServerSection,
+    vaultmesh: VaultmeshSection,
+    portal: PortalSection,
+    mesh: Option<MeshConfig>,
+    approvals: ApprovalPolicy,
+    dispatch: DispatchConfig,
+    ledger: LedgerConfig,
+    correlation: CorrelationConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ServerSection {
+    listen: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct VaultmeshSection {
+    portal_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PortalSection {
+    data_dir: Option<String>,
+    capability_audience: Option<String>,
+    jwt_public_key_file: Option<String>,
+    jwt_hs256_secret: Option<String>,
+    jwt_allow_hs256: Option<bool>,
+    jwks_file: Option<String>,
+    guardian_url: Option<String>,
+    signing_key_file: Option<String>,
+    durability: Option<DurabilityMode>,
 }
 
 impl OffsecConfig {
-    pub fn from_env() -> Self {
+    pub fn signing_key_path(&self) -> Option<&str> {
+        self.signing_key_file
+            .as_deref()
+            .or_else(|| self.mesh.as_ref().map(|m| m.privkey_file.as_str()))
+    }
+
+    pub fn ledger_key_path(&self) -> PathBuf {
+        match &self.ledger.key_file {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&self.data_dir).join("ledger").join("node.key"),
+        }
+    }
+
+    fn defaults() -> Self {
         Self {
-            listen: env::var("OFFSEC_LISTEN").unwrap_or_else(|_| "0.0.0.0:9115".to_string()),
-            vaultmesh_url: env::var("VAULTMESH_URL")
-                .unwrap_or_else(|_| "http://localhost:9110".to_string()),
-            capability_audience: env::var("OFFSEC_CAP_AUD")
-                .unwrap_or_else(|_| "offsec-portal".to_string()),
-            jwt_public_key_pem: env::var("OFFSEC_JWT_PUBLIC_KEY").ok(),
-            jwt_hs256_secret: {
-                let secret = env::var("OFFSEC_JWT_HS256_SECRET")
-                    .expect("OFFSEC_JWT_HS256_SECRET must be set - generate with: openssl rand -hex 32");
-                if secret == "dev-secret" {
-                    panic!("OFFSEC_JWT_HS256_SECRET cannot be 'dev-secret' in production");
+            listen: "0.0.0.0:9115".to_string(),
+            vaultmesh_url: "http://localhost:9110".to_string(),
+            capability_audience: "offsec-portal".to_string(),
+            jwt_public_key_pem: None,
+            jwt_hs256_secret: None,
+            jwt_allow_hs256: false,
+            jwks_file: None,
+            data_dir: "data-offsec".to_string(),
+            guardian_url: None,
+            signing_key_file: None,
+            durability: DurabilityMode::default(),
+            mesh: None,
+            approvals: ApprovalPolicy::default(),
+            dispatch: DispatchConfig::default(),
+            ledger: LedgerConfig::default(),
+            correlation: CorrelationConfig::default(),
+            config_file: None,
+        }
+    }
+
+    /// Startup configuration: the TOML file named by `OFFSEC_CONFIG` (if
+    /// set), then env var overrides, then [`validate`](Self::validate).
+    pub fn load() -> anyhow::Result<Self> {
+        let config = match env::var(CONFIG_ENV) {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            Err(_) => Self::defaults(),
+        };
+        let config = config.with_overrides(|name| env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        let mut config = Self::from_toml(&contents)
+            .with_context(|| format!("in config file {}", path.display()))?;
+        config.config_file = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Parse a TOML config on top of the defaults. Nothing is validated.
+    pub fn from_toml(contents: &str) -> anyhow::Result<Self> {
+        let file: ConfigFile = toml::from_str(contents)?;
+        let mut config = Self::defaults();
+        if let Some(listen) = file.server.listen {
+            config.listen = listen;
+        }
+        if let Some(url) = file.vaultmesh.portal_url {
+            config.vaultmesh_url = url;
+        }
+        let portal = file.portal;
+        if let Some(dir) = portal.data_dir {
+            config.data_dir = dir;
+        }
+        if let Some(aud) = portal.capability_audience {
+            config.capability_audience = aud;
+        }
+        if let Some(path) = portal.jwt_public_key_file {
+            let pem = fs::read_to_string(&path)
+                .with_context(|| format!("reading portal.jwt_public_key_file {path}"))?;
+            config.jwt_public_key_pem = Some(pem);
+        }
+        config.jwt_hs256_secret = portal.jwt_hs256_secret;
+        config.jwt_allow_hs256 = portal.jwt_allow_hs256.unwrap_or_default();
+        config.jwks_file = portal.jwks_file;
+        config.guardian_url = portal.guardian_url;
+        config.signing_key_file = portal.signing_key_file;
+        config.durability = portal.durability.unwrap_or_default();
+        config.mesh = file.mesh;
+        config.approvals = file.approvals;
+        config.dispatch = file.dispatch;
+        config.ledger = file.ledger;
+        config.correlation = file.correlation;
+        Ok(config)
+    }
+
+    /// Apply env var overrides, looking each variable up with `var`. Mesh
+    /// settings use the `OFFSEC_MESH_*` names from `ops/`; `OFFSEC_MESH_PEERS`
+    /// is a JSON array of `{id, url, pubkey}`. `OFFSEC_APPROVALS` is a JSON
+    /// object of approvers needed per action, e.g. `{"quarantine": 2}`.
+    /// `OFFSEC_CORRELATION_KEYS` is comma-separated.
+    pub fn with_overrides(mut self, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        if let Some(v) = var("OFFSEC_LISTEN") {
+            self.listen = v;
+        }
+        if let Some(v) = var("VAULTMESH_URL") {
+            self.vaultmesh_url = v;
+        }
+        if let Some(v) = var("OFFSEC_CAP_AUD") {
+            self.capability_audience = v;
+        }
+        if let Some(v) = var("OFFSEC_JWT_PUBLIC_KEY") {
+            self.jwt_public_key_pem = Some(v);
+        }
+        if let Some(v) = var("OFFSEC_JWT_HS256_SECRET") {
+            self.jwt_hs256_secret = Some(v);
+        }
+        if let Some(v) = var("OFFSEC_JWT_ALLOW_HS256") {
+            self.jwt_allow_hs256 = v
+                .parse()
+                .with_context(|| format!("OFFSEC_JWT_ALLOW_HS256 {v:?} is not true or false"))?;
+        }
+        if let Some(v) = var("OFFSEC_JWKS_FILE") {
+            self.jwks_file = Some(v);
+        }
+        if let Some(v) = var("OFFSEC_DATA_DIR") {
+            self.data_dir = v;
+        }
+        if let Some(v) = var("OFFSEC_GUARDIAN_URL") {
+            self.guardian_url = Some(v);
+        }
+        if let Some(v) = var("OFFSEC_SIGNING_KEY_FILE") {
+            self.signing_key_file = Some(v);
+        }
+        if let Some(v) = var("OFFSEC_DURABILITY") {
+            self.durability = DurabilityMode::from_env_value(&v)?;
+        }
+        if let Some(v) = var("OFFSEC_APPROVALS") {
+            self.approvals.required = serde_json::from_str(&v)
+                .context("OFFSEC_APPROVALS must be a JSON object of action -> approvers")?;
+        }
+        if let Some(v) = var("OFFSEC_APPROVAL_TIMEOUT_SECONDS") {
+            self.approvals.timeout_seconds = v.parse().with_context(|| {
+                format!("OFFSEC_APPROVAL_TIMEOUT_SECONDS {v:?} is not a number")
+            })?;
+        }
+        if let Some(v) = var("OFFSEC_DISPATCH_MAX_ATTEMPTS") {
+            self.dispatch.max_attempts = v
+                .parse()
+                .with_context(|| format!("OFFSEC_DISPATCH_MAX_ATTEMPTS {v:?} is not a number"))?;
+        }
+        if let Some(v) = var("OFFSEC_LEDGER_ISSUER_ID") {
+            self.ledger.issuer_id = v;
+        }
+        if let Some(v) = var("OFFSEC_LEDGER_KEY_FILE") {
+            self.ledger.key_file = Some(v);
+        }
+        if let Some(v) = var("OFFSEC_CORRELATION_WINDOW_SECONDS") {
+            self.correlation.window_seconds = v.parse().with_context(|| {
+                format!("OFFSEC_CORRELATION_WINDOW_SECONDS {v:?} is not a number")
+            })?;
+        }
+        if let Some(v) = var("OFFSEC_CORRELATION_KEYS") {
+            self.correlation.keys = v
+                .split(',')
+                .map(str::trim)
+                .filter(|k| !k.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        let node_id = var("OFFSEC_MESH_NODE_ID");
+        let privkey_file = var("OFFSEC_MESH_PRIVKEY_FILE");
+        if self.mesh.is_none() && (node_id.is_some() || privkey_file.is_some()) {
+            self.mesh = Some(MeshConfig {
+                node_id: String::new(),
+                privkey_file: String::new(),
+                pubkey_file: None,
+                peers: Vec::new(),
+                interval_seconds: MeshConfig::default_interval(),
+            });
+        }
+        if let Some(mesh) = self.mesh.as_mut() {
+            if let Some(v) = node_id {
+                mesh.node_id = v;
+            }
+            if let Some(v) = privkey_file {
+                mesh.privkey_file = v;
+            }
+            if let Some(v) = var("OFFSEC_MESH_PUBKEY_FILE") {
+                mesh.pubkey_file = Some(v);
+            }
+            if let Some(v) = var("OFFSEC_MESH_PEERS") {
+                mesh.peers = serde_json::from_str(&v)
+                    .context("OFFSEC_MESH_PEERS must be a JSON array of {id, url, pubkey}")?;
+            }
+            if let Some(v) = var("OFFSEC_MESH_INTERVAL_SECONDS") {
+                mesh.interval_seconds = v.parse().with_context(|| {
+                    format!("OFFSEC_MESH_INTERVAL_SECONDS {v:?} is not a number")
+                })?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Check keys, peers and paths, reporting every problem at once.
+    /// Creates `data_dir` if it does not exist.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        match self.jwt_hs256_secret.as_deref() {
+            Some("dev-secret") => errors.push(
+                "OFFSEC_JWT_HS256_SECRET cannot be 'dev-secret' in production".to_string(),
+            ),
+            Some(secret) if secret.len() < 32 => {
+                tracing::warn!("JWT secret is shorter than recommended (32+ chars)")
+            }
+            Some(_) => {}
+            None => {}
+        }
+        match Keyring::from_config(self) {
+            Ok(keyring) if keyring.is_empty() => errors.push(
+                "no capability key: set OFFSEC_JWT_HS256_SECRET (generate with: openssl rand -hex 32), OFFSEC_JWT_PUBLIC_KEY or OFFSEC_JWKS_FILE"
+                    .to_string(),
+            ),
+            Ok(_) => {}
+            Err(e) => errors.push(format!("{e:#}")),
+        }
+
+        if let Some(path) = &self.signing_key_file {
+            if let Err(e) = load_signing_key(path) {
+                errors.push(format!("signing_key_file: {e}"));
+            }
+        }
+
+        if let Some(mesh) = &self.mesh {
+            mesh.validate(&mut errors);
+        }
+
+        if self.approvals.timeout_seconds == 0 {
+            errors.push("approvals.timeout_seconds must be greater than 0".to_string());
+        }
+        let dispatch = &self.dispatch;
+        if dispatch.max_attempts == 0 {
+            errors.push("dispatch.max_attempts must be greater than 0".to_string());
+        }
+        if dispatch.timeout_ms == 0 {
+            errors.push("dispatch.timeout_ms must be greater than 0".to_string());
+        }
+        if dispatch.base_delay_ms > dispatch.max_delay_ms {
+            errors.push("dispatch.base_delay_ms must not exceed dispatch.max_delay_ms".to_string());
+        }
+        if !self.ledger.issuer_id.starts_with("did:") {
+            errors.push(format!(
+                "ledger.issuer_id {:?} must be a DID (did:...)",
+                self.ledger.issuer_id
+            ));
+        }
+
+        let correlation = &self.correlation;
+        if correlation.enabled {
+            if correlation.window_seconds == 0 {
+                errors.push("correlation.window_seconds must be greater than 0".to_string());
+            }
+            if correlation.keys.is_empty() {
+                errors.push("correlation.keys must name at least one field".to_string());
+            }
+            for key in &correlation.keys {
+                if !is_known_key(key) {
+                    errors.push(format!("correlation.keys: unknown field {key:?}"));
                 }
-                if secret.len() < 32 {
-                    tracing::warn!("JWT secret is shorter than recommended (32+ chars)");
+            }
+        }
+
+        if let Err(e) = fs::create_dir_all(&self.data_dir) {
+            errors.push(format!("data_dir {}: {e}", self.data_dir));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            bail!("invalid configuration:\n  - {}", errors.join("\n  - "))
+        }
+    }
+
+    /// Configuration from env vars only, without validation. Panics if no
+    /// usable HS256 secret is set; use [`load`](Self::load) at startup.
+    pub fn from_env() -> Self {
+        let config = Self::defaults()
+            .with_overrides(|name| env::var(name).ok())
+            .unwrap_or_else(|e| panic!("{e:#}"));
+        match config.jwt_hs256_secret.as_deref() {
+            None => {
+                panic!("OFFSEC_JWT_HS256_SECRET must be set - generate with: openssl rand -hex 32")
+            }
+            Some("dev-secret") => {
+                panic!("OFFSEC_JWT_HS256_SECRET cannot be 'dev-secret' in production")
+            }
+            Some(_) => {}
+        }
+        config
+    }
+}
+
+impl MeshConfig {
+    fn validate(&self, errors: &mut Vec<String>) {
+        if self.node_id.trim().is_empty() {
+            errors.push("mesh.node_id must be set".to_string());
+        }
+        if self.interval_seconds == 0 {
+            errors.push("mesh.interval_seconds must be greater than 0".to_string());
+        }
+
+        match load_signing_key(&self.privkey_file) {
+            Ok(key) => {
+                if let Some(path) = &self.pubkey_file {
+                    match fs::read_to_string(path) {
+                        Ok(text) if text.trim() == public_key_b64(&key) => {}
+                        Ok(_) => errors.push(format!(
+                            "mesh.pubkey_file {path} does not match mesh.privkey_file {}",
+                            self.privkey_file
+                        )),
+                        Err(e) => errors.push(format!("mesh.pubkey_file {path}: {e}")),
+                    }
                 }
-                Some(secret)
-            },
-            data_dir: env::var("OFFSEC_DATA_DIR").unwrap_or_else(|_| "data-offsec".to_string()),
-            guardian_url: env::var("OFFSEC_GUARDIAN_URL").ok(),
-            mesh: None,
+            }
+            Err(e) if self.privkey_file.is_empty() => {
+                errors.push(format!("mesh.privkey_file must be set ({e})"))
+            }
+            Err(e) => errors.push(format!("mesh.privkey_file: {e}")),
+        }
+
+        errors.extend(validate_peers(&self.peers, &self.node_id));
+    }
+}
+
+/// Problems with a peer list: missing, duplicate or self ids, non-HTTP URLs
+/// and pubkeys that are not base64 Ed25519 keys.
+pub fn validate_peers(peers: &[MeshPeer], node_id: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for (i, peer) in peers.iter().enumerate() {
+        let name = if peer.id.is_empty() {
+            format!("mesh.peers[{i}]")
+        } else {
+            format!("mesh peer {:?}", peer.id)
+        };
+        if peer.id.trim().is_empty() {
+            errors.push(format!("{name}: id must be set"));
+        } else if peer.id == node_id {
+            errors.push(format!("{name}: id is this node's own node_id"));
+        } else if !seen.insert(peer.id.as_str()) {
+            errors.push(format!("{name}: duplicate peer id"));
+        }
+        match reqwest::Url::parse(&peer.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(url) => errors.push(format!("{name}: unsupported url scheme {:?}", url.scheme())),
+            Err(e) => errors.push(format!("{name}: invalid url {:?}: {e}", peer.url)),
+        }
+        if let Err(e) = parse_pubkey(&peer.pubkey) {
+            errors.push(format!("{name}: {e}"));
         }
     }
+    errors
 }
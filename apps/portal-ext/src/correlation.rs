@@ -0,0 +1,383 @@
+//! Grouping of ThreatEvents into incidents. Each event's correlation keys
+//! are built from the `[correlation] keys` fields; an event whose key was
+//! last seen within `window_seconds` joins that key's incident, anything
+//! else opens a new one. Keys are kept in `receipts.db`, so open windows
+//! survive a restart.
+
+use std::{net::IpAddr, sync::Arc};
+
+use anyhow::anyhow;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+use sqlx::{sqlite::SqlitePool, Row};
+
+use crate::{
+    incidents::{self, IncidentStatus, NewIncident, Severity},
+    models::ThreatEvent,
+    AppState,
+};
+
+const SCHEMA: &[&str] = &["CREATE TABLE IF NOT EXISTS incident_correlations (
+        correlation_key TEXT PRIMARY KEY,
+        incident_id TEXT NOT NULL,
+        last_seen TEXT NOT NULL
+    )"];
+
+/// Actor recorded for incidents the correlator opens or updates.
+pub const CORRELATOR: &str = "correlator";
+
+const KEY_FIELDS: &[&str] = &[
+    "source_ip",
+    "event_type",
+    "guardian_id",
+    "source",
+    "source_host",
+];
+
+/// Values of a field that are used for correlation; an event listing more
+/// (say, a scan touching hundreds of IPs) is correlated on the first ones.
+pub const MAX_VALUES_PER_FIELD: usize = 8;
+/// Keys one event may produce across all fields.
+pub const MAX_KEYS_PER_EVENT: usize = 64;
+
+pub fn is_known_key(key: &str) -> bool {
+    KEY_FIELDS.contains(&key)
+        || key
+            .strip_prefix("metadata.")
+            .is_some_and(|field| !field.is_empty())
+}
+
+/// Values of one key field in `event`, sorted and capped at
+/// [`MAX_VALUES_PER_FIELD`]. An event may touch several IPs.
+fn field_values(event: &ThreatEvent, key: &str) -> Vec<String> {
+    let values = match key {
+        "source_ip" => event
+            .affected
+            .iter()
+            .filter_map(|a| a.parse::<IpAddr>().ok())
+            .map(|ip| ip.to_string())
+            .collect(),
+        "event_type" => vec![event.event_type.clone()],
+        "guardian_id" => event.guardian_id.iter().cloned().collect(),
+        "source" => vec![event.source.clone()],
+        "source_host" => event.source_host.iter().cloned().collect(),
+        _ => key
+            .strip_prefix("metadata.")
+            .and_then(|field| event.metadata.get(field))
+            .and_then(|value| match value {
+                Value::String(s) => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                Value::Bool(b) => Some(b.to_string()),
+                _ => None,
+            })
+            .into_iter()
+            .collect(),
+    };
+    let mut values: Vec<String> = values.into_iter().filter(|v| !v.is_empty()).collect();
+    values.sort();
+    values.dedup();
+    values.truncate(MAX_VALUES_PER_FIELD);
+    values
+}
+
+/// Correlation keys for `event`, e.g. `source_ip=203.0.113.42|event_type=ssh`,
+/// one per combination of values, at most [`MAX_KEYS_PER_EVENT`]. Empty if
+/// any field is missing, in which case the event is not correlated.
+pub fn correlation_keys(fields: &[String], event: &ThreatEvent) -> Vec<String> {
+    if fields.is_empty() {
+        return Vec::new();
+    }
+    let mut keys = vec![String::new()];
+    for field in fields {
+        let values = field_values(event, field);
+        keys = keys
+            .iter()
+            .flat_map(|prefix| {
+                values.iter().map(move |value| {
+                    let sep = if prefix.is_empty() { "" } else { "|" };
+                    format!("{prefix}{sep}{field}={value}")
+                })
+            })
+            .take(MAX_KEYS_PER_EVENT)
+            .collect();
+    }
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// When the event happened, or now if its timestamp is unreadable.
+fn event_time(event: &ThreatEvent) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(&event.timestamp)
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+}
+
+/// Last sighting of each correlation key and the incident it belongs to.
+#[derive(Clone)]
+pub struct CorrelationIndex {
+    pool: SqlitePool,
+    /// Held while an event is correlated, so events sharing a key cannot
+    /// open two incidents.
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl CorrelationIndex {
+    pub async fn open(pool: SqlitePool) -> anyhow::Result<Self> {
+        for stmt in SCHEMA {
+            sqlx::query(stmt).execute(&pool).await?;
+        }
+        Ok(Self {
+            pool,
+            lock: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    /// The incidents whose keys were seen within `window_seconds` of `at`,
+    /// either side, most recent sighting first.
+    pub async fn find(
+        &self,
+        keys: &[String],
+        at: DateTime<Utc>,
+        window_seconds: u64,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut found: Vec<(DateTime<Utc>, String)> = Vec::new();
+        for key in keys {
+            let Some(row) = sqlx::query(
+                "SELECT incident_id, last_seen FROM incident_correlations
+                 WHERE correlation_key = ?",
+            )
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?
+            else {
+                continue;
+            };
+            let last_seen = DateTime::parse_from_rfc3339(&row.get::<String, _>("last_seen"))?
+                .with_timezone(&Utc);
+            if (at - last_seen).num_seconds().unsigned_abs() > window_seconds {
+                continue;
+            }
+            let incident_id: String = row.get("incident_id");
+            match found.iter_mut().find(|(_, id)| *id == incident_id) {
+                Some((seen, _)) => *seen = last_seen.max(*seen),
+                None => found.push((last_seen, incident_id)),
+            }
+        }
+        found.sort_by_key(|(seen, _)| std::cmp::Reverse(*seen));
+        Ok(found
+            .into_iter()
+            .map(|(_, incident_id)| incident_id)
+            .collect())
+    }
+
+    /// Point `keys` at `incident_id`, seen at `at`. A key already on that
+    /// incident keeps its later sighting. Keys last seen more than
+    /// `window_seconds` before `at` (or now, if `at` is in the future) can
+    /// no longer match and are dropped.
+    pub async fn touch(
+        &self,
+        keys: &[String],
+        incident_id: &str,
+        at: DateTime<Utc>,
+        window_seconds: u64,
+    ) -> anyhow::Result<()> {
+        let expired = at.min(Utc::now()) - chrono::Duration::seconds(window_seconds as i64);
+        let at = at.to_rfc3339_opts(SecondsFormat::Millis, true);
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM incident_correlations WHERE last_seen < ?")
+            .bind(expired.to_rfc3339_opts(SecondsFormat::Millis, true))
+            .execute(&mut *tx)
+            .await?;
+        for key in keys {
+            sqlx::query(
+                "INSERT INTO incident_correlations (correlation_key, incident_id, last_seen)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT (correlation_key) DO UPDATE SET
+                    last_seen = CASE
+                        WHEN incident_id = excluded.incident_id AND last_seen > excluded.last_seen
+                        THEN last_seen ELSE excluded.last_seen END,
+                    incident_id = excluded.incident_id",
+            )
+            .bind(key)
+            .bind(incident_id)
+            .bind(&at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// What the correlator did with one event.
+#[derive(Debug, Clone)]
+pub struct Correlation {
+    pub incident_id: String,
+    /// Whether the event opened the incident.
+    pub opened: bool,
+    /// Ledger receipt for the event; `None` if it was already linked.
+    pub receipt_id: Option<String>,
+}
+
+/// Add `event` to the incident its correlation keys point at, or open one.
+/// Closed incidents are skipped: the event joins the most recently seen
+/// open incident its keys match, or starts a new one.
+/// Returns `None` when correlation is off or the event has no key.
+pub async fn correlate(
+    state: &AppState,
+    event: &ThreatEvent,
+) -> anyhow::Result<Option<Correlation>> {
+    let config = &state.config.correlation;
+    if !config.enabled {
+        return Ok(None);
+    }
+    let keys = correlation_keys(&config.keys, event);
+    if keys.is_empty() {
+        return Ok(None);
+    }
+
+    let _guard = state.correlation.lock.lock().await;
+    let at = event_time(event);
+    let severity = Severity::parse(&event.severity).unwrap_or(Severity::Medium);
+    // The most recently seen incident still open, held so that it cannot
+    // be closed while the event is added to it.
+    let mut existing = None;
+    let mut _incident_guard = None;
+    for incident_id in state
+        .correlation
+        .find(&keys, at, config.window_seconds)
+        .await?
+    {
+        let guard = state.incidents.lock(&incident_id).await;
+        if let Some(record) = state
+            .incidents
+            .get(&incident_id)
+            .await?
+            .filter(|record| record.status != IncidentStatus::Closed)
+        {
+            existing = Some(record);
+            _incident_guard = Some(guard);
+            break;
+        }
+    }
+
+    let (correlation, frame_type) = match existing {
+        Some(record) if record.threat_event_ids.contains(&event.id) => (
+            Correlation {
+                incident_id: record.incident_id,
+                opened: false,
+                receipt_id: None,
+            },
+            None,
+        ),
+        Some(record) => {
+            let raised = severity > record.severity;
+            let severity = record.severity.max(severity);
+            let receipt_id = promote(
+                state,
+                &record.incident_id,
+                severity,
+                "offsec.incident.correlated",
+                json!({
+                    "threat_event_id": event.id,
+                    "correlation_keys": keys,
+                    "previous_severity": raised.then_some(record.severity),
+                    "status": record.status,
+                    "actor": CORRELATOR,
+                }),
+            )
+            .await?;
+            state
+                .incidents
+                .link(&record.incident_id, std::slice::from_ref(&event.id), &[])
+                .await?;
+            if raised {
+                state
+                    .incidents
+                    .set_severity(&record.incident_id, severity)
+                    .await?;
+            }
+            (
+                Correlation {
+                    incident_id: record.incident_id,
+                    opened: false,
+                    receipt_id: Some(receipt_id),
+                },
+                Some("incident.updated"),
+            )
+        }
+        None => {
+            let incident = NewIncident {
+                incident_id: format!("inc-{}", uuid::Uuid::new_v4()),
+                title: Some(format!("{} ({})", event.event_type, keys[0])),
+                severity,
+            };
+            let receipt_id = promote(
+                state,
+                &incident.incident_id,
+                severity,
+                "offsec.incident.opened",
+                json!({
+                    "title": incident.title,
+                    "status": IncidentStatus::Open,
+                    "actor": CORRELATOR,
+                    "threat_event_ids": [event.id],
+                    "correlation_keys": keys,
+                }),
+            )
+            .await?;
+            state
+                .incidents
+                .create(&incident, CORRELATOR, Some(&receipt_id))
+                .await?;
+            state
+                .incidents
+                .link(&incident.incident_id, std::slice::from_ref(&event.id), &[])
+                .await?;
+            (
+                Correlation {
+                    incident_id: incident.incident_id,
+                    opened: true,
+                    receipt_id: Some(receipt_id),
+                },
+                Some("incident.opened"),
+            )
+        }
+    };
+    state
+        .correlation
+        .touch(&keys, &correlation.incident_id, at, config.window_seconds)
+        .await?;
+
+    if let Some(frame_type) = frame_type {
+        if let Some(record) = state.incidents.get(&correlation.incident_id).await? {
+            let mut data = serde_json::to_value(incidents::with_chain(state, record))?;
+            data["threat_event_id"] = json!(event.id);
+            data["correlation_keys"] = json!(keys);
+            data["receipt_id"] = json!(correlation.receipt_id);
+            state
+                .ws
+                .send_json(&json!({ "type": frame_type, "data": data }));
+        }
+    }
+    Ok(Some(correlation))
+}
+
+/// Append an event to the incident's ledger chain. Returns the receipt id.
+async fn promote(
+    state: &AppState,
+    incident_id: &str,
+    severity: Severity,
+    event_type: &str,
+    mut data: Value,
+) -> anyhow::Result<String> {
+    data["incident_id"] = json!(incident_id);
+    data["severity"] = json!(severity);
+    data["ts"] = json!(Utc::now().to_rfc3339());
+    state
+        .ledger
+        .record(incident_id, event_type, severity.as_str(), data)
+        .await
+        .map_err(|e| anyhow!("ledger write for incident {incident_id}: {e}"))
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    config::DurabilityMode,
+    models::ErrorResponse,
+    receipts::{write_receipt, OffsecReceipt},
+    wal, AppState,
+};
+
+/// Receipts waiting for retry in best-effort mode, one JSON entry per line,
+/// relative to the data dir.
+pub const BACKLOG_FILE: &str = "RECEIPT_BACKLOG.jsonl";
+/// Best-effort mode refuses new work once this many receipts are queued.
+pub const MAX_BACKLOG: usize = 10_000;
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedReceipt {
+    pub event_type: String,
+    pub guardian_id: Option<String>,
+    #[serde(default)]
+    pub guardian_tags: Vec<String>,
+    pub payload: Value,
+    /// Action whose request this receipts, linked to it once written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_id: Option<String>,
+    pub queued_at: String,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+/// Receipts that could not be written, persisted to `RECEIPT_BACKLOG.jsonl`
+/// so they survive a restart.
+#[derive(Clone)]
+pub struct ReceiptBacklog {
+    path: PathBuf,
+    queue: Arc<Mutex<VecDeque<QueuedReceipt>>>,
+    /// Serialises drains so each entry is written once.
+    draining: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BacklogStatus {
+    pub mode: DurabilityMode,
+    pub pending: usize,
+    pub capacity: usize,
+    pub oldest_queued_at: Option<String>,
+    pub last_error: Option<String>,
+}
+
+impl ReceiptBacklog {
+    /// Load the backlog left by a previous run. Unparsable lines are dropped
+    /// with a warning.
+    pub fn load(data_dir: &str) -> anyhow::Result<Self> {
+        let path = Path::new(data_dir).join(BACKLOG_FILE);
+        let mut queue = VecDeque::new();
+        if let Ok(content) = fs::read_to_string(&path) {
+            for (i, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<QueuedReceipt>(line) {
+                    Ok(entry) => queue.push_back(entry),
+                    Err(e) => tracing::warn!("dropping {} line {}: {}", path.display(), i + 1, e),
+                }
+            }
+        }
+        if !queue.is_empty() {
+            tracing::warn!("{} receipts queued from a previous run", queue.len());
+        }
+        Ok(Self {
+            path,
+            queue: Arc::new(Mutex::new(queue)),
+            draining: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock().map(|q| q.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn status(&self, mode: DurabilityMode) -> BacklogStatus {
+        let queue = self.queue.lock().ok();
+        let front = queue.as_ref().and_then(|q| q.front());
+        BacklogStatus {
+            mode,
+            pending: queue.as_ref().map_or(0, |q| q.len()),
+            capacity: MAX_BACKLOG,
+            oldest_queued_at: front.map(|e| e.queued_at.clone()),
+            last_error: front.and_then(|e| e.last_error.clone()),
+        }
+    }
+
+    /// Queue a receipt and append it to the backlog file.
+    pub fn push(&self, entry: QueuedReceipt) -> Result<(), String> {
+        let mut queue = self
+            .queue
+            .lock()
+            .map_err(|_| "backlog lock poisoned".to_string())?;
+        if queue.len() >= MAX_BACKLOG {
+            return Err(format!("receipt backlog full ({MAX_BACKLOG} pending)"));
+        }
+        let mut line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+        line.push('\n');
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| {
+                f.write_all(line.as_bytes())?;
+                f.sync_data()
+            })
+            .map_err(|e| format!("persisting receipt backlog: {e}"))?;
+        queue.push_back(entry);
+        Ok(())
+    }
+
+    fn persist(&self, queue: &VecDeque<QueuedReceipt>) -> std::io::Result<()> {
+        if queue.is_empty() {
+            return wal::remove(&self.path);
+        }
+        let mut content = String::new();
+        for entry in queue {
+            content.push_str(&serde_json::to_string(entry).map_err(std::io::Error::other)?);
+            content.push('\n');
+        }
+        wal::atomic_write(&self.path, content.as_bytes())
+    }
+}
+
+/// Write queued receipts in order, broadcasting each one. Stops at the first
+/// failure so ordering is kept. Returns how many were written.
+pub async fn retry_backlog(state: &AppState) -> usize {
+    let backlog = &state.backlog;
+    let _drain = backlog.draining.lock().await;
+    let mut written = 0;
+
+    while let Some(entry) = backlog.queue.lock().ok().and_then(|q| q.front().cloned()) {
+        let result = write_receipt(
+            state,
+            &entry.event_type,
+            entry.guardian_id.as_deref(),
+            &entry.guardian_tags,
+            &entry.payload,
+        )
+        .await;
+        if let (Ok(receipt), Some(action_id)) = (&result, &entry.action_id) {
+            if let Err(e) = state
+                .actions
+                .set_request_receipt(action_id, &receipt.id)
+                .await
+            {
+                tracing::warn!("linking receipt {} to {}: {}", receipt.id, action_id, e);
+            }
+        }
+
+        let Ok(mut queue) = backlog.queue.lock() else {
+            break;
+        };
+        match result {
+            Ok(receipt) => {
+                queue.pop_front();
+                written += 1;
+                state
+                    .ws
+                    .send_json(&serde_json::json!({ "type": "receipt", "data": receipt }));
+            }
+            Err(err) => {
+                if let Some(front) = queue.front_mut() {
+                    front.attempts += 1;
+                    front.last_error = Some(err.clone());
+                }
+                tracing::warn!("receipt retry failed ({} pending): {}", queue.len(), err);
+                if let Err(e) = backlog.persist(&queue) {
+                    tracing::error!("failed to persist receipt backlog: {}", e);
+                }
+                break;
+            }
+        }
+        if let Err(e) = backlog.persist(&queue) {
+            tracing::error!("failed to persist receipt backlog: {}", e);
+        }
+    }
+
+    written
+}
+
+/// Retry the backlog every few seconds for the life of the process.
+pub fn spawn_retry_worker(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !state.backlog.is_empty() {
+                let written = retry_backlog(&state).await;
+                if written > 0 {
+                    tracing::info!("wrote {} queued receipts", written);
+                }
+            }
+        }
+    });
+}
+
+/// Receipt an event and broadcast it according to the durability mode.
+///
+/// `payload` is both the receipted payload and the WebSocket frame for the
+/// event. In strict mode nothing is broadcast unless the receipt was
+/// written. In best-effort mode a failed write is queued and the event is
+/// broadcast anyway; the receipt follows when the retry succeeds, and is
+/// then attached to `action_id` as its request receipt. Returns the receipt
+/// if it was written now, `None` if it was queued.
+pub async fn commit_event(
+    state: &AppState,
+    event_type: &str,
+    guardian_id: Option<&str>,
+    guardian_tags: &[String],
+    payload: &Value,
+    action_id: Option<&str>,
+) -> Result<Option<OffsecReceipt>, (StatusCode, Json<ErrorResponse>)> {
+    let mode = state.config.durability;
+    // Keep best-effort receipts in order behind anything already queued.
+    let result = if mode == DurabilityMode::BestEffort && !state.backlog.is_empty() {
+        Err("earlier receipts still queued".to_string())
+    } else {
+        write_receipt(state, event_type, guardian_id, guardian_tags, payload).await
+    };
+
+    match (result, mode) {
+        (Ok(receipt), _) => {
+            state.ws.send_json(payload);
+            state
+                .ws
+                .send_json(&serde_json::json!({ "type": "receipt", "data": receipt }));
+            Ok(Some(receipt))
+        }
+        (Err(err), DurabilityMode::Strict) => {
+            tracing::error!("receipt write failed for {}: {}", event_type, err);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "receipt write failed".to_string(),
+                    details: Some(err),
+                }),
+            ))
+        }
+        (Err(err), DurabilityMode::BestEffort) => {
+            let entry = QueuedReceipt {
+                event_type: event_type.to_string(),
+                guardian_id: guardian_id.map(|g| g.to_string()),
+                guardian_tags: guardian_tags.to_vec(),
+                payload: payload.clone(),
+                action_id: action_id.map(str::to_string),
+                queued_at: Utc::now().to_rfc3339(),
+                attempts: 0,
+                last_error: Some(err.clone()),
+            };
+            if let Err(queue_err) = state.backlog.push(entry) {
+                tracing::error!(
+                    "receipt write failed for {} and could not be queued: {}; {}",
+                    event_type,
+                    err,
+                    queue_err
+                );
+                return Err((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Json(ErrorResponse {
+                        error: "receipt backlog unavailable".to_string(),
+                        details: Some(queue_err),
+                    }),
+                ));
+            }
+            tracing::warn!("receipt for {} queued for retry: {}", event_type, err);
+            state.ws.send_json(payload);
+            Ok(None)
+        }
+    }
+}
+
+/// Response body for a committed event: `receipt_id` once written, or
+/// `receipt: "queued"` in best-effort mode.
+pub fn receipt_status(status: &str, receipt: Option<&OffsecReceipt>) -> Value {
+    match receipt {
+        Some(r) => serde_json::json!({ "status": status, "receipt_id": r.id }),
+        None => serde_json::json!({ "status": status, "receipt": "queued" }),
+    }
+}
+
+pub async fn backlog_status(State(state): State<AppState>) -> Json<BacklogStatus> {
+    Json(state.backlog.status(state.config.durability))
+}
@@ -0,0 +1,1035 @@
+//! Incident records on top of the ledger's incident chains. Each incident
+//! has a status (`open → triaged → contained → closed`), a severity, an
+//! owner and the ThreatEvents and actions linked to it. Opening, assigning
+//! and every transition are promoted onto the incident's ledger chain.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{
+    sqlite::{SqliteConnection, SqlitePool},
+    Row,
+};
+
+use crate::{
+    auth::{AuthRejection, Authorized, IncidentWrite},
+    models::ErrorResponse,
+    offsec_ledger::IncidentLedger,
+    AppState,
+};
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS incidents (
+        incident_id TEXT PRIMARY KEY,
+        title TEXT,
+        status TEXT NOT NULL,
+        severity TEXT NOT NULL,
+        owner TEXT,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS idx_incidents_status ON incidents (status)",
+    "CREATE TABLE IF NOT EXISTS incident_history (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        incident_id TEXT NOT NULL,
+        from_status TEXT,
+        to_status TEXT NOT NULL,
+        actor TEXT NOT NULL,
+        receipt_id TEXT,
+        at TEXT NOT NULL,
+        note TEXT
+    )",
+    "CREATE INDEX IF NOT EXISTS idx_incident_history_incident
+        ON incident_history (incident_id, seq)",
+    "CREATE TABLE IF NOT EXISTS incident_links (
+        incident_id TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        ref_id TEXT NOT NULL,
+        at TEXT NOT NULL,
+        PRIMARY KEY (incident_id, kind, ref_id)
+    )",
+];
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+const THREAT_EVENT_LINK: &str = "threat_event";
+const ACTION_LINK: &str = "action";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IncidentStatus {
+    Open,
+    Triaged,
+    Contained,
+    Closed,
+}
+
+impl IncidentStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Triaged => "triaged",
+            Self::Contained => "contained",
+            Self::Closed => "closed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        Some(match status {
+            "open" => Self::Open,
+            "triaged" => Self::Triaged,
+            "contained" => Self::Contained,
+            "closed" => Self::Closed,
+            _ => return None,
+        })
+    }
+
+    /// Whether an incident may move from `self` to `to`. Incidents move
+    /// forward one step at a time, may be closed early (e.g. as a false
+    /// positive) and may be reopened.
+    pub fn can_transition(self, to: Self) -> bool {
+        use IncidentStatus::*;
+        matches!(
+            (self, to),
+            (Open, Triaged)
+                | (Triaged, Contained)
+                | (Open | Triaged | Contained, Closed)
+                | (Closed, Open)
+        )
+    }
+}
+
+impl fmt::Display for IncidentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Critical => "critical",
+        }
+    }
+
+    pub fn parse(severity: &str) -> Option<Self> {
+        Some(match severity {
+            "low" => Self::Low,
+            "medium" => Self::Medium,
+            "high" => Self::High,
+            "critical" => Self::Critical,
+            _ => return None,
+        })
+    }
+}
+
+/// One status change. The first step (opening) has no `from`.
+#[derive(Debug, Clone, Serialize)]
+pub struct IncidentTransition {
+    pub from: Option<IncidentStatus>,
+    pub to: IncidentStatus,
+    pub actor: String,
+    /// Ledger receipt promoted for the change.
+    pub receipt_id: Option<String>,
+    pub at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IncidentRecord {
+    pub incident_id: String,
+    pub title: Option<String>,
+    pub status: IncidentStatus,
+    pub severity: Severity,
+    pub owner: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub threat_event_ids: Vec<String>,
+    pub action_ids: Vec<String>,
+    pub history: Vec<IncidentTransition>,
+    /// The incident's ledger chain, oldest first.
+    pub receipt_ids: Vec<String>,
+}
+
+/// An incident about to be recorded as `open`.
+#[derive(Debug, Clone)]
+pub struct NewIncident {
+    pub incident_id: String,
+    pub title: Option<String>,
+    pub severity: Severity,
+}
+
+#[derive(Debug)]
+pub enum IncidentError {
+    Unknown(String),
+    Invalid {
+        incident_id: String,
+        from: IncidentStatus,
+        to: IncidentStatus,
+    },
+    Store(anyhow::Error),
+}
+
+impl fmt::Display for IncidentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(id) => write!(f, "no incident {id:?}"),
+            Self::Invalid {
+                incident_id,
+                from,
+                to,
+            } => write!(
+                f,
+                "incident {incident_id:?} cannot move from {from} to {to}"
+            ),
+            Self::Store(e) => write!(f, "incident store error: {e}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for IncidentError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Store(e.into())
+    }
+}
+
+pub fn incident_response(err: IncidentError) -> (StatusCode, Json<ErrorResponse>) {
+    let (code, error) = match &err {
+        IncidentError::Unknown(_) => (StatusCode::NOT_FOUND, "unknown_incident"),
+        IncidentError::Invalid { .. } => (StatusCode::CONFLICT, "invalid_transition"),
+        IncidentError::Store(_) => (StatusCode::INTERNAL_SERVER_ERROR, "incident_store_failed"),
+    };
+    reject(code, error, err.to_string())
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct IncidentFilter {
+    pub status: Option<IncidentStatus>,
+    pub severity: Option<Severity>,
+    pub owner: Option<String>,
+}
+
+/// Incident records, their status history and links, kept in
+/// `receipts.db`. The receipts themselves live on the ledger chain.
+#[derive(Clone)]
+pub struct IncidentStore {
+    pool: SqlitePool,
+    /// One lock per incident being changed (see [`IncidentStore::lock`]).
+    locks: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl IncidentStore {
+    pub async fn open(pool: SqlitePool) -> anyhow::Result<Self> {
+        for stmt in SCHEMA {
+            sqlx::query(stmt).execute(&pool).await?;
+        }
+        Ok(Self {
+            pool,
+            locks: Arc::default(),
+        })
+    }
+
+    /// Serialise changes to `incident_id`: held from reading the record
+    /// until its ledger receipt and row update are both written, so the
+    /// chain never records a change the row then refuses.
+    pub async fn lock(&self, incident_id: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(incident_id.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+
+    /// Create rows for ledger chains that have none, such as chains written
+    /// before incident records existed. Status, severity, owner, history and
+    /// links are read back from the chain's receipts. Returns how many were
+    /// added.
+    pub async fn backfill(&self, ledger: &IncidentLedger) -> anyhow::Result<usize> {
+        let mut added = 0;
+        for incident_id in ledger.list_incident_ids() {
+            if self.status(&incident_id).await?.is_some() {
+                continue;
+            }
+            let Some(chain) = ledger.get_incident(&incident_id) else {
+                continue;
+            };
+            let receipts = chain
+                .receipts
+                .iter()
+                .map(serde_json::to_value)
+                .collect::<Result<Vec<_>, _>>()?;
+            let Some(first) = receipts.first() else {
+                continue;
+            };
+            let at = |r: &Value| {
+                r["ts"]
+                    .as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| Utc::now().to_rfc3339())
+            };
+            let actor = |r: &Value| {
+                r["body"]["extra"]["actor"]
+                    .as_str()
+                    .unwrap_or("ledger")
+                    .to_string()
+            };
+            let status_of = |v: &Value| v.as_str().and_then(IncidentStatus::parse);
+            let severity = first["body"]["extra"]["severity"]
+                .as_str()
+                .or(first["body"]["severity"].as_str())
+                .and_then(Severity::parse)
+                .unwrap_or(Severity::Medium);
+
+            let mut status = IncidentStatus::Open;
+            let mut owner = None;
+            let mut history = vec![(None, IncidentStatus::Open, first)];
+            let mut links = Vec::new();
+            for receipt in &receipts {
+                let extra = &receipt["body"]["extra"];
+                let ids = |field: &str| {
+                    extra[field]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string)
+                        .collect::<Vec<_>>()
+                };
+                let mut threat_event_ids = ids("threat_event_ids");
+                threat_event_ids.extend(extra["threat_event_id"].as_str().map(str::to_string));
+                links.push((threat_event_ids, ids("action_ids"), at(receipt)));
+            }
+            for receipt in &receipts[1..] {
+                let extra = &receipt["body"]["extra"];
+                if let (Some(from), Some(to)) = (status_of(&extra["from"]), status_of(&extra["to"]))
+                {
+                    status = to;
+                    history.push((Some(from), to, receipt));
+                }
+                if extra.get("previous_owner").is_some() {
+                    owner = extra["owner"].as_str().map(str::to_string);
+                }
+            }
+            let updated_at = receipts.last().map(at).unwrap_or_else(|| at(first));
+
+            let mut tx = self.pool.begin().await?;
+            let inserted = sqlx::query(
+                "INSERT INTO incidents
+                    (incident_id, title, status, severity, owner, created_at, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)
+                 ON CONFLICT (incident_id) DO NOTHING",
+            )
+            .bind(&incident_id)
+            .bind(first["body"]["extra"]["title"].as_str())
+            .bind(status.as_str())
+            .bind(severity.as_str())
+            .bind(&owner)
+            .bind(at(first))
+            .bind(&updated_at)
+            .execute(&mut *tx)
+            .await?;
+            if inserted.rows_affected() == 0 {
+                continue;
+            }
+            for (from, to, receipt) in history {
+                sqlx::query(
+                    "INSERT INTO incident_history
+                        (incident_id, from_status, to_status, actor, receipt_id, at, note)
+                     VALUES (?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(&incident_id)
+                .bind(from.map(IncidentStatus::as_str))
+                .bind(to.as_str())
+                .bind(actor(receipt))
+                .bind(receipt["id"].as_str())
+                .bind(at(receipt))
+                .bind(receipt["body"]["extra"]["note"].as_str())
+                .execute(&mut *tx)
+                .await?;
+            }
+            for (threat_event_ids, action_ids, at) in &links {
+                insert_links(&mut tx, &incident_id, threat_event_ids, action_ids, at).await?;
+            }
+            tx.commit().await?;
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    /// Record a new incident as `open` by `actor`. Returns `false` if the
+    /// `incident_id` is already taken.
+    pub async fn create(
+        &self,
+        incident: &NewIncident,
+        actor: &str,
+        receipt_id: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let now = Utc::now().to_rfc3339();
+        let mut tx = self.pool.begin().await?;
+        let inserted = sqlx::query(
+            "INSERT INTO incidents (incident_id, title, status, severity, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT (incident_id) DO NOTHING",
+        )
+        .bind(&incident.incident_id)
+        .bind(&incident.title)
+        .bind(IncidentStatus::Open.as_str())
+        .bind(incident.severity.as_str())
+        .bind(&now)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query(
+            "INSERT INTO incident_history (incident_id, from_status, to_status, actor, receipt_id, at)
+             VALUES (?, NULL, ?, ?, ?, ?)",
+        )
+        .bind(&incident.incident_id)
+        .bind(IncidentStatus::Open.as_str())
+        .bind(actor)
+        .bind(receipt_id)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Move `incident_id` from `from` to `to`. Fails if the lifecycle does
+    /// not allow it or the incident is no longer in `from`.
+    pub async fn transition(
+        &self,
+        incident_id: &str,
+        from: IncidentStatus,
+        to: IncidentStatus,
+        actor: &str,
+        receipt_id: Option<&str>,
+        note: Option<&str>,
+    ) -> Result<(), IncidentError> {
+        let invalid = |from| IncidentError::Invalid {
+            incident_id: incident_id.to_string(),
+            from,
+            to,
+        };
+        if !from.can_transition(to) {
+            return Err(invalid(from));
+        }
+
+        let now = Utc::now().to_rfc3339();
+        let mut tx = self.pool.begin().await?;
+        let updated = sqlx::query(
+            "UPDATE incidents SET status = ?, updated_at = ? WHERE incident_id = ? AND status = ?",
+        )
+        .bind(to.as_str())
+        .bind(&now)
+        .bind(incident_id)
+        .bind(from.as_str())
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            drop(tx);
+            return Err(
+                match self
+                    .status(incident_id)
+                    .await
+                    .map_err(IncidentError::Store)?
+                {
+                    Some(status) => invalid(status),
+                    None => IncidentError::Unknown(incident_id.to_string()),
+                },
+            );
+        }
+        sqlx::query(
+            "INSERT INTO incident_history
+                (incident_id, from_status, to_status, actor, receipt_id, at, note)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(incident_id)
+        .bind(from.as_str())
+        .bind(to.as_str())
+        .bind(actor)
+        .bind(receipt_id)
+        .bind(&now)
+        .bind(note)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Set the incident's owner. Returns `false` if there is no such
+    /// incident.
+    pub async fn assign(&self, incident_id: &str, owner: &str) -> anyhow::Result<bool> {
+        let updated =
+            sqlx::query("UPDATE incidents SET owner = ?, updated_at = ? WHERE incident_id = ?")
+                .bind(owner)
+                .bind(Utc::now().to_rfc3339())
+                .bind(incident_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(updated.rows_affected() == 1)
+    }
+
+    pub async fn set_severity(
+        &self,
+        incident_id: &str,
+        severity: Severity,
+    ) -> anyhow::Result<bool> {
+        let updated =
+            sqlx::query("UPDATE incidents SET severity = ?, updated_at = ? WHERE incident_id = ?")
+                .bind(severity.as_str())
+                .bind(Utc::now().to_rfc3339())
+                .bind(incident_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(updated.rows_affected() == 1)
+    }
+
+    /// Link ThreatEvent and action ids. Links already present are kept.
+    pub async fn link(
+        &self,
+        incident_id: &str,
+        threat_event_ids: &[String],
+        action_ids: &[String],
+    ) -> anyhow::Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let mut tx = self.pool.begin().await?;
+        insert_links(&mut tx, incident_id, threat_event_ids, action_ids, &now).await?;
+        sqlx::query("UPDATE incidents SET updated_at = ? WHERE incident_id = ?")
+            .bind(&now)
+            .bind(incident_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn status(&self, incident_id: &str) -> anyhow::Result<Option<IncidentStatus>> {
+        let row = sqlx::query("SELECT status FROM incidents WHERE incident_id = ?")
+            .bind(incident_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.and_then(|r| IncidentStatus::parse(&r.get::<String, _>("status"))))
+    }
+
+    /// The incident without its ledger chain (`receipt_ids` is empty).
+    pub async fn get(&self, incident_id: &str) -> anyhow::Result<Option<IncidentRecord>> {
+        let row = sqlx::query("SELECT * FROM incidents WHERE incident_id = ?")
+            .bind(incident_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        match row {
+            Some(row) => Ok(Some(self.with_history(row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Most recently updated incidents first, without their ledger chains.
+    pub async fn list(
+        &self,
+        filter: &IncidentFilter,
+        limit: usize,
+    ) -> anyhow::Result<Vec<IncidentRecord>> {
+        let rows = sqlx::query(
+            "SELECT * FROM incidents
+             WHERE (?1 IS NULL OR status = ?1)
+               AND (?2 IS NULL OR severity = ?2)
+               AND (?3 IS NULL OR owner = ?3)
+             ORDER BY updated_at DESC, incident_id ASC
+             LIMIT ?4",
+        )
+        .bind(filter.status.map(IncidentStatus::as_str))
+        .bind(filter.severity.map(Severity::as_str))
+        .bind(&filter.owner)
+        .bind(limit.clamp(1, MAX_LIMIT) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut records = Vec::with_capacity(rows.len());
+        for row in rows {
+            records.push(self.with_history(row).await?);
+        }
+        Ok(records)
+    }
+
+    async fn with_history(&self, row: sqlx::sqlite::SqliteRow) -> anyhow::Result<IncidentRecord> {
+        let incident_id: String = row.get("incident_id");
+        let history = sqlx::query(
+            "SELECT from_status, to_status, actor, receipt_id, at, note
+             FROM incident_history WHERE incident_id = ? ORDER BY seq ASC",
+        )
+        .bind(&incident_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|h| {
+            let to: String = h.get("to_status");
+            Ok(IncidentTransition {
+                from: h
+                    .get::<Option<String>, _>("from_status")
+                    .as_deref()
+                    .and_then(IncidentStatus::parse),
+                to: IncidentStatus::parse(&to)
+                    .ok_or_else(|| anyhow::anyhow!("unknown incident status {to:?}"))?,
+                actor: h.get("actor"),
+                receipt_id: h.get("receipt_id"),
+                at: h.get("at"),
+                note: h.get("note"),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut threat_event_ids = Vec::new();
+        let mut action_ids = Vec::new();
+        for link in sqlx::query(
+            "SELECT kind, ref_id FROM incident_links WHERE incident_id = ? ORDER BY at, ref_id",
+        )
+        .bind(&incident_id)
+        .fetch_all(&self.pool)
+        .await?
+        {
+            let ref_id: String = link.get("ref_id");
+            match link.get::<String, _>("kind").as_str() {
+                THREAT_EVENT_LINK => threat_event_ids.push(ref_id),
+                _ => action_ids.push(ref_id),
+            }
+        }
+
+        let status: String = row.get("status");
+        let severity: String = row.get("severity");
+        Ok(IncidentRecord {
+            title: row.get("title"),
+            status: IncidentStatus::parse(&status)
+                .ok_or_else(|| anyhow::anyhow!("unknown incident status {status:?}"))?,
+            severity: Severity::parse(&severity)
+                .ok_or_else(|| anyhow::anyhow!("unknown severity {severity:?}"))?,
+            owner: row.get("owner"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+            incident_id,
+            threat_event_ids,
+            action_ids,
+            history,
+            receipt_ids: Vec::new(),
+        })
+    }
+}
+
+async fn insert_links(
+    conn: &mut SqliteConnection,
+    incident_id: &str,
+    threat_event_ids: &[String],
+    action_ids: &[String],
+    at: &str,
+) -> anyhow::Result<()> {
+    let links = threat_event_ids
+        .iter()
+        .map(|id| (THREAT_EVENT_LINK, id))
+        .chain(action_ids.iter().map(|id| (ACTION_LINK, id)));
+    for (kind, ref_id) in links {
+        sqlx::query(
+            "INSERT INTO incident_links (incident_id, kind, ref_id, at) VALUES (?, ?, ?, ?)
+             ON CONFLICT (incident_id, kind, ref_id) DO NOTHING",
+        )
+        .bind(incident_id)
+        .bind(kind)
+        .bind(ref_id)
+        .bind(at)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+fn reject(code: StatusCode, error: &str, details: String) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        code,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            details: Some(details),
+        }),
+    )
+}
+
+fn store_error(e: anyhow::Error) -> AuthRejection {
+    incident_response(IncidentError::Store(e))
+}
+
+/// Fill in the incident's ledger chain.
+pub fn with_chain(state: &AppState, mut record: IncidentRecord) -> IncidentRecord {
+    if let Some(chain) = state.ledger.get_incident(&record.incident_id) {
+        record.receipt_ids = chain.receipts.into_iter().map(|r| r.id).collect();
+    }
+    record
+}
+
+async fn load(state: &AppState, incident_id: &str) -> Result<IncidentRecord, AuthRejection> {
+    state
+        .incidents
+        .get(incident_id)
+        .await
+        .map_err(store_error)?
+        .map(|record| with_chain(state, record))
+        .ok_or_else(|| incident_response(IncidentError::Unknown(incident_id.to_string())))
+}
+
+/// Promote a change onto the incident's ledger chain and broadcast it.
+/// Returns the receipt id.
+async fn promote(
+    state: &AppState,
+    incident_id: &str,
+    severity: Severity,
+    event_type: &str,
+    mut data: Value,
+) -> Result<String, AuthRejection> {
+    data["incident_id"] = json!(incident_id);
+    data["severity"] = json!(severity);
+    data["ts"] = json!(Utc::now().to_rfc3339());
+    let receipt_id = state
+        .ledger
+        .record(incident_id, event_type, severity.as_str(), data.clone())
+        .await
+        .map_err(|e| {
+            reject(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "ledger_write_failed",
+                e.to_string(),
+            )
+        })?;
+    data["receipt_id"] = json!(receipt_id);
+    state
+        .ws
+        .send_json(&json!({ "type": event_type, "data": data }));
+    Ok(receipt_id)
+}
+
+/// Reject action ids with no action record.
+async fn check_actions(state: &AppState, action_ids: &[String]) -> Result<(), AuthRejection> {
+    for id in action_ids {
+        if state.actions.get(id).await.map_err(store_error)?.is_none() {
+            return Err(reject(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "unknown_action",
+                format!("no action {id:?}"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Make sure an incident record exists for a chain started by
+/// `/api/offsec/events`, taking its severity from the first event.
+pub async fn ensure_incident(
+    state: &AppState,
+    incident_id: &str,
+    severity: Option<&str>,
+    receipt_id: &str,
+    actor: &str,
+) -> anyhow::Result<()> {
+    let incident = NewIncident {
+        incident_id: incident_id.to_string(),
+        title: None,
+        severity: severity
+            .and_then(Severity::parse)
+            .unwrap_or(Severity::Medium),
+    };
+    state
+        .incidents
+        .create(&incident, actor, Some(receipt_id))
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IncidentQuery {
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub severity: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// Incidents, most recently updated first.
+pub async fn list_incidents(
+    State(state): State<AppState>,
+    Query(params): Query<IncidentQuery>,
+) -> Result<Json<Vec<IncidentRecord>>, AuthRejection> {
+    let bad_query =
+        |details: String| reject(StatusCode::BAD_REQUEST, "invalid incident query", details);
+    let filter = IncidentFilter {
+        status: params
+            .status
+            .as_deref()
+            .map(|s| {
+                IncidentStatus::parse(s).ok_or_else(|| bad_query(format!("unknown status {s:?}")))
+            })
+            .transpose()?,
+        severity: params
+            .severity
+            .as_deref()
+            .map(|s| Severity::parse(s).ok_or_else(|| bad_query(format!("unknown severity {s:?}"))))
+            .transpose()?,
+        owner: params.owner,
+    };
+    let records = state
+        .incidents
+        .list(&filter, params.limit.unwrap_or(DEFAULT_LIMIT))
+        .await
+        .map_err(store_error)?;
+    Ok(Json(
+        records
+            .into_iter()
+            .map(|record| with_chain(&state, record))
+            .collect(),
+    ))
+}
+
+pub async fn get_incident(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<IncidentRecord>, AuthRejection> {
+    load(&state, &id).await.map(Json)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenIncident {
+    #[serde(default)]
+    pub incident_id: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    pub severity: Severity,
+    #[serde(default)]
+    pub threat_event_ids: Vec<String>,
+    #[serde(default)]
+    pub action_ids: Vec<String>,
+}
+
+/// `POST /offsec/incidents`: open an incident and start its ledger chain.
+pub async fn open_incident(
+    State(state): State<AppState>,
+    Authorized(principal, _): Authorized<IncidentWrite>,
+    Json(body): Json<OpenIncident>,
+) -> Result<Json<IncidentRecord>, AuthRejection> {
+    let incident_id = body
+        .incident_id
+        .unwrap_or_else(|| format!("inc-{}", uuid::Uuid::new_v4()));
+    let duplicate = || {
+        reject(
+            StatusCode::CONFLICT,
+            "duplicate_incident",
+            format!("incident {incident_id:?} already exists"),
+        )
+    };
+    let _guard = state.incidents.lock(&incident_id).await;
+    if state
+        .incidents
+        .get(&incident_id)
+        .await
+        .map_err(store_error)?
+        .is_some()
+        || state.ledger.get_incident(&incident_id).is_some()
+    {
+        return Err(duplicate());
+    }
+    check_actions(&state, &body.action_ids).await?;
+
+    let incident = NewIncident {
+        incident_id: incident_id.clone(),
+        title: body.title,
+        severity: body.severity,
+    };
+    let receipt = promote(
+        &state,
+        &incident_id,
+        incident.severity,
+        "offsec.incident.opened",
+        json!({
+            "title": incident.title,
+            "status": IncidentStatus::Open,
+            "actor": principal.sub,
+            "threat_event_ids": body.threat_event_ids,
+            "action_ids": body.action_ids,
+        }),
+    )
+    .await?;
+    if !state
+        .incidents
+        .create(&incident, &principal.sub, Some(&receipt))
+        .await
+        .map_err(store_error)?
+    {
+        return Err(duplicate());
+    }
+    state
+        .incidents
+        .link(&incident_id, &body.threat_event_ids, &body.action_ids)
+        .await
+        .map_err(store_error)?;
+    load(&state, &incident_id).await.map(Json)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssignIncident {
+    pub owner: String,
+}
+
+/// `POST /offsec/incidents/:id/assign`.
+pub async fn assign(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Authorized(principal, _): Authorized<IncidentWrite>,
+    Json(body): Json<AssignIncident>,
+) -> Result<Json<IncidentRecord>, AuthRejection> {
+    let _guard = state.incidents.lock(&id).await;
+    let record = load(&state, &id).await?;
+    if body.owner.trim().is_empty() {
+        return Err(reject(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_owner",
+            "owner must not be empty".to_string(),
+        ));
+    }
+    promote(
+        &state,
+        &id,
+        record.severity,
+        "offsec.incident.assigned",
+        json!({
+            "owner": body.owner,
+            "previous_owner": record.owner,
+            "status": record.status,
+            "actor": principal.sub,
+        }),
+    )
+    .await?;
+    if !state
+        .incidents
+        .assign(&id, &body.owner)
+        .await
+        .map_err(store_error)?
+    {
+        return Err(incident_response(IncidentError::Unknown(id)));
+    }
+    load(&state, &id).await.map(Json)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransitionIncident {
+    pub status: IncidentStatus,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// `POST /offsec/incidents/:id/transition`.
+pub async fn transition(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Authorized(principal, _): Authorized<IncidentWrite>,
+    Json(body): Json<TransitionIncident>,
+) -> Result<Json<IncidentRecord>, AuthRejection> {
+    let _guard = state.incidents.lock(&id).await;
+    let record = load(&state, &id).await?;
+    if !record.status.can_transition(body.status) {
+        return Err(incident_response(IncidentError::Invalid {
+            incident_id: id,
+            from: record.status,
+            to: body.status,
+        }));
+    }
+    let receipt = promote(
+        &state,
+        &id,
+        record.severity,
+        "offsec.incident.transition",
+        json!({
+            "from": record.status,
+            "to": body.status,
+            "note": body.note,
+            "owner": record.owner,
+            "actor": principal.sub,
+        }),
+    )
+    .await?;
+    state
+        .incidents
+        .transition(
+            &id,
+            record.status,
+            body.status,
+            &principal.sub,
+            Some(&receipt),
+            body.note.as_deref(),
+        )
+        .await
+        .map_err(incident_response)?;
+    load(&state, &id).await.map(Json)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinkIncident {
+    #[serde(default)]
+    pub threat_event_ids: Vec<String>,
+    #[serde(default)]
+    pub action_ids: Vec<String>,
+}
+
+/// `POST /offsec/incidents/:id/links`: link ThreatEvents and actions.
+pub async fn link(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Authorized(principal, _): Authorized<IncidentWrite>,
+    Json(body): Json<LinkIncident>,
+) -> Result<Json<IncidentRecord>, AuthRejection> {
+    let _guard = state.incidents.lock(&id).await;
+    let record = load(&state, &id).await?;
+    check_actions(&state, &body.action_ids).await?;
+    promote(
+        &state,
+        &id,
+        record.severity,
+        "offsec.incident.linked",
+        json!({
+            "threat_event_ids": body.threat_event_ids,
+            "action_ids": body.action_ids,
+            "status": record.status,
+            "actor": principal.sub,
+        }),
+    )
+    .await?;
+    state
+        .incidents
+        .link(&id, &body.threat_event_ids, &body.action_ids)
+        .await
+        .map_err(store_error)?;
+    load(&state, &id).await.map(Json)
+}
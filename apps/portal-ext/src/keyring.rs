@@ -0,0 +1,254 @@
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use anyhow::{anyhow, bail, Context};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey};
+use serde::Deserialize;
+
+use crate::config::OffsecConfig;
+
+/// `kid` of the key from `OFFSEC_JWT_PUBLIC_KEY` / `portal.jwt_public_key_file`.
+pub const CONFIG_EDDSA_KID: &str = "config-eddsa";
+/// `kid` of the key from `OFFSEC_JWT_HS256_SECRET` / `portal.jwt_hs256_secret`.
+pub const CONFIG_HS256_KID: &str = "config-hs256";
+
+/// Where a key is in its validity window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStatus {
+    /// Before `not_before`: tokens are not accepted yet.
+    Pending,
+    Active,
+    /// After `not_after`: tokens are rejected as signed by a retired key.
+    Retired,
+}
+
+/// One capability verification key.
+#[derive(Clone)]
+pub struct JwtKey {
+    pub kid: String,
+    pub alg: Algorithm,
+    pub not_before: Option<DateTime<Utc>>,
+    pub not_after: Option<DateTime<Utc>>,
+    key: DecodingKey,
+    /// BLAKE3 of the key material, so keys can be compared and named in
+    /// receipts without revealing HS256 secrets.
+    fingerprint: String,
+}
+
+impl JwtKey {
+    fn new(kid: &str, alg: Algorithm, key: DecodingKey, material: &[u8]) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(format!("{alg:?}:").as_bytes());
+        hasher.update(material);
+        Self {
+            kid: kid.to_string(),
+            alg,
+            not_before: None,
+            not_after: None,
+            key,
+            fingerprint: hasher.finalize().to_hex()[..16].to_string(),
+        }
+    }
+
+    pub fn decoding_key(&self) -> &DecodingKey {
+        &self.key
+    }
+
+    pub fn status(&self, now: DateTime<Utc>) -> KeyStatus {
+        if self.not_before.is_some_and(|t| now < t) {
+            KeyStatus::Pending
+        } else if self.not_after.is_some_and(|t| now >= t) {
+            KeyStatus::Retired
+        } else {
+            KeyStatus::Active
+        }
+    }
+
+    /// Algorithm, fingerprint and window, e.g. for reload receipts.
+    pub fn describe(&self) -> String {
+        let bound = |t: Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339()).unwrap_or_default();
+        format!(
+            "{:?} {} [{}, {})",
+            self.alg,
+            self.fingerprint,
+            bound(self.not_before),
+            bound(self.not_after)
+        )
+    }
+}
+
+impl PartialEq for JwtKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.kid == other.kid
+            && self.alg == other.alg
+            && self.fingerprint == other.fingerprint
+            && self.not_before == other.not_before
+            && self.not_after == other.not_after
+    }
+}
+
+impl fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JwtKey({} {})", self.kid, self.describe())
+    }
+}
+
+/// Capability JWT keys by `kid`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Keyring {
+    keys: BTreeMap<String, JwtKey>,
+}
+
+/// JWKS-style keyring file: `{"keys": [...]}` with `OKP`/`Ed25519` and
+/// `oct` entries plus optional RFC 3339 `not_before` / `not_after`.
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kid: String,
+    kty: String,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    alg: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    k: Option<String>,
+    #[serde(default, alias = "nbf")]
+    not_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    not_after: Option<DateTime<Utc>>,
+}
+
+impl Jwk {
+    fn into_key(self) -> anyhow::Result<JwtKey> {
+        let mut key = match (self.kty.as_str(), self.crv.as_deref()) {
+            ("OKP", Some("Ed25519")) => {
+                let x = self.x.as_deref().ok_or_else(|| anyhow!("missing \"x\""))?;
+                let bytes = URL_SAFE_NO_PAD
+                    .decode(x)
+                    .map_err(|e| anyhow!("\"x\" is not base64url: {e}"))?;
+                if bytes.len() != 32 {
+                    bail!(
+                        "\"x\" must be a 32-byte Ed25519 key, got {} bytes",
+                        bytes.len()
+                    );
+                }
+                let key = DecodingKey::from_ed_components(x)?;
+                JwtKey::new(&self.kid, Algorithm::EdDSA, key, &bytes)
+            }
+            ("OKP", crv) => bail!("unsupported OKP curve {crv:?} (only Ed25519)"),
+            ("oct", _) => {
+                let k = self.k.as_deref().ok_or_else(|| anyhow!("missing \"k\""))?;
+                let secret = URL_SAFE_NO_PAD
+                    .decode(k)
+                    .map_err(|e| anyhow!("\"k\" is not base64url: {e}"))?;
+                if secret.is_empty() {
+                    bail!("\"k\" is empty");
+                }
+                JwtKey::new(
+                    &self.kid,
+                    Algorithm::HS256,
+                    DecodingKey::from_secret(&secret),
+                    &secret,
+                )
+            }
+            (kty, _) => bail!("unsupported kty {kty:?} (expected OKP or oct)"),
+        };
+        if let Some(alg) = &self.alg {
+            if *alg != format!("{:?}", key.alg) {
+                bail!("alg {alg:?} does not match kty {:?}", self.kty);
+            }
+        }
+        if let (Some(not_before), Some(not_after)) = (self.not_before, self.not_after) {
+            if not_before >= not_after {
+                bail!("not_before must be earlier than not_after");
+            }
+        }
+        key.not_before = self.not_before;
+        key.not_after = self.not_after;
+        Ok(key)
+    }
+}
+
+impl Keyring {
+    /// Keys from the config (`CONFIG_EDDSA_KID`, `CONFIG_HS256_KID`) plus
+    /// everything in `jwks_file`. The HS256 secret is only used on its own,
+    /// or alongside the others when `jwt_allow_hs256` is set.
+    pub fn from_config(config: &OffsecConfig) -> anyhow::Result<Self> {
+        let mut keyring = Self::default();
+        if let Some(pem) = &config.jwt_public_key_pem {
+            let key = DecodingKey::from_ed_pem(pem.as_bytes())
+                .context("JWT public key is not an Ed25519 PEM key")?;
+            keyring.insert(JwtKey::new(
+                CONFIG_EDDSA_KID,
+                Algorithm::EdDSA,
+                key,
+                pem.trim().as_bytes(),
+            ))?;
+        }
+        let other_keys = config.jwt_public_key_pem.is_some() || config.jwks_file.is_some();
+        match &config.jwt_hs256_secret {
+            Some(_) if other_keys && !config.jwt_allow_hs256 => {
+                tracing::warn!(
+                    "ignoring the HS256 secret because a JWT public key or JWKS file is \
+                     configured; set OFFSEC_JWT_ALLOW_HS256=true to accept both"
+                );
+            }
+            Some(secret) => keyring.insert(JwtKey::new(
+                CONFIG_HS256_KID,
+                Algorithm::HS256,
+                DecodingKey::from_secret(secret.as_bytes()),
+                secret.as_bytes(),
+            ))?,
+            None => {}
+        }
+        if let Some(path) = &config.jwks_file {
+            for key in load_jwks(Path::new(path))? {
+                keyring.insert(key)?;
+            }
+        }
+        Ok(keyring)
+    }
+
+    pub fn insert(&mut self, key: JwtKey) -> anyhow::Result<()> {
+        if self.keys.contains_key(&key.kid) {
+            bail!("duplicate JWT key id {:?}", key.kid);
+        }
+        self.keys.insert(key.kid.clone(), key);
+        Ok(())
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&JwtKey> {
+        self.keys.get(kid)
+    }
+
+    pub fn keys(&self) -> &BTreeMap<String, JwtKey> {
+        &self.keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+pub fn load_jwks(path: &Path) -> anyhow::Result<Vec<JwtKey>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("reading JWKS {}", path.display()))?;
+    let set: JwkSet = serde_json::from_str(&content)
+        .with_context(|| format!("parsing JWKS {}", path.display()))?;
+    set.keys
+        .into_iter()
+        .map(|jwk| {
+            let kid = jwk.kid.clone();
+            jwk.into_key()
+                .with_context(|| format!("JWKS {} key {kid:?}", path.display()))
+        })
+        .collect()
+}
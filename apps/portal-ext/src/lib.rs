@@ -1,15 +1,30 @@
+pub mod actions;
+pub mod approvals;
+pub mod auth;
 pub mod capabilities;
 pub mod config;
+pub mod correlation;
+pub mod durability;
+pub mod incidents;
+pub mod keyring;
 pub mod merkle;
 pub mod mesh;
 pub mod models;
 pub mod offsec_ledger;
+pub mod outbox;
 pub mod receipts;
+pub mod replay;
 pub mod routes;
+pub mod scope;
+pub mod store;
+pub mod trust;
+pub mod wal;
 pub mod ws;
 
+use anyhow::Context;
 use axum::Router;
-use std::sync::{Arc, Mutex};
+use ed25519_dalek::SigningKey;
+use std::sync::{atomic::AtomicUsize, Arc, Mutex};
 use ws::WsBroadcaster;
 
 #[derive(Clone)]
@@ -17,14 +32,123 @@ pub struct AppState {
     pub ws: WsBroadcaster,
     pub config: config::OffsecConfig,
     pub frontier: Arc<Mutex<merkle::MerkleFrontier>>,
+    /// Leaves durably committed (see `receipts::committed_size`).
+    pub committed: Arc<AtomicUsize>,
+    /// Node key for receipt and tree head signatures (unsigned when `None`).
+    pub signer: Option<Arc<SigningKey>>,
+    pub store: store::ReceiptStore,
+    /// Held for the whole of a receipt commit (see `receipts::write_receipt`).
+    pub commit_lock: Arc<tokio::sync::Mutex<()>>,
+    pub backlog: durability::ReceiptBacklog,
+    /// Mesh peers and trusted issuers, swapped on reload.
+    pub trust: trust::TrustStore,
+    /// Single-use capability tokens already spent.
+    pub replay: replay::ReplayCache,
+    /// Action records and their lifecycle history.
+    pub actions: actions::ActionStore,
+    /// Approved actions waiting for (or given) delivery to Guardian.
+    pub outbox: outbox::DispatchOutbox,
+    /// Civilization-ledger incident chains and their issuer.
+    pub ledger: Arc<offsec_ledger::IncidentLedger>,
+    /// Incident status, ownership and links.
+    pub incidents: incidents::IncidentStore,
+    /// Recent correlation keys of ThreatEvents and their incidents.
+    pub correlation: correlation::CorrelationIndex,
 }
 
-pub fn build_state(config: config::OffsecConfig) -> AppState {
-    AppState {
+pub async fn build_state(config: config::OffsecConfig) -> anyhow::Result<AppState> {
+    let rolled_back = receipts::roll_back_pending(&config.data_dir)
+        .context("rolling back interrupted receipt commit")?;
+    let (frontier, migration) = receipts::load_frontier(&config.data_dir)?;
+    let store = store::ReceiptStore::open(&config.data_dir).await?;
+    if let Some(size) = rolled_back {
+        store.delete_from_leaf(size).await?;
+        wal::clear(&config.data_dir)?;
+        tracing::warn!("rolled back interrupted receipt commit at leaf {}", size);
+    }
+    let imported = store
+        .import_json_receipts(&config.data_dir, &frontier)
+        .await
+        .context("importing JSON receipts")?;
+    if imported.imported > 0 {
+        tracing::info!(
+            "imported {} JSON receipts into {}",
+            imported.imported,
+            store::RECEIPTS_DB
+        );
+    }
+    if imported.collisions > 0 {
+        tracing::warn!(
+            "{} JSON receipts were not imported: their leaf index was taken",
+            imported.collisions
+        );
+    }
+    let signer = match config.signing_key_path() {
+        Some(path) => Some(Arc::new(
+            mesh::util::load_signing_key(path).context("loading node signing key")?,
+        )),
+        None => {
+            tracing::warn!("no signing key configured; receipts and roots will be unsigned");
+            None
+        }
+    };
+    let backlog = durability::ReceiptBacklog::load(&config.data_dir)?;
+    let replay = replay::ReplayCache::open(store.pool().clone())
+        .await
+        .context("opening replay cache")?;
+    let actions = actions::ActionStore::open(store.pool().clone())
+        .await
+        .context("opening action store")?;
+    let outbox = outbox::DispatchOutbox::open(store.pool().clone(), &config)
+        .await
+        .context("opening dispatch outbox")?;
+    let incidents = incidents::IncidentStore::open(store.pool().clone())
+        .await
+        .context("opening incident store")?;
+    let correlation = correlation::CorrelationIndex::open(store.pool().clone())
+        .await
+        .context("opening correlation index")?;
+    let ledger = offsec_ledger::IncidentLedger::open(&config).context("opening incident ledger")?;
+    let backfilled = incidents
+        .backfill(&ledger)
+        .await
+        .context("backfilling incident records")?;
+    if backfilled > 0 {
+        tracing::info!("created {} incident records from ledger chains", backfilled);
+    }
+    let trust = trust::TrustStore::new(
+        trust::TrustSnapshot::load(&config).context("loading trust stores")?,
+    );
+
+    let state = AppState {
         ws: WsBroadcaster::new(),
         config,
-        frontier: Arc::new(Mutex::new(merkle::MerkleFrontier::new())),
+        committed: Arc::new(AtomicUsize::new(frontier.len())),
+        frontier: Arc::new(Mutex::new(frontier)),
+        signer,
+        store,
+        commit_lock: Arc::new(tokio::sync::Mutex::new(())),
+        backlog,
+        trust,
+        replay,
+        actions,
+        outbox,
+        ledger: Arc::new(ledger),
+        incidents,
+        correlation,
+    };
+    if let Some(migration) = migration {
+        let payload = serde_json::json!({
+            "leaves": migration.leaves,
+            "root": migration.root,
+            "previous_root": migration.previous_root,
+            "tree_version": merkle::TreeVersion::V1.as_u8(),
+        });
+        receipts::write_receipt(&state, "offsec.merkle.migrated", None, &[], &payload)
+            .await
+            .map_err(|e| anyhow::anyhow!("receipting merkle log migration: {e}"))?;
     }
+    Ok(state)
 }
 
 pub fn app_router(state: AppState) -> Router {
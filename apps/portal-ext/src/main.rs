@@ -5,15 +5,26 @@ use tower_http::trace::TraceLayer;
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let config = OffsecConfig::from_env();
+    let config = match OffsecConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Failed to load configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
-    if let Err(e) = portal_ext::offsec_ledger::rebuild_index() {
-        tracing::warn!("failed to rebuild incident index: {}", e);
-    } else {
-        tracing::info!("incident index rebuilt from receipts");
-    }
+    let state = match build_state(config.clone()).await {
+        Ok(state) => state,
+        Err(e) => {
+            tracing::error!("Failed to initialise state: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let state = build_state(config.clone());
+    portal_ext::durability::spawn_retry_worker(state.clone());
+    portal_ext::trust::spawn_reload_triggers(state.clone());
+    portal_ext::approvals::spawn_expiry_worker(state.clone());
+    portal_ext::outbox::spawn_dispatch_worker(state.clone());
 
     let app = app_router(state).layer(TraceLayer::new_for_http());
 
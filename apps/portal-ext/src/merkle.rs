@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use blake3;
 use serde::{Deserialize, Serialize};
 
@@ -7,88 +9,278 @@ pub struct MerklePathElement {
     pub position: String, // "left" | "right"
 }
 
-/// Simple in-memory frontier for building roots and paths.
-/// For larger logs this could be swapped for a streaming/stack-based frontier.
+/// Domain-separation prefixes for v2 trees (RFC 6962 §2.1).
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Merkle tree hashing format.
+///
+/// - `V1`: parents hash the two children's hex strings concatenated as text;
+///   the last node on an odd level is paired with itself. Kept so logs
+///   written before v2 keep their roots.
+/// - `V2`: leaves are `H(0x00 || leaf)` and parents `H(0x01 || left || right)`
+///   over raw 32-byte digests; an odd node is promoted unchanged, giving the
+///   RFC 6962 tree shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TreeVersion {
+    V1,
+    #[default]
+    V2,
+}
+
+impl TreeVersion {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            TreeVersion::V1 => 1,
+            TreeVersion::V2 => 2,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(TreeVersion::V1),
+            2 => Some(TreeVersion::V2),
+            _ => None,
+        }
+    }
+
+    /// Hash stored for a leaf at level 0 of the tree.
+    pub fn leaf_node(self, leaf_hex: &str) -> String {
+        match self {
+            TreeVersion::V1 => leaf_hex.to_string(),
+            TreeVersion::V2 => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&[LEAF_PREFIX]);
+                hasher.update(&digest_bytes(leaf_hex));
+                hasher.finalize().to_hex().to_string()
+            }
+        }
+    }
+
+    pub fn hash_pair(self, left: &str, right: &str) -> String {
+        match self {
+            TreeVersion::V1 => {
+                let combined = format!("{}{}", left, right);
+                blake3::hash(combined.as_bytes()).to_hex().to_string()
+            }
+            TreeVersion::V2 => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&[NODE_PREFIX]);
+                hasher.update(&digest_bytes(left));
+                hasher.update(&digest_bytes(right));
+                hasher.finalize().to_hex().to_string()
+            }
+        }
+    }
+}
+
+/// Serde default for receipts and bundles written before `tree_version`.
+pub fn legacy_tree_version() -> u8 {
+    TreeVersion::V1.as_u8()
+}
+
+/// Raw bytes of a hex digest. Every node in the log is BLAKE3 hex; anything
+/// else is hashed as text so malformed input still yields a non-matching root.
+fn digest_bytes(hex_digest: &str) -> Vec<u8> {
+    hex::decode(hex_digest).unwrap_or_else(|_| hex_digest.as_bytes().to_vec())
+}
+
+/// Recompute the root from a leaf and its inclusion path.
+pub fn root_from_path(version: TreeVersion, leaf_hex: &str, path: &[MerklePathElement]) -> String {
+    path.iter().fold(version.leaf_node(leaf_hex), |h, step| {
+        match step.position.as_str() {
+            "left" => version.hash_pair(&step.sibling, &h),
+            _ => version.hash_pair(&h, &step.sibling),
+        }
+    })
+}
+
+/// Append-only Merkle tree with cached subtree hashes.
+///
+/// `levels[0]` holds the leaf nodes and `levels[k]` holds every *complete*
+/// subtree of 2^k leaves, so an append hashes at most one node per level.
+/// Nodes on the ragged right edge are derived on demand according to the
+/// tree's [`TreeVersion`]. Every root the log has had is indexed by value
+/// so anchors and proofs can find its tree size without a scan.
 #[derive(Debug, Clone, Default)]
 pub struct MerkleFrontier {
-    pub leaves: Vec<String>,
+    version: TreeVersion,
+    leaves: Vec<String>,
+    levels: Vec<Vec<String>>,
+    roots: HashMap<String, usize>,
 }
 
 impl MerkleFrontier {
     pub fn new() -> Self {
-        Self { leaves: Vec::new() }
+        Self::with_version(TreeVersion::default())
     }
 
-    pub fn append_with_path(&mut self, leaf_hex: String) -> (String, Vec<MerklePathElement>) {
-        self.leaves.push(leaf_hex.clone());
-        self.build_path_for_index(self.leaves.len() - 1)
+    pub fn with_version(version: TreeVersion) -> Self {
+        Self {
+            version,
+            leaves: Vec::new(),
+            levels: Vec::new(),
+            roots: HashMap::new(),
+        }
     }
 
-    pub fn current_root(&self) -> String {
-        if self.leaves.is_empty() {
-            return "0".repeat(64);
+    /// Rebuild a frontier from leaves given in append order.
+    pub fn from_leaves(version: TreeVersion, leaves: Vec<String>) -> Self {
+        let mut frontier = Self::with_version(version);
+        for leaf in leaves {
+            frontier.append(leaf);
         }
-        let (_, root) = self.build_tree();
-        root
+        frontier
+    }
+
+    pub fn version(&self) -> TreeVersion {
+        self.version
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn leaf(&self, index: usize) -> Option<&str> {
+        self.leaves.get(index).map(|s| s.as_str())
     }
 
-    fn build_tree(&self) -> (Vec<Vec<String>>, String) {
-        let mut levels = Vec::new();
-        if self.leaves.is_empty() {
-            return (levels, "0".repeat(64));
+    /// Drop every leaf from `size` on, as if only the first `size` had been
+    /// appended. No-op if the tree is not larger than `size`.
+    pub fn truncate(&mut self, size: usize) {
+        if size >= self.len() {
+            return;
+        }
+        self.leaves.truncate(size);
+        for (k, nodes) in self.levels.iter_mut().enumerate() {
+            nodes.truncate(size >> k);
         }
+        while self.levels.last().is_some_and(|l| l.is_empty()) {
+            self.levels.pop();
+        }
+        // A dropped size may have shadowed an older size with the same root.
+        self.roots = (1..=size)
+            .filter_map(|n| Some((self.root_at(n)?, n)))
+            .collect();
+    }
 
-        let mut current = self.leaves.clone();
-        levels.push(current.clone());
+    /// Append a leaf and return its index.
+    pub fn append(&mut self, leaf_hex: String) -> usize {
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+        self.levels[0].push(self.version.leaf_node(&leaf_hex));
+        self.leaves.push(leaf_hex);
 
-        while current.len() > 1 {
-            let mut next = Vec::new();
-            for chunk in current.chunks(2) {
-                let combined = if chunk.len() == 2 {
-                    format!("{}{}", chunk[0], chunk[1])
-                } else {
-                    // duplicate the last node when odd length
-                    format!("{}{}", chunk[0], chunk[0])
-                };
-                let h = blake3::hash(combined.as_bytes()).to_hex().to_string();
-                next.push(h);
+        let mut level = 0;
+        while self.levels[level].len().is_multiple_of(2) {
+            let nodes = &self.levels[level];
+            let parent = self
+                .version
+                .hash_pair(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+            if self.levels.len() == level + 1 {
+                self.levels.push(Vec::new());
             }
-            current = next.clone();
-            levels.push(current.clone());
+            self.levels[level + 1].push(parent);
+            level += 1;
         }
+        self.roots.insert(self.current_root(), self.len());
 
-        let root = current[0].clone();
-        (levels, root)
+        self.len() - 1
     }
 
-    fn build_path_for_index(&self, index: usize) -> (String, Vec<MerklePathElement>) {
-        let (levels, root) = self.build_tree();
-        if self.leaves.is_empty() || index >= self.leaves.len() {
-            return (root, Vec::new());
+    pub fn append_with_path(&mut self, leaf_hex: String) -> (String, Vec<MerklePathElement>) {
+        let index = self.append(leaf_hex);
+        let path = self.inclusion_path(index).unwrap_or_default();
+        (self.current_root(), path)
+    }
+
+    pub fn current_root(&self) -> String {
+        self.root_at(self.len()).unwrap_or_else(|| "0".repeat(64))
+    }
+
+    /// Root of the tree formed by the first `size` leaves.
+    pub fn root_at(&self, size: usize) -> Option<String> {
+        if size == 0 || size > self.len() {
+            return None;
+        }
+        let partials = self.right_edge(size);
+        let top = partials.len() - 1;
+        Some(
+            partials[top]
+                .clone()
+                .unwrap_or_else(|| self.levels[top][0].clone()),
+        )
+    }
+
+    /// Tree size whose root equals `root`, the newest if several share it.
+    pub fn size_of_root(&self, root: &str) -> Option<usize> {
+        self.roots.get(root).copied()
+    }
+
+    /// Like [`size_of_root`](Self::size_of_root), but only among the first
+    /// `size` leaves, so a root produced by a leaf still being committed is
+    /// not matched.
+    pub fn size_of_root_within(&self, root: &str, size: usize) -> Option<usize> {
+        match self.size_of_root(root)? {
+            newest if newest <= size => Some(newest),
+            // Only a repeated v1 root can also occur below `size`.
+            _ => (1..=size.min(self.len()))
+                .rev()
+                .find(|&n| self.root_at(n).as_deref() == Some(root)),
         }
+    }
+
+    /// Index of the first leaf equal to `leaf_hex`.
+    pub fn index_of(&self, leaf_hex: &str) -> Option<usize> {
+        self.leaves.iter().position(|l| l == leaf_hex)
+    }
 
+    /// Index of `leaf_hex` as appended when the root was `root`; falls back
+    /// to the leaf's position when it occurs exactly once.
+    pub fn locate(&self, leaf_hex: &str, root: &str) -> Option<usize> {
+        let positions: Vec<usize> = (0..self.len())
+            .filter(|&i| self.leaves[i] == leaf_hex)
+            .collect();
+        positions
+            .iter()
+            .copied()
+            .find(|&i| self.root_at(i + 1).as_deref() == Some(root))
+            .or(match positions[..] {
+                [only] => Some(only),
+                _ => None,
+            })
+    }
+
+    /// Inclusion path for the leaf at `index` against the current root.
+    pub fn inclusion_path(&self, index: usize) -> Option<Vec<MerklePathElement>> {
+        self.inclusion_path_at(index, self.len())
+    }
+
+    /// Inclusion path for the leaf at `index` against the root of the first
+    /// `size` leaves.
+    pub fn inclusion_path_at(&self, index: usize, size: usize) -> Option<Vec<MerklePathElement>> {
+        if index >= size || size > self.len() {
+            return None;
+        }
+
+        let partials = self.right_edge(size);
         let mut path = Vec::new();
         let mut idx = index;
 
-        for level_nodes in levels.iter() {
-            if level_nodes.len() == 1 {
+        for (level, partial) in partials.iter().enumerate() {
+            let count = level_count(size, level);
+            if count == 1 {
                 break;
             }
 
             let is_right = idx % 2 == 1;
-            let sibling_idx = if is_right {
-                idx.saturating_sub(1)
-            } else {
-                idx + 1
-            };
-
-            if sibling_idx < level_nodes.len() {
-                let sibling = level_nodes[sibling_idx].clone();
-                let position = if is_right { "left" } else { "right" }.to_string();
-                path.push(MerklePathElement { sibling, position });
-            } else {
-                // odd end node; sibling is the node itself
-                let sibling = level_nodes[idx].clone();
+            if let Some(sibling_idx) = self.sibling_index(idx, count) {
+                let sibling = self.node(size, level, sibling_idx, partial.as_ref())?;
                 let position = if is_right { "left" } else { "right" }.to_string();
                 path.push(MerklePathElement { sibling, position });
             }
@@ -96,6 +288,168 @@ impl MerkleFrontier {
             idx /= 2;
         }
 
-        (root, path)
+        Some(path)
+    }
+
+    /// Consistency proof between the trees of `old_size` and `new_size` leaves.
+    ///
+    /// The proof is the path from the smallest complete subtree on the right
+    /// edge of the old tree up to the new root, prefixed with that subtree's
+    /// hash unless the old size is a power of two (RFC 6962 §2.1.2 layout).
+    /// Check it with [`verify_consistency`].
+    pub fn consistency_proof(&self, old_size: usize, new_size: usize) -> Option<Vec<String>> {
+        if old_size == 0 || old_size > new_size || new_size > self.len() {
+            return None;
+        }
+        if old_size == new_size {
+            return Some(Vec::new());
+        }
+
+        let start_level = old_size.trailing_zeros() as usize;
+        let mut idx = (old_size >> start_level) - 1;
+        let mut proof = Vec::new();
+        if !old_size.is_power_of_two() {
+            proof.push(self.levels[start_level][idx].clone());
+        }
+
+        let partials = self.right_edge(new_size);
+        for (level, partial) in partials.iter().enumerate().skip(start_level) {
+            let count = level_count(new_size, level);
+            if count == 1 {
+                break;
+            }
+            if let Some(sibling_idx) = self.sibling_index(idx, count) {
+                proof.push(self.node(new_size, level, sibling_idx, partial.as_ref())?);
+            }
+            idx /= 2;
+        }
+
+        Some(proof)
+    }
+
+    /// Sibling of node `idx` on a level with `count` nodes. The odd end node
+    /// is its own sibling in v1 and has none (it is promoted) in v2.
+    fn sibling_index(&self, idx: usize, count: usize) -> Option<usize> {
+        let sibling_idx = if idx % 2 == 1 { idx - 1 } else { idx + 1 };
+        if sibling_idx < count {
+            Some(sibling_idx)
+        } else if self.version == TreeVersion::V1 {
+            Some(idx)
+        } else {
+            None
+        }
     }
+
+    /// Node `index` at `level` in the tree of the first `size` leaves.
+    fn node(
+        &self,
+        size: usize,
+        level: usize,
+        index: usize,
+        partial: Option<&String>,
+    ) -> Option<String> {
+        if index < size >> level {
+            self.levels.get(level)?.get(index).cloned()
+        } else {
+            partial.cloned()
+        }
+    }
+
+    /// Hash of the incomplete right-most node at each level (if any) of the
+    /// tree formed by the first `size` leaves, from the leaves up to the root.
+    fn right_edge(&self, size: usize) -> Vec<Option<String>> {
+        let mut partials: Vec<Option<String>> = vec![None];
+        let mut level = 0;
+
+        while level_count(size, level) > 1 {
+            let count = level_count(size, level);
+            let full = &self.levels[level][..size >> level];
+            let partial = partials[level].as_ref();
+            let odd_end = if count % 2 == 1 {
+                partial.or(full.last())
+            } else {
+                None
+            };
+            let next = match (odd_end, partial) {
+                (Some(end), _) if self.version == TreeVersion::V1 => {
+                    Some(self.version.hash_pair(end, end))
+                }
+                (Some(end), _) => Some(end.clone()),
+                (None, Some(p)) => full.last().map(|l| self.version.hash_pair(l, p)),
+                (None, None) => None,
+            };
+            partials.push(next);
+            level += 1;
+        }
+
+        partials
+    }
+}
+
+/// Number of nodes at `level` in a tree of `size` leaves, including the
+/// ragged right edge.
+fn level_count(size: usize, level: usize) -> usize {
+    size.div_ceil(1 << level)
+}
+
+/// Check a proof from [`MerkleFrontier::consistency_proof`]: the first
+/// `old_size` leaves of the tree with `new_root` hash to `old_root`.
+pub fn verify_consistency(
+    version: TreeVersion,
+    old_size: usize,
+    new_size: usize,
+    old_root: &str,
+    new_root: &str,
+    proof: &[String],
+) -> bool {
+    if old_size == 0 || old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && old_root == new_root;
+    }
+
+    let start_level = old_size.trailing_zeros() as usize;
+    let mut idx = (old_size >> start_level) - 1;
+    let mut nodes = proof.iter();
+    let seed = if old_size.is_power_of_two() {
+        old_root.to_string()
+    } else {
+        match nodes.next() {
+            Some(h) => h.clone(),
+            None => return false,
+        }
+    };
+
+    let mut old_hash = seed.clone();
+    let mut new_hash = seed;
+    let mut level = start_level;
+    while level_count(new_size, level) > 1 {
+        if idx % 2 == 1 {
+            // left siblings lie inside the old tree
+            let Some(sibling) = nodes.next() else {
+                return false;
+            };
+            new_hash = version.hash_pair(sibling, &new_hash);
+            old_hash = version.hash_pair(sibling, &old_hash);
+        } else if version == TreeVersion::V1 {
+            let Some(sibling) = nodes.next() else {
+                return false;
+            };
+            new_hash = version.hash_pair(&new_hash, sibling);
+            if level_count(old_size, level) > 1 {
+                old_hash = version.hash_pair(&old_hash, &old_hash);
+            }
+        } else if idx + 1 < level_count(new_size, level) {
+            let Some(sibling) = nodes.next() else {
+                return false;
+            };
+            new_hash = version.hash_pair(&new_hash, sibling);
+        }
+        // otherwise (v2) the node has no sibling and is promoted unchanged
+        idx /= 2;
+        level += 1;
+    }
+
+    nodes.next().is_none() && old_hash == old_root && new_hash == new_root
 }
@@ -4,18 +4,9 @@ use anyhow::{anyhow, Result};
 use base64::engine::general_purpose::STANDARD as BASE64;
 use base64::Engine;
 use blake3;
-use ed25519_dalek::{Signature, VerifyingKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 use serde_json::Value;
 
-use crate::config::{MeshPeer, OffsecConfig};
-
-/// Find a peer by node_id from config.mesh
-pub fn find_peer<'a>(cfg: &'a OffsecConfig, node_id: &str) -> Option<&'a MeshPeer> {
-    cfg.mesh
-        .as_ref()
-        .and_then(|m| m.peers.iter().find(|p| p.id == node_id))
-}
-
 /// Canonicalize JSON by sorting object keys recursively.
 pub fn canonical_json(value: &Value) -> Result<Vec<u8>> {
     fn sort_value(v: &Value) -> Value {
@@ -43,15 +34,41 @@ pub fn compute_payload_hash(payload: &Value) -> Result<[u8; 32]> {
     Ok(*hash.as_bytes())
 }
 
-pub fn verify_signature(pubkey_b64: &str, sig_b64: &str, payload: &Value) -> Result<()> {
+/// Load a raw 32-byte Ed25519 secret key (as written by ops/generate-mesh-keys.sh).
+pub fn load_signing_key(path: &str) -> Result<SigningKey> {
+    let bytes = std::fs::read(path).map_err(|e| anyhow!("reading signing key {path}: {e}"))?;
+    let seed: [u8; 32] = bytes.try_into().map_err(|b: Vec<u8>| {
+        anyhow!(
+            "expected 32-byte Ed25519 key in {path}, got {} bytes",
+            b.len()
+        )
+    })?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+pub fn public_key_b64(key: &SigningKey) -> String {
+    BASE64.encode(key.verifying_key().to_bytes())
+}
+
+/// Base64 Ed25519 signature over BLAKE3(canonical_json(payload)).
+pub fn sign_payload(key: &SigningKey, payload: &Value) -> Result<String> {
+    let h = compute_payload_hash(payload)?;
+    Ok(BASE64.encode(key.sign(&h).to_bytes()))
+}
+
+/// Decode a base64 Ed25519 public key as used in peer configs.
+pub fn parse_pubkey(pubkey_b64: &str) -> Result<VerifyingKey> {
     let pk_vec = BASE64
         .decode(pubkey_b64)
         .map_err(|e| anyhow!("invalid mesh peer pubkey base64: {e}"))?;
     let pk_bytes: [u8; 32] = pk_vec
         .try_into()
         .map_err(|_| anyhow!("invalid mesh peer pubkey length"))?;
-    let vk = VerifyingKey::from_bytes(&pk_bytes)
-        .map_err(|e| anyhow!("invalid mesh peer pubkey: {e}"))?;
+    VerifyingKey::from_bytes(&pk_bytes).map_err(|e| anyhow!("invalid mesh peer pubkey: {e}"))
+}
+
+pub fn verify_signature(pubkey_b64: &str, sig_b64: &str, payload: &Value) -> Result<()> {
+    let vk = parse_pubkey(pubkey_b64)?;
 
     let sig_vec = BASE64
         .decode(sig_b64)
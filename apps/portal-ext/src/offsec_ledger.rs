@@ -1,80 +1,335 @@
-//! OffSec Shield ↔ Civilization Ledger adapter (reference).
-//!
-//! This is intended to be copied into the Portal-Ext (Rust) repo and
-//! wired into its Axum router. It assumes the engine workspace provides:
-//! - civilization-ledger-core
-//!
-//! ## Usage
-//!
-//! 1. Add to your Portal-Ext Cargo.toml:
-//!    ```toml
-//!    [dependencies]
-//!    civilization-ledger-core = { path = "../engine/crates/civilization-ledger-core" }
-//!    once_cell = "1.0"
-//!    ```
-//!
-//! 2. Wire into Axum router:
-//!    ```rust
-//!    use axum::{routing::{post, get}, Router, Json, extract::Path};
-//!    use civilization_ledger_core::infrastructure::InfrastructureEvent;
-//!
-//!    async fn post_offsec_event(Json(ev): Json<InfrastructureEvent>) -> Json<serde_json::Value> {
-//!        match offsec_ledger::handle_infra_event(ev) {
-//!            Ok((incident_id, receipt_id)) => Json(serde_json::json!({
-//!                "status": "ok",
-//!                "incident_id": incident_id,
-//!                "receipt_id": receipt_id
-//!            })),
-//!            Err(e) => Json(serde_json::json!({
-//!                "status": "error",
-//!                "error": e.to_string()
-//!            })),
-//!        }
-//!    }
-//!
-//!    async fn get_offsec_incident(Path(id): Path<String>) -> Json<serde_json::Value> {
-//!        if let Some(chain) = offsec_ledger::get_incident(&id) {
-//!            Json(serde_json::json!({
-//!                "incident_id": chain.incident_id,
-//!                "receipts": chain.receipts
-//!            }))
-//!        } else {
-//!            Json(serde_json::json!({ "error": "not_found" }))
-//!        }
-//!    }
-//!
-//!    pub fn offsec_routes() -> Router {
-//!        Router::new()
-//!            .route("/api/offsec/events", post(post_offsec_event))
-//!            .route("/api/offsec/incidents/:id", get(get_offsec_incident))
-//!    }
-//!    ```
-
+//! Bridge to the Civilization Ledger. `IncidentLedger` promotes
+//! InfrastructureEvents onto per-incident receipt chains in the ledger's
+//! file store, seals every receipt with the node key and builds the signed
+//! incident proofs served under `/api/offsec/incidents/:id/proof`. Ledger
+//! capabilities are checked here as well.
+
+use anyhow::Context;
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use civilization_ledger_core::{
     infrastructure::{promote_event, InfrastructureEvent},
     types::Receipt,
     FileStore, NodeKeys,
 };
-use ed25519_dalek::{Signature, VerifyingKey};
-use once_cell::sync::Lazy;
-use std::{collections::HashMap, convert::TryInto, sync::Mutex};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use rand::RngCore;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    convert::TryInto,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 use walkdir::WalkDir;
 
-/// Where to keep receipts/proofs on disk.
-const DEFAULT_DATA_DIR: &str = "./data-offsec";
+use crate::{
+    config::OffsecConfig,
+    mesh::util::{compute_payload_hash, load_signing_key, sign_payload, verify_signature},
+    models::ErrorResponse,
+    AppState,
+};
+
+/// Format of [`IncidentProof`].
+pub const INCIDENT_PROOF_VERSION: u8 = 1;
+
+/// The DID and key that infrastructure receipts and proofs are issued
+/// under. The key is kept on disk so the issuer survives restarts.
+pub struct NodeIdentity {
+    issuer_id: String,
+    signing_key: SigningKey,
+    keys: NodeKeys,
+}
+
+impl NodeIdentity {
+    pub fn new(issuer_id: impl Into<String>, signing_key: SigningKey) -> Self {
+        Self {
+            issuer_id: issuer_id.into(),
+            keys: NodeKeys::from_signing_key(signing_key.clone()),
+            signing_key,
+        }
+    }
+
+    /// Load the node key from `config.ledger_key_path()`, generating and
+    /// persisting one on first boot.
+    pub fn load(config: &OffsecConfig) -> anyhow::Result<Self> {
+        let path = config.ledger_key_path();
+        let signing_key = if path.exists() {
+            load_signing_key(&path.display().to_string())?
+        } else {
+            let key = generate_key_file(&path)?;
+            tracing::info!(
+                "generated ledger node key {} for {}",
+                path.display(),
+                config.ledger.issuer_id
+            );
+            key
+        };
+        Ok(Self::new(config.ledger.issuer_id.clone(), signing_key))
+    }
+
+    pub fn issuer_id(&self) -> &str {
+        &self.issuer_id
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    pub fn keys(&self) -> &NodeKeys {
+        &self.keys
+    }
+}
+
+/// Write a fresh 32-byte Ed25519 key to `path`, readable only by its owner.
+/// Fails rather than overwrite a key created concurrently.
+fn generate_key_file(path: &Path) -> anyhow::Result<SigningKey> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    let mut seed = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut seed);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("creating ledger node key {}", path.display()))?;
+    file.write_all(&seed)?;
+    file.sync_all()?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// `GET /api/offsec/did`: the DID and verifying key that ledger receipts
+/// and proofs are signed with, in the `trusted_issuers.json` shape.
+pub async fn node_did(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let identity = state.ledger.identity();
+    let verifying_key = hex::encode(identity.verifying_key().to_bytes());
+    Json(serde_json::json!({
+        "did": identity.issuer_id(),
+        "algorithm": "ed25519",
+        "verifying_key": verifying_key,
+        "trusted_issuers": { identity.issuer_id(): verifying_key },
+    }))
+}
 
-/// Issuer identifier for receipts created by the OffSec Shield node.
-const ISSUER_ID: &str = "did:vm:node:offsec-shield";
+/// Node signature over one infrastructure receipt, made when it is
+/// written: base64 Ed25519 over BLAKE3(canonical_json(receipt)).
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ReceiptSeal {
+    pub receipt_id: String,
+    /// Hex BLAKE3 of the canonical receipt JSON.
+    pub hash: String,
+    pub signature: String,
+}
 
-static STORE: Lazy<FileStore> = Lazy::new(|| {
-    let base = std::env::var("OFFSEC_DATA_DIR").unwrap_or_else(|_| DEFAULT_DATA_DIR.into());
-    FileStore::new(base).expect("failed to init FileStore")
-});
+/// One receipt of an exported incident chain.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainEntry {
+    pub receipt: serde_json::Value,
+    pub hash: String,
+    /// The receipt's seal; `None` for receipts written before seals were.
+    #[serde(default)]
+    pub signature: Option<String>,
+}
 
-static KEYS: Lazy<NodeKeys> = Lazy::new(NodeKeys::generate);
+/// An incident chain exported for verification: every receipt with its
+/// seal, the civilization-ledger proof over them and a node signature over
+/// the list of receipt hashes (see [`IncidentProof::statement`]).
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncidentProof {
+    pub proof_version: u8,
+    pub incident_id: String,
+    pub issuer: String,
+    /// Hex Ed25519 key of `issuer`. Informational; verifiers use the key
+    /// they trust for the issuer.
+    pub verifying_key: String,
+    /// Oldest first.
+    pub entries: Vec<ChainEntry>,
+    /// Hash of the newest receipt.
+    pub head: String,
+    pub built_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ledger_proof: Option<serde_json::Value>,
+    pub signature: String,
+}
 
-/// In-memory list of incidents for the reference implementation.
-static INCIDENTS: Lazy<Mutex<Vec<IncidentChain>>> = Lazy::new(|| Mutex::new(Vec::new()));
+impl IncidentProof {
+    /// What `signature` is made over.
+    pub fn statement(&self) -> serde_json::Value {
+        serde_json::json!({
+            "proof_version": self.proof_version,
+            "incident_id": self.incident_id,
+            "issuer": self.issuer,
+            "hashes": self.entries.iter().map(|e| &e.hash).collect::<Vec<_>>(),
+            "built_at": self.built_at,
+        })
+    }
+}
+
+/// Result of checking an [`IncidentProof`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChainVerification {
+    pub incident_id: String,
+    pub valid: bool,
+    pub receipts: usize,
+    /// Receipts whose seal verifies.
+    pub sealed: usize,
+    /// Every receipt names the previous one as `prev_id`.
+    pub linked: bool,
+    pub proof_signature: bool,
+    pub problems: Vec<String>,
+}
+
+/// Check an exported chain against the issuer's key: each receipt hash and
+/// seal, the `prev_id` links and incident id of each receipt, the head and
+/// the proof signature.
+pub fn verify_incident_proof(proof: &IncidentProof, key: &VerifyingKey) -> ChainVerification {
+    let pubkey = BASE64.encode(key.to_bytes());
+    let mut problems = Vec::new();
+    let mut sealed = 0;
+    let mut linked = true;
+    let mut prev: Option<&str> = None;
+
+    if proof.proof_version != INCIDENT_PROOF_VERSION {
+        problems.push(format!("unsupported proof version {}", proof.proof_version));
+    }
+    if proof.entries.is_empty() {
+        problems.push("chain has no receipts".to_string());
+    }
+    for (i, entry) in proof.entries.iter().enumerate() {
+        let receipt = &entry.receipt;
+        let id = receipt["id"].as_str().unwrap_or_default();
+        let label = format!("receipt {i} ({id})");
+
+        match compute_payload_hash(receipt) {
+            Ok(hash) if hex::encode(hash) == entry.hash => {}
+            _ => problems.push(format!("{label}: hash does not match receipt")),
+        }
+        match &entry.signature {
+            Some(sig) if verify_signature(&pubkey, sig, receipt).is_ok() => sealed += 1,
+            Some(_) => problems.push(format!("{label}: seal does not verify")),
+            None => problems.push(format!("{label}: not sealed")),
+        }
+        if receipt["prev_id"].as_str() != prev {
+            linked = false;
+            problems.push(format!(
+                "{label}: prev_id {} does not name the previous receipt",
+                receipt["prev_id"]
+            ));
+        }
+        let ref_id = receipt["body"]["ref_id"]
+            .as_str()
+            .or_else(|| receipt["body"]["extra"]["incident_id"].as_str());
+        if ref_id != Some(proof.incident_id.as_str()) {
+            problems.push(format!("{label}: belongs to incident {ref_id:?}"));
+        }
+        prev = Some(id);
+    }
+    if proof.entries.last().map(|e| e.hash.as_str()) != Some(proof.head.as_str()) {
+        problems.push("head is not the newest receipt".to_string());
+    }
+    let proof_signature = verify_signature(&pubkey, &proof.signature, &proof.statement()).is_ok();
+    if !proof_signature {
+        problems.push("proof signature does not verify".to_string());
+    }
+
+    ChainVerification {
+        incident_id: proof.incident_id.clone(),
+        valid: problems.is_empty(),
+        receipts: proof.entries.len(),
+        sealed,
+        linked,
+        proof_signature,
+        problems,
+    }
+}
+
+type LedgerRejection = (StatusCode, Json<ErrorResponse>);
+
+fn reject(code: StatusCode, error: &str, details: String) -> LedgerRejection {
+    (
+        code,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            details: Some(details),
+        }),
+    )
+}
+
+fn load_proof(state: &AppState, incident_id: &str) -> Result<IncidentProof, LedgerRejection> {
+    state
+        .ledger
+        .proof(incident_id)
+        .map_err(|e| {
+            reject(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "proof_build_failed",
+                e.to_string(),
+            )
+        })?
+        .ok_or_else(|| {
+            reject(
+                StatusCode::NOT_FOUND,
+                "unknown_incident",
+                format!("no incident chain {incident_id:?}"),
+            )
+        })
+}
+
+/// `GET /api/offsec/incidents/:id/proof`: the chain's latest proof.
+pub async fn incident_proof(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Json<IncidentProof>, LedgerRejection> {
+    load_proof(&state, &id).map(Json)
+}
+
+/// `GET /api/offsec/incidents/:id/verify`: check the chain as stored.
+pub async fn verify_incident(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Json<ChainVerification>, LedgerRejection> {
+    let proof = load_proof(&state, &id)?;
+    let key = state.ledger.identity().verifying_key();
+    Ok(Json(verify_incident_proof(&proof, &key)))
+}
+
+/// `POST /api/offsec/incidents/verify`: check an exported chain issued by
+/// this node or a trusted issuer.
+pub async fn verify_exported(
+    State(state): State<AppState>,
+    Json(proof): Json<IncidentProof>,
+) -> Result<Json<ChainVerification>, LedgerRejection> {
+    let identity = state.ledger.identity();
+    let key = if proof.issuer == identity.issuer_id() {
+        identity.verifying_key()
+    } else {
+        state
+            .trust
+            .snapshot()
+            .issuers
+            .get(&proof.issuer)
+            .copied()
+            .ok_or_else(|| {
+                reject(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "untrusted_issuer",
+                    format!("issuer {:?} is not trusted", proof.issuer),
+                )
+            })?
+    };
+    Ok(Json(verify_incident_proof(&proof, &key)))
+}
 
 /// Simplified incident chain for the integration
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -96,39 +351,374 @@ impl IncidentChain {
     }
 }
 
-/// Trusted issuers loader: JSON file at $OFFSEC_DATA_DIR/trusted_issuers.json
-/// Format: { "did:vm:node:alice": "02aabbcc...", ... } where value is hex of verifying key bytes
-fn load_trusted_issuers() -> Result<HashMap<String, VerifyingKey>, Box<dyn std::error::Error>> {
-    use std::fs::File;
-    use std::io::BufReader;
-
-    let base = std::env::var("OFFSEC_DATA_DIR").unwrap_or_else(|_| DEFAULT_DATA_DIR.into());
-    let path = std::path::Path::new(&base).join("trusted_issuers.json");
-    let mut map = HashMap::new();
-
-    if !path.exists() {
-        // No trusted issuers yet — that's fine; the operator can provision the file.
-        return Ok(map);
-    }
-
-    let f = File::open(&path)?;
-    let reader = BufReader::new(f);
-    let raw: HashMap<String, String> = serde_json::from_reader(reader)?;
-    for (did, key_hex) in raw {
-        let key_bytes = hex::decode(key_hex)?;
-        let key_arr: [u8; 32] = key_bytes
-            .try_into()
-            .map_err(|_| "verifying key must be 32 bytes")?;
-        let vk = VerifyingKey::from_bytes(&key_arr)?;
-        map.insert(did, vk);
-    }
-    Ok(map)
+/// Infrastructure receipts and proofs under `data_dir`, the incident
+/// chains they form, and the identity they are issued under.
+pub struct IncidentLedger {
+    identity: NodeIdentity,
+    data_dir: PathBuf,
+    store: FileStore,
+    incidents: Mutex<Vec<IncidentChain>>,
+}
+
+impl IncidentLedger {
+    /// Open the ledger under `config.data_dir` and index the incident
+    /// chains already on disk.
+    pub fn open(config: &OffsecConfig) -> anyhow::Result<Self> {
+        let identity = NodeIdentity::load(config).context("loading ledger node key")?;
+        let store = FileStore::new(&config.data_dir)
+            .map_err(|e| anyhow::anyhow!("opening ledger store {}: {e}", config.data_dir))?;
+        let ledger = Self {
+            identity,
+            data_dir: PathBuf::from(&config.data_dir),
+            store,
+            incidents: Mutex::new(Vec::new()),
+        };
+        let indexed = ledger
+            .rebuild_index()
+            .map_err(|e| anyhow::anyhow!("rebuilding incident index: {e}"))?;
+        if indexed > 0 {
+            tracing::info!("indexed {} incident chains from receipts", indexed);
+        }
+        Ok(ledger)
+    }
+
+    pub fn identity(&self) -> &NodeIdentity {
+        &self.identity
+    }
+
+    /// Handle a single InfrastructureEvent from Guardian:
+    /// - create or extend an IncidentChain
+    /// - write receipts to disk
+    /// - optionally build a proof
+    pub fn handle_infra_event(
+        &self,
+        ev: InfrastructureEvent,
+    ) -> Result<(String, String), Box<dyn std::error::Error>> {
+        let identity = &self.identity;
+        let mut incidents = self.incidents.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Determine incident ID
+        let incident_id = ev
+            .ref_id
+            .clone()
+            .unwrap_or_else(|| format!("inc-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S%.3f")));
+
+        // Find or create incident chain
+        let chain = if let Some(c) = incidents.iter_mut().find(|c| c.incident_id == incident_id) {
+            c
+        } else {
+            let new_chain = IncidentChain::new(incident_id.clone());
+            incidents.push(new_chain);
+            incidents.last_mut().unwrap()
+        };
+
+        // Promote event to receipt
+        let prev_id = chain.receipts.last().map(|r| r.id.clone());
+        let receipt = promote_event(identity.keys(), identity.issuer_id(), ev, prev_id)?;
+        let receipt_id = receipt.id.clone();
+
+        // Store and seal the receipt, keeping a copy for the in-memory chain
+        self.store.write_receipt(&receipt)?;
+        self.write_seal(&receipt)?;
+        chain.add_receipt(receipt);
+
+        // Build proof periodically (every 5 receipts)
+        if chain.receipts.len() % 5 == 0 {
+            self.build_proof(chain)?;
+        }
+
+        Ok((incident_id, receipt_id))
+    }
+
+    /// [`handle_infra_event`](Self::handle_infra_event) on the blocking
+    /// pool, for async callers: the chain lock is held across the receipt,
+    /// seal and proof file writes.
+    pub async fn append(
+        self: &Arc<Self>,
+        ev: InfrastructureEvent,
+    ) -> Result<(String, String), String> {
+        let ledger = Arc::clone(self);
+        tokio::task::spawn_blocking(move || {
+            ledger.handle_infra_event(ev).map_err(|e| e.to_string())
+        })
+        .await
+        .unwrap_or_else(|e| Err(format!("ledger write task failed: {e}")))
+    }
+
+    /// Promote a portal-side change to `incident_id` (an assignment or a
+    /// status transition) onto its chain. Returns the receipt id.
+    pub async fn record(
+        self: &Arc<Self>,
+        incident_id: &str,
+        event_type: &str,
+        severity: &str,
+        data: serde_json::Value,
+    ) -> Result<String, String> {
+        let ev: InfrastructureEvent = serde_json::from_value(serde_json::json!({
+            "event_type": event_type,
+            "severity": severity,
+            "ref_id": incident_id,
+            "data": data,
+        }))
+        .map_err(|e| e.to_string())?;
+        let (_, receipt_id) = self.append(ev).await?;
+        Ok(receipt_id)
+    }
+
+    /// Rebuild the incident index from receipts on disk. Returns the number
+    /// of incident chains found.
+    pub fn rebuild_index(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let rec_dir = self.data_dir.join("receipts").join("infrastructure");
+
+        if !rec_dir.exists() {
+            return Ok(0);
+        }
+
+        let mut incidents_map: HashMap<String, Vec<Receipt>> = HashMap::new();
+
+        for entry in WalkDir::new(&rec_dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let f = match std::fs::File::open(path) {
+                Ok(f) => f,
+                Err(e) => {
+                    tracing::warn!("rebuild_index: failed to open {:?}: {}", entry.path(), e);
+                    continue;
+                }
+            };
+            let r: Receipt = match serde_json::from_reader(std::io::BufReader::new(f)) {
+                Ok(r) => r,
+                Err(e) => {
+                    tracing::warn!("rebuild_index: failed to parse {:?}: {}", entry.path(), e);
+                    continue;
+                }
+            };
+
+            // determine incident id: first check body.ref_id, then extra.incident_id
+            let mut incident_id_opt: Option<String> = None;
+            if let Some(ref_id) = &r.body.ref_id {
+                incident_id_opt = Some(ref_id.clone());
+            } else if let Some(extra_obj) = r.body.extra.as_object() {
+                if let Some(val) = extra_obj.get("incident_id") {
+                    if let Some(s) = val.as_str() {
+                        incident_id_opt = Some(s.to_string());
+                    }
+                }
+            }
+
+            // if no incident id, group under a per-receipt chain id
+            let incident_id = incident_id_opt.unwrap_or_else(|| format!("orphan-{}", r.id.clone()));
+
+            incidents_map.entry(incident_id).or_default().push(r);
+        }
+
+        // convert map into the incident list
+        let mut incidents_vec = self.incidents.lock().unwrap_or_else(|e| e.into_inner());
+        incidents_vec.clear();
+        for (k, receipts) in incidents_map.into_iter() {
+            let receipts = chain_order(&k, receipts);
+            incidents_vec.push(IncidentChain {
+                incident_id: k,
+                receipts,
+            });
+        }
+
+        Ok(incidents_vec.len())
+    }
+
+    fn seal_path(&self, receipt_id: &str) -> PathBuf {
+        self.data_dir
+            .join("receipts")
+            .join("infrastructure-seals")
+            .join(format!("{receipt_id}.json"))
+    }
+
+    /// Sign the receipt with the node key and write the seal next to the
+    /// receipts directory.
+    fn write_seal(&self, receipt: &Receipt) -> Result<(), Box<dyn std::error::Error>> {
+        let value = serde_json::to_value(receipt)?;
+        let seal = ReceiptSeal {
+            receipt_id: receipt.id.clone(),
+            hash: hex::encode(compute_payload_hash(&value)?),
+            signature: sign_payload(&self.identity.signing_key, &value)?,
+        };
+        let path = self.seal_path(&receipt.id);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(&seal)?)?;
+        Ok(())
+    }
+
+    fn read_seal(&self, receipt_id: &str) -> Option<ReceiptSeal> {
+        let bytes = fs::read(self.seal_path(receipt_id)).ok()?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| tracing::warn!("unreadable seal for receipt {}: {}", receipt_id, e))
+            .ok()
+    }
+
+    fn proof_path(&self, incident_id: &str) -> PathBuf {
+        let name: String = incident_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.data_dir
+            .join("proofs")
+            .join("incidents")
+            .join(format!("{name}.json"))
+    }
+
+    /// Build a civilization-ledger proof over the whole chain and wrap it,
+    /// with the receipts and their seals, in a signed [`IncidentProof`].
+    /// The result is kept as the chain's latest proof.
+    fn build_proof(
+        &self,
+        chain: &IncidentChain,
+    ) -> Result<IncidentProof, Box<dyn std::error::Error>> {
+        use civilization_ledger_core::{build_proof, types::Scroll};
+        let identity = &self.identity;
+        let ids: Vec<String> = chain.receipts.iter().map(|r| r.id.clone()).collect();
+        let ledger_proof = build_proof(
+            identity.keys(),
+            identity.issuer_id(),
+            vec![Scroll::Infrastructure],
+            ids,
+        )?;
+        self.store.write_proof(&ledger_proof)?;
+
+        let mut entries = Vec::with_capacity(chain.receipts.len());
+        for receipt in &chain.receipts {
+            let receipt = serde_json::to_value(receipt)?;
+            let hash = hex::encode(compute_payload_hash(&receipt)?);
+            let id = receipt["id"].as_str().unwrap_or_default();
+            let signature = self.read_seal(id).map(|seal| seal.signature);
+            entries.push(ChainEntry {
+                receipt,
+                hash,
+                signature,
+            });
+        }
+        let mut proof = IncidentProof {
+            proof_version: INCIDENT_PROOF_VERSION,
+            incident_id: chain.incident_id.clone(),
+            issuer: identity.issuer_id().to_string(),
+            verifying_key: hex::encode(identity.verifying_key().to_bytes()),
+            head: entries.last().map(|e| e.hash.clone()).unwrap_or_default(),
+            entries,
+            built_at: chrono::Utc::now().to_rfc3339(),
+            ledger_proof: Some(serde_json::to_value(&ledger_proof)?),
+            signature: String::new(),
+        };
+        proof.signature = sign_payload(&identity.signing_key, &proof.statement())?;
+
+        let path = self.proof_path(&chain.incident_id);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(&proof)?)?;
+        Ok(proof)
+    }
+
+    /// The latest proof for an incident: the stored one if it still covers
+    /// the whole chain, otherwise a fresh one.
+    pub fn proof(
+        &self,
+        incident_id: &str,
+    ) -> Result<Option<IncidentProof>, Box<dyn std::error::Error>> {
+        let Some(chain) = self.get_incident(incident_id) else {
+            return Ok(None);
+        };
+        let stored = fs::read(self.proof_path(incident_id))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<IncidentProof>(&bytes).ok());
+        if let Some(proof) = stored {
+            let covers_chain = proof.incident_id == incident_id
+                && proof.entries.len() == chain.receipts.len()
+                && proof
+                    .entries
+                    .iter()
+                    .zip(&chain.receipts)
+                    .all(|(entry, receipt)| entry.receipt["id"] == receipt.id.as_str());
+            if covers_chain {
+                return Ok(Some(proof));
+            }
+        }
+        self.build_proof(&chain).map(Some)
+    }
+
+    /// Read an incident chain by id.
+    pub fn get_incident(&self, id: &str) -> Option<IncidentChain> {
+        self.incidents
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|c| c.incident_id == id)
+            .cloned()
+    }
+
+    /// List all incident IDs (for optional list endpoint)
+    pub fn list_incident_ids(&self) -> Vec<String> {
+        self.incidents
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|c| c.incident_id.clone())
+            .collect()
+    }
+}
+
+/// Order an incident's receipts by following `prev_id` from the receipt
+/// with none. Timestamps can tie or run backwards, so they only order what
+/// the links do not reach, which leaves the chain failing verification.
+fn chain_order(incident_id: &str, receipts: Vec<Receipt>) -> Vec<Receipt> {
+    let mut by_prev: HashMap<Option<String>, Receipt> = HashMap::new();
+    let mut unlinked = Vec::new();
+    for r in receipts {
+        match by_prev.entry(r.prev_id.clone()) {
+            Entry::Vacant(slot) => {
+                slot.insert(r);
+            }
+            Entry::Occupied(_) => unlinked.push(r),
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(by_prev.len() + unlinked.len());
+    let mut prev = None;
+    while let Some(r) = by_prev.remove(&prev) {
+        prev = Some(r.id.clone());
+        ordered.push(r);
+    }
+
+    unlinked.extend(by_prev.into_values());
+    if !unlinked.is_empty() {
+        tracing::warn!(
+            "rebuild_index: {} receipt(s) of {} are not on its prev_id chain",
+            unlinked.len(),
+            incident_id
+        );
+        unlinked.sort_by_key(|r| r.ts);
+        ordered.extend(unlinked);
+    }
+    ordered
 }
 
-/// Validate a capability passed as base64(JSON). Returns parsed capability on success.
-pub fn validate_capability_base64(
+/// Decode a base64(JSON) capability and check its expiry and issuer
+/// signature, leaving scopes to the caller (see `auth::LedgerVerifier`).
+pub fn decode_capability(
     token_b64: &str,
-    required_scope: &str,
+    issuers: &BTreeMap<String, VerifyingKey>,
 ) -> Result<civilization_ledger_core::capability::Capability, Box<dyn std::error::Error>> {
     use base64::engine::general_purpose::STANDARD as base64_std;
     use base64::Engine;
@@ -144,22 +734,6 @@ pub fn validate_capability_base64(
         return Err("capability expired".into());
     }
 
-    // check scope
-    let mut has_scope = false;
-    for s in &cap.scopes {
-        if s == required_scope
-            || s == &format!("{}:*", required_scope.split(':').next().unwrap_or(""))
-        {
-            has_scope = true;
-            break;
-        }
-    }
-    if !has_scope {
-        return Err("capability missing required scope".into());
-    }
-
-    // load trusted issuers
-    let issuers = load_trusted_issuers()?;
     let issuer_vk = issuers
         .get(&cap.issued_by)
         .ok_or_else(|| format!("unknown capability issuer: {}", cap.issued_by))?;
@@ -188,137 +762,3 @@ pub fn validate_capability_base64(
 
     Ok(cap)
 }
-
-/// Handle a single InfrastructureEvent from Guardian:
-/// - create or extend an IncidentChain
-/// - write receipts to disk
-/// - optionally build a proof
-pub fn handle_infra_event(
-    ev: InfrastructureEvent,
-) -> Result<(String, String), Box<dyn std::error::Error>> {
-    let mut incidents = INCIDENTS.lock().unwrap();
-
-    // Determine incident ID
-    let incident_id = ev
-        .ref_id
-        .clone()
-        .unwrap_or_else(|| format!("inc-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S%.3f")));
-
-    // Find or create incident chain
-    let chain = if let Some(c) = incidents.iter_mut().find(|c| c.incident_id == incident_id) {
-        c
-    } else {
-        let new_chain = IncidentChain::new(incident_id.clone());
-        incidents.push(new_chain);
-        incidents.last_mut().unwrap()
-    };
-
-    // Promote event to receipt
-    let prev_id = chain.receipts.last().map(|r| r.id.clone());
-    let receipt = promote_event(&KEYS, ISSUER_ID, ev, prev_id)?;
-    let receipt_id = receipt.id.clone();
-
-    // Store receipt and keep a copy for the in-memory chain
-    STORE.write_receipt(&receipt)?;
-    chain.add_receipt(receipt);
-
-    // Build proof periodically (every 5 receipts)
-    if chain.receipts.len() % 5 == 0 {
-        use civilization_ledger_core::{build_proof, types::Scroll};
-        let ids: Vec<String> = chain.receipts.iter().map(|r| r.id.clone()).collect();
-        let proof = build_proof(&KEYS, ISSUER_ID, vec![Scroll::Infrastructure], ids)?;
-        STORE.write_proof(&proof)?;
-    }
-
-    Ok((incident_id, receipt_id))
-}
-
-/// Rebuild the incident index from receipts on disk.
-/// Call this at startup to populate INCIDENTS from existing files.
-pub fn rebuild_index() -> Result<(), Box<dyn std::error::Error>> {
-    let base = std::env::var("OFFSEC_DATA_DIR").unwrap_or_else(|_| DEFAULT_DATA_DIR.into());
-    let rec_dir = std::path::Path::new(&base)
-        .join("receipts")
-        .join("infrastructure");
-
-    if !rec_dir.exists() {
-        return Ok(());
-    }
-
-    let mut incidents_map: HashMap<String, Vec<Receipt>> = HashMap::new();
-
-    for entry in WalkDir::new(&rec_dir)
-        .min_depth(1)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if !entry.file_type().is_file() {
-            continue;
-        }
-        let path = entry.path();
-        let f = match std::fs::File::open(path) {
-            Ok(f) => f,
-            Err(e) => {
-                tracing::warn!("rebuild_index: failed to open {:?}: {}", entry.path(), e);
-                continue;
-            }
-        };
-        let r: Receipt = match serde_json::from_reader(std::io::BufReader::new(f)) {
-            Ok(r) => r,
-            Err(e) => {
-                tracing::warn!("rebuild_index: failed to parse {:?}: {}", entry.path(), e);
-                continue;
-            }
-        };
-
-        // determine incident id: first check body.ref_id, then extra.incident_id
-        let mut incident_id_opt: Option<String> = None;
-        if let Some(ref_id) = &r.body.ref_id {
-            incident_id_opt = Some(ref_id.clone());
-        } else if let Some(extra_obj) = r.body.extra.as_object() {
-            if let Some(val) = extra_obj.get("incident_id") {
-                if let Some(s) = val.as_str() {
-                    incident_id_opt = Some(s.to_string());
-                }
-            }
-        }
-
-        // if no incident id, group under a per-receipt chain id
-        let incident_id = incident_id_opt.unwrap_or_else(|| format!("orphan-{}", r.id.clone()));
-
-        incidents_map.entry(incident_id).or_default().push(r);
-    }
-
-    // convert map into INCIDENTS vector
-    let mut incidents_vec = INCIDENTS.lock().unwrap();
-    incidents_vec.clear();
-    for (k, mut receipts) in incidents_map.into_iter() {
-        receipts.sort_by_key(|r| r.ts);
-        incidents_vec.push(IncidentChain {
-            incident_id: k,
-            receipts,
-        });
-    }
-
-    Ok(())
-}
-
-/// Read an incident chain by id.
-pub fn get_incident(id: &str) -> Option<IncidentChain> {
-    INCIDENTS
-        .lock()
-        .unwrap()
-        .iter()
-        .find(|c| c.incident_id == id)
-        .cloned()
-}
-
-/// List all incident IDs (for optional list endpoint)
-pub fn list_incident_ids() -> Vec<String> {
-    INCIDENTS
-        .lock()
-        .unwrap()
-        .iter()
-        .map(|c| c.incident_id.clone())
-        .collect()
-}
@@ -0,0 +1,580 @@
+//! Durable delivery of approved actions to Guardian. Each action is queued
+//! in `dispatch_outbox` before the first attempt, in the same transaction
+//! that marks it dispatched. Failed attempts are retried with jittered
+//! exponential backoff until Guardian accepts the action. Once
+//! `dispatch.max_attempts` is reached it is dead-lettered and the action
+//! fails. Every attempt carries the same `Idempotency-Key`, derived from
+//! the `action_id`.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{TimeZone, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{
+    sqlite::{SqliteConnection, SqlitePool},
+    Row,
+};
+
+use crate::{
+    actions::{ActionState, Transition, TransitionError},
+    config::{DispatchConfig, OffsecConfig},
+    models::ErrorResponse,
+    receipts::write_receipt,
+    routes::action_apply::ActionRequestPayload,
+    AppState,
+};
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS dispatch_outbox (
+        action_id TEXT PRIMARY KEY,
+        idempotency_key TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        status TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        next_attempt_at INTEGER NOT NULL,
+        last_error TEXT,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS idx_dispatch_outbox_due
+        ON dispatch_outbox (status, next_attempt_at)",
+];
+
+/// How often the worker looks for due retries.
+const WORKER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Header Guardian uses to recognise a retried delivery.
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+
+const DEFAULT_GUARDIAN_URL: &str = "http://localhost:9120";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxStatus {
+    Pending,
+    Delivered,
+    /// Gave up after `dispatch.max_attempts`.
+    Dead,
+}
+
+impl OutboxStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Delivered => "delivered",
+            Self::Dead => "dead",
+        }
+    }
+
+    fn parse(status: &str) -> Option<Self> {
+        Some(match status {
+            "pending" => Self::Pending,
+            "delivered" => Self::Delivered,
+            "dead" => Self::Dead,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboxEntry {
+    pub action_id: String,
+    pub idempotency_key: String,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub next_attempt_at: String,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub payload: Value,
+}
+
+/// What one delivery attempt came to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    Delivered {
+        attempts: u32,
+    },
+    Retrying {
+        attempts: u32,
+        error: String,
+    },
+    DeadLettered {
+        attempts: u32,
+        error: String,
+    },
+    /// Not due, already delivered, or being attempted elsewhere.
+    Skipped,
+}
+
+/// Stable per action, so Guardian can drop a delivery it already ran.
+pub fn idempotency_key(action_id: &str) -> String {
+    let hash = blake3::hash(format!("offsec.action.dispatch:{action_id}").as_bytes());
+    hash.to_hex()[..32].to_string()
+}
+
+/// Delay before the retry that follows attempt number `attempts`, before
+/// jitter: `base_delay_ms` doubled per attempt, capped at `max_delay_ms`.
+pub fn backoff(config: &DispatchConfig, attempts: u32) -> Duration {
+    let factor = 1u64 << attempts.saturating_sub(1).min(32);
+    Duration::from_millis(
+        config
+            .base_delay_ms
+            .saturating_mul(factor)
+            .min(config.max_delay_ms),
+    )
+}
+
+/// A random delay between half of `delay` and all of it.
+pub fn jitter(delay: Duration) -> Duration {
+    let ms = delay.as_millis() as u64;
+    Duration::from_millis(rand::thread_rng().gen_range(ms / 2..=ms))
+}
+
+fn now_ms() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+fn rfc3339_ms(ms: i64) -> String {
+    Utc.timestamp_millis_opt(ms)
+        .single()
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_default()
+}
+
+/// The outbox table and the HTTP client shared by every delivery.
+#[derive(Clone)]
+pub struct DispatchOutbox {
+    pool: SqlitePool,
+    client: reqwest::Client,
+    config: DispatchConfig,
+    guardian_url: String,
+}
+
+impl DispatchOutbox {
+    pub async fn open(pool: SqlitePool, config: &OffsecConfig) -> anyhow::Result<Self> {
+        for stmt in SCHEMA {
+            sqlx::query(stmt).execute(&pool).await?;
+        }
+        let timeout = Duration::from_millis(config.dispatch.timeout_ms);
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .build()?;
+        Ok(Self {
+            pool,
+            client,
+            config: config.dispatch.clone(),
+            guardian_url: config
+                .guardian_url
+                .clone()
+                .unwrap_or_else(|| DEFAULT_GUARDIAN_URL.to_string()),
+        })
+    }
+
+    /// Queue `req` for delivery now. Queuing the same action twice keeps
+    /// the first entry.
+    pub async fn enqueue(&self, req: &ActionRequestPayload) -> anyhow::Result<String> {
+        let mut conn = self.pool.acquire().await?;
+        self.enqueue_in(&mut conn, req).await
+    }
+
+    /// [`Self::enqueue`] on the caller's connection, so it can share a
+    /// transaction with the action's transition.
+    pub async fn enqueue_in(
+        &self,
+        conn: &mut SqliteConnection,
+        req: &ActionRequestPayload,
+    ) -> anyhow::Result<String> {
+        let key = idempotency_key(&req.action_id);
+        let now = Utc::now().to_rfc3339();
+        sqlx::query(
+            "INSERT INTO dispatch_outbox
+                (action_id, idempotency_key, payload, status, attempts, next_attempt_at,
+                 created_at, updated_at)
+             VALUES (?, ?, ?, ?, 0, ?, ?, ?)
+             ON CONFLICT (action_id) DO NOTHING",
+        )
+        .bind(&req.action_id)
+        .bind(&key)
+        .bind(serde_json::to_string(req)?)
+        .bind(OutboxStatus::Pending.as_str())
+        .bind(now_ms())
+        .bind(&now)
+        .bind(&now)
+        .execute(conn)
+        .await?;
+        Ok(key)
+    }
+
+    /// Take a due entry for one attempt. Its next attempt is pushed past
+    /// the request timeout so the worker and a request handler never send
+    /// it at the same time.
+    async fn claim(&self, action_id: &str) -> anyhow::Result<Option<(ActionRequestPayload, u32)>> {
+        let now = now_ms();
+        let lease = now + 2 * self.config.timeout_ms as i64;
+        let row = sqlx::query(
+            "UPDATE dispatch_outbox SET next_attempt_at = ?
+             WHERE action_id = ? AND status = ? AND next_attempt_at <= ?
+             RETURNING payload, attempts",
+        )
+        .bind(lease)
+        .bind(action_id)
+        .bind(OutboxStatus::Pending.as_str())
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(|r| {
+            let payload = serde_json::from_str(&r.get::<String, _>("payload"))?;
+            Ok((payload, r.get::<i64, _>("attempts") as u32))
+        })
+        .transpose()
+    }
+
+    async fn send(&self, req: &ActionRequestPayload) -> Result<(), String> {
+        self.client
+            .post(format!("{}/actions/apply", self.guardian_url))
+            .header(IDEMPOTENCY_HEADER, idempotency_key(&req.action_id))
+            .json(req)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .error_for_status()
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Record attempt number `attempts`: delivered, or failed with `error`.
+    /// Returns the entry's new status.
+    async fn record_attempt(
+        &self,
+        action_id: &str,
+        attempts: u32,
+        error: Option<&str>,
+    ) -> anyhow::Result<OutboxStatus> {
+        let (status, next_attempt_at) = match error {
+            None => (OutboxStatus::Delivered, now_ms()),
+            Some(_) if attempts >= self.config.max_attempts => (OutboxStatus::Dead, now_ms()),
+            Some(_) => (
+                OutboxStatus::Pending,
+                now_ms() + jitter(backoff(&self.config, attempts)).as_millis() as i64,
+            ),
+        };
+        sqlx::query(
+            "UPDATE dispatch_outbox
+             SET status = ?, attempts = ?, next_attempt_at = ?, last_error = ?, updated_at = ?
+             WHERE action_id = ?",
+        )
+        .bind(status.as_str())
+        .bind(attempts as i64)
+        .bind(next_attempt_at)
+        .bind(error)
+        .bind(Utc::now().to_rfc3339())
+        .bind(action_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(status)
+    }
+
+    /// Pending entries whose next attempt is due, oldest first.
+    pub async fn due(&self, limit: usize) -> anyhow::Result<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT action_id FROM dispatch_outbox
+             WHERE status = ? AND next_attempt_at <= ?
+             ORDER BY next_attempt_at ASC LIMIT ?",
+        )
+        .bind(OutboxStatus::Pending.as_str())
+        .bind(now_ms())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| r.get("action_id")).collect())
+    }
+
+    pub async fn get(&self, action_id: &str) -> anyhow::Result<Option<OutboxEntry>> {
+        let row = sqlx::query("SELECT * FROM dispatch_outbox WHERE action_id = ?")
+            .bind(action_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(entry).transpose()
+    }
+
+    /// Entries, most recently updated first.
+    pub async fn entries(
+        &self,
+        status: Option<OutboxStatus>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<OutboxEntry>> {
+        sqlx::query(
+            "SELECT * FROM dispatch_outbox WHERE (?1 IS NULL OR status = ?1)
+             ORDER BY updated_at DESC, action_id ASC LIMIT ?2",
+        )
+        .bind(status.map(OutboxStatus::as_str))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(entry)
+        .collect()
+    }
+}
+
+fn entry(row: sqlx::sqlite::SqliteRow) -> anyhow::Result<OutboxEntry> {
+    let status: String = row.get("status");
+    Ok(OutboxEntry {
+        action_id: row.get("action_id"),
+        idempotency_key: row.get("idempotency_key"),
+        status: OutboxStatus::parse(&status)
+            .ok_or_else(|| anyhow::anyhow!("unknown outbox status {status:?}"))?,
+        attempts: row.get::<i64, _>("attempts") as u32,
+        next_attempt_at: rfc3339_ms(row.get("next_attempt_at")),
+        last_error: row.get("last_error"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+        payload: serde_json::from_str(&row.get::<String, _>("payload"))?,
+    })
+}
+
+/// Queue an approved action and mark it dispatched in one transaction,
+/// then make the first attempt. Returns the action's state afterwards:
+/// `dispatched`, or `failed` if it could not be queued or was
+/// dead-lettered at once.
+pub async fn dispatch(
+    state: &AppState,
+    req: &ActionRequestPayload,
+) -> Result<ActionState, TransitionError> {
+    let key = idempotency_key(&req.action_id);
+    let details = json!({ "idempotency_key": key });
+    let queued = async {
+        let mut tx = state.store.pool().begin().await?;
+        state
+            .outbox
+            .enqueue_in(&mut tx, req)
+            .await
+            .map_err(TransitionError::Store)?;
+        state
+            .actions
+            .transition_in(
+                &mut tx,
+                &req.action_id,
+                Transition {
+                    from: ActionState::Approved,
+                    to: ActionState::Dispatched,
+                    actor: "portal",
+                    receipt_id: None,
+                    details: Some(&details),
+                },
+            )
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    .await;
+    match queued {
+        Ok(()) => {}
+        Err(TransitionError::Store(e)) => {
+            tracing::error!("queueing dispatch of {}: {}", req.action_id, e);
+            let record = state
+                .actions
+                .transition(
+                    &req.action_id,
+                    ActionState::Approved,
+                    ActionState::Failed,
+                    "portal",
+                    None,
+                    Some(&json!({ "error": format!("dispatch not queued: {e}") })),
+                )
+                .await?;
+            return Ok(record.state);
+        }
+        Err(e) => return Err(e),
+    }
+    match deliver(state, &req.action_id).await {
+        Ok(Delivery::DeadLettered { .. }) => Ok(ActionState::Failed),
+        Ok(_) => Ok(ActionState::Dispatched),
+        Err(e) => {
+            tracing::warn!("dispatching {}: {}", req.action_id, e);
+            Ok(ActionState::Dispatched)
+        }
+    }
+}
+
+/// Make one attempt at delivering `action_id` if it is due. Deliveries and
+/// dead letters are receipted as `offsec.action.dispatch` and linked to the
+/// action; every outcome is broadcast as an `offsec.action.dispatch` frame.
+pub async fn deliver(state: &AppState, action_id: &str) -> anyhow::Result<Delivery> {
+    let Some((req, previous)) = state.outbox.claim(action_id).await? else {
+        return Ok(Delivery::Skipped);
+    };
+    let attempts = previous + 1;
+    let error = state.outbox.send(&req).await.err();
+    let status = state
+        .outbox
+        .record_attempt(action_id, attempts, error.as_deref())
+        .await?;
+
+    let mut data = json!({
+        "action_id": req.action_id,
+        "action_type": req.action_type,
+        "guardian_id": req.guardian_id,
+        "idempotency_key": idempotency_key(action_id),
+        "attempts": attempts,
+        "max_attempts": state.config.dispatch.max_attempts,
+        "error": error,
+        "ts": Utc::now().to_rfc3339(),
+    });
+    let delivery = match (status, error) {
+        (OutboxStatus::Delivered, _) => Delivery::Delivered { attempts },
+        (OutboxStatus::Dead, error) => Delivery::DeadLettered {
+            attempts,
+            error: error.unwrap_or_default(),
+        },
+        (OutboxStatus::Pending, error) => Delivery::Retrying {
+            attempts,
+            error: error.unwrap_or_default(),
+        },
+    };
+    data["outcome"] = json!(match &delivery {
+        Delivery::Delivered { .. } => "delivered",
+        Delivery::DeadLettered { .. } => "dead_letter",
+        _ => "retry",
+    });
+
+    if let Delivery::Retrying { error, .. } = &delivery {
+        tracing::warn!(
+            "dispatch of {} failed (attempt {}/{}): {}",
+            action_id,
+            attempts,
+            state.config.dispatch.max_attempts,
+            error
+        );
+        state
+            .ws
+            .send_json(&json!({ "type": "offsec.action.dispatch", "data": data }));
+        return Ok(delivery);
+    }
+
+    let receipt = write_receipt(
+        state,
+        "offsec.action.dispatch",
+        req.guardian_id.as_deref(),
+        &req.guardian_tags,
+        &data,
+    )
+    .await
+    .map_err(|e| anyhow::anyhow!("receipting dispatch of {action_id}: {e}"))?;
+    state.actions.link_receipt(action_id, &receipt.id).await?;
+    data["receipt_id"] = json!(receipt.id);
+    state
+        .ws
+        .send_json(&json!({ "type": "offsec.action.dispatch", "data": data }));
+
+    if let Delivery::DeadLettered { error, .. } = &delivery {
+        tracing::error!(
+            "dead-lettered dispatch of {} after {} attempts: {}",
+            action_id,
+            attempts,
+            error
+        );
+        match state
+            .actions
+            .transition(
+                action_id,
+                ActionState::Dispatched,
+                ActionState::Failed,
+                "portal",
+                Some(&receipt.id),
+                Some(
+                    &json!({ "error": format!("dispatch failed: {error}"), "attempts": attempts }),
+                ),
+            )
+            .await
+        {
+            // Guardian reported on it after all.
+            Ok(_) | Err(TransitionError::Invalid { .. }) => {}
+            Err(e) => return Err(anyhow::anyhow!(e.to_string())),
+        }
+    }
+    Ok(delivery)
+}
+
+/// Attempt every due entry. Returns how many were attempted.
+pub async fn process_due(state: &AppState) -> usize {
+    let ids = match state.outbox.due(100).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::warn!("listing due dispatches: {}", e);
+            return 0;
+        }
+    };
+    let mut attempted = 0;
+    for id in ids {
+        match deliver(state, &id).await {
+            Ok(Delivery::Skipped) => {}
+            Ok(_) => attempted += 1,
+            Err(e) => tracing::warn!("dispatching {}: {}", id, e),
+        }
+    }
+    attempted
+}
+
+pub fn spawn_dispatch_worker(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(WORKER_INTERVAL);
+        loop {
+            interval.tick().await;
+            process_due(&state).await;
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OutboxQuery {
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// Outbox entries, e.g. `?status=dead` for dead letters.
+pub async fn list_outbox(
+    State(state): State<AppState>,
+    Query(params): Query<OutboxQuery>,
+) -> Result<Json<Vec<OutboxEntry>>, (StatusCode, Json<ErrorResponse>)> {
+    let status = params
+        .status
+        .as_deref()
+        .map(|s| {
+            OutboxStatus::parse(s).ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "invalid outbox query".to_string(),
+                        details: Some(format!("unknown status {s:?}")),
+                    }),
+                )
+            })
+        })
+        .transpose()?;
+    state
+        .outbox
+        .entries(status, params.limit.unwrap_or(50).clamp(1, 500))
+        .await
+        .map(Json)
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "outbox_unavailable".to_string(),
+                    details: Some(e.to_string()),
+                }),
+            )
+        })
+}
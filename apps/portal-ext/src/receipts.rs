@@ -1,20 +1,36 @@
-use std::{
-    fs,
-    io::Write,
-    path::{Path, PathBuf},
-};
+use std::{fs, io::Write, path::Path, sync::atomic::Ordering};
 
+use anyhow::{anyhow, Context};
 use axum::{
     extract::{Query, State},
-    http::StatusCode,
-    response::IntoResponse,
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
 use blake3;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::SigningKey;
 use serde::{Deserialize, Serialize};
 
-use crate::{merkle::MerklePathElement, AppState};
+use crate::{
+    merkle::{legacy_tree_version, MerkleFrontier, MerklePathElement, TreeVersion},
+    mesh::util::{canonical_json, public_key_b64, sign_payload},
+    models::ErrorResponse,
+    store::{read_json_receipts, PageError, ReceiptFilter, ReceiptOrder},
+    wal::{self, PendingWrite},
+    AppState,
+};
+
+/// Append-only log of Merkle leaves (one hex hash per line, in append order).
+/// Logs created with a versioned tree start with a `# offsec-merkle v<N>`
+/// header; logs without one are v1.
+const LEAVES_FILE: &str = "LEAVES.log";
+const LEAVES_HEADER: &str = "# offsec-merkle v";
+const ROOT_FILE: &str = "ROOT.txt";
+/// Signed tree head for the root in `ROOT.txt`.
+const STH_FILE: &str = "STH.json";
+/// Response header carrying the cursor for the next page of receipts.
+pub const NEXT_CURSOR_HEADER: HeaderName = HeaderName::from_static("x-next-cursor");
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OffsecReceipt {
@@ -33,88 +49,432 @@ pub struct OffsecReceipt {
     pub merkle_root: String,
     #[serde(default)]
     pub merkle_path: Vec<MerklePathElement>,
+    /// Position of `hash` in the Merkle log; the tree had `leaf_index + 1`
+    /// leaves when `merkle_root` was computed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leaf_index: Option<usize>,
+    /// Merkle tree format `merkle_root` and `merkle_path` were built with.
+    #[serde(default = "legacy_tree_version")]
+    pub tree_version: u8,
+    /// The payload `hash` commits to: `hash = BLAKE3(canonical_json(payload))`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<serde_json::Value>,
+    /// Node signature over [`receipt_statement`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Signed statement that the log had `tree_size` leaves with root `root`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SignedTreeHead {
+    pub tree_version: u8,
+    pub tree_size: usize,
+    pub root: String,
+    pub timestamp: String,
+    /// Base64 Ed25519 signature over BLAKE3(canonical_json(head without
+    /// `signature`)).
+    pub signature: String,
+}
+
+/// The fields a receipt signature covers. The payload is covered through
+/// `hash`; the guardian fields and the root the receipt was issued under are
+/// signed as they appear on the receipt.
+pub fn receipt_statement(receipt: &OffsecReceipt) -> serde_json::Value {
+    serde_json::json!({
+        "id": receipt.id,
+        "event_type": receipt.event_type,
+        "hash": receipt.hash,
+        "leaf_index": receipt.leaf_index,
+        "tree_version": receipt.tree_version,
+        "ts": receipt.ts,
+        "guardian_id": receipt.guardian_id,
+        "guardian_tags": receipt.guardian_tags,
+        "agent_id": receipt.agent_id,
+        "merkle_root": receipt.merkle_root,
+    })
+}
+
+pub fn sign_tree_head(
+    key: &SigningKey,
+    version: TreeVersion,
+    tree_size: usize,
+    root: &str,
+) -> anyhow::Result<SignedTreeHead> {
+    let timestamp = Utc::now().to_rfc3339();
+    let statement = serde_json::json!({
+        "tree_version": version.as_u8(),
+        "tree_size": tree_size,
+        "root": root,
+        "timestamp": timestamp,
+    });
+    Ok(SignedTreeHead {
+        tree_version: version.as_u8(),
+        tree_size,
+        root: root.to_string(),
+        timestamp,
+        signature: sign_payload(key, &statement)?,
+    })
 }
 
-pub fn write_receipt(
+pub async fn write_receipt(
     state: &AppState,
     event_type: &str,
     guardian_id: Option<&str>,
     guardian_tags: &[String],
     payload: &serde_json::Value,
 ) -> Result<OffsecReceipt, String> {
-    let serialized = serde_json::to_vec(payload).map_err(|e| e.to_string())?;
+    let serialized = canonical_json(payload).map_err(|e| e.to_string())?;
     let leaf_hash = blake3::hash(&serialized).to_hex().to_string();
 
-    let (merkle_root, merkle_path) = {
+    // Commits are serialised so there is at most one write-ahead record.
+    let _commit = state.commit_lock.lock().await;
+    let data_dir = state.config.data_dir.as_str();
+
+    // The leaf is appended in memory under the frontier lock; the file
+    // writes run without it so proofs and roots are not held up by fsyncs.
+    let (version, receipt) = {
         let mut frontier = state
             .frontier
             .lock()
             .map_err(|_| "frontier lock poisoned".to_string())?;
-        frontier.append_with_path(leaf_hash.clone())
+        let leaf_index = frontier.len();
+        let (merkle_root, merkle_path) = frontier.append_with_path(leaf_hash.clone());
+        let timestamp = Utc::now().to_rfc3339();
+        let receipt = OffsecReceipt {
+            id: format!("offsec-{}", leaf_hash),
+            event_type: event_type.to_string(),
+            guardian_id: guardian_id.map(|g| g.to_string()),
+            guardian_tags: guardian_tags.to_vec(),
+            agent_id: guardian_id.map(|g| g.to_string()),
+            timestamp: timestamp.clone(),
+            ts: timestamp,
+            hash: leaf_hash,
+            merkle_root,
+            merkle_path,
+            leaf_index: Some(leaf_index),
+            tree_version: frontier.version().as_u8(),
+            payload: Some(payload.clone()),
+            signature: None,
+        };
+        (frontier.version(), receipt)
     };
+    let leaf_index = receipt.leaf_index.unwrap_or_default();
 
-    let timestamp = Utc::now().to_rfc3339();
-    let receipt_id = format!("offsec-{}", leaf_hash);
-    let guardian_id = guardian_id.map(|g| g.to_string());
-    let receipt = OffsecReceipt {
-        id: receipt_id.clone(),
-        event_type: event_type.to_string(),
-        guardian_id: guardian_id.clone(),
-        guardian_tags: guardian_tags.to_vec(),
-        agent_id: guardian_id.clone(),
-        timestamp: timestamp.clone(),
-        ts: timestamp.clone(),
-        hash: leaf_hash.clone(),
-        merkle_root: merkle_root.clone(),
-        merkle_path: merkle_path.clone(),
+    let committed = {
+        let data_dir = state.config.data_dir.clone();
+        let signer = state.signer.clone();
+        tokio::task::spawn_blocking(move || {
+            commit_files(&data_dir, signer.as_deref(), version, receipt)
+        })
+        .await
+        .unwrap_or_else(|e| Err(format!("receipt commit task failed: {e}")))
+    };
+
+    let stored = match committed {
+        Ok(receipt) => match state.store.insert(&receipt).await {
+            Ok(()) => wal::clear(data_dir)
+                .map(|_| receipt)
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        },
+        Err(e) => Err(e),
     };
+    match &stored {
+        Ok(_) => state.committed.store(leaf_index + 1, Ordering::Release),
+        Err(_) => {
+            if let Ok(mut frontier) = state.frontier.lock() {
+                frontier.truncate(leaf_index);
+            }
+            abort_commit(state, leaf_index).await;
+        }
+    }
+    stored
+}
 
-    let receipts_dir = Path::new(&state.config.data_dir).join("receipts/offsec");
-    fs::create_dir_all(&receipts_dir).map_err(|e| e.to_string())?;
-    let path = receipts_dir.join(format!("{}.json", &receipt_id));
-    let content = serde_json::to_vec_pretty(&receipt).map_err(|e| e.to_string())?;
-    fs::write(&path, content).map_err(|e| e.to_string())?;
+/// Size of the committed tree: every leaf below it is in `LEAVES.log` and
+/// `STH.json` is signed at it. The frontier can hold one more leaf while a
+/// commit is in flight, and that leaf is dropped again if the commit fails.
+pub fn committed_size(state: &AppState) -> usize {
+    state.committed.load(Ordering::Acquire)
+}
 
-    let root_path = Path::new(&state.config.data_dir).join("ROOT.txt");
-    let mut root_file = fs::File::create(root_path).map_err(|e| e.to_string())?;
-    root_file
-        .write_all(format!("{}\n", merkle_root).as_bytes())
+/// Sign the receipt and durably write the leaf log, root and tree head
+/// behind a write-ahead record. Blocking; called with the commit lock held,
+/// after the receipt's leaf was appended to the frontier.
+fn commit_files(
+    data_dir: &str,
+    signer: Option<&SigningKey>,
+    version: TreeVersion,
+    mut receipt: OffsecReceipt,
+) -> Result<OffsecReceipt, String> {
+    let leaf_index = receipt.leaf_index.unwrap_or_default();
+    let sth = match signer {
+        Some(key) => {
+            receipt.signature =
+                Some(sign_payload(key, &receipt_statement(&receipt)).map_err(|e| e.to_string())?);
+            Some(
+                sign_tree_head(key, version, leaf_index + 1, &receipt.merkle_root)
+                    .map_err(|e| e.to_string())?,
+            )
+        }
+        None => None,
+    };
+
+    wal::begin(
+        data_dir,
+        &PendingWrite {
+            leaf_index,
+            leaf: receipt.hash.clone(),
+            root: receipt.merkle_root.clone(),
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    let data_dir = Path::new(data_dir);
+    let mut leaves_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir.join(LEAVES_FILE))
         .map_err(|e| e.to_string())?;
+    leaves_file
+        .write_all(format!("{}\n", receipt.hash).as_bytes())
+        .and_then(|_| leaves_file.sync_data())
+        .map_err(|e| e.to_string())?;
+
+    wal::atomic_write(
+        &data_dir.join(ROOT_FILE),
+        format!("{}\n", receipt.merkle_root).as_bytes(),
+    )
+    .map_err(|e| e.to_string())?;
+
+    if let Some(sth) = sth {
+        let content = serde_json::to_vec_pretty(&sth).map_err(|e| e.to_string())?;
+        wal::atomic_write(&data_dir.join(STH_FILE), &content).map_err(|e| e.to_string())?;
+    }
 
     Ok(receipt)
 }
 
-pub fn read_receipts(data_dir: &str, limit: usize) -> Vec<OffsecReceipt> {
-    read_receipts_filtered(data_dir, limit, None)
+/// Undo a failed commit of `leaf_index`. If this fails too, the write-ahead
+/// record stays and startup finishes the rollback.
+async fn abort_commit(state: &AppState, leaf_index: usize) {
+    let data_dir = &state.config.data_dir;
+    let result = async {
+        roll_back_pending(data_dir)?;
+        state.store.delete_from_leaf(leaf_index).await?;
+        wal::clear(data_dir)?;
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::error!(
+            "failed to roll back receipt commit at leaf {}; will retry at startup: {:#}",
+            leaf_index,
+            e
+        );
+    }
 }
 
-pub fn read_receipts_filtered(
-    data_dir: &str,
-    limit: usize,
-    guardian_id: Option<&str>,
-) -> Vec<OffsecReceipt> {
-    let dir = PathBuf::from(data_dir).join("receipts/offsec");
-    let mut receipts = Vec::new();
-
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            if let Ok(content) = fs::read_to_string(entry.path()) {
-                if let Ok(r) = serde_json::from_str::<OffsecReceipt>(&content) {
-                    receipts.push(r);
-                }
+/// Roll the leaf log, `ROOT.txt` and `STH.json` back to the tree before an
+/// interrupted commit. Returns the restored tree size, or `None` when no
+/// commit was pending. The caller removes the receipt rows from that leaf
+/// index on and then clears the write-ahead record.
+pub fn roll_back_pending(data_dir: &str) -> anyhow::Result<Option<usize>> {
+    let Some(pending) = wal::read_pending(data_dir)? else {
+        return Ok(None);
+    };
+    let base = Path::new(data_dir);
+    let leaves_path = base.join(LEAVES_FILE);
+
+    let (version, leaves) = if leaves_path.exists() {
+        read_leaves_log(&leaves_path, Some(pending.leaf_index))?
+    } else {
+        (TreeVersion::default(), Vec::new())
+    };
+    if leaves.len() < pending.leaf_index {
+        return Err(anyhow!(
+            "{} has {} leaves but the pending commit is for leaf {}; refusing to start",
+            leaves_path.display(),
+            leaves.len(),
+            pending.leaf_index
+        ));
+    }
+
+    let mut content = format!("{}{}\n", LEAVES_HEADER, version.as_u8());
+    for leaf in &leaves {
+        content.push_str(leaf);
+        content.push('\n');
+    }
+    wal::atomic_write(&leaves_path, content.as_bytes())
+        .with_context(|| format!("rewriting {}", leaves_path.display()))?;
+
+    let root_path = base.join(ROOT_FILE);
+    if leaves.is_empty() {
+        wal::remove(&root_path)?;
+    } else {
+        let root = MerkleFrontier::from_leaves(version, leaves).current_root();
+        wal::atomic_write(&root_path, format!("{}\n", root).as_bytes())
+            .with_context(|| format!("rewriting {}", root_path.display()))?;
+    }
+
+    let sth_path = base.join(STH_FILE);
+    let sth_ahead = fs::read(&sth_path)
+        .ok()
+        .map(|b| {
+            serde_json::from_slice::<SignedTreeHead>(&b)
+                .map_or(true, |sth| sth.tree_size > pending.leaf_index)
+        })
+        .unwrap_or(false);
+    if sth_ahead {
+        wal::remove(&sth_path)?;
+    }
+
+    Ok(Some(pending.leaf_index))
+}
+
+/// Parse `LEAVES.log`. With `limit`, stops after that many leaves so a torn
+/// trailing line from an interrupted commit is ignored.
+fn read_leaves_log(
+    path: &Path,
+    limit: Option<usize>,
+) -> anyhow::Result<(TreeVersion, Vec<String>)> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let mut version = TreeVersion::V1;
+    let mut leaves = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if limit.is_some_and(|n| leaves.len() >= n) {
+            break;
+        }
+        let leaf = line.trim();
+        if leaf.is_empty() {
+            continue;
+        }
+        if let Some(v) = leaf.strip_prefix(LEAVES_HEADER) {
+            version = v
+                .parse()
+                .ok()
+                .and_then(TreeVersion::from_u8)
+                .ok_or_else(|| {
+                    anyhow!("{} has unsupported tree version {:?}", path.display(), v)
+                })?;
+            continue;
+        }
+        if leaf.len() != 64 || !leaf.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!(
+                "{} line {} is not a 32-byte hex leaf",
+                path.display(),
+                i + 1
+            ));
+        }
+        leaves.push(leaf.to_string());
+    }
+    Ok((version, leaves))
+}
+
+/// A data dir that predated `LEAVES.log` and whose `ROOT.txt` did not
+/// match the tree rebuilt by [`load_frontier`].
+#[derive(Debug, Clone)]
+pub struct LegacyMigration {
+    pub leaves: usize,
+    pub root: String,
+    /// The replaced `ROOT.txt`.
+    pub previous_root: String,
+}
+
+/// Rebuild the Merkle frontier for `data_dir` at startup.
+///
+/// Leaves come from `LEAVES.log` when present, and the rebuilt root must
+/// match `ROOT.txt`. Data dirs written before the leaf log existed are
+/// rebuilt as a v1 tree from `receipts/offsec/` in timestamp order (ties by
+/// hash) and the log is written out so later boots use it. Their
+/// `ROOT.txt` only covered receipts since the last restart, so there it is
+/// advisory: a mismatch is logged and returned in the [`LegacyMigration`]
+/// for the caller to receipt. A fresh data dir starts a v2 log.
+pub fn load_frontier(data_dir: &str) -> anyhow::Result<(MerkleFrontier, Option<LegacyMigration>)> {
+    let base = Path::new(data_dir);
+    let leaves_path = base.join(LEAVES_FILE);
+    let root_path = base.join(ROOT_FILE);
+    let stored_root = || -> anyhow::Result<Option<String>> {
+        if !root_path.exists() {
+            return Ok(None);
+        }
+        let stored = fs::read_to_string(&root_path)
+            .with_context(|| format!("reading {}", root_path.display()))?;
+        Ok(Some(stored.trim().to_string()))
+    };
+
+    if leaves_path.exists() {
+        let (version, leaves) = read_leaves_log(&leaves_path, None)?;
+        let frontier = MerkleFrontier::from_leaves(version, leaves);
+        if let Some(stored) = stored_root()? {
+            let rebuilt = frontier.current_root();
+            if stored != rebuilt {
+                return Err(anyhow!(
+                    "rebuilt merkle root {} ({} leaves) does not match {} ({}); refusing to start",
+                    rebuilt,
+                    frontier.len(),
+                    root_path.display(),
+                    stored
+                ));
             }
         }
+        return Ok((frontier, None));
     }
 
-    if let Some(gid) = guardian_id {
-        receipts.retain(|r| {
-            r.guardian_id.as_deref() == Some(gid) || r.agent_id.as_deref() == Some(gid)
+    let mut receipts = read_json_receipts(data_dir);
+    receipts.sort_by(|a, b| (&a.timestamp, &a.hash).cmp(&(&b.timestamp, &b.hash)));
+    let leaves: Vec<String> = receipts.into_iter().map(|r| r.hash).collect();
+    let version = if leaves.is_empty() {
+        TreeVersion::V2
+    } else {
+        TreeVersion::V1
+    };
+    let frontier = MerkleFrontier::from_leaves(version, leaves.clone());
+
+    fs::create_dir_all(base).with_context(|| format!("creating {}", base.display()))?;
+    let mut migration = None;
+    if !leaves.is_empty() {
+        let root = frontier.current_root();
+        let previous_root = stored_root()?.filter(|stored| *stored != root);
+        if let Some(previous) = &previous_root {
+            tracing::warn!(
+                "{} ({}) does not match the root rebuilt from {} existing receipts ({}); \
+                 it predates {} and is replaced",
+                root_path.display(),
+                previous,
+                leaves.len(),
+                root,
+                LEAVES_FILE
+            );
+        }
+        // ROOT.txt first: if the log write is interrupted, the next boot
+        // migrates again and finds a matching root.
+        wal::atomic_write(&root_path, format!("{}\n", root).as_bytes())
+            .with_context(|| format!("writing {}", root_path.display()))?;
+        migration = previous_root.map(|previous_root| LegacyMigration {
+            leaves: leaves.len(),
+            root,
+            previous_root,
         });
     }
 
-    receipts.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-    receipts.truncate(limit);
-    receipts
+    let mut content = format!("{}{}\n", LEAVES_HEADER, version.as_u8());
+    for leaf in &leaves {
+        content.push_str(leaf);
+        content.push('\n');
+    }
+    wal::atomic_write(&leaves_path, content.as_bytes())
+        .with_context(|| format!("writing {}", leaves_path.display()))?;
+    if !leaves.is_empty() {
+        tracing::info!(
+            "rebuilt {} from {} existing receipts",
+            LEAVES_FILE,
+            leaves.len()
+        );
+    }
+
+    Ok((frontier, migration))
 }
 
 #[derive(Debug, Deserialize)]
@@ -123,23 +483,145 @@ pub struct ReceiptQuery {
     pub guardian_id: Option<String>,
     #[serde(default)]
     pub limit: Option<usize>,
+    /// `next` cursor from the previous page (`x-next-cursor` header).
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Exact event type, or a prefix ending in `*` (e.g. `offsec.action.*`).
+    #[serde(default)]
+    pub event_type: Option<String>,
+    /// Inclusive RFC 3339 lower bound on the receipt timestamp.
+    #[serde(default)]
+    pub since: Option<String>,
+    /// Exclusive RFC 3339 upper bound on the receipt timestamp.
+    #[serde(default)]
+    pub until: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub severity: Option<String>,
+    /// `time` (newest first, default) or `leaf` (Merkle log order).
+    #[serde(default)]
+    pub order: ReceiptOrder,
 }
 
+/// Receipts are stored with UTC RFC 3339 timestamps; normalise bounds so
+/// they compare as text.
+fn utc_bound(name: &str, value: Option<&str>) -> Result<Option<String>, String> {
+    value
+        .map(|v| {
+            DateTime::parse_from_rfc3339(v)
+                .map(|t| t.with_timezone(&Utc).to_rfc3339())
+                .map_err(|e| format!("invalid {name}: {e}"))
+        })
+        .transpose()
+}
+
+/// Receipts as a JSON array; the cursor for the next page, if any, is in
+/// the `x-next-cursor` header.
 pub async fn list_receipts(
     State(state): State<AppState>,
     Query(params): Query<ReceiptQuery>,
-) -> impl IntoResponse {
+) -> Response {
+    let bad_request = |details: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "invalid receipt query".to_string(),
+                details: Some(details),
+            }),
+        )
+            .into_response()
+    };
+    let (since, until) = match (
+        utc_bound("since", params.since.as_deref()),
+        utc_bound("until", params.until.as_deref()),
+    ) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(e), _) | (_, Err(e)) => return bad_request(e),
+    };
+
+    let filter = ReceiptFilter {
+        guardian_id: params.guardian_id,
+        event_type: params.event_type,
+        since,
+        until,
+        tag: params.tag,
+        severity: params.severity,
+    };
     let limit = params.limit.unwrap_or(50);
-    let receipts =
-        read_receipts_filtered(&state.config.data_dir, limit, params.guardian_id.as_deref());
-    (StatusCode::OK, Json(receipts))
-}
-
-pub async fn current_root(State(state): State<AppState>) -> impl IntoResponse {
-    let root = state
-        .frontier
-        .lock()
-        .map(|f| f.current_root())
-        .unwrap_or_else(|_| "0".repeat(64));
-    (StatusCode::OK, Json(serde_json::json!({ "root": root })))
+    let page = match state
+        .store
+        .page(&filter, params.order, limit, params.cursor.as_deref())
+        .await
+    {
+        Ok(page) => page,
+        Err(PageError::Invalid(reason)) => return bad_request(reason),
+        Err(PageError::Store(e)) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "receipt_store_unavailable".to_string(),
+                    details: Some(e.to_string()),
+                }),
+            )
+                .into_response()
+        }
+    };
+
+    let mut response = (StatusCode::OK, Json(page.receipts)).into_response();
+    if let Some(next) = page.next.and_then(|n| HeaderValue::from_str(&n).ok()) {
+        response.headers_mut().insert(NEXT_CURSOR_HEADER, next);
+    }
+    response
+}
+
+/// Current root, plus the signed tree head written with it and the node
+/// public key when a signing key is configured.
+pub async fn current_root(State(state): State<AppState>) -> Response {
+    let server_error = |error: &str, details: Option<String>| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: error.to_string(),
+                details,
+            }),
+        )
+            .into_response()
+    };
+
+    if let Some(key) = state.signer.as_deref() {
+        let path = Path::new(&state.config.data_dir).join(STH_FILE);
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let sth: SignedTreeHead = match serde_json::from_slice(&bytes) {
+                    Ok(sth) => sth,
+                    Err(e) => return server_error("invalid signed tree head", Some(e.to_string())),
+                };
+                return Json(serde_json::json!({
+                    "root": sth.root,
+                    "signed_tree_head": sth,
+                    "pubkey": public_key_b64(key),
+                }))
+                .into_response();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return server_error("failed to read signed tree head", Some(e.to_string())),
+        }
+    }
+
+    // No tree head yet: the log is empty or was written without a key.
+    let root = match state.frontier.lock() {
+        Ok(frontier) => frontier
+            .root_at(committed_size(&state))
+            .unwrap_or_else(|| "0".repeat(64)),
+        Err(_) => return server_error("frontier lock poisoned", None),
+    };
+    match state.signer.as_deref() {
+        Some(key) => Json(serde_json::json!({
+            "root": root,
+            "pubkey": public_key_b64(key),
+        }))
+        .into_response(),
+        None => Json(serde_json::json!({ "root": root })).into_response(),
+    }
 }
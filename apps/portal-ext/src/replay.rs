@@ -0,0 +1,273 @@
+use axum::{http::StatusCode, Json};
+use chrono::Utc;
+use serde_json::{json, Value};
+use sqlx::{sqlite::SqlitePool, Row};
+
+use crate::{
+    auth::Principal,
+    capabilities::{denial_payload, error_response, CapabilityError},
+    models::ErrorResponse,
+    receipts::write_receipt,
+    AppState,
+};
+
+/// Actions that may be authorised only once per capability token.
+pub const DESTRUCTIVE_ACTIONS: &[&str] = &["block_ip", "quarantine", "isolate_host"];
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS used_tokens (
+        token_key TEXT PRIMARY KEY,
+        sub TEXT NOT NULL,
+        token_id TEXT NOT NULL,
+        action TEXT NOT NULL,
+        used_at TEXT NOT NULL,
+        exp INTEGER NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS idx_used_tokens_exp ON used_tokens (exp)",
+    "CREATE TABLE IF NOT EXISTS token_uses (
+        token_key TEXT NOT NULL,
+        action TEXT NOT NULL,
+        uses INTEGER NOT NULL,
+        exp INTEGER NOT NULL,
+        PRIMARY KEY (token_key, action)
+    )",
+];
+
+pub fn is_destructive(action: &str) -> bool {
+    DESTRUCTIVE_ACTIONS.contains(&action)
+}
+
+/// First use of a token that was presented again.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub token_id: String,
+    pub first_action: String,
+    pub first_used_at: String,
+}
+
+#[derive(Debug)]
+pub enum ConsumeError {
+    /// The token has no `jti` or `nonce`, so it cannot be single-use.
+    MissingId,
+    Replayed(Replay),
+    Store(anyhow::Error),
+}
+
+/// Tokens already spent on a destructive action, and per-action use counts
+/// for scoped tokens, kept in `receipts.db` until they expire.
+#[derive(Clone)]
+pub struct ReplayCache {
+    pool: SqlitePool,
+}
+
+impl ReplayCache {
+    pub async fn open(pool: SqlitePool) -> anyhow::Result<Self> {
+        for stmt in SCHEMA {
+            sqlx::query(stmt).execute(&pool).await?;
+        }
+        let cache = Self { pool };
+        let pruned = cache.prune().await?;
+        if pruned > 0 {
+            tracing::info!("pruned {} expired single-use tokens", pruned);
+        }
+        Ok(cache)
+    }
+
+    /// Forget tokens past their `exp`; they would be rejected anyway.
+    pub async fn prune(&self) -> anyhow::Result<u64> {
+        let now = Utc::now().timestamp();
+        let mut pruned = 0;
+        for table in ["used_tokens", "token_uses"] {
+            pruned += sqlx::query(&format!("DELETE FROM {table} WHERE exp <= ?"))
+                .bind(now)
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+        }
+        Ok(pruned)
+    }
+
+    /// Count one use of the token for `action`, up to `max`. Returns the
+    /// new count, or `None` once `max` uses have been counted.
+    pub async fn count_use(
+        &self,
+        principal: &Principal,
+        action: &str,
+        max: u32,
+    ) -> anyhow::Result<Option<u32>> {
+        let id = principal
+            .token_id
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("token has no jti or nonce to count uses against"))?;
+        self.prune().await?;
+        let row = sqlx::query(
+            "INSERT INTO token_uses (token_key, action, uses, exp) VALUES (?, ?, 1, ?)
+             ON CONFLICT (token_key, action) DO UPDATE SET uses = uses + 1
+             WHERE uses < ?
+             RETURNING uses",
+        )
+        .bind(format!("{}:{}", principal.sub, id))
+        .bind(action)
+        .bind(principal.exp)
+        .bind(max as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row
+            .map(|row| row.get::<i64, _>("uses") as u32)
+            .filter(|uses| *uses <= max))
+    }
+
+    /// Take back one use counted by [`count_use`](Self::count_use).
+    pub async fn uncount_use(&self, principal: &Principal, action: &str) -> anyhow::Result<()> {
+        let Some(id) = principal.token_id.as_deref() else {
+            return Ok(());
+        };
+        sqlx::query(
+            "UPDATE token_uses SET uses = uses - 1
+             WHERE token_key = ? AND action = ? AND uses > 0",
+        )
+        .bind(format!("{}:{}", principal.sub, id))
+        .bind(action)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Spend the principal's token on `action`. Fails if the token was spent before.
+    pub async fn consume(&self, principal: &Principal, action: &str) -> Result<(), ConsumeError> {
+        let id = principal
+            .token_id
+            .as_deref()
+            .ok_or(ConsumeError::MissingId)?;
+        let key = format!("{}:{}", principal.sub, id);
+        self.prune().await.map_err(ConsumeError::Store)?;
+
+        let inserted = sqlx::query(
+            "INSERT INTO used_tokens (token_key, sub, token_id, action, used_at, exp)
+             VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT (token_key) DO NOTHING",
+        )
+        .bind(&key)
+        .bind(&principal.sub)
+        .bind(id)
+        .bind(action)
+        .bind(Utc::now().to_rfc3339())
+        .bind(principal.exp)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| ConsumeError::Store(e.into()))?;
+        if inserted.rows_affected() == 1 {
+            return Ok(());
+        }
+
+        let row = sqlx::query("SELECT action, used_at FROM used_tokens WHERE token_key = ?")
+            .bind(&key)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| ConsumeError::Store(e.into()))?;
+        Err(ConsumeError::Replayed(Replay {
+            token_id: id.to_string(),
+            first_action: row.get("action"),
+            first_used_at: row.get("used_at"),
+        }))
+    }
+
+    /// Unspend the principal's token, if it was spent on `action`.
+    pub async fn release(&self, principal: &Principal, action: &str) -> anyhow::Result<()> {
+        let Some(id) = principal.token_id.as_deref() else {
+            return Ok(());
+        };
+        sqlx::query("DELETE FROM used_tokens WHERE token_key = ? AND action = ?")
+            .bind(format!("{}:{}", principal.sub, id))
+            .bind(action)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn len(&self) -> anyhow::Result<usize> {
+        let row = sqlx::query("SELECT COUNT(*) AS n FROM used_tokens")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get::<i64, _>("n") as usize)
+    }
+}
+
+/// `capability_denied` frame for a replayed token, told apart from other
+/// denials by `event_type: "capability_replay"`.
+pub fn replay_payload(guardian: &str, action: &str, replay: &Replay) -> Value {
+    let mut frame = denial_payload(Some(guardian), action, "token replayed");
+    let data = &mut frame["data"];
+    data["event_type"] = json!("capability_replay");
+    data["description"] = json!(format!(
+        "Replayed capability token for action '{}' (first used for '{}' at {})",
+        action, replay.first_action, replay.first_used_at
+    ));
+    data["metadata"]["token_id"] = json!(replay.token_id);
+    data["metadata"]["first_action"] = json!(replay.first_action);
+    data["metadata"]["first_used_at"] = json!(replay.first_used_at);
+    frame
+}
+
+/// Spend the token on `action` if it is destructive. A replay is broadcast
+/// as a `capability_replay` denial and receipted as
+/// `offsec.capability.replay` before the request is refused.
+pub async fn enforce_single_use(
+    state: &AppState,
+    principal: &Principal,
+    action: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if !is_destructive(action) {
+        return Ok(());
+    }
+    match state.replay.consume(principal, action).await {
+        Ok(()) => Ok(()),
+        Err(ConsumeError::MissingId) => {
+            state.ws.send_json(&denial_payload(
+                Some(&principal.sub),
+                action,
+                "single-use token required",
+            ));
+            let (code, err) = error_response(CapabilityError::Invalid(format!(
+                "'{action}' needs a single-use token with a jti or nonce claim"
+            )));
+            Err((code, Json(err)))
+        }
+        Err(ConsumeError::Replayed(replay)) => {
+            tracing::warn!(
+                "replayed capability token {} from {} for {}",
+                replay.token_id,
+                principal.sub,
+                action
+            );
+            let frame = replay_payload(&principal.sub, action, &replay);
+            state.ws.send_json(&frame);
+            match write_receipt(
+                state,
+                "offsec.capability.replay",
+                Some(&principal.sub),
+                &[],
+                &frame,
+            )
+            .await
+            {
+                Ok(receipt) => state
+                    .ws
+                    .send_json(&json!({ "type": "receipt", "data": receipt })),
+                Err(e) => tracing::error!("failed to receipt token replay: {}", e),
+            }
+            let (code, err) = error_response(CapabilityError::Replayed(replay.token_id));
+            Err((code, Json(err)))
+        }
+        Err(ConsumeError::Store(e)) => {
+            tracing::error!("single-use token check failed: {:#}", e);
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse {
+                    error: "replay_cache_unavailable".to_string(),
+                    details: Some(e.to_string()),
+                }),
+            ))
+        }
+    }
+}
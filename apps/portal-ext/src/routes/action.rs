@@ -1,59 +1,89 @@
-use axum::{
-    extract::State,
-    http::{HeaderMap, StatusCode},
-    Json,
-};
+use axum::{extract::State, http::StatusCode, Json};
 use serde_json::json;
 
 use crate::{
-    capabilities,
-    capabilities::{denial_payload, ensure_action, error_response, verify_token, CapabilityError},
+    actions::{transition_response, ActionState, NewAction},
+    auth::Caller,
+    durability::{commit_event, receipt_status},
     models::{ActionRequest, ActionUpdate, ErrorResponse},
-    receipts::write_receipt,
+    scope::ScopeTarget,
     AppState,
 };
 
+fn store_failed(e: anyhow::Error) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: "action_store_failed".to_string(),
+            details: Some(e.to_string()),
+        }),
+    )
+}
+
+/// An action a Guardian decided on and carries out itself: recorded as
+/// requested, approved by its capability, and dispatched to it at once.
+/// Actions under the approval policy are refused here; they go through
+/// `/offsec/action/apply` and wait for sign-off.
 pub async fn submit_action(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Json(action): Json<ActionRequest>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    let Some(token) = capabilities::extract_token(&headers) else {
-        let denial = denial_payload(None, &action.action, "missing token");
-        state.ws.send_json(&denial);
-        let (code, err) = error_response(CapabilityError::Missing);
-        return Err((code, Json(err)));
-    };
-
-    let claims = match verify_token(&token, &state.config) {
-        Ok(c) => c,
-        Err(err) => {
-            let denial = denial_payload(None, &action.action, "invalid token");
-            state.ws.send_json(&denial);
-            let (code, err) = error_response(err);
-            return Err((code, Json(err)));
-        }
-    };
-
-    if let Err(err) = ensure_action(&claims, &action.action) {
-        let denial = denial_payload(Some(&claims.sub), &action.action, "action not allowed");
-        state.ws.send_json(&denial);
-        let (code, err) = error_response(err);
-        return Err((code, Json(err)));
+    let principal = caller.permit(&state, &action.action)?;
+    if state.config.approvals.required_for(&action.action) > 0 {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: "approval_required".to_string(),
+                details: Some(format!(
+                    "{} actions need approval; request them through /offsec/action/apply",
+                    action.action
+                )),
+            }),
+        ));
     }
 
     let mut action = action;
     if action.guardian_id.is_none() {
-        action.guardian_id = Some(claims.sub.clone());
+        action.guardian_id = Some(principal.sub.clone());
     }
+
+    let mut target = ScopeTarget::parse(&action.target, &principal.tags);
+    target.claimed_tags = action.guardian_tags.clone();
+    principal.enforce(&state, &action.action, &target).await?;
     if action.guardian_tags.is_empty() {
-        if let Some(tags_val) = claims.extra.get("tags").and_then(|v| v.as_array()) {
-            let tags: Vec<String> = tags_val
-                .iter()
-                .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                .collect();
-            action.guardian_tags = tags;
-        }
+        action.guardian_tags = principal.tags.clone();
+    }
+
+    let duplicate = || {
+        (
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: "duplicate_action".to_string(),
+                details: Some(format!("action {:?} already exists", action.id)),
+            }),
+        )
+    };
+    // Claim the id before anything is receipted or broadcast, so a
+    // duplicate is refused without leaving a receipt behind or spending
+    // the caller's token.
+    let new = NewAction {
+        action_id: action.id.clone(),
+        action_type: action.action.clone(),
+        target: json!(action.target),
+        guardian_id: action.guardian_id.clone(),
+        requested_by: Some(principal.sub.clone()),
+        approval: None,
+    };
+    let claimed = state
+        .actions
+        .create(&new, &principal.sub)
+        .await
+        .map_err(store_failed)
+        .and_then(|record| record.ok_or_else(duplicate));
+    if let Err(rejection) = claimed {
+        principal.release(&state, &action.action).await;
+        return Err(rejection);
     }
 
     let update = ActionUpdate {
@@ -70,24 +100,50 @@ pub async fn submit_action(
         "type": "action_update",
         "data": update
     });
-    state.ws.send_json(&payload);
 
-    match write_receipt(
+    let receipt = match commit_event(
         &state,
         &format!("offsec.action.{}", action.action),
         action.guardian_id.as_deref(),
         &action.guardian_tags,
         &payload,
-    ) {
-        Ok(receipt) => {
+        Some(&action.id),
+    )
+    .await
+    {
+        Ok(receipt) => receipt,
+        Err(rejection) => {
             state
-                .ws
-                .send_json(&serde_json::json!({ "type": "receipt", "data": receipt }));
-        }
-        Err(err) => {
-            tracing::warn!("Failed to write receipt: {}", err);
+                .actions
+                .abandon(&action.id, &principal.sub, "receipt write failed")
+                .await;
+            principal.release(&state, &action.action).await;
+            return Err(rejection);
         }
+    };
+    if let Some(receipt) = &receipt {
+        state
+            .actions
+            .set_request_receipt(&action.id, &receipt.id)
+            .await
+            .map_err(store_failed)?;
+    }
+    for (from, to, details) in [
+        (
+            ActionState::Requested,
+            ActionState::Approved,
+            Some(json!({ "approved_by": "capability" })),
+        ),
+        (ActionState::Approved, ActionState::Dispatched, None),
+    ] {
+        state
+            .actions
+            .transition(&action.id, from, to, &principal.sub, None, details.as_ref())
+            .await
+            .map_err(transition_response)?;
     }
 
-    Ok(Json(json!({"status": "accepted"})))
+    let mut response = receipt_status("accepted", receipt.as_ref());
+    response["state"] = json!(ActionState::Dispatched);
+    Ok(Json(response))
 }
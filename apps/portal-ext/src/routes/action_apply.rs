@@ -1,12 +1,15 @@
-use axum::{extract::State, http::HeaderMap, Json};
-use reqwest::Client;
+use axum::{extract::State, Json};
+use chrono::{Duration, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::{
-    capabilities::{ensure_action, extract_token, verify_token},
+    actions::{transition_response, ActionState, NewAction, PendingApproval},
+    auth::Caller,
     models::ErrorResponse,
+    outbox,
     receipts::write_receipt,
+    scope::ScopeTarget,
     AppState,
 };
 
@@ -14,6 +17,8 @@ use crate::{
 pub struct ActionTarget {
     #[serde(default)]
     pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -35,48 +40,95 @@ pub struct ActionRequestPayload {
 #[derive(Debug, Serialize)]
 pub struct ActionApplyResponse {
     pub status: String,
+    pub state: ActionState,
+}
+
+fn reject(
+    code: axum::http::StatusCode,
+    error: &str,
+    details: String,
+) -> (axum::http::StatusCode, Json<ErrorResponse>) {
+    (
+        code,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            details: Some(details),
+        }),
+    )
 }
 
 pub async fn apply(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Json(req): Json<ActionRequestPayload>,
 ) -> Result<Json<ActionApplyResponse>, (axum::http::StatusCode, Json<ErrorResponse>)> {
-    // Capability check
-    let Some(token) = extract_token(&headers) else {
-        let (code, err) =
-            crate::capabilities::error_response(crate::capabilities::CapabilityError::Missing);
-        return Err((code, Json(err)));
-    };
-
-    let claims = match verify_token(&token, &state.config) {
-        Ok(c) => c,
-        Err(err) => {
-            let (code, err) = crate::capabilities::error_response(err);
-            return Err((code, Json(err)));
-        }
-    };
-
-    if let Err(err) = ensure_action(&claims, &req.action_type) {
-        let (code, err) = crate::capabilities::error_response(err);
-        return Err((code, Json(err)));
-    }
+    let principal = caller.permit(&state, &req.action_type)?;
 
     let mut req = req;
     let guardian_id = req
         .guardian_id
         .clone()
-        .unwrap_or_else(|| claims.sub.clone());
+        .unwrap_or_else(|| principal.sub.clone());
+    req.guardian_id = Some(guardian_id.clone());
+
+    let target = ScopeTarget {
+        ip: req
+            .target
+            .ip
+            .as_deref()
+            .and_then(|ip| ip.trim().parse().ok()),
+        host: req.target.host.clone(),
+        guardian_tags: principal.tags.clone(),
+        claimed_tags: req.guardian_tags.clone(),
+    };
+    principal.enforce(&state, &req.action_type, &target).await?;
     if req.guardian_tags.is_empty() {
-        if let Some(tags_val) = claims.extra.get("tags").and_then(|v| v.as_array()) {
-            let tags: Vec<String> = tags_val
-                .iter()
-                .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                .collect();
-            req.guardian_tags = tags;
-        }
+        req.guardian_tags = principal.tags.clone();
+    }
+
+    let duplicate = || {
+        reject(
+            axum::http::StatusCode::CONFLICT,
+            "duplicate_action",
+            format!("action {:?} already exists", req.action_id),
+        )
+    };
+    let store_failed = |e: anyhow::Error| {
+        reject(
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            "action_store_failed",
+            e.to_string(),
+        )
+    };
+    // Claim the id before anything is receipted or broadcast, so a
+    // duplicate is refused without leaving a receipt behind or spending
+    // the caller's token. Actions under
+    // the approval policy wait for sign-off; the rest are approved by the
+    // capability that permitted them.
+    let required = state.config.approvals.required_for(&req.action_type);
+    let pending = (required > 0).then(|| PendingApproval {
+        required,
+        expires_at: (Utc::now() + Duration::seconds(state.config.approvals.timeout_seconds as i64))
+            .to_rfc3339(),
+    });
+    let new = NewAction {
+        action_id: req.action_id.clone(),
+        action_type: req.action_type.clone(),
+        target: json!(req.target),
+        guardian_id: req.guardian_id.clone(),
+        requested_by: req.requested_by.clone(),
+        approval: pending.clone(),
+    };
+    let claimed = state
+        .actions
+        .create(&new, &principal.sub)
+        .await
+        .map_err(store_failed)
+        .and_then(|record| record.ok_or_else(duplicate));
+    if let Err(rejection) = claimed {
+        principal.release(&state, &req.action_type).await;
+        return Err(rejection);
     }
-    req.guardian_id = Some(guardian_id.clone());
 
     // Receipt for action request
     let payload = json!({
@@ -96,15 +148,48 @@ pub async fn apply(
         &req.guardian_tags,
         &payload,
     )
-    .map_err(|e| {
+    .await;
+    let receipt = match receipt {
+        Ok(receipt) => receipt,
+        Err(e) => {
+            state
+                .actions
+                .abandon(&req.action_id, &principal.sub, "receipt write failed")
+                .await;
+            principal.release(&state, &req.action_type).await;
+            return Err(reject(
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "receipt_write_failed",
+                e,
+            ));
+        }
+    };
+    state
+        .actions
+        .set_request_receipt(&req.action_id, &receipt.id)
+        .await
+        .map_err(store_failed)?;
+
+    let (action_state, approval) = if let Some(pending) = pending {
         (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "receipt_write_failed".to_string(),
-                details: Some(e),
-            }),
+            ActionState::Requested,
+            json!({ "required": pending.required, "expires_at": pending.expires_at }),
         )
-    })?;
+    } else {
+        state
+            .actions
+            .transition(
+                &req.action_id,
+                ActionState::Requested,
+                ActionState::Approved,
+                &principal.sub,
+                None,
+                Some(&json!({ "approved_by": "capability" })),
+            )
+            .await
+            .map_err(transition_response)?;
+        (ActionState::Approved, serde_json::Value::Null)
+    };
 
     // Broadcast WS
     state.ws.send_json(&json!({
@@ -119,35 +204,22 @@ pub async fn apply(
             "guardian_id": req.guardian_id,
             "guardian_tags": req.guardian_tags,
             "receipt_id": receipt.id,
+            "state": action_state,
+            "approval": approval,
         }
     }));
 
-    // Forward to guardian (best-effort)
-    if let Err(err) = forward_to_guardian(&state, &req).await {
-        tracing::warn!("forward_to_guardian failed: {}", err);
+    if action_state == ActionState::Requested {
+        return Ok(Json(ActionApplyResponse {
+            status: "pending_approval".to_string(),
+            state: action_state,
+        }));
     }
-
+    let action_state = outbox::dispatch(&state, &req)
+        .await
+        .map_err(transition_response)?;
     Ok(Json(ActionApplyResponse {
         status: "accepted".to_string(),
+        state: action_state,
     }))
 }
-
-async fn forward_to_guardian(state: &AppState, req: &ActionRequestPayload) -> Result<(), String> {
-    let guardian_url = state
-        .config
-        .guardian_url
-        .clone()
-        .unwrap_or_else(|| "http://localhost:9120".to_string());
-    let url = format!("{guardian_url}/actions/apply");
-
-    let client = Client::new();
-    client
-        .post(url)
-        .json(req)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?
-        .error_for_status()
-        .map_err(|e| e.to_string())?;
-    Ok(())
-}
@@ -1,8 +1,15 @@
-use axum::{extract::State, Json};
+use axum::{extract::State, http::StatusCode, Json};
 use serde::Deserialize;
 use serde_json::json;
 
-use crate::{models::ErrorResponse, receipts::write_receipt, AppState};
+use crate::{
+    actions::{transition_response, ActionState, TransitionError},
+    auth::{ActionReport, Authorized},
+    capabilities::denial_payload,
+    models::ErrorResponse,
+    receipts::write_receipt,
+    AppState,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct ActionUpdatePayload {
@@ -18,10 +25,85 @@ pub struct ActionUpdatePayload {
     pub guardian_tags: Vec<String>,
 }
 
+fn reject(code: StatusCode, error: &str, details: String) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        code,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            details: Some(details),
+        }),
+    )
+}
+
+/// Record a Guardian's result for an action and move the action record
+/// along. Only the guardian the action was requested for may report on it.
 pub async fn update(
     State(state): State<AppState>,
+    Authorized(principal, _): Authorized<ActionReport>,
     Json(update): Json<ActionUpdatePayload>,
-) -> Result<Json<serde_json::Value>, (axum::http::StatusCode, Json<ErrorResponse>)> {
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let record = state
+        .actions
+        .get(&update.action_id)
+        .await
+        .map_err(|e| {
+            reject(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "action_store_failed",
+                e.to_string(),
+            )
+        })?
+        .ok_or_else(|| transition_response(TransitionError::Unknown(update.action_id.clone())))?;
+
+    let reporter = update.guardian_id.as_deref().unwrap_or(&principal.sub);
+    if record.guardian_id.as_deref() != Some(principal.sub.as_str()) || reporter != principal.sub {
+        state.ws.send_json(&denial_payload(
+            Some(&principal.sub),
+            &update.action_type,
+            "not the originating guardian",
+        ));
+        return Err(reject(
+            StatusCode::FORBIDDEN,
+            "not_originating_guardian",
+            format!(
+                "action {:?} was requested for {:?}",
+                update.action_id,
+                record.guardian_id.as_deref().unwrap_or_default()
+            ),
+        ));
+    }
+    if record.action_type != update.action_type {
+        return Err(reject(
+            StatusCode::CONFLICT,
+            "action_type_mismatch",
+            format!(
+                "action {:?} was requested as {:?}",
+                update.action_id, record.action_type
+            ),
+        ));
+    }
+    let to = ActionState::from_result_status(&update.status).ok_or_else(|| {
+        reject(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unknown_action_status",
+            format!(
+                "status {:?} is not one of applied, executed, failed, rolled_back",
+                update.status
+            ),
+        )
+    })?;
+    // Checked before the receipt is written; the transition below re-checks
+    // against the stored state.
+    if !record.state.can_transition(to) {
+        return Err(transition_response(TransitionError::Invalid {
+            action_id: update.action_id.clone(),
+            from: record.state,
+            to,
+        }));
+    }
+
+    let mut update = update;
+    update.guardian_id = Some(principal.sub.clone());
     let payload = json!({
         "action_id": update.action_id,
         "action_type": update.action_type,
@@ -39,15 +121,21 @@ pub async fn update(
         &update.guardian_tags,
         &payload,
     )
-    .map_err(|e| {
-        (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "receipt_write_failed".to_string(),
-                details: Some(e),
-            }),
+    .await
+    .map_err(|e| reject(StatusCode::INTERNAL_SERVER_ERROR, "receipt_write_failed", e))?;
+
+    let record = state
+        .actions
+        .transition(
+            &update.action_id,
+            record.state,
+            to,
+            &principal.sub,
+            Some(&receipt.id),
+            Some(&update.details).filter(|d| !d.is_null()),
         )
-    })?;
+        .await
+        .map_err(transition_response)?;
 
     state.ws.send_json(&json!({
         "type": "offsec.action.result",
@@ -60,8 +148,9 @@ pub async fn update(
             "guardian_id": update.guardian_id,
             "guardian_tags": update.guardian_tags,
             "receipt_id": receipt.id,
+            "state": record.state,
         }
     }));
 
-    Ok(Json(json!({ "status": "ok" })))
+    Ok(Json(json!({ "status": "ok", "state": record.state })))
 }
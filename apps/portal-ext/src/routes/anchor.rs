@@ -2,7 +2,12 @@ use axum::{extract::State, http::StatusCode, Json};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::{models::ErrorResponse, receipts::write_receipt, AppState};
+use crate::{
+    auth::{AnchorWrite, Authorized},
+    models::ErrorResponse,
+    receipts::{committed_size, write_receipt},
+    AppState,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AnchorPayload {
@@ -13,10 +18,41 @@ pub struct AnchorPayload {
     pub status: String,
 }
 
+/// Announce an external anchor of one of this node's Merkle roots.
 pub async fn anchor(
     State(state): State<AppState>,
+    Authorized(principal, _): Authorized<AnchorWrite>,
     Json(payload): Json<AnchorPayload>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let committed = committed_size(&state);
+    let produced = state
+        .frontier
+        .lock()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "frontier lock poisoned".to_string(),
+                    details: None,
+                }),
+            )
+        })?
+        .size_of_root_within(&payload.root, committed);
+    let Some(tree_size) = produced else {
+        tracing::warn!(
+            "rejected anchor from {} for unknown root {}",
+            principal.sub,
+            payload.root
+        );
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse {
+                error: "unknown_root".to_string(),
+                details: Some(format!("{} is not a root of this node's log", payload.root)),
+            }),
+        ));
+    };
+
     let event = json!({
         "type": "offsec.anchor",
         "data": payload
@@ -30,8 +66,10 @@ pub async fn anchor(
         "chain": event["data"]["chain"],
         "txid": event["data"]["txid"],
         "status": event["data"]["status"],
+        "tree_size": tree_size,
+        "anchored_by": principal.sub,
     });
-    if let Err(err) = write_receipt(&state, "offsec.anchor", None, &[], &receipt_payload) {
+    if let Err(err) = write_receipt(&state, "offsec.anchor", None, &[], &receipt_payload).await {
         tracing::warn!("Failed to write anchor receipt: {}", err);
     }
 
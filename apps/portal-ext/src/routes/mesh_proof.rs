@@ -6,13 +6,12 @@ use axum::{
     http::StatusCode,
     Json,
 };
-use blake3;
 use serde::Deserialize;
 use serde_json::Value;
 
-use crate::merkle::MerklePathElement;
+use crate::merkle::{legacy_tree_version, root_from_path, MerklePathElement, TreeVersion};
 use crate::mesh::envelope::MeshEnvelope;
-use crate::mesh::util::{find_peer, verify_signature};
+use crate::mesh::util::{canonical_json, verify_signature};
 use crate::models::ErrorResponse;
 use crate::AppState;
 
@@ -32,6 +31,14 @@ struct ProofBundle {
     _source_node: Option<String>,
     #[serde(rename = "realm")]
     _realm: Option<String>,
+    #[serde(
+        rename = "treeVersion",
+        alias = "tree_version",
+        default = "legacy_tree_version"
+    )]
+    tree_version: u8,
+    #[serde(default)]
+    payload: Option<Value>,
 }
 
 fn is_hex(s: &str) -> bool {
@@ -46,19 +53,25 @@ fn verify_merkle(bundle: &ProofBundle) -> Result<(), String> {
         return Err("root is not valid hex".to_string());
     }
 
-    let mut h = bundle.leaf.clone();
+    if let Some(payload) = &bundle.payload {
+        let bytes = canonical_json(payload).map_err(|e| e.to_string())?;
+        if blake3::hash(&bytes).to_hex().as_str() != bundle.leaf {
+            return Err("leaf does not commit to payload".to_string());
+        }
+    }
+
+    let version = TreeVersion::from_u8(bundle.tree_version)
+        .ok_or_else(|| format!("unsupported tree version {}", bundle.tree_version))?;
     for (i, step) in bundle.path.iter().enumerate() {
         if !is_hex(&step.sibling) {
             return Err(format!("path[{i}].sibling is not valid hex"));
         }
-        let combined = match step.position.as_str() {
-            "left" => format!("{}{}", step.sibling, h),
-            "right" => format!("{}{}", h, step.sibling),
-            other => return Err(format!("invalid position {other} at path[{i}]")),
-        };
-        h = blake3::hash(combined.as_bytes()).to_hex().to_string();
+        if step.position != "left" && step.position != "right" {
+            return Err(format!("invalid position {} at path[{i}]", step.position));
+        }
     }
 
+    let h = root_from_path(version, &bundle.leaf, &bundle.path);
     if h != bundle.root {
         return Err("merkle proof does not match root".to_string());
     }
@@ -70,7 +83,7 @@ pub async fn mesh_proof(
     State(state): State<AppState>,
     Json(env): Json<MeshEnvelope>,
 ) -> Result<Json<Value>, (StatusCode, Json<ErrorResponse>)> {
-    let peer = find_peer(&state.config, &env.node_id).ok_or_else(|| {
+    let peer = state.trust.peer(&env.node_id).ok_or_else(|| {
         (
             StatusCode::FORBIDDEN,
             Json(ErrorResponse {
@@ -6,7 +6,7 @@ use serde::Deserialize;
 use serde_json::Value;
 
 use crate::mesh::envelope::MeshEnvelope;
-use crate::mesh::util::{find_peer, verify_signature};
+use crate::mesh::util::verify_signature;
 use crate::models::ErrorResponse;
 use crate::AppState;
 
@@ -25,7 +25,7 @@ pub async fn mesh_root(
     State(state): State<AppState>,
     Json(env): Json<MeshEnvelope>,
 ) -> Result<Json<Value>, (StatusCode, Json<ErrorResponse>)> {
-    let peer = find_peer(&state.config, &env.node_id).ok_or_else(|| {
+    let peer = state.trust.peer(&env.node_id).ok_or_else(|| {
         (
             StatusCode::FORBIDDEN,
             Json(ErrorResponse {
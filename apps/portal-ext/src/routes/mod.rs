@@ -7,10 +7,15 @@ pub mod mesh_proof;
 pub mod mesh_root;
 pub mod proof;
 
-use crate::{offsec_ledger, receipts, ws, AppState};
+use crate::{
+    actions, approvals,
+    auth::{Authorized, InfrastructureWrite},
+    durability,
+    incidents::{self, IncidentStatus},
+    offsec_ledger, outbox, receipts, trust, ws, AppState,
+};
 use axum::{
-    extract::Path,
-    http::{header::AUTHORIZATION, HeaderMap},
+    extract::{Path, State},
     routing::{get, post},
     Json, Router,
 };
@@ -24,18 +29,51 @@ pub fn router(state: AppState) -> Router {
         .route("/offsec/action", post(action::submit_action))
         .route("/offsec/action/apply", post(action_apply::apply))
         .route("/offsec/action/update", post(action_update::update))
+        .route("/offsec/actions", get(actions::list_actions))
+        .route("/offsec/actions/:id", get(actions::get_action))
+        .route("/offsec/actions/:id/approve", post(approvals::approve))
+        .route("/offsec/actions/:id/deny", post(approvals::deny))
+        .route("/offsec/dispatch/outbox", get(outbox::list_outbox))
+        .route(
+            "/offsec/incidents",
+            get(incidents::list_incidents).post(incidents::open_incident),
+        )
+        .route("/offsec/incidents/:id", get(incidents::get_incident))
+        .route("/offsec/incidents/:id/assign", post(incidents::assign))
+        .route(
+            "/offsec/incidents/:id/transition",
+            post(incidents::transition),
+        )
+        .route("/offsec/incidents/:id/links", post(incidents::link))
         .route("/offsec/anchor", post(anchor::anchor))
         .route("/offsec/receipts", get(receipts::list_receipts))
+        .route("/offsec/receipts/backlog", get(durability::backlog_status))
         .route("/offsec/root", get(receipts::current_root))
         .route("/offsec/proof/:id", get(proof::proof))
+        .route("/offsec/proof/:id/inclusion", get(proof::inclusion))
+        .route("/offsec/consistency", get(proof::consistency))
         .route("/offsec/mesh/proof", post(mesh_proof::mesh_proof))
         .route(
             "/offsec/mesh/proof/:node/:id",
             get(mesh_proof::get_mesh_proof),
         )
         .route("/offsec/mesh/root", post(mesh_root::mesh_root))
+        .route("/offsec/admin/reload", post(trust::admin_reload))
         .route("/api/offsec/events", post(post_offsec_event))
         .route("/api/offsec/incidents/:id", get(get_offsec_incident))
+        .route(
+            "/api/offsec/incidents/:id/proof",
+            get(offsec_ledger::incident_proof),
+        )
+        .route(
+            "/api/offsec/incidents/:id/verify",
+            get(offsec_ledger::verify_incident),
+        )
+        .route(
+            "/api/offsec/incidents/verify",
+            post(offsec_ledger::verify_exported),
+        )
+        .route("/api/offsec/did", get(offsec_ledger::node_did))
         .route("/offsec/ws", get(ws::stream::handler))
         .with_state(state)
 }
@@ -45,46 +83,65 @@ async fn health_check() -> &'static str {
 }
 
 async fn post_offsec_event(
-    headers: HeaderMap,
-    Json(ev): Json<InfrastructureEvent>,
+    State(state): State<AppState>,
+    Authorized(principal, _): Authorized<InfrastructureWrite>,
+    Json(body): Json<serde_json::Value>,
 ) -> Json<serde_json::Value> {
-    // Extract Bearer token
-    let auth_header = headers
-        .get(AUTHORIZATION)
-        .and_then(|hv| hv.to_str().ok())
-        .unwrap_or("");
-
-    let mut parts = auth_header.split_whitespace();
-    match (parts.next(), parts.next()) {
-        (Some("Bearer"), Some(token_b64)) => {
-            if let Err(e) =
-                offsec_ledger::validate_capability_base64(token_b64, "infrastructure:write")
-            {
-                return Json(
-                    json!({"status":"error","error": format!("invalid capability: {}", e)}),
-                );
+    let severity = body["severity"].as_str().map(str::to_string);
+    let ev = match serde_json::from_value::<InfrastructureEvent>(body) {
+        Ok(ev) => ev,
+        Err(e) => return Json(json!({ "status": "error", "error": e.to_string() })),
+    };
+    // Hold the incident while its chain is extended, and leave a closed
+    // incident's chain as it was closed.
+    let _guard = match &ev.ref_id {
+        Some(incident_id) => {
+            let guard = state.incidents.lock(incident_id).await;
+            match state.incidents.get(incident_id).await {
+                Ok(Some(record)) if record.status == IncidentStatus::Closed => {
+                    return Json(json!({
+                        "status": "error",
+                        "error": "incident_closed",
+                        "incident_id": incident_id,
+                    }));
+                }
+                Ok(_) => Some(guard),
+                Err(e) => return Json(json!({ "status": "error", "error": e.to_string() })),
             }
         }
-        _ => {
-            return Json(json!({"status":"error","error":"missing or invalid authorization"}));
+        None => None,
+    };
+    match state.ledger.append(ev).await {
+        Ok((incident_id, receipt_id)) => {
+            if let Err(e) = incidents::ensure_incident(
+                &state,
+                &incident_id,
+                severity.as_deref(),
+                &receipt_id,
+                &principal.sub,
+            )
+            .await
+            {
+                tracing::warn!("recording incident {}: {}", incident_id, e);
+            }
+            Json(json!({
+                "status": "ok",
+                "incident_id": incident_id,
+                "receipt_id": receipt_id
+            }))
         }
-    }
-
-    match offsec_ledger::handle_infra_event(ev) {
-        Ok((incident_id, receipt_id)) => Json(json!({
-            "status": "ok",
-            "incident_id": incident_id,
-            "receipt_id": receipt_id
-        })),
         Err(e) => Json(json!({
             "status": "error",
-            "error": e.to_string()
+            "error": e
         })),
     }
 }
 
-async fn get_offsec_incident(Path(id): Path<String>) -> Json<serde_json::Value> {
-    if let Some(chain) = offsec_ledger::get_incident(&id) {
+async fn get_offsec_incident(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Json<serde_json::Value> {
+    if let Some(chain) = state.ledger.get_incident(&id) {
         Json(json!({
             "incident_id": chain.incident_id,
             "receipts": chain.receipts
@@ -1,14 +1,31 @@
 use std::fs;
 use std::path::PathBuf;
+use std::sync::MutexGuard;
 
-use axum::{extract::Path, extract::State, http::StatusCode, Json};
-use serde::Serialize;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
 
-use crate::merkle::MerklePathElement;
+use crate::merkle::{MerkleFrontier, MerklePathElement, TreeVersion};
 use crate::models::ErrorResponse;
-use crate::receipts::OffsecReceipt;
+use crate::receipts::{committed_size, sign_tree_head, OffsecReceipt, SignedTreeHead};
 use crate::AppState;
 
+type ProofError = (StatusCode, Json<ErrorResponse>);
+
+fn proof_error(code: StatusCode, error: &str, details: Option<String>) -> ProofError {
+    (
+        code,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            details,
+        }),
+    )
+}
+
 #[derive(Debug, Serialize)]
 pub struct AnchorBundle {
     root: Option<String>,
@@ -31,70 +48,190 @@ pub struct ProofBundle {
     event_type: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     ts: Option<String>,
+    #[serde(rename = "guardianId", skip_serializing_if = "Option::is_none")]
+    guardian_id: Option<String>,
+    #[serde(rename = "guardianTags")]
+    guardian_tags: Vec<String>,
+    #[serde(rename = "agentId", skip_serializing_if = "Option::is_none")]
+    agent_id: Option<String>,
+    /// Root the receipt was issued under; differs from `root` in a fresh
+    /// inclusion proof against a later tree.
+    #[serde(rename = "receiptRoot")]
+    receipt_root: String,
+    #[serde(rename = "leafIndex", skip_serializing_if = "Option::is_none")]
+    leaf_index: Option<usize>,
+    #[serde(rename = "treeSize", skip_serializing_if = "Option::is_none")]
+    tree_size: Option<usize>,
+    #[serde(rename = "treeVersion")]
+    tree_version: u8,
+    /// Receipt payload; `leaf == BLAKE3(canonical_json(payload))`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<serde_json::Value>,
+    /// Node signature over the receipt statement (see `receipt_statement`).
+    #[serde(rename = "receiptSignature", skip_serializing_if = "Option::is_none")]
+    receipt_signature: Option<String>,
+    /// Signed tree head for `root` at `treeSize`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sth: Option<SignedTreeHead>,
 }
 
-pub async fn proof(
-    State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> Result<Json<ProofBundle>, (StatusCode, Json<ErrorResponse>)> {
-    let receipt_path = PathBuf::from(&state.config.data_dir)
-        .join("receipts/offsec")
-        .join(format!("{id}.json"));
-
-    let receipt_json = fs::read_to_string(&receipt_path).map_err(|e| {
-        (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "receipt not found".to_string(),
-                details: Some(e.to_string()),
-            }),
+#[derive(Debug, Serialize)]
+pub struct ConsistencyBundle {
+    #[serde(rename = "treeVersion")]
+    tree_version: u8,
+    first: usize,
+    second: usize,
+    #[serde(rename = "firstRoot")]
+    first_root: String,
+    #[serde(rename = "secondRoot")]
+    second_root: String,
+    proof: Vec<String>,
+    #[serde(rename = "firstSth", skip_serializing_if = "Option::is_none")]
+    first_sth: Option<SignedTreeHead>,
+    #[serde(rename = "secondSth", skip_serializing_if = "Option::is_none")]
+    second_sth: Option<SignedTreeHead>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InclusionQuery {
+    /// Prove against the root of the first `tree_size` leaves.
+    #[serde(default)]
+    pub tree_size: Option<usize>,
+    /// Prove against the root recorded in ANCHOR.json.
+    #[serde(default)]
+    pub anchored: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConsistencyQuery {
+    #[serde(default)]
+    pub first: Option<usize>,
+    #[serde(default)]
+    pub second: Option<usize>,
+    /// Use the anchored tree size as `first`.
+    #[serde(default)]
+    pub anchored: bool,
+}
+
+async fn load_receipt(state: &AppState, id: &str) -> Result<OffsecReceipt, ProofError> {
+    state
+        .store
+        .get(id)
+        .await
+        .map_err(|e| {
+            proof_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "receipt store error",
+                Some(e.to_string()),
+            )
+        })?
+        .ok_or_else(|| {
+            proof_error(
+                StatusCode::NOT_FOUND,
+                "receipt not found",
+                Some(id.to_string()),
+            )
+        })
+}
+
+fn lock_frontier(state: &AppState) -> Result<MutexGuard<'_, MerkleFrontier>, ProofError> {
+    state.frontier.lock().map_err(|_| {
+        proof_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "frontier lock poisoned",
+            None,
         )
-    })?;
+    })
+}
+
+/// Signed tree head for `root` at `size`, if the node has a signing key.
+/// Only a root the committed log has at `size` is signed; a root the log
+/// does not reproduce, or one whose last leaf is still being committed,
+/// gets no head.
+fn signed_head(
+    state: &AppState,
+    frontier: &MerkleFrontier,
+    size: usize,
+    root: &str,
+) -> Result<Option<SignedTreeHead>, ProofError> {
+    let Some(key) = state.signer.as_deref() else {
+        return Ok(None);
+    };
+    if size > committed_size(state) || frontier.root_at(size).as_deref() != Some(root) {
+        return Ok(None);
+    }
+    sign_tree_head(key, frontier.version(), size, root)
+        .map(Some)
+        .map_err(|e| {
+            proof_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to sign tree head",
+                Some(e.to_string()),
+            )
+        })
+}
+
+/// Optional anchor bundle from ANCHOR.json.
+fn read_anchor(data_dir: &str) -> Result<Option<AnchorBundle>, ProofError> {
+    let anchor_path = PathBuf::from(data_dir).join("ANCHOR.json");
+    let Ok(contents) = fs::read_to_string(&anchor_path) else {
+        return Ok(None);
+    };
 
-    let receipt: OffsecReceipt = serde_json::from_str(&receipt_json).map_err(|e| {
-        (
+    let v = serde_json::from_str::<serde_json::Value>(&contents).map_err(|e| {
+        proof_error(
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "receipt parse error".to_string(),
-                details: Some(e.to_string()),
-            }),
+            "anchor parse error",
+            Some(e.to_string()),
         )
     })?;
+    let field = |name: &str| v.get(name).and_then(|x| x.as_str()).map(|s| s.to_string());
+
+    Ok(Some(AnchorBundle {
+        root: field("root"),
+        ts: field("ts"),
+        chain: field("chain"),
+        txid: field("txid"),
+        status: field("status"),
+    }))
+}
+
+/// Tree size of the anchored root in the local log.
+fn anchored_size(state: &AppState, anchor: Option<&AnchorBundle>) -> Result<usize, ProofError> {
+    let root = anchor
+        .and_then(|a| a.root.as_deref())
+        .ok_or_else(|| proof_error(StatusCode::NOT_FOUND, "no anchored root", None))?;
 
-    // Optional anchor bundle
-    let anchor_path = PathBuf::from(&state.config.data_dir).join("ANCHOR.json");
-    let anchor = match fs::read_to_string(&anchor_path) {
-        Ok(contents) => match serde_json::from_str::<serde_json::Value>(&contents) {
-            Ok(v) => Some(AnchorBundle {
-                root: v
-                    .get("root")
-                    .and_then(|x| x.as_str())
-                    .map(|s| s.to_string()),
-                ts: v.get("ts").and_then(|x| x.as_str()).map(|s| s.to_string()),
-                chain: v
-                    .get("chain")
-                    .and_then(|x| x.as_str())
-                    .map(|s| s.to_string()),
-                txid: v
-                    .get("txid")
-                    .and_then(|x| x.as_str())
-                    .map(|s| s.to_string()),
-                status: v
-                    .get("status")
-                    .and_then(|x| x.as_str())
-                    .map(|s| s.to_string()),
-            }),
-            Err(e) => {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse {
-                        error: "anchor parse error".to_string(),
-                        details: Some(e.to_string()),
-                    }),
-                ))
+    let committed = committed_size(state);
+    let frontier = lock_frontier(state)?;
+    frontier
+        .size_of_root_within(root, committed)
+        .ok_or_else(|| {
+            proof_error(
+                StatusCode::NOT_FOUND,
+                "anchored root not found in local log",
+                Some(root.to_string()),
+            )
+        })
+}
+
+pub async fn proof(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ProofBundle>, ProofError> {
+    let receipt = load_receipt(&state, &id).await?;
+    let anchor = read_anchor(&state.config.data_dir)?;
+    let tree_size = receipt.leaf_index.map(|i| i + 1);
+    let sth = {
+        let frontier = lock_frontier(&state)?;
+        match tree_size {
+            Some(size)
+                if TreeVersion::from_u8(receipt.tree_version) == Some(frontier.version()) =>
+            {
+                signed_head(&state, &frontier, size, &receipt.merkle_root)?
             }
-        },
-        Err(_) => None,
+            _ => None,
+        }
     };
 
     let bundle = ProofBundle {
@@ -105,7 +242,127 @@ pub async fn proof(
         receipt_id: Some(receipt.id.clone()),
         event_type: Some(receipt.event_type.clone()),
         ts: Some(receipt.ts.clone()),
+        guardian_id: receipt.guardian_id.clone(),
+        guardian_tags: receipt.guardian_tags.clone(),
+        agent_id: receipt.agent_id.clone(),
+        receipt_root: receipt.merkle_root.clone(),
+        leaf_index: receipt.leaf_index,
+        tree_size,
+        tree_version: receipt.tree_version,
+        payload: receipt.payload.clone(),
+        receipt_signature: receipt.signature.clone(),
+        sth,
     };
 
     Ok(Json(bundle))
 }
+
+/// Fresh inclusion proof for a receipt against the current tree, a given
+/// `tree_size`, or the anchored root.
+pub async fn inclusion(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<InclusionQuery>,
+) -> Result<Json<ProofBundle>, ProofError> {
+    let receipt = load_receipt(&state, &id).await?;
+    let anchor = read_anchor(&state.config.data_dir)?;
+    let anchored = if params.anchored {
+        Some(anchored_size(&state, anchor.as_ref())?)
+    } else {
+        None
+    };
+
+    let frontier = lock_frontier(&state)?;
+    let committed = committed_size(&state);
+
+    let index = receipt
+        .leaf_index
+        .or_else(|| frontier.index_of(&receipt.hash))
+        .filter(|&i| frontier.leaf(i) == Some(receipt.hash.as_str()))
+        .ok_or_else(|| {
+            proof_error(
+                StatusCode::CONFLICT,
+                "receipt leaf not found in merkle log",
+                Some(receipt.hash.clone()),
+            )
+        })?;
+
+    let size = anchored.or(params.tree_size).unwrap_or(committed);
+    let (true, Some(root), Some(path)) = (
+        size <= committed,
+        frontier.root_at(size),
+        frontier.inclusion_path_at(index, size),
+    ) else {
+        return Err(proof_error(
+            StatusCode::BAD_REQUEST,
+            "receipt is not included in requested tree size",
+            Some(format!("leaf_index={index} tree_size={size}")),
+        ));
+    };
+    let sth = signed_head(&state, &frontier, size, &root)?;
+
+    Ok(Json(ProofBundle {
+        leaf: receipt.hash.clone(),
+        path,
+        root,
+        anchor: if params.anchored { anchor } else { None },
+        receipt_id: Some(receipt.id.clone()),
+        event_type: Some(receipt.event_type.clone()),
+        ts: Some(receipt.ts.clone()),
+        guardian_id: receipt.guardian_id.clone(),
+        guardian_tags: receipt.guardian_tags.clone(),
+        agent_id: receipt.agent_id.clone(),
+        receipt_root: receipt.merkle_root.clone(),
+        leaf_index: Some(index),
+        tree_size: Some(size),
+        tree_version: frontier.version().as_u8(),
+        payload: receipt.payload.clone(),
+        receipt_signature: receipt.signature.clone(),
+        sth,
+    }))
+}
+
+/// Consistency proof between two tree sizes (defaults: anchored or 1 → current).
+pub async fn consistency(
+    State(state): State<AppState>,
+    Query(params): Query<ConsistencyQuery>,
+) -> Result<Json<ConsistencyBundle>, ProofError> {
+    let first = if params.anchored {
+        let anchor = read_anchor(&state.config.data_dir)?;
+        anchored_size(&state, anchor.as_ref())?
+    } else {
+        params.first.unwrap_or(1)
+    };
+
+    let frontier = lock_frontier(&state)?;
+    let committed = committed_size(&state);
+    let second = params.second.unwrap_or(committed);
+
+    let (true, Some(first_root), Some(second_root), Some(proof)) = (
+        second <= committed,
+        frontier.root_at(first),
+        frontier.root_at(second),
+        frontier.consistency_proof(first, second),
+    ) else {
+        return Err(proof_error(
+            StatusCode::BAD_REQUEST,
+            "invalid tree sizes",
+            Some(format!(
+                "first={first} second={second} tree_size={committed}"
+            )),
+        ));
+    };
+    let first_sth = signed_head(&state, &frontier, first, &first_root)?;
+    let second_sth = signed_head(&state, &frontier, second, &second_root)?;
+
+    Ok(Json(ConsistencyBundle {
+        tree_version: frontier.version().as_u8(),
+        first,
+        second,
+        first_root,
+        second_root,
+        proof,
+        first_sth,
+        second_sth,
+    }))
+}
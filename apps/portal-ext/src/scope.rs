@@ -0,0 +1,258 @@
+use std::{fmt, net::IpAddr};
+
+use axum::{http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    auth::Principal,
+    capabilities::{denial_payload, error_response, CapabilityError},
+    models::ErrorResponse,
+    AppState,
+};
+
+/// Per-action constraints carried in the `scopes` claim, e.g.
+/// `{"block_ip": {"cidrs": ["10.0.0.0/8"], "guardian_tags": ["lab"]}}`.
+/// Every listed constraint must hold; an empty list means unconstrained.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActionScope {
+    /// The target IP must fall inside one of these ranges.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cidrs: Vec<String>,
+    /// The target host must match one of these globs (`*` and `?`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    /// The token must carry at least one of these guardian tags.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub guardian_tags: Vec<String>,
+    /// How many times the token may be used for this action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_count: Option<u32>,
+}
+
+/// What an action request is aimed at, as far as scopes are concerned.
+#[derive(Debug, Clone, Default)]
+pub struct ScopeTarget {
+    pub ip: Option<IpAddr>,
+    pub host: Option<String>,
+    /// The token's guardian tags, which `guardian_tags` constraints are
+    /// checked against.
+    pub guardian_tags: Vec<String>,
+    /// Tags named in the request body. They must all be carried by the
+    /// token and never count towards a constraint.
+    pub claimed_tags: Vec<String>,
+}
+
+impl ScopeTarget {
+    /// A free-form target string: an IP address or a host name.
+    pub fn parse(target: &str, guardian_tags: &[String]) -> Self {
+        let target = target.trim();
+        Self {
+            ip: target.parse().ok(),
+            host: (!target.is_empty()).then(|| target.to_string()),
+            guardian_tags: guardian_tags.to_vec(),
+            claimed_tags: Vec::new(),
+        }
+    }
+
+    /// Reject body tags the token does not carry.
+    pub fn check_claimed_tags(&self, action: &str) -> Result<(), ScopeViolation> {
+        let unknown: Vec<&str> = self
+            .claimed_tags
+            .iter()
+            .filter(|tag| !self.guardian_tags.contains(tag))
+            .map(String::as_str)
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        Err(ScopeViolation {
+            action: action.to_string(),
+            constraint: "guardian_tags",
+            detail: format!("token does not carry tags [{}]", unknown.join(", ")),
+        })
+    }
+}
+
+/// The first constraint a request failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeViolation {
+    pub action: String,
+    /// Name of the constraint, as spelled in the claim.
+    pub constraint: &'static str,
+    pub detail: String,
+}
+
+impl fmt::Display for ScopeViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' violates scope constraint `{}`: {}",
+            self.action, self.constraint, self.detail
+        )
+    }
+}
+
+impl ActionScope {
+    /// Check the static constraints. `max_count` needs the replay cache and
+    /// is enforced by [`enforce_scope`].
+    pub fn check(&self, action: &str, target: &ScopeTarget) -> Result<(), ScopeViolation> {
+        let violation = |constraint, detail: String| ScopeViolation {
+            action: action.to_string(),
+            constraint,
+            detail,
+        };
+
+        if !self.cidrs.is_empty() {
+            let ip = target
+                .ip
+                .ok_or_else(|| violation("cidrs", "target has no IP address".to_string()))?;
+            let mut matched = false;
+            for cidr in &self.cidrs {
+                matched |= cidr_contains(cidr, ip)
+                    .map_err(|e| violation("cidrs", format!("invalid range {cidr:?}: {e}")))?;
+            }
+            if !matched {
+                return Err(violation(
+                    "cidrs",
+                    format!("{ip} is outside {}", self.cidrs.join(", ")),
+                ));
+            }
+        }
+
+        if !self.hosts.is_empty() {
+            let host = target
+                .host
+                .as_deref()
+                .ok_or_else(|| violation("hosts", "target has no host".to_string()))?;
+            if !self.hosts.iter().any(|glob| glob_match(glob, host)) {
+                return Err(violation(
+                    "hosts",
+                    format!("{host} matches none of {}", self.hosts.join(", ")),
+                ));
+            }
+        }
+
+        if !self.guardian_tags.is_empty()
+            && !self
+                .guardian_tags
+                .iter()
+                .any(|tag| target.guardian_tags.contains(tag))
+        {
+            return Err(violation(
+                "guardian_tags",
+                format!(
+                    "token tags [{}] include none of {}",
+                    target.guardian_tags.join(", "),
+                    self.guardian_tags.join(", ")
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `ip` is inside `cidr` (`a.b.c.d/n`, `x::/n`, or a bare address).
+pub fn cidr_contains(cidr: &str, ip: IpAddr) -> Result<bool, String> {
+    let (addr, len) = match cidr.split_once('/') {
+        Some((addr, len)) => (addr, Some(len)),
+        None => (cidr, None),
+    };
+    let net: IpAddr = addr.trim().parse().map_err(|_| "not an IP address")?;
+    let max = if net.is_ipv4() { 32 } else { 128 };
+    let len: u32 = match len {
+        Some(len) => len
+            .trim()
+            .parse()
+            .ok()
+            .filter(|l| *l <= max)
+            .ok_or_else(|| format!("prefix length must be 0-{max}"))?,
+        None => max,
+    };
+    let (net, ip) = match (net, ip) {
+        (IpAddr::V4(n), IpAddr::V4(i)) => (u32::from(n) as u128, u32::from(i) as u128),
+        (IpAddr::V6(n), IpAddr::V6(i)) => (u128::from(n), u128::from(i)),
+        _ => return Ok(false),
+    };
+    let shift = max - len;
+    Ok(shift == max || (net >> shift) == (ip >> shift))
+}
+
+/// Case-insensitive glob with `*` (any run) and `?` (one character).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Enforce the principal's scope for `action` against `target`. Body tags
+/// the token lacks are rejected whether or not the action is scoped.
+/// Violations are broadcast as `capability_denied` frames naming the
+/// constraint. `max_count` is counted separately by [`count_scoped_use`].
+pub fn enforce_scope(
+    state: &AppState,
+    principal: &Principal,
+    action: &str,
+    target: &ScopeTarget,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let mut result = target.check_claimed_tags(action);
+    if let (Ok(()), Some(scope)) = (&result, principal.scope(action)) {
+        result = scope.check(action, target);
+    }
+    result.map_err(|violation| deny(state, principal, violation))
+}
+
+/// Count one use of the token towards the `max_count` of its scope for
+/// `action`, denying the request once the count is reached.
+pub async fn count_scoped_use(
+    state: &AppState,
+    principal: &Principal,
+    action: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(max) = principal.scope(action).and_then(|s| s.max_count) else {
+        return Ok(());
+    };
+    let violation = |detail| ScopeViolation {
+        action: action.to_string(),
+        constraint: "max_count",
+        detail,
+    };
+    match state.replay.count_use(principal, action, max).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(violation(format!("token already used {max} time(s)"))),
+        Err(e) => Err(violation(e.to_string())),
+    }
+    .map_err(|violation| deny(state, principal, violation))
+}
+
+fn deny(
+    state: &AppState,
+    principal: &Principal,
+    violation: ScopeViolation,
+) -> (StatusCode, Json<ErrorResponse>) {
+    let mut denial = denial_payload(Some(&principal.sub), &violation.action, "out of scope");
+    denial["data"]["description"] = json!(violation.to_string());
+    denial["data"]["metadata"]["constraint"] = json!(violation.constraint);
+    denial["data"]["metadata"]["detail"] = json!(violation.detail);
+    state.ws.send_json(&denial);
+    let (code, err) = error_response(CapabilityError::OutOfScope(violation));
+    (code, Json(err))
+}
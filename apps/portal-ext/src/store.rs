@@ -0,0 +1,438 @@
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions},
+    QueryBuilder, Row, Sqlite,
+};
+
+use crate::{merkle::MerkleFrontier, receipts::OffsecReceipt};
+
+/// SQLite database holding receipts, relative to the data dir.
+pub const RECEIPTS_DB: &str = "receipts.db";
+/// Pre-SQLite receipt files (one JSON file per receipt id).
+pub const LEGACY_RECEIPTS_DIR: &str = "receipts/offsec";
+
+const JSON_IMPORT_KEY: &str = "json_import";
+/// Upper bound on a single page of receipts.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS receipts (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        id TEXT NOT NULL,
+        leaf_index INTEGER UNIQUE,
+        event_type TEXT NOT NULL,
+        guardian_id TEXT,
+        timestamp TEXT NOT NULL,
+        body TEXT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS idx_receipts_timestamp ON receipts (timestamp)",
+    "CREATE INDEX IF NOT EXISTS idx_receipts_guardian ON receipts (guardian_id, timestamp)",
+    "CREATE INDEX IF NOT EXISTS idx_receipts_event_type ON receipts (event_type, timestamp)",
+    "CREATE INDEX IF NOT EXISTS idx_receipts_id ON receipts (id)",
+    "CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiptOrder {
+    /// Newest first.
+    #[default]
+    Time,
+    /// Merkle leaf order, oldest first. Receipts without a leaf index are
+    /// skipped.
+    Leaf,
+}
+
+/// Receipt filters; all set fields must match.
+#[derive(Debug, Default, Clone)]
+pub struct ReceiptFilter {
+    pub guardian_id: Option<String>,
+    /// Exact event type, or a prefix when it ends in `*` (`offsec.action.*`).
+    pub event_type: Option<String>,
+    /// Inclusive lower bound on `timestamp` (RFC 3339).
+    pub since: Option<String>,
+    /// Exclusive upper bound on `timestamp` (RFC 3339).
+    pub until: Option<String>,
+    /// Must be one of `guardian_tags`.
+    pub tag: Option<String>,
+    /// `severity` of the wrapped payload (`payload.data.severity` or
+    /// `payload.severity`).
+    pub severity: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ReceiptPage {
+    pub receipts: Vec<OffsecReceipt>,
+    /// Opaque cursor for the next page; `None` on the last page.
+    pub next: Option<String>,
+}
+
+/// Outcome of [`ReceiptStore::import_json_receipts`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct JsonImport {
+    pub imported: usize,
+    /// Receipts skipped because another receipt already holds their
+    /// `leaf_index`.
+    pub collisions: usize,
+}
+
+/// Why a page of receipts could not be read.
+#[derive(Debug)]
+pub enum PageError {
+    /// A malformed cursor, one issued for a different order, or a bad filter.
+    Invalid(String),
+    Store(anyhow::Error),
+}
+
+impl fmt::Display for PageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(reason) => f.write_str(reason),
+            Self::Store(e) => write!(f, "receipt store error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for PageError {}
+
+impl From<sqlx::Error> for PageError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Store(e.into())
+    }
+}
+
+/// Position after the last row of a page.
+#[derive(Debug, PartialEq)]
+enum Cursor {
+    Time { timestamp: String, seq: i64 },
+    Leaf { leaf_index: i64 },
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let raw = match self {
+            Cursor::Time { timestamp, seq } => format!("t:{seq}:{timestamp}"),
+            Cursor::Leaf { leaf_index } => format!("l:{leaf_index}"),
+        };
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    fn decode(token: &str) -> anyhow::Result<Self> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(token)?)?;
+        match raw.split_once(':') {
+            Some(("t", rest)) => {
+                let (seq, timestamp) = rest.split_once(':').ok_or_else(|| anyhow!("bad cursor"))?;
+                Ok(Cursor::Time {
+                    timestamp: timestamp.to_string(),
+                    seq: seq.parse()?,
+                })
+            }
+            Some(("l", leaf_index)) => Ok(Cursor::Leaf {
+                leaf_index: leaf_index.parse()?,
+            }),
+            _ => Err(anyhow!("bad cursor")),
+        }
+    }
+}
+
+/// Indexed receipt store. The full receipt is kept as JSON in `body`; the
+/// other columns exist for lookups and ordering.
+#[derive(Clone)]
+pub struct ReceiptStore {
+    pool: SqlitePool,
+}
+
+impl ReceiptStore {
+    /// Open (or create) `receipts.db` in `data_dir`.
+    pub async fn open(data_dir: &str) -> anyhow::Result<Self> {
+        fs::create_dir_all(data_dir).with_context(|| format!("creating {data_dir}"))?;
+        let path = Path::new(data_dir).join(RECEIPTS_DB);
+        let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.display()))?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_with(options)
+            .await
+            .with_context(|| format!("opening {}", path.display()))?;
+
+        for stmt in SCHEMA {
+            sqlx::query(stmt).execute(&pool).await?;
+        }
+        Ok(Self { pool })
+    }
+
+    /// The underlying pool, for other tables kept in `receipts.db`.
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    pub async fn insert(&self, receipt: &OffsecReceipt) -> anyhow::Result<()> {
+        let body = serde_json::to_string(receipt)?;
+        sqlx::query(
+            "INSERT INTO receipts (id, leaf_index, event_type, guardian_id, timestamp, body)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&receipt.id)
+        .bind(receipt.leaf_index.map(|i| i as i64))
+        .bind(&receipt.event_type)
+        .bind(receipt.guardian_id.as_ref().or(receipt.agent_id.as_ref()))
+        .bind(&receipt.timestamp)
+        .bind(body)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Delete receipts at `leaf_index` and later (rollback of an interrupted
+    /// commit).
+    pub async fn delete_from_leaf(&self, leaf_index: usize) -> anyhow::Result<u64> {
+        let result = sqlx::query("DELETE FROM receipts WHERE leaf_index >= ?")
+            .bind(leaf_index as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Most recently written receipt with `id`.
+    pub async fn get(&self, id: &str) -> anyhow::Result<Option<OffsecReceipt>> {
+        let row = sqlx::query("SELECT body FROM receipts WHERE id = ? ORDER BY seq DESC LIMIT 1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|r| parse_body(r.get("body"))).transpose()
+    }
+
+    /// Newest receipts first, optionally for a single guardian.
+    pub async fn list(
+        &self,
+        limit: usize,
+        guardian_id: Option<&str>,
+    ) -> anyhow::Result<Vec<OffsecReceipt>> {
+        let filter = ReceiptFilter {
+            guardian_id: guardian_id.map(str::to_string),
+            ..Default::default()
+        };
+        Ok(self
+            .page(&filter, ReceiptOrder::Time, limit, None)
+            .await?
+            .receipts)
+    }
+
+    /// One page of receipts matching `filter`, starting after `cursor`.
+    /// Fails with [`PageError::Invalid`] on a malformed cursor or one issued
+    /// for a different order.
+    pub async fn page(
+        &self,
+        filter: &ReceiptFilter,
+        order: ReceiptOrder,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<ReceiptPage, PageError> {
+        let cursor = cursor
+            .map(Cursor::decode)
+            .transpose()
+            .map_err(|e| PageError::Invalid(format!("bad cursor: {e}")))?;
+        let limit = limit.clamp(1, MAX_PAGE_SIZE);
+
+        let mut q: QueryBuilder<Sqlite> =
+            QueryBuilder::new("SELECT seq, timestamp, leaf_index, body FROM receipts WHERE 1 = 1");
+        if let Some(gid) = &filter.guardian_id {
+            q.push(" AND guardian_id = ").push_bind(gid.clone());
+        }
+        if let Some(event_type) = &filter.event_type {
+            match event_type.strip_suffix('*') {
+                Some(prefix) => {
+                    if prefix.contains(['*', '?', '[']) {
+                        return Err(PageError::Invalid(
+                            "event_type may only end in a single '*'".to_string(),
+                        ));
+                    }
+                    q.push(" AND event_type GLOB ")
+                        .push_bind(format!("{prefix}*"));
+                }
+                None => {
+                    q.push(" AND event_type = ").push_bind(event_type.clone());
+                }
+            }
+        }
+        if let Some(since) = &filter.since {
+            q.push(" AND timestamp >= ").push_bind(since.clone());
+        }
+        if let Some(until) = &filter.until {
+            q.push(" AND timestamp < ").push_bind(until.clone());
+        }
+        if let Some(tag) = &filter.tag {
+            q.push(
+                " AND EXISTS (SELECT 1 FROM json_each(receipts.body, '$.guardian_tags') WHERE json_each.value = ",
+            )
+            .push_bind(tag.clone())
+            .push(")");
+        }
+        if let Some(severity) = &filter.severity {
+            q.push(
+                " AND COALESCE(json_extract(body, '$.payload.data.severity'), json_extract(body, '$.payload.severity')) = ",
+            )
+            .push_bind(severity.clone());
+        }
+
+        match (order, cursor) {
+            (ReceiptOrder::Time, None) => {}
+            (ReceiptOrder::Time, Some(Cursor::Time { timestamp, seq })) => {
+                q.push(" AND (timestamp, seq) < (")
+                    .push_bind(timestamp)
+                    .push(", ")
+                    .push_bind(seq)
+                    .push(")");
+            }
+            (ReceiptOrder::Leaf, None) => {
+                q.push(" AND leaf_index IS NOT NULL");
+            }
+            (ReceiptOrder::Leaf, Some(Cursor::Leaf { leaf_index })) => {
+                q.push(" AND leaf_index > ").push_bind(leaf_index);
+            }
+            _ => {
+                return Err(PageError::Invalid(
+                    "cursor does not match requested order".to_string(),
+                ))
+            }
+        }
+        q.push(match order {
+            ReceiptOrder::Time => " ORDER BY timestamp DESC, seq DESC",
+            ReceiptOrder::Leaf => " ORDER BY leaf_index ASC",
+        });
+        // One extra row tells us whether there is a next page.
+        q.push(" LIMIT ").push_bind(limit as i64 + 1);
+
+        let mut rows = q.build().fetch_all(&self.pool).await?;
+        let next = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|last| {
+                match order {
+                    ReceiptOrder::Time => Cursor::Time {
+                        timestamp: last.get("timestamp"),
+                        seq: last.get("seq"),
+                    },
+                    ReceiptOrder::Leaf => Cursor::Leaf {
+                        leaf_index: last.get("leaf_index"),
+                    },
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+
+        let receipts = rows
+            .into_iter()
+            .map(|r| parse_body(r.get("body")))
+            .collect::<anyhow::Result<_>>()
+            .map_err(PageError::Store)?;
+        Ok(ReceiptPage { receipts, next })
+    }
+
+    pub async fn count(&self) -> anyhow::Result<usize> {
+        let n: i64 = sqlx::query("SELECT COUNT(*) AS n FROM receipts")
+            .fetch_one(&self.pool)
+            .await?
+            .get("n");
+        Ok(n as usize)
+    }
+
+    /// One-shot import of `receipts/offsec/*.json` into the store. Runs once
+    /// per database; later calls import nothing. The JSON files are left in
+    /// place. Receipts written before `leaf_index` existed get it from
+    /// `frontier` so they can be listed in leaf order. A receipt whose
+    /// `leaf_index` is already taken by another is skipped and logged.
+    pub async fn import_json_receipts(
+        &self,
+        data_dir: &str,
+        frontier: &MerkleFrontier,
+    ) -> anyhow::Result<JsonImport> {
+        let done = sqlx::query("SELECT value FROM meta WHERE key = ?")
+            .bind(JSON_IMPORT_KEY)
+            .fetch_optional(&self.pool)
+            .await?;
+        if done.is_some() {
+            return Ok(JsonImport::default());
+        }
+
+        let mut receipts = read_json_receipts(data_dir);
+        for receipt in receipts.iter_mut().filter(|r| r.leaf_index.is_none()) {
+            receipt.leaf_index = frontier.locate(&receipt.hash, &receipt.merkle_root);
+        }
+        receipts.sort_by(|a, b| (a.leaf_index, &a.timestamp).cmp(&(b.leaf_index, &b.timestamp)));
+
+        let mut tx = self.pool.begin().await?;
+        let mut outcome = JsonImport::default();
+        for receipt in &receipts {
+            let result = sqlx::query(
+                "INSERT OR IGNORE INTO receipts
+                 (id, leaf_index, event_type, guardian_id, timestamp, body)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&receipt.id)
+            .bind(receipt.leaf_index.map(|i| i as i64))
+            .bind(&receipt.event_type)
+            .bind(receipt.guardian_id.as_ref().or(receipt.agent_id.as_ref()))
+            .bind(&receipt.timestamp)
+            .bind(serde_json::to_string(receipt)?)
+            .execute(&mut *tx)
+            .await?;
+            if result.rows_affected() > 0 {
+                outcome.imported += 1;
+                continue;
+            }
+            // Ignored: either this receipt is already stored, or another
+            // one holds its leaf.
+            let stored = sqlx::query("SELECT 1 FROM receipts WHERE id = ?")
+                .bind(&receipt.id)
+                .fetch_optional(&mut *tx)
+                .await?;
+            if stored.is_none() {
+                tracing::warn!(
+                    "skipped JSON receipt {}: leaf {:?} already belongs to another receipt",
+                    receipt.id,
+                    receipt.leaf_index
+                );
+                outcome.collisions += 1;
+            }
+        }
+        sqlx::query("INSERT INTO meta (key, value) VALUES (?, ?)")
+            .bind(JSON_IMPORT_KEY)
+            .bind(chrono::Utc::now().to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(outcome)
+    }
+}
+
+fn parse_body(body: String) -> anyhow::Result<OffsecReceipt> {
+    serde_json::from_str(&body).context("parsing stored receipt")
+}
+
+/// Receipts from the pre-SQLite `receipts/offsec` directory, in no
+/// particular order. Unreadable files are skipped.
+pub fn read_json_receipts(data_dir: &str) -> Vec<OffsecReceipt> {
+    let dir = Path::new(data_dir).join(LEGACY_RECEIPTS_DIR);
+    let mut receipts = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if let Ok(content) = fs::read_to_string(entry.path()) {
+                if let Ok(r) = serde_json::from_str::<OffsecReceipt>(&content) {
+                    receipts.push(r);
+                }
+            }
+        }
+    }
+
+    receipts
+}
@@ -0,0 +1,372 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context};
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::Utc;
+use ed25519_dalek::VerifyingKey;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    auth::{AdminReload, Authorized},
+    config::{validate_peers, MeshPeer, OffsecConfig},
+    keyring::{JwtKey, Keyring},
+    models::ErrorResponse,
+    receipts::write_receipt,
+    AppState,
+};
+
+/// Capability issuers trusted by `/api/offsec/events`, relative to the data
+/// dir. Format: `{ "did:vm:node:alice": "<hex verifying key>", ... }`.
+pub const TRUSTED_ISSUERS_FILE: &str = "trusted_issuers.json";
+/// Capability action required by `POST /offsec/admin/reload`.
+pub const RELOAD_ACTION: &str = "admin:reload";
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Mesh peers, trusted issuers and capability keys as of one load.
+#[derive(Debug, Default)]
+pub struct TrustSnapshot {
+    pub peers: BTreeMap<String, MeshPeer>,
+    pub issuers: BTreeMap<String, VerifyingKey>,
+    /// Capability JWT keys.
+    pub keyring: Keyring,
+}
+
+impl TrustSnapshot {
+    /// Peers and JWT keys from `config` as loaded at startup, issuers from
+    /// the data dir.
+    pub fn load(config: &OffsecConfig) -> anyhow::Result<Self> {
+        let peers = config
+            .mesh
+            .as_ref()
+            .map(|m| m.peers.clone())
+            .unwrap_or_default();
+        Ok(Self {
+            peers: peers.into_iter().map(|p| (p.id.clone(), p)).collect(),
+            issuers: load_trusted_issuers(&config.data_dir)?,
+            keyring: Keyring::from_config(config)?,
+        })
+    }
+
+    /// Re-read the config file (with env overrides), the issuers file and
+    /// the JWKS file. Fails without side effects if any is invalid.
+    fn reread(config: &OffsecConfig) -> anyhow::Result<Self> {
+        let fresh = match &config.config_file {
+            Some(path) => {
+                OffsecConfig::from_file(path)?.with_overrides(|name| std::env::var(name).ok())?
+            }
+            None => config.clone(),
+        };
+        let keyring = Keyring::from_config(&fresh)?;
+        if keyring.is_empty() {
+            bail!("no capability keys left after reload");
+        }
+        let peers = fresh.mesh.map(|m| m.peers).unwrap_or_default();
+        let node_id = config.mesh.as_ref().map_or("", |m| m.node_id.as_str());
+        let errors = validate_peers(&peers, node_id);
+        if !errors.is_empty() {
+            bail!("invalid mesh peers:\n  - {}", errors.join("\n  - "));
+        }
+        Ok(Self {
+            peers: peers.into_iter().map(|p| (p.id.clone(), p)).collect(),
+            issuers: load_trusted_issuers(&config.data_dir)?,
+            keyring,
+        })
+    }
+}
+
+/// The live trust stores. Readers take a cheap snapshot; reloads swap the
+/// whole snapshot at once.
+#[derive(Clone)]
+pub struct TrustStore {
+    current: Arc<RwLock<Arc<TrustSnapshot>>>,
+    /// Serialises reloads so each diff is against the snapshot it replaces.
+    reloading: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl TrustStore {
+    pub fn new(snapshot: TrustSnapshot) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(snapshot))),
+            reloading: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<TrustSnapshot> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn peer(&self, node_id: &str) -> Option<MeshPeer> {
+        self.snapshot().peers.get(node_id).cloned()
+    }
+
+    fn replace(&self, snapshot: TrustSnapshot) {
+        let mut current = match self.current.write() {
+            Ok(current) => current,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *current = Arc::new(snapshot);
+    }
+}
+
+pub fn load_trusted_issuers(data_dir: &str) -> anyhow::Result<BTreeMap<String, VerifyingKey>> {
+    let path = Path::new(data_dir).join(TRUSTED_ISSUERS_FILE);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        // No trusted issuers yet — that's fine; the operator can provision the file.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let raw: BTreeMap<String, String> =
+        serde_json::from_str(&content).with_context(|| format!("parsing {}", path.display()))?;
+    raw.into_iter()
+        .map(|(did, key_hex)| {
+            let key: [u8; 32] = hex::decode(&key_hex)
+                .ok()
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(|| anyhow!("issuer {did}: verifying key must be 32 bytes of hex"))?;
+            let vk = VerifyingKey::from_bytes(&key)
+                .map_err(|e| anyhow!("issuer {did}: invalid verifying key: {e}"))?;
+            Ok((did, vk))
+        })
+        .collect::<anyhow::Result<_>>()
+        .with_context(|| format!("in {}", path.display()))
+}
+
+/// Entries added, removed or given a new key (or URL, for peers). Added and
+/// changed entries map to their new key.
+#[derive(Debug, Default, Serialize)]
+pub struct ChangeSet {
+    pub added: BTreeMap<String, String>,
+    pub removed: Vec<String>,
+    pub changed: BTreeMap<String, String>,
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    fn between<V: PartialEq>(
+        old: &BTreeMap<String, V>,
+        new: &BTreeMap<String, V>,
+        key: impl Fn(&V) -> String,
+    ) -> Self {
+        let mut changes = Self::default();
+        for (id, value) in new {
+            match old.get(id) {
+                None => {
+                    changes.added.insert(id.clone(), key(value));
+                }
+                Some(prev) if prev != value => {
+                    changes.changed.insert(id.clone(), key(value));
+                }
+                Some(_) => {}
+            }
+        }
+        changes.removed = old
+            .keys()
+            .filter(|id| !new.contains_key(*id))
+            .cloned()
+            .collect();
+        changes
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrustReload {
+    pub trigger: String,
+    pub peers: ChangeSet,
+    pub issuers: ChangeSet,
+    pub capability_keys: ChangeSet,
+    /// `None` when nothing changed and no receipt was written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ReloadError {
+    /// The new config or issuers file is invalid; the old stores stay live.
+    Invalid(anyhow::Error),
+    /// The reload receipt could not be written; the old stores stay live.
+    Receipt(String),
+}
+
+impl std::fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReloadError::Invalid(e) => write!(f, "{e:#}"),
+            ReloadError::Receipt(e) => write!(f, "receipt write failed: {e}"),
+        }
+    }
+}
+
+/// Re-read mesh peers, trusted issuers and capability keys and swap them
+/// in. The swap happens only once an `offsec.trust.reload` receipt
+/// recording the change has been written, so every trust change is on the
+/// ledger. A reload that changes nothing writes no receipt.
+pub async fn reload(
+    state: &AppState,
+    trigger: &str,
+    actor: Option<&str>,
+) -> Result<TrustReload, ReloadError> {
+    let _reloading = state.trust.reloading.lock().await;
+    let next = TrustSnapshot::reread(&state.config).map_err(ReloadError::Invalid)?;
+    let current = state.trust.snapshot();
+    let peers = ChangeSet::between(&current.peers, &next.peers, |p| p.pubkey.clone());
+    let issuers = ChangeSet::between(&current.issuers, &next.issuers, |k| {
+        hex::encode(k.to_bytes())
+    });
+    let capability_keys = ChangeSet::between(
+        current.keyring.keys(),
+        next.keyring.keys(),
+        JwtKey::describe,
+    );
+    if peers.is_empty() && issuers.is_empty() && capability_keys.is_empty() {
+        tracing::debug!("trust stores unchanged ({}); nothing to reload", trigger);
+        return Ok(TrustReload {
+            trigger: trigger.to_string(),
+            peers,
+            issuers,
+            capability_keys,
+            receipt_id: None,
+        });
+    }
+
+    let payload = json!({
+        "type": "trust_reload",
+        "data": {
+            "trigger": trigger,
+            "actor": actor,
+            "timestamp": Utc::now().to_rfc3339(),
+            "peers": peers,
+            "issuers": issuers,
+            "capability_keys": capability_keys,
+        }
+    });
+    let receipt = write_receipt(state, "offsec.trust.reload", actor, &[], &payload)
+        .await
+        .map_err(ReloadError::Receipt)?;
+    state.trust.replace(next);
+
+    tracing::info!(
+        "trust stores reloaded ({}): peers +{} -{} ~{}, issuers +{} -{} ~{}, keys +{} -{} ~{}",
+        trigger,
+        peers.added.len(),
+        peers.removed.len(),
+        peers.changed.len(),
+        issuers.added.len(),
+        issuers.removed.len(),
+        issuers.changed.len(),
+        capability_keys.added.len(),
+        capability_keys.removed.len(),
+        capability_keys.changed.len()
+    );
+    state.ws.send_json(&payload);
+    state
+        .ws
+        .send_json(&json!({ "type": "receipt", "data": receipt }));
+
+    Ok(TrustReload {
+        trigger: trigger.to_string(),
+        peers,
+        issuers,
+        capability_keys,
+        receipt_id: Some(receipt.id),
+    })
+}
+
+async fn reload_logged(state: &AppState, trigger: &str) {
+    if let Err(e) = reload(state, trigger, None).await {
+        tracing::error!(
+            "trust reload ({}) failed; keeping current stores: {}",
+            trigger,
+            e
+        );
+    }
+}
+
+/// Files whose changes trigger a reload.
+fn watched_files(config: &OffsecConfig) -> Vec<PathBuf> {
+    let mut files = vec![Path::new(&config.data_dir).join(TRUSTED_ISSUERS_FILE)];
+    files.extend(config.config_file.clone());
+    files.extend(config.jwks_file.as_ref().map(PathBuf::from));
+    files
+}
+
+fn fingerprint(files: &[PathBuf]) -> Vec<Option<(SystemTime, u64)>> {
+    files
+        .iter()
+        .map(|path| {
+            let meta = fs::metadata(path).ok()?;
+            Some((meta.modified().ok()?, meta.len()))
+        })
+        .collect()
+}
+
+/// Reload on SIGHUP and whenever a watched file changes, for the life of the
+/// process.
+pub fn spawn_reload_triggers(state: AppState) {
+    let watcher = state.clone();
+    tokio::spawn(async move {
+        let files = watched_files(&watcher.config);
+        let mut seen = fingerprint(&files);
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = fingerprint(&files);
+            if now != seen {
+                seen = now;
+                reload_logged(&watcher, "file_change").await;
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!("cannot listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            reload_logged(&state, "sighup").await;
+        }
+    });
+}
+
+/// `POST /offsec/admin/reload`: reload trust stores now. Requires a
+/// capability token allowing `admin:reload`.
+pub async fn admin_reload(
+    State(state): State<AppState>,
+    Authorized(principal, _): Authorized<AdminReload>,
+) -> Result<Json<TrustReload>, (StatusCode, Json<ErrorResponse>)> {
+    match reload(&state, "admin", Some(&principal.sub)).await {
+        Ok(result) => Ok(Json(result)),
+        Err(err) => {
+            let code = match err {
+                ReloadError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                ReloadError::Receipt(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((
+                code,
+                Json(ErrorResponse {
+                    error: "trust reload failed".to_string(),
+                    details: Some(err.to_string()),
+                }),
+            ))
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Write-ahead record for the receipt commit in progress, relative to the
+/// data dir. Present only between the first file write of a commit and the
+/// receipt landing in the store.
+pub const PENDING_FILE: &str = "PENDING.json";
+
+/// A receipt commit that has not finished yet. Startup rolls the data dir
+/// back to `leaf_index` leaves while one exists.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingWrite {
+    pub leaf_index: usize,
+    pub leaf: String,
+    pub root: String,
+}
+
+/// Replace `path` with `contents`: write a sibling temp file, fsync it,
+/// rename it over `path` and fsync the directory.
+pub fn atomic_write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = dir.join(tmp_name);
+
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    sync_dir(dir)
+}
+
+/// Remove `path` (if present) and fsync its directory.
+pub fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => sync_dir(path.parent().unwrap_or(Path::new("."))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+pub fn begin(data_dir: &str, pending: &PendingWrite) -> io::Result<()> {
+    let content = serde_json::to_vec(pending).map_err(io::Error::other)?;
+    atomic_write(&Path::new(data_dir).join(PENDING_FILE), &content)
+}
+
+pub fn read_pending(data_dir: &str) -> anyhow::Result<Option<PendingWrite>> {
+    let path = Path::new(data_dir).join(PENDING_FILE);
+    match fs::read(&path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .with_context(|| format!("parsing {}", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+pub fn clear(data_dir: &str) -> io::Result<()> {
+    remove(&Path::new(data_dir).join(PENDING_FILE))
+}
@@ -0,0 +1,279 @@
+mod common;
+
+use std::path::Path;
+
+use axum::http::StatusCode;
+use common::{apply, config, get, post, send, token};
+use portal_ext::{
+    actions::{ActionState, NewAction, TransitionError},
+    build_state, AppState,
+};
+use serde_json::{json, Value};
+
+/// A Guardian action server that accepts every forwarded action.
+async fn guardian() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let app = axum::Router::new().route(
+        "/actions/apply",
+        axum::routing::post(|| async { axum::Json(json!({ "status": "accepted" })) }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    url
+}
+
+async fn state(dir: &Path, guardian_url: String) -> AppState {
+    let mut config = config(dir);
+    config.guardian_url = Some(guardian_url);
+    // Undeliverable actions are dead-lettered on the first attempt.
+    config.dispatch.max_attempts = 1;
+    build_state(config).await.expect("state")
+}
+
+async fn report(state: &AppState, action_id: &str, status: &str) -> (StatusCode, Value) {
+    send(
+        state,
+        post(
+            "/offsec/action/update",
+            Some(&token("guardian-a", vec!["action:report"])),
+            json!({
+                "action_id": action_id,
+                "action_type": "block_ip",
+                "status": status,
+                "details": { "ok": status != "failed" },
+                "ts": "2025-11-23T01:34:00Z",
+            }),
+        ),
+    )
+    .await
+}
+
+fn states(record: &Value) -> Vec<&str> {
+    record["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|h| h["to"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn lifecycle_only_moves_forward() {
+    use ActionState::*;
+    assert!(Requested.can_transition(Approved));
+    assert!(Approved.can_transition(Dispatched));
+    assert!(Dispatched.can_transition(Executed));
+    assert!(Dispatched.can_transition(Failed));
+    assert!(Executed.can_transition(RolledBack));
+    assert!(!Requested.can_transition(Dispatched));
+    assert!(!Approved.can_transition(Executed));
+    assert!(!Executed.can_transition(Failed));
+    assert!(!Failed.can_transition(Executed));
+    assert!(!RolledBack.can_transition(Executed));
+
+    assert_eq!(ActionState::from_result_status("applied"), Some(Executed));
+    assert_eq!(ActionState::from_result_status("failed"), Some(Failed));
+    assert_eq!(ActionState::from_result_status("accepted"), None);
+}
+
+#[tokio::test]
+async fn transitions_are_checked_against_the_stored_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), "http://127.0.0.1:1".to_string()).await;
+    let new = NewAction {
+        action_id: "act-1".to_string(),
+        action_type: "block_ip".to_string(),
+        target: json!({ "ip": "10.1.2.3" }),
+        guardian_id: Some("guardian-a".to_string()),
+        requested_by: None,
+        approval: None,
+    };
+    let record = state.actions.create(&new, "operator").await.unwrap();
+    assert_eq!(record.unwrap().state, ActionState::Requested);
+    assert!(state
+        .actions
+        .create(&new, "operator")
+        .await
+        .unwrap()
+        .is_none());
+
+    let approve = || {
+        state.actions.transition(
+            "act-1",
+            ActionState::Requested,
+            ActionState::Approved,
+            "operator",
+            None,
+            None,
+        )
+    };
+    assert_eq!(approve().await.unwrap().state, ActionState::Approved);
+    // A second approval lost the race: the action is no longer requested.
+    match approve().await {
+        Err(TransitionError::Invalid { from, .. }) => assert_eq!(from, ActionState::Approved),
+        other => panic!("expected an invalid transition, got {other:?}"),
+    }
+    assert!(matches!(
+        state
+            .actions
+            .transition(
+                "act-404",
+                ActionState::Requested,
+                ActionState::Approved,
+                "operator",
+                None,
+                None
+            )
+            .await,
+        Err(TransitionError::Unknown(_))
+    ));
+}
+
+#[tokio::test]
+async fn apply_and_report_build_the_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), guardian().await).await;
+
+    let (status, body) = apply(&state, "act-1", "block_ip").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["state"], "dispatched");
+    let dispatch_receipt = state.store.list(1, None).await.unwrap()[0].id.clone();
+
+    let (_, record) = send(&state, get("/offsec/actions/act-1")).await;
+    assert_eq!(record["state"], "dispatched");
+    assert_eq!(states(&record), vec!["requested", "approved", "dispatched"]);
+    assert_eq!(record["history"][0]["actor"], "operator");
+    // The request and the delivery are receipted.
+    assert_eq!(record["receipt_ids"].as_array().unwrap().len(), 2);
+    assert_eq!(record["receipt_ids"][0], record["history"][0]["receipt_id"]);
+    assert_eq!(record["receipt_ids"][1], json!(dispatch_receipt));
+
+    let (status, body) = report(&state, "act-1", "applied").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["state"], "executed");
+    let result_receipt = state.store.list(1, None).await.unwrap()[0].id.clone();
+
+    // Reporting again is not a valid transition and writes no receipt.
+    let (status, body) = report(&state, "act-1", "failed").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "invalid_transition");
+    assert_eq!(state.store.count().await.unwrap(), 3);
+
+    let (status, _) = report(&state, "act-1", "rolled_back").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, record) = send(&state, get("/offsec/actions/act-1")).await;
+    assert_eq!(
+        states(&record),
+        vec![
+            "requested",
+            "approved",
+            "dispatched",
+            "executed",
+            "rolled_back"
+        ]
+    );
+    assert_eq!(record["history"][3]["receipt_id"], json!(result_receipt));
+    assert_eq!(record["history"][3]["details"]["ok"], true);
+    assert_eq!(record["receipt_ids"].as_array().unwrap().len(), 4);
+
+    let (status, body) = report(&state, "act-1", "accepted").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "unknown_action_status");
+}
+
+#[tokio::test]
+async fn unreachable_guardian_fails_the_action() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), "http://127.0.0.1:1".to_string()).await;
+
+    let (status, body) = apply(&state, "act-1", "block_ip").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["state"], "failed");
+    let (_, record) = send(&state, get("/offsec/actions/act-1")).await;
+    assert_eq!(
+        states(&record),
+        vec!["requested", "approved", "dispatched", "failed"]
+    );
+    assert!(record["history"][3]["details"]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("dispatch failed"));
+
+    let (status, _) = report(&state, "act-1", "applied").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn action_ids_are_unique_and_listable() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), guardian().await).await;
+
+    assert_eq!(apply(&state, "act-1", "block_ip").await.0, StatusCode::OK);
+    let receipts = state.store.count().await.unwrap();
+    let (status, body) = apply(&state, "act-1", "block_ip").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "duplicate_action");
+    assert_eq!(state.store.count().await.unwrap(), receipts);
+
+    let (status, _) = send(
+        &state,
+        post(
+            "/offsec/action",
+            Some(&token("guardian-b", vec!["alert_human"])),
+            json!({
+                "id": "act-2",
+                "event_id": "evt-1",
+                "action": "alert_human",
+                "target": "10.1.2.3",
+                "reason": "test",
+                "created_at": "2025-11-23T01:33:22Z",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, record) = send(&state, get("/offsec/actions/act-2")).await;
+    assert_eq!(record["guardian_id"], "guardian-b");
+    assert_eq!(record["requested_by"], "guardian-b");
+    assert_eq!(states(&record), vec!["requested", "approved", "dispatched"]);
+    assert_eq!(record["receipt_ids"].as_array().unwrap().len(), 1);
+
+    // A duplicate is refused before it is receipted or broadcast.
+    let receipts = state.store.count().await.unwrap();
+    let mut frames = state.ws.subscribe();
+    let (status, body) = send(
+        &state,
+        post(
+            "/offsec/action",
+            Some(&token("guardian-b", vec!["alert_human"])),
+            json!({
+                "id": "act-2",
+                "event_id": "evt-2",
+                "action": "alert_human",
+                "target": "10.1.2.3",
+                "reason": "test",
+                "created_at": "2025-11-23T01:33:22Z",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "duplicate_action");
+    assert_eq!(state.store.count().await.unwrap(), receipts);
+    assert!(frames.try_recv().is_err());
+
+    assert_eq!(report(&state, "act-1", "applied").await.0, StatusCode::OK);
+    let (_, all) = send(&state, get("/offsec/actions")).await;
+    assert_eq!(all.as_array().unwrap().len(), 2);
+    let (_, executed) = send(&state, get("/offsec/actions?state=executed")).await;
+    assert_eq!(executed.as_array().unwrap().len(), 1);
+    assert_eq!(executed[0]["action_id"], "act-1");
+    let (_, mine) = send(&state, get("/offsec/actions?guardian_id=guardian-b")).await;
+    assert_eq!(mine[0]["action_id"], "act-2");
+
+    let (status, _) = send(&state, get("/offsec/actions?state=bogus")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = send(&state, get("/offsec/actions/act-404")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], "unknown_action");
+}
@@ -0,0 +1,330 @@
+mod common;
+
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use axum::http::StatusCode;
+use common::{apply, apply_as, config, post, send, token};
+use portal_ext::{approvals, build_state, AppState};
+use serde_json::{json, Value};
+
+/// A Guardian action server that counts the actions forwarded to it.
+async fn guardian() -> (String, Arc<AtomicUsize>) {
+    let forwarded = Arc::new(AtomicUsize::new(0));
+    let counter = forwarded.clone();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let app = axum::Router::new().route(
+        "/actions/apply",
+        axum::routing::post(move || async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            axum::Json(json!({ "status": "accepted" }))
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, forwarded)
+}
+
+/// `quarantine` needs two approvers.
+async fn state(dir: &Path, guardian_url: String, timeout_seconds: u64) -> AppState {
+    let mut config = config(dir);
+    config.guardian_url = Some(guardian_url);
+    config
+        .approvals
+        .required
+        .insert("quarantine".to_string(), 2);
+    config.approvals.timeout_seconds = timeout_seconds;
+    build_state(config).await.expect("state")
+}
+
+async fn decide(
+    state: &AppState,
+    action_id: &str,
+    who: &str,
+    decision: &str,
+) -> (StatusCode, Value) {
+    send(
+        state,
+        post(
+            &format!("/offsec/actions/{action_id}/{decision}"),
+            Some(&token(who, vec!["approve:quarantine"])),
+            json!({ "reason": format!("{who} checked it") }),
+        ),
+    )
+    .await
+}
+
+async fn record(state: &AppState, action_id: &str) -> Value {
+    serde_json::to_value(state.actions.get(action_id).await.unwrap().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn quarantine_waits_for_two_distinct_approvers() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, forwarded) = guardian().await;
+    let state = state(dir.path(), url, 600).await;
+    let mut frames = state.ws.subscribe();
+
+    let requester = token("operator", vec!["quarantine", "approve:*"]);
+    let (status, body) = apply_as(&state, &requester, "act-1", "quarantine").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "pending_approval");
+    assert_eq!(body["state"], "requested");
+    let frame: Value = serde_json::from_str(&frames.try_recv().unwrap()).unwrap();
+    assert_eq!(frame["data"]["approval"]["required"], 2);
+
+    // The requester holds approve:* but cannot sign off on its own action.
+    let (status, body) = decide(&state, "act-1", "operator", "approve").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "self_approval");
+    let (status, _) = send(
+        &state,
+        post(
+            "/offsec/actions/act-1/approve",
+            Some(&token("alice", vec!["approve:block_ip"])),
+            json!({}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = decide(&state, "act-1", "alice", "approve").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "pending_approval");
+    assert_eq!(body["approvals"], 1);
+    let (status, body) = decide(&state, "act-1", "alice", "approve").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "already_decided");
+    assert_eq!(forwarded.load(Ordering::SeqCst), 0);
+
+    let (status, body) = decide(&state, "act-1", "bob", "approve").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "approved");
+    assert_eq!(body["state"], "dispatched");
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+
+    let record = record(&state, "act-1").await;
+    let states: Vec<_> = record["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|h| h["to"].as_str().unwrap())
+        .collect();
+    assert_eq!(states, vec!["requested", "approved", "dispatched"]);
+    assert_eq!(record["history"][1]["actor"], "bob");
+    assert_eq!(
+        record["history"][1]["details"]["approved_by"],
+        json!(["alice", "bob"])
+    );
+    assert_eq!(record["approval"]["decisions"].as_array().unwrap().len(), 2);
+    // The request, both approvals and the delivery are receipted.
+    assert_eq!(record["receipt_ids"].as_array().unwrap().len(), 4);
+    let receipts = state.store.list(2, None).await.unwrap();
+    assert_eq!(receipts[0].event_type, "offsec.action.dispatch");
+    assert_eq!(receipts[1].event_type, "offsec.action.approval");
+    assert_eq!(receipts[1].payload.as_ref().unwrap()["approver"], "bob");
+
+    let frames: Vec<Value> = std::iter::from_fn(|| frames.try_recv().ok())
+        .map(|f| serde_json::from_str(&f).unwrap())
+        .filter(|f: &Value| f["type"] == "offsec.action.approval")
+        .collect();
+    assert_eq!(frames.len(), 2);
+}
+
+#[tokio::test]
+async fn one_denial_fails_the_action() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, forwarded) = guardian().await;
+    let state = state(dir.path(), url, 600).await;
+    apply(&state, "act-1", "quarantine").await;
+
+    assert_eq!(
+        decide(&state, "act-1", "alice", "approve").await.0,
+        StatusCode::OK
+    );
+    let (status, body) = decide(&state, "act-1", "bob", "deny").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "denied");
+    assert_eq!(body["state"], "failed");
+
+    let (status, body) = decide(&state, "act-1", "carol", "approve").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "not_pending_approval");
+    assert_eq!(forwarded.load(Ordering::SeqCst), 0);
+    let record = record(&state, "act-1").await;
+    assert_eq!(record["history"][1]["details"]["denied_by"], "bob");
+    assert_eq!(state.store.count().await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn pending_actions_expire() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, forwarded) = guardian().await;
+    let state = state(dir.path(), url, 1).await;
+    apply(&state, "act-1", "quarantine").await;
+    apply(&state, "act-2", "quarantine").await;
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    let (status, body) = decide(&state, "act-1", "alice", "approve").await;
+    assert_eq!(status, StatusCode::GONE);
+    assert_eq!(body["error"], "approval_expired");
+    assert_eq!(approvals::expire_pending(&state).await, 1);
+    assert_eq!(approvals::expire_pending(&state).await, 0);
+
+    for id in ["act-1", "act-2"] {
+        let record = record(&state, id).await;
+        assert_eq!(record["state"], "failed");
+        assert_eq!(record["history"][1]["actor"], "portal");
+        assert_eq!(record["history"][1]["details"]["expired"], true);
+    }
+    let receipt = &state.store.list(1, None).await.unwrap()[0];
+    assert_eq!(receipt.payload.as_ref().unwrap()["decision"], "expired");
+    assert_eq!(forwarded.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn unlisted_actions_skip_approval() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, forwarded) = guardian().await;
+    let state = state(dir.path(), url, 600).await;
+
+    let (status, body) = apply(&state, "act-1", "alert_human").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["state"], "dispatched");
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+
+    let (status, body) = send(
+        &state,
+        post(
+            "/offsec/actions/act-1/approve",
+            Some(&token("alice", vec!["approve:alert_human"])),
+            json!({}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "approval_not_required");
+}
+
+#[tokio::test]
+async fn guardian_actions_under_the_policy_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, forwarded) = guardian().await;
+    let state = state(dir.path(), url, 600).await;
+
+    let (status, body) = send(
+        &state,
+        post(
+            "/offsec/action",
+            Some(&token("guardian-a", vec!["quarantine"])),
+            json!({
+                "id": "act-1",
+                "event_id": "evt-1",
+                "action": "quarantine",
+                "target": "web-1.lab.internal",
+                "reason": "test",
+                "created_at": "2025-11-23T01:33:22Z",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "approval_required");
+    assert!(state.actions.get("act-1").await.unwrap().is_none());
+    assert_eq!(state.store.count().await.unwrap(), 0);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn concurrent_approvals_reach_the_threshold() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, forwarded) = guardian().await;
+    let state = state(dir.path(), url, 600).await;
+    apply(&state, "act-1", "quarantine").await;
+
+    let ((first, a), (second, b)) = tokio::join!(
+        decide(&state, "act-1", "alice", "approve"),
+        decide(&state, "act-1", "bob", "approve"),
+    );
+    assert_eq!(first, StatusCode::OK, "{a}");
+    assert_eq!(second, StatusCode::OK, "{b}");
+    // Whichever decided last saw both approvals.
+    assert!(
+        a["status"] == "approved" || b["status"] == "approved",
+        "{a} {b}"
+    );
+
+    let record = record(&state, "act-1").await;
+    assert_eq!(record["state"], "dispatched");
+    assert_eq!(record["approval"]["decisions"].as_array().unwrap().len(), 2);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn only_decisions_that_win_are_receipted() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, _) = guardian().await;
+    let state = state(dir.path(), url, 600).await;
+    apply(&state, "act-1", "quarantine").await;
+
+    let ((first, _), (second, _)) = tokio::join!(
+        decide(&state, "act-1", "alice", "approve"),
+        decide(&state, "act-1", "alice", "approve"),
+    );
+    assert_eq!(
+        [first, second]
+            .iter()
+            .filter(|s| **s == StatusCode::OK)
+            .count(),
+        1
+    );
+    assert_eq!(state.store.count().await.unwrap(), 2);
+
+    // A denial and the approval that would reach the threshold race; a
+    // decision refused for losing leaves no receipt behind.
+    let ((approved, _), (denied, _)) = tokio::join!(
+        decide(&state, "act-1", "bob", "approve"),
+        decide(&state, "act-1", "carol", "deny"),
+    );
+    let accepted = [approved, denied]
+        .iter()
+        .filter(|s| **s == StatusCode::OK)
+        .count();
+    assert!(accepted >= 1);
+    let receipts = state.store.list(10, None).await.unwrap();
+    let approvals = receipts
+        .iter()
+        .filter(|r| r.event_type == "offsec.action.approval")
+        .count();
+    assert_eq!(approvals, 1 + accepted);
+
+    let record = record(&state, "act-1").await;
+    let decisions = record["approval"]["decisions"].as_array().unwrap();
+    assert_eq!(decisions.len(), 1 + accepted);
+    assert!(decisions.iter().all(|d| d["receipt_id"].is_string()));
+}
+
+#[tokio::test]
+async fn racing_expiries_receipt_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, _) = guardian().await;
+    let state = state(dir.path(), url, 1).await;
+    apply(&state, "act-1", "quarantine").await;
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    let (first, second) = tokio::join!(
+        approvals::expire_pending(&state),
+        approvals::expire_pending(&state),
+    );
+    assert_eq!(first + second, 1);
+    let receipts = state.store.list(10, None).await.unwrap();
+    assert_eq!(receipts.len(), 2);
+    assert_eq!(receipts[0].payload.as_ref().unwrap()["decision"], "expired");
+    let record = record(&state, "act-1").await;
+    assert_eq!(record["history"][1]["receipt_id"], json!(receipts[0].id));
+}
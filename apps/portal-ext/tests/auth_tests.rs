@@ -0,0 +1,215 @@
+mod common;
+
+use std::path::Path;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use civilization_ledger_core::capability::Capability;
+use common::{config, post, send, token};
+use ed25519_dalek::{Signer, SigningKey};
+use portal_ext::{
+    auth::{grant_matches, verify_bearer, TokenFormat},
+    build_state, trust, AppState,
+};
+use serde_json::{json, Value};
+
+const ISSUER: &str = "did:vm:node:issuer";
+
+async fn state(dir: &Path) -> AppState {
+    let mut config = config(dir);
+    config.guardian_url = Some("http://127.0.0.1:1".to_string());
+    std::fs::create_dir_all(dir).unwrap();
+    let issuer = SigningKey::from_bytes(&[4; 32]).verifying_key();
+    std::fs::write(
+        dir.join(trust::TRUSTED_ISSUERS_FILE),
+        json!({ ISSUER: hex::encode(issuer.to_bytes()) }).to_string(),
+    )
+    .unwrap();
+    build_state(config).await.expect("state")
+}
+
+/// A base64 ledger capability signed the way `offsec_ledger` verifies it.
+fn ledger_cap(scopes: Vec<&str>, constraints: Value) -> String {
+    let mut cap = Capability {
+        sub: "did:vm:node:guardian".to_string(),
+        scopes: scopes.into_iter().map(str::to_string).collect(),
+        constraints,
+        issued_by: ISSUER.to_string(),
+        exp: chrono::Utc::now() + chrono::Duration::seconds(600),
+        signature: String::new(),
+    };
+    let unsigned = json!({
+        "sub": cap.sub,
+        "scopes": cap.scopes,
+        "constraints": cap.constraints,
+        "issued_by": cap.issued_by,
+        "exp": cap.exp,
+    });
+    let sig = SigningKey::from_bytes(&[4; 32]).sign(&serde_json::to_vec(&unsigned).unwrap());
+    cap.signature = hex::encode(sig.to_bytes());
+    BASE64.encode(serde_json::to_vec(&cap).unwrap())
+}
+
+fn ingest_body() -> Value {
+    json!({
+        "id": "evt-1",
+        "timestamp": "2025-11-23T01:33:22Z",
+        "severity": "high",
+        "event_type": "brute_force",
+        "source": "ssh",
+        "description": "failed auth",
+        "affected": ["192.168.1.1"],
+        "metadata": {}
+    })
+}
+
+fn apply_body(ip: &str) -> Value {
+    json!({
+        "action_id": "act-1",
+        "action_type": "block_ip",
+        "target": { "ip": ip },
+        "ts": "2025-11-23T01:33:22Z",
+    })
+}
+
+async fn status(state: &AppState, request: Request<Body>) -> StatusCode {
+    send(state, request).await.0
+}
+
+#[test]
+fn grants_use_one_wildcard_model() {
+    assert!(grant_matches("ingest", "ingest"));
+    assert!(grant_matches("*", "block_ip"));
+    assert!(grant_matches("admin:*", "admin:reload"));
+    assert!(grant_matches("infrastructure:*", "infrastructure:write"));
+    assert!(!grant_matches("admin:*", "admin"));
+    assert!(!grant_matches("admin:*", "infrastructure:write"));
+    assert!(!grant_matches("block", "block_ip"));
+}
+
+#[tokio::test]
+async fn both_formats_produce_a_principal() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+
+    let principal = verify_bearer(&state, &token("guardian-1", vec!["ingest"])).unwrap();
+    assert_eq!(principal.format, TokenFormat::Jwt);
+    assert_eq!(principal.sub, "guardian-1");
+    assert!(principal.token_id.is_some());
+
+    let principal = verify_bearer(
+        &state,
+        &ledger_cap(
+            vec!["offsec:*"],
+            json!({ "block_ip": { "cidrs": ["10.0.0.0/8"] } }),
+        ),
+    )
+    .unwrap();
+    assert_eq!(principal.format, TokenFormat::Ledger);
+    assert!(principal.allows("offsec:anything"));
+    assert!(!principal.allows("ingest"));
+    assert_eq!(
+        principal.scope("block_ip").unwrap().cidrs,
+        vec!["10.0.0.0/8"]
+    );
+
+    assert!(verify_bearer(&state, "not a token").is_err());
+    let untrusted = {
+        let cap = ledger_cap(vec!["ingest"], json!({}));
+        let mut decoded: Value = serde_json::from_slice(&BASE64.decode(cap).unwrap()).unwrap();
+        decoded["issued_by"] = json!("did:vm:node:stranger");
+        BASE64.encode(decoded.to_string())
+    };
+    assert!(verify_bearer(&state, &untrusted).is_err());
+}
+
+#[tokio::test]
+async fn ledger_capabilities_work_on_offsec_routes() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+
+    let cap = ledger_cap(vec!["ingest"], json!({}));
+    assert_eq!(
+        status(&state, post("/offsec/ingest", Some(&cap), ingest_body())).await,
+        StatusCode::OK
+    );
+
+    // Ledger constraints are enforced like JWT scopes.
+    let cap = ledger_cap(
+        vec!["block_ip"],
+        json!({ "block_ip": { "cidrs": ["10.0.0.0/8"] } }),
+    );
+    assert_eq!(
+        status(
+            &state,
+            post(
+                "/offsec/action/apply",
+                Some(&cap),
+                apply_body("192.168.1.5")
+            )
+        )
+        .await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        status(
+            &state,
+            post("/offsec/action/apply", Some(&cap), apply_body("10.1.2.3"))
+        )
+        .await,
+        StatusCode::OK
+    );
+    // The signature is the token id, so the capability is single-use for
+    // destructive actions too.
+    assert_eq!(
+        status(
+            &state,
+            post("/offsec/action/apply", Some(&cap), apply_body("10.1.2.3"))
+        )
+        .await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn infrastructure_events_use_the_same_checks() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    let mut frames = state.ws.subscribe();
+
+    assert_eq!(
+        status(&state, post("/api/offsec/events", None, json!({}))).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let frame: Value = serde_json::from_str(&frames.try_recv().unwrap()).unwrap();
+    assert_eq!(frame["data"]["metadata"]["action"], "infrastructure:write");
+
+    assert_eq!(
+        status(
+            &state,
+            post(
+                "/api/offsec/events",
+                Some(&token("guardian-1", vec!["ingest"])),
+                json!({})
+            )
+        )
+        .await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        status(
+            &state,
+            post(
+                "/api/offsec/events",
+                Some(&ledger_cap(vec!["ingest"], json!({}))),
+                json!({})
+            )
+        )
+        .await,
+        StatusCode::FORBIDDEN
+    );
+}
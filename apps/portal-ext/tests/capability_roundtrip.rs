@@ -1,27 +1,31 @@
-use std::{collections::HashMap, env, fs};
+mod common;
 
-use base64;
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use civilization_ledger_core::capability::Capability;
+use common::config;
 use ed25519_dalek::{Signer, SigningKey};
-use hex;
-use rand::rngs::OsRng;
-
-#[test]
-fn capability_validation_roundtrip() {
-    // generate keypair
-    let mut csprng = OsRng {};
-    let kp = SigningKey::generate(&mut csprng);
+use portal_ext::{auth::verify_bearer, build_state, trust::TRUSTED_ISSUERS_FILE};
 
-    // write trusted_issuers.json into temp dir and set OFFSEC_DATA_DIR
-    let tmpdir = tempfile::tempdir().expect("tmpdir");
-    let base = tmpdir.path().to_path_buf();
-    env::set_var("OFFSEC_DATA_DIR", base.to_str().unwrap());
+#[tokio::test]
+async fn capability_validation_roundtrip() {
+    let key = SigningKey::from_bytes(&[9; 32]);
 
-    let vk_hex = hex::encode(kp.verifying_key().to_bytes());
+    // Trust the issuer through trusted_issuers.json in the data dir.
+    let dir = tempfile::tempdir().expect("tmpdir");
     let mut issuers = HashMap::new();
-    issuers.insert("did:vm:node:test".to_string(), vk_hex.clone());
-    let issuers_json = serde_json::to_string(&issuers).expect("json");
-    fs::write(base.join("trusted_issuers.json"), issuers_json).expect("write issuers");
+    issuers.insert(
+        "did:vm:node:test".to_string(),
+        hex::encode(key.verifying_key().to_bytes()),
+    );
+    std::fs::write(
+        dir.path().join(TRUSTED_ISSUERS_FILE),
+        serde_json::to_string(&issuers).expect("json"),
+    )
+    .expect("write issuers");
+    let state = build_state(config(dir.path())).await.expect("state");
 
     // Build capability struct (no signature)
     let exp = chrono::Utc::now() + chrono::Duration::seconds(3600);
@@ -36,27 +40,25 @@ fn capability_validation_roundtrip() {
 
     // Build canonical unsigned json: { sub, scopes, constraints, issued_by, exp }
     let unsigned = serde_json::json!({
-        "sub": cap.sub.clone(),
-        "scopes": cap.scopes.clone(),
-        "constraints": cap.constraints.clone(),
-        "issued_by": cap.issued_by.clone(),
-        "exp": cap.exp.clone(),
+        "sub": cap.sub,
+        "scopes": cap.scopes,
+        "constraints": cap.constraints,
+        "issued_by": cap.issued_by,
+        "exp": cap.exp,
     });
     let unsigned_bytes = serde_json::to_vec(&unsigned).expect("unsigned bytes");
-
-    // Sign
-    let sig = kp.sign(&unsigned_bytes);
-    cap.signature = hex::encode(sig.to_bytes());
+    cap.signature = hex::encode(key.sign(&unsigned_bytes).to_bytes());
 
     // Build final JSON, base64 encode
-    let cap_json = serde_json::to_vec(&cap).expect("cap json");
-    let cap_b64 = base64::encode(&cap_json);
+    let cap_b64 = BASE64.encode(serde_json::to_vec(&cap).expect("cap json"));
 
-    // Call the validate function from offsec_ledger.rs
-    let parsed =
-        portal_ext::offsec_ledger::validate_capability_base64(&cap_b64, "infrastructure:write")
-            .expect("validation failed");
+    let principal = verify_bearer(&state, &cap_b64).expect("validation failed");
+    assert_eq!(principal.sub, "test-sub");
+    assert!(principal.allows("infrastructure:write"));
+    assert!(!principal.allows("block_ip"));
 
-    assert_eq!(parsed.issued_by, "did:vm:node:test");
-    assert!(parsed.scopes.iter().any(|s| s == "infrastructure:write"));
+    // A signature over different claims is refused.
+    cap.sub = "someone-else".to_string();
+    let forged = BASE64.encode(serde_json::to_vec(&cap).expect("cap json"));
+    assert!(verify_bearer(&state, &forged).is_err());
 }
@@ -0,0 +1,112 @@
+//! Helpers shared by the integration tests. Each test binary pulls this in
+//! with `mod common;` and uses only part of it.
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use portal_ext::{build_state, AppState, OffsecConfig};
+use serde_json::{json, Value};
+use tower::util::ServiceExt;
+
+pub const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+/// A default config writing to `dir` and accepting HS256 tokens signed
+/// with `SECRET`.
+pub fn config(dir: &Path) -> OffsecConfig {
+    config_toml(dir, "")
+}
+
+/// [`config`] on top of `toml`.
+pub fn config_toml(dir: &Path, toml: &str) -> OffsecConfig {
+    let mut config = OffsecConfig::from_toml(toml).unwrap();
+    config.data_dir = dir.display().to_string();
+    config.jwt_hs256_secret = Some(SECRET.to_string());
+    config
+}
+
+pub async fn state(dir: &Path) -> AppState {
+    build_state(config(dir)).await.expect("state")
+}
+
+pub fn token(sub: &str, actions: Vec<&str>) -> String {
+    let now = chrono::Utc::now().timestamp();
+    let claims = json!({
+        "sub": sub,
+        "aud": "offsec-portal",
+        "exp": now + 600,
+        "iat": now,
+        "actions": actions,
+        "jti": uuid::Uuid::new_v4().to_string(),
+    });
+    encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+pub fn post(path: &str, token: Option<&str>, body: Value) -> Request<Body> {
+    let mut request = Request::post(path).header(header::CONTENT_TYPE, "application/json");
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+/// `operator` asks for `action_type` on 10.1.2.3 through
+/// `/offsec/action/apply`.
+pub async fn apply(state: &AppState, action_id: &str, action_type: &str) -> (StatusCode, Value) {
+    apply_as(
+        state,
+        &token("operator", vec![action_type]),
+        action_id,
+        action_type,
+    )
+    .await
+}
+
+/// [`apply`] with `token` as the requester's capability.
+pub async fn apply_as(
+    state: &AppState,
+    token: &str,
+    action_id: &str,
+    action_type: &str,
+) -> (StatusCode, Value) {
+    send(
+        state,
+        post(
+            "/offsec/action/apply",
+            Some(token),
+            json!({
+                "action_id": action_id,
+                "action_type": action_type,
+                "target": { "ip": "10.1.2.3" },
+                "ts": "2025-11-23T01:33:22Z",
+                "guardian_id": "guardian-a",
+            }),
+        ),
+    )
+    .await
+}
+
+pub fn get(path: &str) -> Request<Body> {
+    Request::get(path).body(Body::empty()).unwrap()
+}
+
+pub async fn send(state: &AppState, request: Request<Body>) -> (StatusCode, Value) {
+    let response = portal_ext::app_router(state.clone())
+        .oneshot(request)
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
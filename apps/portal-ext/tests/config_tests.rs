@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::SigningKey;
+use portal_ext::config::{DurabilityMode, OffsecConfig};
+
+fn no_env(_: &str) -> Option<String> {
+    None
+}
+
+fn write_key(dir: &std::path::Path, name: &str, seed: u8) -> (String, String) {
+    let key = SigningKey::from_bytes(&[seed; 32]);
+    let path = dir.join(name);
+    std::fs::write(&path, key.to_bytes()).unwrap();
+    (
+        path.to_string_lossy().into_owned(),
+        BASE64.encode(key.verifying_key().to_bytes()),
+    )
+}
+
+fn mesh_toml(dir: &std::path::Path, peers: &str) -> String {
+    let (privkey, _) = write_key(dir, "node.key", 1);
+    format!(
+        r#"
+[server]
+listen = "127.0.0.1:9200"
+log_level = "info"
+
+[vaultmesh]
+portal_url = "http://vaultmesh:9110"
+timeout_secs = 30
+
+[guardian]
+api_url = "http://localhost:8001"
+jwt_hs256_secret = "dev-secret"
+
+[portal]
+data_dir = "{data}"
+jwt_hs256_secret = "0123456789abcdef0123456789abcdef"
+durability = "best-effort"
+
+[mesh]
+node_id = "shield-lon-01"
+privkey_file = "{privkey}"
+interval_seconds = 30
+{peers}
+"#,
+        data = dir.join("data").display(),
+    )
+}
+
+#[tokio::test]
+async fn toml_config_loads_mesh_section() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, peer_pub) = write_key(dir.path(), "peer.key", 2);
+    let toml = mesh_toml(
+        dir.path(),
+        &format!(
+            "[[mesh.peers]]\nid = \"shield-nyc-01\"\nurl = \"https://nyc.example.com\"\npubkey = \"{peer_pub}\"\n"
+        ),
+    );
+
+    let config = OffsecConfig::from_toml(&toml).unwrap();
+    config.validate().unwrap();
+
+    assert_eq!(config.listen, "127.0.0.1:9200");
+    assert_eq!(config.vaultmesh_url, "http://vaultmesh:9110");
+    assert_eq!(config.durability, DurabilityMode::BestEffort);
+    // [guardian] belongs to the guardian agent and is not read.
+    assert_eq!(
+        config.jwt_hs256_secret.as_deref(),
+        Some("0123456789abcdef0123456789abcdef")
+    );
+    assert_eq!(config.mesh.as_ref().unwrap().interval_seconds, 30);
+    assert!(dir.path().join("data").is_dir());
+
+    let state = portal_ext::build_state(config).await.expect("state");
+    let peer = state.trust.peer("shield-nyc-01").expect("peer");
+    assert_eq!(peer.pubkey, peer_pub);
+}
+
+#[test]
+fn env_overrides_file_values() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, peer_pub) = write_key(dir.path(), "peer.key", 3);
+    let env: HashMap<&str, String> = [
+        ("OFFSEC_LISTEN", "0.0.0.0:9300".to_string()),
+        ("OFFSEC_DURABILITY", "strict".to_string()),
+        ("OFFSEC_MESH_NODE_ID", "shield-gamma".to_string()),
+        (
+            "OFFSEC_MESH_PEERS",
+            format!(
+                r#"[{{"id":"shield-beta","url":"http://10.0.0.2:9115","pubkey":"{peer_pub}"}}]"#
+            ),
+        ),
+    ]
+    .into_iter()
+    .collect();
+
+    let config = OffsecConfig::from_toml(&mesh_toml(dir.path(), ""))
+        .unwrap()
+        .with_overrides(|name| env.get(name).cloned())
+        .unwrap();
+    config.validate().unwrap();
+
+    assert_eq!(config.listen, "0.0.0.0:9300");
+    assert_eq!(config.durability, DurabilityMode::Strict);
+    let mesh = config.mesh.as_ref().unwrap();
+    assert_eq!(mesh.node_id, "shield-gamma");
+    assert_eq!(mesh.peers.len(), 1);
+    assert_eq!(mesh.peers[0].id, "shield-beta");
+}
+
+#[test]
+fn unknown_durability_is_rejected() {
+    let override_durability = |value: &str| {
+        let value = value.to_string();
+        OffsecConfig::from_toml("")
+            .unwrap()
+            .with_overrides(move |name| (name == "OFFSEC_DURABILITY").then(|| value.clone()))
+    };
+    assert_eq!(
+        override_durability("best_effort").unwrap().durability,
+        DurabilityMode::BestEffort
+    );
+    let err = format!("{:#}", override_durability("relaxed").unwrap_err());
+    assert!(err.contains("OFFSEC_DURABILITY \"relaxed\""), "{err}");
+}
+
+#[test]
+fn validation_reports_bad_peers_and_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, peer_pub) = write_key(dir.path(), "peer.key", 4);
+    let peers = format!(
+        r#"
+[[mesh.peers]]
+id = "shield-nyc-01"
+url = "https://nyc.example.com"
+pubkey = "{peer_pub}"
+
+[[mesh.peers]]
+id = "shield-nyc-01"
+url = "ftp://nyc.example.com"
+pubkey = "GAMMA_PUBKEY_HERE"
+
+[[mesh.peers]]
+id = "shield-lon-01"
+url = "https://lon.example.com"
+pubkey = "{peer_pub}"
+"#
+    );
+    let mut config = OffsecConfig::from_toml(&mesh_toml(dir.path(), &peers)).unwrap();
+    config.signing_key_file = Some(dir.path().join("missing.key").display().to_string());
+
+    let err = format!("{:#}", config.validate().unwrap_err());
+    assert!(err.contains("duplicate peer id"), "{err}");
+    assert!(err.contains("unsupported url scheme"), "{err}");
+    assert!(err.contains("invalid mesh peer pubkey"), "{err}");
+    assert!(err.contains("this node's own node_id"), "{err}");
+    assert!(err.contains("signing_key_file"), "{err}");
+}
+
+#[test]
+fn validation_rejects_missing_or_dev_secret() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = OffsecConfig::from_toml("")
+        .unwrap()
+        .with_overrides(no_env)
+        .unwrap();
+    config.data_dir = dir.path().display().to_string();
+    let err = format!("{:#}", config.validate().unwrap_err());
+    assert!(err.contains("OFFSEC_JWT_HS256_SECRET"), "{err}");
+
+    config.jwt_hs256_secret = Some("dev-secret".to_string());
+    let err = format!("{:#}", config.validate().unwrap_err());
+    assert!(err.contains("dev-secret"), "{err}");
+}
+
+#[test]
+fn unknown_portal_keys_are_rejected() {
+    let err = OffsecConfig::from_toml("[portal]\ndata_dri = \"x\"\n").unwrap_err();
+    assert!(format!("{err:#}").contains("data_dri"), "{err:#}");
+}
+
+#[test]
+fn approval_policy_loads_from_toml_and_env() {
+    let toml = "[approvals]\ntimeout_seconds = 600\n\n[approvals.required]\nquarantine = 2\n";
+    let config = OffsecConfig::from_toml(toml).unwrap();
+    assert_eq!(config.approvals.required_for("quarantine"), 2);
+    assert_eq!(config.approvals.required_for("block_ip"), 0);
+    assert_eq!(config.approvals.timeout_seconds, 600);
+
+    let config = config
+        .with_overrides(|name| match name {
+            "OFFSEC_APPROVALS" => Some(r#"{"isolate_host": 1}"#.to_string()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(config.approvals.required_for("quarantine"), 0);
+    assert_eq!(config.approvals.required_for("isolate_host"), 1);
+
+    assert!(OffsecConfig::from_toml("[approvals]\nrequire = 1\n").is_err());
+}
+
+#[test]
+fn ledger_identity_is_configurable() {
+    let config = OffsecConfig::from_toml("[portal]\ndata_dir = \"/srv/offsec\"\n").unwrap();
+    assert_eq!(config.ledger.issuer_id, "did:vm:node:offsec-shield");
+    assert_eq!(
+        config.ledger_key_path(),
+        std::path::Path::new("/srv/offsec/ledger/node.key")
+    );
+
+    let toml =
+        "[ledger]\nissuer_id = \"did:vm:node:shield-eu\"\nkey_file = \"/etc/offsec/ledger.key\"\n";
+    let config = OffsecConfig::from_toml(toml).unwrap();
+    assert_eq!(config.ledger.issuer_id, "did:vm:node:shield-eu");
+    assert_eq!(
+        config.ledger_key_path(),
+        std::path::Path::new("/etc/offsec/ledger.key")
+    );
+
+    let config = config
+        .with_overrides(|name| match name {
+            "OFFSEC_LEDGER_ISSUER_ID" => Some("shield-us".to_string()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(config.ledger.issuer_id, "shield-us");
+    let err = format!("{:#}", config.validate().unwrap_err());
+    assert!(err.contains("ledger.issuer_id"), "{err}");
+}
+
+#[test]
+fn correlation_is_configurable() {
+    let config = OffsecConfig::from_toml("").unwrap();
+    assert!(config.correlation.enabled);
+    assert_eq!(config.correlation.window_seconds, 900);
+    assert_eq!(config.correlation.keys, vec!["source_ip"]);
+
+    let toml = "[correlation]\nwindow_seconds = 300\nkeys = [\"source_ip\", \"metadata.user\"]\n";
+    let config = OffsecConfig::from_toml(toml).unwrap();
+    assert_eq!(config.correlation.window_seconds, 300);
+    assert_eq!(config.correlation.keys, vec!["source_ip", "metadata.user"]);
+
+    let config = config
+        .with_overrides(|name| match name {
+            "OFFSEC_CORRELATION_KEYS" => Some("event_type, hostname".to_string()),
+            "OFFSEC_CORRELATION_WINDOW_SECONDS" => Some("0".to_string()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(config.correlation.keys, vec!["event_type", "hostname"]);
+    let err = format!("{:#}", config.validate().unwrap_err());
+    assert!(err.contains("correlation.window_seconds"), "{err}");
+    assert!(err.contains("unknown field \"hostname\""), "{err}");
+}
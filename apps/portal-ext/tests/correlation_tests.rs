@@ -0,0 +1,363 @@
+mod common;
+
+use std::path::Path;
+
+use axum::http::StatusCode;
+use common::{config, get, post, send, token};
+use portal_ext::{
+    build_state,
+    correlation::{correlation_keys, is_known_key, MAX_KEYS_PER_EVENT, MAX_VALUES_PER_FIELD},
+    models::ThreatEvent,
+    AppState,
+};
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+
+async fn state(dir: &Path, keys: &[&str]) -> AppState {
+    let mut config = config(dir);
+    config.correlation.keys = keys.iter().map(|k| k.to_string()).collect();
+    build_state(config).await.expect("state")
+}
+
+fn event(id: &str, timestamp: &str, severity: &str, ip: &str) -> Value {
+    json!({
+        "id": id,
+        "timestamp": timestamp,
+        "severity": severity,
+        "event_type": "ssh_bruteforce",
+        "source": "auth.log",
+        "description": "failed logins",
+        "affected": [ip],
+        "metadata": { "user": "root" },
+    })
+}
+
+/// Ingest `event` as guardian-a, returning the incident it joined.
+async fn ingest(state: &AppState, event: Value) -> Option<String> {
+    let (status, body) = send(
+        state,
+        post(
+            "/offsec/ingest",
+            Some(&token("guardian-a", vec!["ingest"])),
+            event,
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    body["incident_id"].as_str().map(str::to_string)
+}
+
+fn incident_frames(frames: &mut broadcast::Receiver<String>) -> Vec<Value> {
+    std::iter::from_fn(|| frames.try_recv().ok())
+        .map(|f| serde_json::from_str::<Value>(&f).unwrap())
+        .filter(|f| {
+            matches!(
+                f["type"].as_str(),
+                Some("incident.opened" | "incident.updated")
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn keys_combine_the_configured_fields() {
+    let event: ThreatEvent = serde_json::from_value(json!({
+        "id": "evt-1",
+        "timestamp": "2025-11-23T01:00:00Z",
+        "severity": "high",
+        "event_type": "ssh_bruteforce",
+        "source": "auth.log",
+        "description": "failed logins",
+        "affected": ["203.0.113.42", "bastion-01", "2001:db8::1", "203.0.113.42"],
+        "metadata": { "user": "root", "attempts": 40 },
+    }))
+    .unwrap();
+    let keys = |fields: &[&str]| {
+        let fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+        correlation_keys(&fields, &event)
+    };
+
+    assert_eq!(
+        keys(&["source_ip"]),
+        vec!["source_ip=2001:db8::1", "source_ip=203.0.113.42"]
+    );
+    assert_eq!(
+        keys(&["event_type", "source_ip", "metadata.attempts"]),
+        vec![
+            "event_type=ssh_bruteforce|source_ip=2001:db8::1|metadata.attempts=40",
+            "event_type=ssh_bruteforce|source_ip=203.0.113.42|metadata.attempts=40",
+        ]
+    );
+    // No guardian_id on the event, so nothing to correlate on.
+    assert!(keys(&["source_ip", "guardian_id"]).is_empty());
+    assert!(keys(&["metadata.session"]).is_empty());
+
+    assert!(is_known_key("source_host"));
+    assert!(is_known_key("metadata.user"));
+    assert!(!is_known_key("metadata."));
+    assert!(!is_known_key("hostname"));
+}
+
+#[test]
+fn keys_are_capped_for_wide_events() {
+    let affected: Vec<String> = (0..200)
+        .map(|i| format!("10.0.{}.{}", i / 250, i % 250))
+        .collect();
+    let event: ThreatEvent = serde_json::from_value(json!({
+        "id": "evt-1",
+        "timestamp": "2025-11-23T01:00:00Z",
+        "severity": "high",
+        "event_type": "port_scan",
+        "source": "ids",
+        "description": "scan",
+        "affected": affected,
+    }))
+    .unwrap();
+    let fields = vec!["source_ip".to_string()];
+    assert_eq!(
+        correlation_keys(&fields, &event).len(),
+        MAX_VALUES_PER_FIELD
+    );
+
+    let fields = vec![
+        "source_ip".to_string(),
+        "source_ip".to_string(),
+        "source_ip".to_string(),
+    ];
+    let keys = correlation_keys(&fields, &event);
+    assert_eq!(keys.len(), MAX_KEYS_PER_EVENT);
+}
+
+#[tokio::test]
+async fn events_within_the_window_share_an_incident() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), &["source_ip"]).await;
+    let mut frames = state.ws.subscribe();
+
+    let first = ingest(
+        &state,
+        event("evt-1", "2025-11-23T01:00:00Z", "medium", "203.0.113.42"),
+    )
+    .await
+    .expect("incident");
+    // Ten minutes later, then ten more: each sighting extends the window.
+    let second = ingest(
+        &state,
+        event("evt-2", "2025-11-23T01:10:00Z", "critical", "203.0.113.42"),
+    )
+    .await;
+    let third = ingest(
+        &state,
+        event("evt-3", "2025-11-23T01:20:00Z", "low", "203.0.113.42"),
+    )
+    .await;
+    assert_eq!(second.as_deref(), Some(first.as_str()));
+    assert_eq!(third.as_deref(), Some(first.as_str()));
+
+    let other = ingest(
+        &state,
+        event("evt-4", "2025-11-23T01:20:00Z", "low", "198.51.100.7"),
+    )
+    .await
+    .expect("incident");
+    assert_ne!(other, first);
+    let later = ingest(
+        &state,
+        event("evt-5", "2025-11-23T02:00:00Z", "low", "203.0.113.42"),
+    )
+    .await
+    .expect("incident");
+    assert_ne!(later, first);
+
+    let (_, record) = send(&state, get(&format!("/offsec/incidents/{first}"))).await;
+    assert_eq!(record["status"], "open");
+    assert_eq!(record["severity"], "critical");
+    assert_eq!(
+        record["threat_event_ids"],
+        json!(["evt-1", "evt-2", "evt-3"])
+    );
+    assert_eq!(record["history"][0]["actor"], "correlator");
+    assert_eq!(record["title"], "ssh_bruteforce (source_ip=203.0.113.42)");
+    let chain = state.ledger.get_incident(&first).expect("chain");
+    assert_eq!(chain.receipts.len(), 3);
+    for pair in chain.receipts.windows(2) {
+        assert_eq!(pair[1].prev_id.as_deref(), Some(pair[0].id.as_str()));
+    }
+
+    let frames = incident_frames(&mut frames);
+    let summary: Vec<(&str, &str)> = frames
+        .iter()
+        .map(|f| {
+            (
+                f["type"].as_str().unwrap(),
+                f["data"]["threat_event_id"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("incident.opened", "evt-1"),
+            ("incident.updated", "evt-2"),
+            ("incident.updated", "evt-3"),
+            ("incident.opened", "evt-4"),
+            ("incident.opened", "evt-5"),
+        ]
+    );
+    assert_eq!(frames[1]["data"]["incident_id"], json!(first));
+    assert_eq!(frames[1]["data"]["severity"], "critical");
+    assert_eq!(
+        frames[1]["data"]["receipt_id"].as_str(),
+        Some(chain.receipts[1].id.as_str())
+    );
+}
+
+#[tokio::test]
+async fn closed_incidents_are_not_extended() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), &["source_ip", "event_type"]).await;
+
+    let first = ingest(
+        &state,
+        event("evt-1", "2025-11-23T01:00:00Z", "high", "203.0.113.42"),
+    )
+    .await
+    .expect("incident");
+    // A retried event is not linked or receipted twice.
+    let retried = ingest(
+        &state,
+        event("evt-1", "2025-11-23T01:00:00Z", "high", "203.0.113.42"),
+    )
+    .await;
+    assert_eq!(retried.as_deref(), Some(first.as_str()));
+    assert_eq!(state.ledger.get_incident(&first).unwrap().receipts.len(), 1);
+
+    let (status, _) = send(
+        &state,
+        post(
+            &format!("/offsec/incidents/{first}/transition"),
+            Some(&token("analyst", vec!["incident:write"])),
+            json!({ "status": "closed", "note": "false positive" }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let next = ingest(
+        &state,
+        event("evt-2", "2025-11-23T01:05:00Z", "high", "203.0.113.42"),
+    )
+    .await
+    .expect("incident");
+    assert_ne!(next, first);
+
+    // The window is kept in the database.
+    drop(state);
+    let state = self::state(dir.path(), &["source_ip", "event_type"]).await;
+    let again = ingest(
+        &state,
+        event("evt-3", "2025-11-23T01:10:00Z", "high", "203.0.113.42"),
+    )
+    .await;
+    assert_eq!(again, Some(next));
+}
+
+#[tokio::test]
+async fn closed_incidents_give_way_to_open_ones() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), &["source_ip"]).await;
+
+    let open = ingest(
+        &state,
+        event("evt-1", "2025-11-23T01:00:00Z", "high", "203.0.113.42"),
+    )
+    .await
+    .expect("incident");
+    let closed = ingest(
+        &state,
+        event("evt-2", "2025-11-23T01:01:00Z", "high", "198.51.100.7"),
+    )
+    .await
+    .expect("incident");
+    let (status, _) = send(
+        &state,
+        post(
+            &format!("/offsec/incidents/{closed}/transition"),
+            Some(&token("analyst", vec!["incident:write"])),
+            json!({ "status": "closed" }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The closed incident was seen last, but the open one also matches.
+    let mut both = event("evt-3", "2025-11-23T01:02:00Z", "high", "203.0.113.42");
+    both["affected"] = json!(["203.0.113.42", "198.51.100.7"]);
+    assert_eq!(ingest(&state, both).await, Some(open.clone()));
+    assert_eq!(state.ledger.get_incident(&open).unwrap().receipts.len(), 2);
+    assert_eq!(
+        state.ledger.get_incident(&closed).unwrap().receipts.len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn expired_keys_are_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), &["source_ip"]).await;
+    let count = || async {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM incident_correlations")
+            .fetch_one(state.store.pool())
+            .await
+            .unwrap()
+    };
+
+    ingest(
+        &state,
+        event("evt-1", "2025-11-23T01:00:00Z", "high", "203.0.113.1"),
+    )
+    .await;
+    ingest(
+        &state,
+        event("evt-2", "2025-11-23T01:05:00Z", "high", "203.0.113.2"),
+    )
+    .await;
+    assert_eq!(count().await, 2);
+
+    // Past the 900 s window of both, only the new key is left.
+    ingest(
+        &state,
+        event("evt-3", "2025-11-23T02:00:00Z", "high", "203.0.113.3"),
+    )
+    .await;
+    assert_eq!(count().await, 1);
+}
+
+#[tokio::test]
+async fn uncorrelated_events_open_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), &["source_ip"]).await;
+    assert_eq!(
+        ingest(
+            &state,
+            event("evt-1", "2025-11-23T01:00:00Z", "high", "bastion-01")
+        )
+        .await,
+        None
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(dir.path());
+    config.correlation.enabled = false;
+    let state = build_state(config).await.unwrap();
+    assert_eq!(
+        ingest(
+            &state,
+            event("evt-1", "2025-11-23T01:00:00Z", "high", "203.0.113.42")
+        )
+        .await,
+        None
+    );
+    let (_, all) = send(&state, get("/offsec/incidents")).await;
+    assert_eq!(all, json!([]));
+}
@@ -0,0 +1,253 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{get, post, send, state, token};
+use portal_ext::{
+    mesh::util::compute_payload_hash,
+    offsec_ledger::{verify_incident_proof, IncidentProof},
+    AppState,
+};
+use serde_json::{json, Value};
+
+/// Append `count` events to `incident_id` through `/api/offsec/events`.
+async fn events(state: &AppState, incident_id: &str, count: usize) {
+    for i in 0..count {
+        let (status, body) = send(
+            state,
+            post(
+                "/api/offsec/events",
+                Some(&token("guardian-a", vec!["infrastructure:write"])),
+                json!({
+                    "event_type": "security.threat.detected",
+                    "severity": "high",
+                    "ref_id": incident_id,
+                    "data": { "n": i },
+                }),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["incident_id"], incident_id);
+    }
+}
+
+async fn proof(state: &AppState, incident_id: &str) -> IncidentProof {
+    let (status, body) = send(
+        state,
+        get(&format!("/api/offsec/incidents/{incident_id}/proof")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_value(body).unwrap()
+}
+
+async fn verify(state: &AppState, proof: &IncidentProof) -> Value {
+    let (status, body) = send(
+        state,
+        post(
+            "/api/offsec/incidents/verify",
+            None,
+            serde_json::to_value(proof).unwrap(),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    body
+}
+
+/// Re-hash an entry after editing its receipt.
+fn rehash(proof: &mut IncidentProof, i: usize) {
+    proof.entries[i].hash = hex::encode(compute_payload_hash(&proof.entries[i].receipt).unwrap());
+}
+
+#[tokio::test]
+async fn proofs_cover_the_whole_chain() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    events(&state, "inc-1", 3).await;
+
+    let first = proof(&state, "inc-1").await;
+    assert_eq!(first.incident_id, "inc-1");
+    assert_eq!(first.issuer, "did:vm:node:offsec-shield");
+    assert_eq!(first.entries.len(), 3);
+    assert!(first.entries.iter().all(|e| e.signature.is_some()));
+    assert_eq!(first.head, first.entries[2].hash);
+    assert!(first.ledger_proof.is_some());
+
+    // Served as built until the chain grows.
+    let again = proof(&state, "inc-1").await;
+    assert_eq!(again.built_at, first.built_at);
+    events(&state, "inc-1", 1).await;
+    let grown = proof(&state, "inc-1").await;
+    assert_eq!(grown.entries.len(), 4);
+    let hashes = |p: &IncidentProof| p.entries.iter().map(|e| e.hash.clone()).collect::<Vec<_>>();
+    assert_eq!(hashes(&grown)[..3], hashes(&first)[..]);
+
+    let (status, report) = send(&state, get("/api/offsec/incidents/inc-1/verify")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["valid"], true, "{report}");
+    assert_eq!(report["receipts"], 4);
+    assert_eq!(report["sealed"], 4);
+    assert_eq!(report["linked"], true);
+    assert_eq!(report["problems"], json!([]));
+
+    // The fifth receipt builds and keeps a proof on its own.
+    events(&state, "inc-2", 5).await;
+    let stored = dir
+        .path()
+        .join("proofs")
+        .join("incidents")
+        .join("inc-2.json");
+    let stored: IncidentProof = serde_json::from_slice(&std::fs::read(stored).unwrap()).unwrap();
+    assert_eq!(stored.entries.len(), 5);
+    let key = state.ledger.identity().verifying_key();
+    assert!(verify_incident_proof(&stored, &key).valid);
+
+    for path in [
+        "/api/offsec/incidents/inc-404/proof",
+        "/api/offsec/incidents/inc-404/verify",
+    ] {
+        let (status, body) = send(&state, get(path)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "unknown_incident");
+    }
+}
+
+#[tokio::test]
+async fn tampered_chains_fail_verification() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    events(&state, "inc-1", 3).await;
+    let proof = proof(&state, "inc-1").await;
+    assert_eq!(verify(&state, &proof).await["valid"], true);
+
+    // An edited receipt no longer matches its hash...
+    let mut edited = proof.clone();
+    edited.entries[1].receipt["body"]["extra"]["data"]["n"] = json!(99);
+    let report = verify(&state, &edited).await;
+    assert_eq!(report["valid"], false);
+    assert!(report["problems"][0]
+        .as_str()
+        .unwrap()
+        .contains("hash does not match"));
+    // ...or, re-hashed, its seal and the proof signature.
+    rehash(&mut edited, 1);
+    let report = verify(&state, &edited).await;
+    assert_eq!(report["sealed"], 2);
+    assert_eq!(report["proof_signature"], false);
+
+    // Dropping a receipt breaks the prev_id links.
+    let mut dropped = proof.clone();
+    dropped.entries.remove(1);
+    let report = verify(&state, &dropped).await;
+    assert_eq!(report["valid"], false);
+    assert_eq!(report["linked"], false);
+
+    // Receipts from another incident do not belong.
+    events(&state, "inc-2", 1).await;
+    let mut foreign = proof.clone();
+    let other = self::proof(&state, "inc-2").await;
+    foreign.entries[0] = other.entries[0].clone();
+    let report = verify(&state, &foreign).await;
+    assert!(report["problems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p.as_str().unwrap().contains("belongs to incident")));
+
+    let mut unknown = proof.clone();
+    unknown.issuer = "did:vm:node:elsewhere".to_string();
+    let (status, body) = send(
+        &state,
+        post(
+            "/api/offsec/incidents/verify",
+            None,
+            serde_json::to_value(&unknown).unwrap(),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "untrusted_issuer");
+}
+
+#[tokio::test]
+async fn seals_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let state = state(dir.path()).await;
+        events(&state, "inc-1", 2).await;
+    }
+    let state = state(dir.path()).await;
+    events(&state, "inc-1", 1).await;
+    let (_, report) = send(&state, get("/api/offsec/incidents/inc-1/verify")).await;
+    assert_eq!(report["valid"], true, "{report}");
+    assert_eq!(report["sealed"], 3);
+
+    // A receipt edited on disk is caught against its seal.
+    let receipt_id = proof(&state, "inc-1").await.entries[0].receipt["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let path = dir
+        .path()
+        .join("receipts")
+        .join("infrastructure")
+        .join(format!("{receipt_id}.json"));
+    let mut receipt: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    receipt["body"]["extra"]["data"]["n"] = json!(42);
+    std::fs::write(&path, serde_json::to_vec(&receipt).unwrap()).unwrap();
+    std::fs::remove_dir_all(dir.path().join("proofs")).unwrap();
+    drop(state);
+
+    let state = self::state(dir.path()).await;
+    let (_, report) = send(&state, get("/api/offsec/incidents/inc-1/verify")).await;
+    assert_eq!(report["valid"], false);
+    assert_eq!(report["sealed"], 2);
+    assert!(report["problems"][0]
+        .as_str()
+        .unwrap()
+        .contains("seal does not verify"));
+}
+
+#[tokio::test]
+async fn restarts_order_chains_by_their_links() {
+    let dir = tempfile::tempdir().unwrap();
+    let ids: Vec<Value> = {
+        let state = state(dir.path()).await;
+        events(&state, "inc-1", 3).await;
+        proof(&state, "inc-1")
+            .await
+            .entries
+            .iter()
+            .map(|e| e.receipt["id"].clone())
+            .collect()
+    };
+
+    // Clocks can step back: give the receipts timestamps in reverse order.
+    for (i, id) in ids.iter().enumerate() {
+        let path = dir
+            .path()
+            .join("receipts")
+            .join("infrastructure")
+            .join(format!("{}.json", id.as_str().unwrap()));
+        let mut receipt: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        receipt["ts"] = json!(format!("2025-01-0{}T00:00:00Z", 3 - i));
+        std::fs::write(&path, serde_json::to_vec(&receipt).unwrap()).unwrap();
+    }
+    std::fs::remove_dir_all(dir.path().join("proofs")).unwrap();
+
+    let state = state(dir.path()).await;
+    let reordered: Vec<Value> = proof(&state, "inc-1")
+        .await
+        .entries
+        .iter()
+        .map(|e| e.receipt["id"].clone())
+        .collect();
+    assert_eq!(reordered, ids);
+    let (_, report) = send(&state, get("/api/offsec/incidents/inc-1/verify")).await;
+    assert!(report["problems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|p| !p.as_str().unwrap().contains("prev_id")));
+}
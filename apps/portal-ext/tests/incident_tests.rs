@@ -0,0 +1,390 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{get, post, send, state, token};
+use portal_ext::{incidents::IncidentStatus, AppState};
+use serde_json::{json, Value};
+
+/// `analyst` changes incident `id`.
+async fn change(state: &AppState, id: &str, op: &str, body: Value) -> (StatusCode, Value) {
+    send(
+        state,
+        post(
+            &format!("/offsec/incidents/{id}/{op}"),
+            Some(&token("analyst", vec!["incident:write"])),
+            body,
+        ),
+    )
+    .await
+}
+
+async fn open(state: &AppState, body: Value) -> (StatusCode, Value) {
+    send(
+        state,
+        post(
+            "/offsec/incidents",
+            Some(&token("analyst", vec!["incident:write"])),
+            body,
+        ),
+    )
+    .await
+}
+
+/// The incident's receipts each point at the one before.
+fn assert_chained(state: &AppState, id: &str, len: usize) {
+    let chain = state.ledger.get_incident(id).expect("chain");
+    assert_eq!(chain.receipts.len(), len);
+    assert_eq!(chain.receipts[0].prev_id, None);
+    for pair in chain.receipts.windows(2) {
+        assert_eq!(pair[1].prev_id.as_deref(), Some(pair[0].id.as_str()));
+    }
+}
+
+#[test]
+fn incidents_move_forward_or_close() {
+    use IncidentStatus::*;
+    assert!(Open.can_transition(Triaged));
+    assert!(Triaged.can_transition(Contained));
+    assert!(Contained.can_transition(Closed));
+    assert!(Open.can_transition(Closed));
+    assert!(Closed.can_transition(Open));
+    assert!(!Open.can_transition(Contained));
+    assert!(!Contained.can_transition(Triaged));
+    assert!(!Closed.can_transition(Contained));
+}
+
+#[tokio::test]
+async fn transitions_are_promoted_onto_the_chain() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    let mut frames = state.ws.subscribe();
+
+    let (status, _) = send(
+        &state,
+        post(
+            "/offsec/incidents",
+            None,
+            json!({ "incident_id": "inc-1", "severity": "high" }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, record) = open(
+        &state,
+        json!({
+            "incident_id": "inc-1",
+            "title": "SSH brute force on bastion-01",
+            "severity": "high",
+            "threat_event_ids": ["evt-1", "evt-2"],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(record["status"], "open");
+    assert_eq!(record["severity"], "high");
+    assert_eq!(record["threat_event_ids"], json!(["evt-1", "evt-2"]));
+    assert_eq!(record["history"][0]["actor"], "analyst");
+    assert_eq!(record["receipt_ids"].as_array().unwrap().len(), 1);
+    let (status, body) = open(&state, json!({ "incident_id": "inc-1", "severity": "low" })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "duplicate_incident");
+
+    let (status, record) = change(&state, "inc-1", "assign", json!({ "owner": "dana" })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(record["owner"], "dana");
+
+    let (status, body) = change(
+        &state,
+        "inc-1",
+        "transition",
+        json!({ "status": "contained" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "invalid_transition");
+
+    for next in ["triaged", "contained", "closed"] {
+        let (status, record) = change(
+            &state,
+            "inc-1",
+            "transition",
+            json!({ "status": next, "note": format!("now {next}") }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(record["status"], next);
+    }
+
+    let (_, record) = send(&state, get("/offsec/incidents/inc-1")).await;
+    let receipts = record["receipt_ids"].as_array().unwrap();
+    // Opened, assigned and three transitions.
+    assert_eq!(receipts.len(), 5);
+    assert_chained(&state, "inc-1", 5);
+    let history = record["history"].as_array().unwrap();
+    assert_eq!(history.len(), 4);
+    assert_eq!(history[3]["from"], "contained");
+    assert_eq!(history[3]["to"], "closed");
+    assert_eq!(history[3]["note"], "now closed");
+    assert_eq!(history[3]["receipt_id"], receipts[4]);
+
+    let types: Vec<Value> = std::iter::from_fn(|| frames.try_recv().ok())
+        .map(|f| serde_json::from_str::<Value>(&f).unwrap())
+        .filter(|f| f["data"]["incident_id"] == "inc-1")
+        .map(|f| f["type"].clone())
+        .collect();
+    assert_eq!(
+        types,
+        vec![
+            "offsec.incident.opened",
+            "offsec.incident.assigned",
+            "offsec.incident.transition",
+            "offsec.incident.transition",
+            "offsec.incident.transition",
+        ]
+    );
+
+    let (status, body) = change(&state, "inc-404", "assign", json!({ "owner": "dana" })).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], "unknown_incident");
+}
+
+#[tokio::test]
+async fn infrastructure_events_open_incidents() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+
+    let (status, body) = send(
+        &state,
+        post(
+            "/api/offsec/events",
+            Some(&token("guardian-a", vec!["infrastructure:write"])),
+            json!({
+                "event_type": "security.threat.detected",
+                "severity": "critical",
+                "ref_id": "inc-ssh",
+                "data": { "source_ip": "203.0.113.42" },
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["incident_id"], "inc-ssh");
+
+    let (_, record) = send(&state, get("/offsec/incidents/inc-ssh")).await;
+    assert_eq!(record["status"], "open");
+    assert_eq!(record["severity"], "critical");
+    assert_eq!(record["history"][0]["actor"], "guardian-a");
+    assert_eq!(record["receipt_ids"], json!([body["receipt_id"]]));
+
+    let (status, _) = change(
+        &state,
+        "inc-ssh",
+        "transition",
+        json!({ "status": "triaged" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_chained(&state, "inc-ssh", 2);
+
+    // The records and the chain both survive a restart.
+    drop(state);
+    let state = self::state(dir.path()).await;
+    let (_, record) = send(&state, get("/offsec/incidents/inc-ssh")).await;
+    assert_eq!(record["status"], "triaged");
+    assert_eq!(record["receipt_ids"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn infrastructure_events_leave_closed_incidents_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    open(
+        &state,
+        json!({ "incident_id": "inc-1", "severity": "high" }),
+    )
+    .await;
+    change(&state, "inc-1", "transition", json!({ "status": "closed" })).await;
+
+    let (status, body) = send(
+        &state,
+        post(
+            "/api/offsec/events",
+            Some(&token("guardian-a", vec!["infrastructure:write"])),
+            json!({
+                "event_type": "security.threat.detected",
+                "severity": "high",
+                "ref_id": "inc-1",
+                "data": { "source_ip": "203.0.113.42" },
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "error");
+    assert_eq!(body["error"], "incident_closed");
+    assert_chained(&state, "inc-1", 2);
+}
+
+#[tokio::test]
+async fn incidents_are_linked_and_filtered() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+
+    for (id, severity) in [("inc-1", "high"), ("inc-2", "low"), ("inc-3", "high")] {
+        let (status, _) = open(&state, json!({ "incident_id": id, "severity": severity })).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    change(&state, "inc-1", "assign", json!({ "owner": "dana" })).await;
+    change(&state, "inc-3", "transition", json!({ "status": "closed" })).await;
+
+    let (status, body) = change(&state, "inc-1", "links", json!({ "action_ids": ["act-9"] })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "unknown_action");
+    let (status, record) = change(
+        &state,
+        "inc-1",
+        "links",
+        json!({ "threat_event_ids": ["evt-7", "evt-7"] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(record["threat_event_ids"], json!(["evt-7"]));
+    // Opened, assigned and linked; the refused link left no receipt.
+    assert_chained(&state, "inc-1", 3);
+    let chain = state.ledger.get_incident("inc-1").unwrap();
+    let linked = serde_json::to_value(&chain.receipts[2]).unwrap();
+    assert_eq!(linked["body"]["extra"]["threat_event_ids"][0], "evt-7");
+
+    let ids = |body: &Value| -> Vec<String> {
+        let mut ids: Vec<String> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["incident_id"].as_str().unwrap().to_string())
+            .collect();
+        ids.sort();
+        ids
+    };
+    let (_, all) = send(&state, get("/offsec/incidents")).await;
+    assert_eq!(ids(&all), vec!["inc-1", "inc-2", "inc-3"]);
+    let (_, high) = send(&state, get("/offsec/incidents?severity=high&status=open")).await;
+    assert_eq!(ids(&high), vec!["inc-1"]);
+    let (_, mine) = send(&state, get("/offsec/incidents?owner=dana")).await;
+    assert_eq!(ids(&mine), vec!["inc-1"]);
+    let (_, closed) = send(&state, get("/offsec/incidents?status=closed")).await;
+    assert_eq!(ids(&closed), vec!["inc-3"]);
+
+    let (status, _) = send(&state, get("/offsec/incidents?status=resolved")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn concurrent_transitions_promote_only_the_one_applied() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    open(
+        &state,
+        json!({ "incident_id": "inc-1", "severity": "high" }),
+    )
+    .await;
+
+    let ((first, _), (second, _)) = tokio::join!(
+        change(
+            &state,
+            "inc-1",
+            "transition",
+            json!({ "status": "triaged" })
+        ),
+        change(
+            &state,
+            "inc-1",
+            "transition",
+            json!({ "status": "triaged" })
+        ),
+    );
+    let mut statuses = [first, second];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::CONFLICT]);
+    // Opened and one transition: the refused one left no receipt.
+    assert_chained(&state, "inc-1", 2);
+}
+
+#[tokio::test]
+async fn chains_without_records_are_backfilled_at_startup() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let state = state(dir.path()).await;
+        state
+            .ledger
+            .record(
+                "inc-old",
+                "security.threat.detected",
+                "high",
+                json!({ "severity": "high", "source_ip": "203.0.113.42" }),
+            )
+            .await
+            .unwrap();
+        state
+            .ledger
+            .record(
+                "inc-old",
+                "offsec.incident.transition",
+                "high",
+                json!({ "from": "open", "to": "triaged", "actor": "analyst" }),
+            )
+            .await
+            .unwrap();
+        let (_, all) = send(&state, get("/offsec/incidents")).await;
+        assert_eq!(all, json!([]));
+    }
+
+    let state = state(dir.path()).await;
+    let (_, all) = send(&state, get("/offsec/incidents")).await;
+    assert_eq!(all.as_array().unwrap().len(), 1);
+    let record = &all[0];
+    assert_eq!(record["incident_id"], "inc-old");
+    assert_eq!(record["status"], "triaged");
+    assert_eq!(record["severity"], "high");
+    assert_eq!(record["history"].as_array().unwrap().len(), 2);
+    assert_eq!(record["history"][1]["actor"], "analyst");
+    assert_eq!(record["history"][1]["receipt_id"], record["receipt_ids"][1]);
+}
+
+#[tokio::test]
+async fn backfilled_records_keep_their_links() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    open(
+        &state,
+        json!({ "incident_id": "inc-1", "severity": "high", "threat_event_ids": ["evt-1"] }),
+    )
+    .await;
+    change(
+        &state,
+        "inc-1",
+        "links",
+        json!({ "threat_event_ids": ["evt-2"] }),
+    )
+    .await;
+    state
+        .ledger
+        .record(
+            "inc-1",
+            "offsec.incident.correlated",
+            "high",
+            json!({ "threat_event_id": "evt-3", "actor": "correlator" }),
+        )
+        .await
+        .unwrap();
+
+    for table in ["incidents", "incident_history", "incident_links"] {
+        sqlx::query(&format!("DELETE FROM {table}"))
+            .execute(state.store.pool())
+            .await
+            .unwrap();
+    }
+    assert_eq!(state.incidents.backfill(&state.ledger).await.unwrap(), 1);
+    let record = state.incidents.get("inc-1").await.unwrap().unwrap();
+    assert_eq!(record.threat_event_ids, vec!["evt-1", "evt-2", "evt-3"]);
+}
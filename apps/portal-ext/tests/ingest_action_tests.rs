@@ -12,7 +12,9 @@ async fn ingest_requires_token() {
     std::env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
     std::env::set_var("OFFSEC_CAP_AUD", "offsec-portal");
     std::env::set_var("OFFSEC_DATA_DIR", dir.path());
-    let state = portal_ext::build_state(portal_ext::OffsecConfig::from_env());
+    let state = portal_ext::build_state(portal_ext::OffsecConfig::from_env())
+        .await
+        .expect("state");
     let app = portal_ext::app_router(state);
 
     let payload = json!({
@@ -45,7 +47,9 @@ async fn action_flow_allows_valid_token() {
     std::env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
     std::env::set_var("OFFSEC_CAP_AUD", "offsec-portal");
     std::env::set_var("OFFSEC_DATA_DIR", dir.path());
-    let state = portal_ext::build_state(portal_ext::OffsecConfig::from_env());
+    let state = portal_ext::build_state(portal_ext::OffsecConfig::from_env())
+        .await
+        .expect("state");
     let app = portal_ext::app_router(state);
 
     let payload = json!({
@@ -73,6 +77,164 @@ async fn action_flow_allows_valid_token() {
     assert_eq!(response.status(), StatusCode::OK);
 }
 
+fn ingest_request(token: &str, id: &str) -> Request<Body> {
+    let payload = json!({
+        "id": id,
+        "timestamp": "2025-11-23T01:33:22Z",
+        "severity": "high",
+        "event_type": "brute_force",
+        "source": "ssh",
+        "description": "failed auth",
+        "affected": ["192.168.1.1"],
+        "metadata": {}
+    });
+    Request::post("/offsec/ingest")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::from(payload.to_string()))
+        .unwrap()
+}
+
+/// A directory where the write-ahead record's temp file goes makes every
+/// receipt commit fail until it is removed.
+fn break_receipt_writes(dir: &std::path::Path) -> std::path::PathBuf {
+    let blocker = dir.join("PENDING.json.tmp");
+    std::fs::create_dir_all(&blocker).unwrap();
+    blocker
+}
+
+#[tokio::test]
+async fn strict_mode_fails_ingest_without_broadcast() {
+    let dir = tempfile::tempdir().unwrap();
+    std::env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+    std::env::set_var("OFFSEC_CAP_AUD", "offsec-portal");
+    let mut config = portal_ext::OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    config.capability_audience = "offsec-portal".to_string();
+    config.durability = portal_ext::config::DurabilityMode::Strict;
+    let state = portal_ext::build_state(config).await.expect("state");
+    let mut frames = state.ws.subscribe();
+    break_receipt_writes(dir.path());
+
+    let response = portal_ext::app_router(state.clone())
+        .oneshot(ingest_request(&signed_token(vec!["ingest"]), "evt-strict"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(frames.try_recv().is_err(), "nothing may be broadcast");
+    assert_eq!(state.store.count().await.unwrap(), 0);
+    assert_eq!(state.frontier.lock().unwrap().len(), 0);
+}
+
+#[tokio::test]
+async fn best_effort_mode_queues_and_retries_receipts() {
+    let dir = tempfile::tempdir().unwrap();
+    std::env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+    std::env::set_var("OFFSEC_CAP_AUD", "offsec-portal");
+    let mut config = portal_ext::OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    config.capability_audience = "offsec-portal".to_string();
+    config.durability = portal_ext::config::DurabilityMode::BestEffort;
+    let state = portal_ext::build_state(config.clone())
+        .await
+        .expect("state");
+    let mut frames = state.ws.subscribe();
+    let blocker = break_receipt_writes(dir.path());
+    let token = signed_token(vec!["ingest"]);
+
+    for id in ["evt-1", "evt-2"] {
+        let response = portal_ext::app_router(state.clone())
+            .oneshot(ingest_request(&token, id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let frame: serde_json::Value = serde_json::from_str(&frames.try_recv().unwrap()).unwrap();
+    assert_eq!(frame["type"], "threat_event");
+
+    let response = portal_ext::app_router(state.clone())
+        .oneshot(
+            Request::get("/offsec/receipts/backlog")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status["mode"], "best-effort");
+    assert_eq!(status["pending"], 2);
+
+    // Still failing: nothing is written and the queue keeps its order.
+    assert_eq!(portal_ext::durability::retry_backlog(&state).await, 0);
+    // The backlog survives a restart.
+    drop(state);
+    let state = portal_ext::build_state(config).await.expect("state");
+    assert_eq!(state.backlog.len(), 2);
+
+    std::fs::remove_dir(blocker).unwrap();
+    assert_eq!(portal_ext::durability::retry_backlog(&state).await, 2);
+    assert!(state.backlog.is_empty());
+    let receipts = state.store.list(10, None).await.unwrap();
+    let ids: Vec<_> = receipts
+        .iter()
+        .rev()
+        .map(|r| r.payload.as_ref().unwrap()["data"]["id"].clone())
+        .collect();
+    assert_eq!(ids, vec![json!("evt-1"), json!("evt-2")]);
+    assert!(!dir.path().join("RECEIPT_BACKLOG.jsonl").exists());
+}
+
+#[tokio::test]
+async fn queued_action_receipts_are_linked_once_written() {
+    let dir = tempfile::tempdir().unwrap();
+    std::env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+    std::env::set_var("OFFSEC_CAP_AUD", "offsec-portal");
+    let mut config = portal_ext::OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    config.capability_audience = "offsec-portal".to_string();
+    config.durability = portal_ext::config::DurabilityMode::BestEffort;
+    let state = portal_ext::build_state(config).await.expect("state");
+    let blocker = break_receipt_writes(dir.path());
+
+    let payload = json!({
+        "id": "action-1",
+        "event_id": "evt-1",
+        "action": "alert_human",
+        "target": "192.168.1.100",
+        "reason": "test",
+        "created_at": "2025-11-23T01:33:22Z"
+    });
+    let response = portal_ext::app_router(state.clone())
+        .oneshot(
+            Request::post("/offsec/action")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", signed_token(vec!["alert_human"])),
+                )
+                .body(Body::from(payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let record = state.actions.get("action-1").await.unwrap().unwrap();
+    assert_eq!(record.history[0].receipt_id, None);
+
+    std::fs::remove_dir(blocker).unwrap();
+    assert_eq!(portal_ext::durability::retry_backlog(&state).await, 1);
+    let receipt = &state.store.list(1, None).await.unwrap()[0];
+    let record = state.actions.get("action-1").await.unwrap().unwrap();
+    assert_eq!(
+        record.history[0].receipt_id.as_deref(),
+        Some(receipt.id.as_str())
+    );
+}
+
 fn signed_token(actions: Vec<&str>) -> String {
     #[derive(serde::Serialize)]
     struct Claims {
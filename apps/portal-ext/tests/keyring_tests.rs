@@ -0,0 +1,310 @@
+use std::path::Path;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use ed25519_dalek::SigningKey;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use portal_ext::capabilities::{error_response, verify_token, CapabilityError};
+use portal_ext::keyring::{load_jwks, Keyring};
+use portal_ext::OffsecConfig;
+use serde_json::json;
+
+const AUD: &str = "offsec-portal";
+
+/// PKCS#8 v1 wrapping of a raw Ed25519 seed, as `EncodingKey::from_ed_der` expects.
+fn ed_encoding_key(seed: u8) -> EncodingKey {
+    let mut der = hex::decode("302e020100300506032b657004220420").unwrap();
+    der.extend_from_slice(&[seed; 32]);
+    EncodingKey::from_ed_der(&der)
+}
+
+fn ed_jwk(kid: &str, seed: u8, window: serde_json::Value) -> serde_json::Value {
+    let x = SigningKey::from_bytes(&[seed; 32])
+        .verifying_key()
+        .to_bytes();
+    let mut jwk =
+        json!({ "kid": kid, "kty": "OKP", "crv": "Ed25519", "x": URL_SAFE_NO_PAD.encode(x) });
+    jwk.as_object_mut()
+        .unwrap()
+        .extend(window.as_object().unwrap().clone());
+    jwk
+}
+
+fn token(alg: Algorithm, kid: Option<&str>, key: &EncodingKey) -> String {
+    let now = Utc::now().timestamp();
+    let mut header = Header::new(alg);
+    header.kid = kid.map(str::to_string);
+    let claims = json!({ "sub": "guardian-1", "aud": AUD, "exp": now + 600, "iat": now, "actions": ["ingest"] });
+    encode(&header, &claims, key).unwrap()
+}
+
+fn keyring_from(dir: &Path, keys: Vec<serde_json::Value>) -> anyhow::Result<Keyring> {
+    let path = dir.join("jwks.json");
+    std::fs::write(&path, json!({ "keys": keys }).to_string()).unwrap();
+    let mut config = OffsecConfig::from_toml("").unwrap();
+    config.jwks_file = Some(path.display().to_string());
+    Keyring::from_config(&config)
+}
+
+fn rotated_keyring(dir: &Path) -> Keyring {
+    let past = Utc::now() - Duration::days(1);
+    let future = Utc::now() + Duration::days(1);
+    keyring_from(
+        dir,
+        vec![
+            ed_jwk("ed-old", 1, json!({ "not_after": past.to_rfc3339() })),
+            ed_jwk("ed-new", 2, json!({ "not_before": past.to_rfc3339() })),
+            ed_jwk("ed-next", 3, json!({ "nbf": future.to_rfc3339() })),
+            json!({ "kid": "hs-1", "kty": "oct", "k": URL_SAFE_NO_PAD.encode("hs-secret-1") }),
+        ],
+    )
+    .unwrap()
+}
+
+#[test]
+fn tokens_verify_against_their_kid() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyring = rotated_keyring(dir.path());
+
+    let claims = verify_token(
+        &token(Algorithm::EdDSA, Some("ed-new"), &ed_encoding_key(2)),
+        &keyring,
+        AUD,
+    )
+    .unwrap();
+    assert_eq!(claims.sub, "guardian-1");
+
+    let hs = EncodingKey::from_secret(b"hs-secret-1");
+    assert!(verify_token(&token(Algorithm::HS256, Some("hs-1"), &hs), &keyring, AUD).is_ok());
+    // Without a kid every key of the header's algorithm is tried.
+    assert!(verify_token(
+        &token(Algorithm::EdDSA, None, &ed_encoding_key(2)),
+        &keyring,
+        AUD
+    )
+    .is_ok());
+    assert!(verify_token(&token(Algorithm::HS256, None, &hs), &keyring, AUD).is_ok());
+
+    // Right kid, wrong key.
+    let err = verify_token(
+        &token(Algorithm::EdDSA, Some("ed-new"), &ed_encoding_key(9)),
+        &keyring,
+        AUD,
+    )
+    .unwrap_err();
+    assert!(matches!(err, CapabilityError::Invalid(_)), "{err:?}");
+
+    let err = verify_token(
+        &token(Algorithm::EdDSA, Some("ed-missing"), &ed_encoding_key(2)),
+        &keyring,
+        AUD,
+    )
+    .unwrap_err();
+    assert!(
+        matches!(err, CapabilityError::Invalid(ref m) if m.contains("unknown key id")),
+        "{err:?}"
+    );
+}
+
+#[test]
+fn retired_and_pending_keys_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyring = rotated_keyring(dir.path());
+
+    for kid in [Some("ed-old"), None] {
+        let err = verify_token(
+            &token(Algorithm::EdDSA, kid, &ed_encoding_key(1)),
+            &keyring,
+            AUD,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, CapabilityError::RetiredKey(ref k) if k == "ed-old"),
+            "{err:?}"
+        );
+    }
+    let (code, body) = error_response(CapabilityError::RetiredKey("ed-old".to_string()));
+    assert_eq!(code, axum::http::StatusCode::UNAUTHORIZED);
+    assert_eq!(body.error, "retired_capability_key");
+
+    let err = verify_token(
+        &token(Algorithm::EdDSA, Some("ed-next"), &ed_encoding_key(3)),
+        &keyring,
+        AUD,
+    )
+    .unwrap_err();
+    assert!(
+        matches!(err, CapabilityError::Invalid(ref m) if m.contains("not active yet")),
+        "{err:?}"
+    );
+}
+
+#[test]
+fn config_keys_join_the_jwks_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("jwks.json");
+    std::fs::write(
+        &path,
+        json!({ "keys": [ed_jwk("ed-new", 2, json!({}))] }).to_string(),
+    )
+    .unwrap();
+    let mut config = OffsecConfig::from_toml("").unwrap();
+    config.jwks_file = Some(path.display().to_string());
+    config.jwt_hs256_secret = Some("legacy-secret".to_string());
+    let legacy = EncodingKey::from_secret(b"legacy-secret");
+
+    // The shared secret is dropped once asymmetric keys are configured...
+    let keyring = Keyring::from_config(&config).unwrap();
+    assert!(keyring.get(portal_ext::keyring::CONFIG_HS256_KID).is_none());
+    assert!(keyring.get("ed-new").is_some());
+    assert!(verify_token(&token(Algorithm::HS256, None, &legacy), &keyring, AUD).is_err());
+
+    // ...unless it is explicitly kept.
+    config.jwt_allow_hs256 = true;
+    let keyring = Keyring::from_config(&config).unwrap();
+    assert!(keyring.get(portal_ext::keyring::CONFIG_HS256_KID).is_some());
+    assert!(keyring.get("ed-new").is_some());
+    assert!(verify_token(&token(Algorithm::HS256, None, &legacy), &keyring, AUD).is_ok());
+}
+
+#[test]
+fn hs256_secret_is_ignored_next_to_a_pem_key() {
+    let public = SigningKey::from_bytes(&[4u8; 32]).verifying_key();
+    let mut der = hex::decode("302a300506032b6570032100").unwrap();
+    der.extend_from_slice(public.as_bytes());
+    let pem = format!(
+        "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+        base64::engine::general_purpose::STANDARD.encode(der)
+    );
+    let mut config = OffsecConfig::from_toml("").unwrap();
+    config.jwt_public_key_pem = Some(pem);
+    config.jwt_hs256_secret = Some("legacy-secret".to_string());
+
+    let keyring = Keyring::from_config(&config).unwrap();
+    let hs = EncodingKey::from_secret(b"legacy-secret");
+    assert!(verify_token(&token(Algorithm::HS256, None, &hs), &keyring, AUD).is_err());
+    assert!(verify_token(
+        &token(Algorithm::EdDSA, None, &ed_encoding_key(4)),
+        &keyring,
+        AUD
+    )
+    .is_ok());
+
+    let config = config
+        .with_overrides(|name| (name == "OFFSEC_JWT_ALLOW_HS256").then(|| "true".to_string()))
+        .unwrap();
+    let keyring = Keyring::from_config(&config).unwrap();
+    assert!(verify_token(&token(Algorithm::HS256, None, &hs), &keyring, AUD).is_ok());
+
+    let err = OffsecConfig::from_toml("")
+        .unwrap()
+        .with_overrides(|name| (name == "OFFSEC_JWT_ALLOW_HS256").then(|| "yes".to_string()))
+        .unwrap_err();
+    assert!(
+        format!("{err:#}").contains("OFFSEC_JWT_ALLOW_HS256"),
+        "{err:#}"
+    );
+}
+
+#[test]
+fn malformed_jwks_entries_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let cases = [
+        (
+            json!({ "kid": "a", "kty": "RSA", "n": "x", "e": "AQAB" }),
+            "unsupported kty",
+        ),
+        (
+            json!({ "kid": "a", "kty": "OKP", "crv": "Ed25519", "x": "AAAA" }),
+            "32-byte",
+        ),
+        (
+            json!({ "kid": "a", "kty": "oct", "k": "c2VjcmV0", "alg": "EdDSA" }),
+            "does not match",
+        ),
+        (
+            ed_jwk(
+                "a",
+                1,
+                json!({ "not_before": "2025-02-01T00:00:00Z", "not_after": "2025-01-01T00:00:00Z" }),
+            ),
+            "earlier than",
+        ),
+    ];
+    for (jwk, expected) in cases {
+        let path = dir.path().join("bad.json");
+        std::fs::write(&path, json!({ "keys": [jwk] }).to_string()).unwrap();
+        let err = format!("{:#}", load_jwks(&path).unwrap_err());
+        assert!(err.contains(expected) && err.contains("\"a\""), "{err}");
+    }
+
+    let err = keyring_from(
+        dir.path(),
+        vec![ed_jwk("dup", 1, json!({})), ed_jwk("dup", 2, json!({}))],
+    )
+    .unwrap_err();
+    assert!(
+        format!("{err:#}").contains("duplicate JWT key id"),
+        "{err:#}"
+    );
+}
+
+#[tokio::test]
+async fn reload_rotates_keys_and_records_them() {
+    let dir = tempfile::tempdir().unwrap();
+    let jwks = dir.path().join("jwks.json");
+    std::fs::write(
+        &jwks,
+        json!({ "keys": [ed_jwk("ed-1", 1, json!({}))] }).to_string(),
+    )
+    .unwrap();
+    let mut config = OffsecConfig::from_toml("").unwrap();
+    config.data_dir = dir.path().join("data").display().to_string();
+    config.jwks_file = Some(jwks.display().to_string());
+    let state = portal_ext::build_state(config).await.expect("state");
+
+    let retire_at = Utc::now() - Duration::seconds(1);
+    std::fs::write(
+        &jwks,
+        json!({ "keys": [
+            ed_jwk("ed-1", 1, json!({ "not_after": retire_at.to_rfc3339() })),
+            ed_jwk("ed-2", 2, json!({})),
+        ] })
+        .to_string(),
+    )
+    .unwrap();
+    let result = portal_ext::trust::reload(&state, "test", None)
+        .await
+        .unwrap();
+    assert!(result.capability_keys.added.contains_key("ed-2"));
+    assert!(result.capability_keys.changed.contains_key("ed-1"));
+
+    let keyring = &state.trust.snapshot().keyring;
+    assert!(verify_token(
+        &token(Algorithm::EdDSA, Some("ed-2"), &ed_encoding_key(2)),
+        keyring,
+        AUD
+    )
+    .is_ok());
+    assert!(matches!(
+        verify_token(
+            &token(Algorithm::EdDSA, Some("ed-1"), &ed_encoding_key(1)),
+            keyring,
+            AUD
+        ),
+        Err(CapabilityError::RetiredKey(_))
+    ));
+
+    let receipt = state
+        .store
+        .get(result.receipt_id.as_deref().unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    let recorded = &receipt.payload.as_ref().unwrap()["data"]["capability_keys"]["added"]["ed-2"];
+    assert!(
+        recorded.as_str().unwrap().starts_with("EdDSA "),
+        "{recorded}"
+    );
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::path::Path;
+
+use axum::http::StatusCode;
+use common::{config, config_toml, get, send};
+use ed25519_dalek::SigningKey;
+use portal_ext::{build_state, AppState};
+use serde_json::Value;
+
+async fn state(dir: &Path, ledger_toml: &str) -> AppState {
+    build_state(config_toml(dir, ledger_toml))
+        .await
+        .expect("state")
+}
+
+async fn did(state: &AppState) -> Value {
+    let (status, body) = send(state, get("/api/offsec/did")).await;
+    assert_eq!(status, StatusCode::OK);
+    body
+}
+
+#[tokio::test]
+async fn node_key_is_generated_once_and_reused() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = did(&state(dir.path(), "").await).await;
+    assert_eq!(first["did"], "did:vm:node:offsec-shield");
+    assert_eq!(first["algorithm"], "ed25519");
+    let key = first["verifying_key"].as_str().unwrap();
+    assert_eq!(key.len(), 64);
+    assert_eq!(first["trusted_issuers"]["did:vm:node:offsec-shield"], key);
+
+    let path = dir.path().join("ledger").join("node.key");
+    assert_eq!(std::fs::read(&path).unwrap().len(), 32);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // A restart signs as the same issuer.
+    let second = did(&state(dir.path(), "").await).await;
+    assert_eq!(second, first);
+}
+
+#[tokio::test]
+async fn configured_key_and_issuer_are_published() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_file = dir.path().join("shield.key");
+    std::fs::write(&key_file, [7u8; 32]).unwrap();
+    let toml = format!(
+        "[ledger]\nissuer_id = \"did:vm:node:shield-eu\"\nkey_file = \"{}\"\n",
+        key_file.display()
+    );
+
+    let state = state(dir.path(), &toml).await;
+    let body = did(&state).await;
+    assert_eq!(body["did"], "did:vm:node:shield-eu");
+    assert_eq!(
+        body["verifying_key"],
+        hex::encode(SigningKey::from_bytes(&[7; 32]).verifying_key().to_bytes())
+    );
+    assert_eq!(state.ledger.identity().issuer_id(), "did:vm:node:shield-eu");
+    assert!(!dir.path().join("ledger").exists());
+}
+
+#[tokio::test]
+async fn a_truncated_key_file_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_file = dir.path().join("shield.key");
+    std::fs::write(&key_file, [7u8; 16]).unwrap();
+    let mut config = config(dir.path());
+    config.ledger.key_file = Some(key_file.display().to_string());
+
+    let err = build_state(config).await.err().expect("truncated key");
+    assert!(format!("{err:#}").contains("32-byte"), "{err:#}");
+    // The bad key is left for the operator to fix, not replaced.
+    assert_eq!(std::fs::read(&key_file).unwrap().len(), 16);
+}
+
+#[tokio::test]
+async fn instances_keep_separate_ledgers() {
+    let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let east = state(a.path(), "[ledger]\nissuer_id = \"did:vm:node:east\"\n").await;
+    let west = state(b.path(), "[ledger]\nissuer_id = \"did:vm:node:west\"\n").await;
+
+    let (east_did, west_did) = (did(&east).await, did(&west).await);
+    assert_eq!(east_did["did"], "did:vm:node:east");
+    assert_eq!(west_did["did"], "did:vm:node:west");
+    assert_ne!(east_did["verifying_key"], west_did["verifying_key"]);
+    assert!(a.path().join("ledger").join("node.key").exists());
+    assert!(b.path().join("ledger").join("node.key").exists());
+
+    assert!(east.ledger.list_incident_ids().is_empty());
+    assert!(east.ledger.get_incident("inc-1").is_none());
+}
@@ -0,0 +1,194 @@
+use portal_ext::merkle::{root_from_path, verify_consistency, MerkleFrontier, TreeVersion};
+
+const VERSIONS: [TreeVersion; 2] = [TreeVersion::V1, TreeVersion::V2];
+
+fn leaf(i: usize) -> String {
+    blake3::hash(format!("leaf-{i}").as_bytes())
+        .to_hex()
+        .to_string()
+}
+
+/// Reference v1 root: full rebuild with odd-node duplication.
+fn reference_v1_root(leaves: &[String]) -> String {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|c| {
+                let right = c.get(1).unwrap_or(&c[0]);
+                blake3::hash(format!("{}{}", c[0], right).as_bytes())
+                    .to_hex()
+                    .to_string()
+            })
+            .collect();
+    }
+    level[0].clone()
+}
+
+/// Reference v2 root: RFC 6962 MTH with 0x00/0x01 prefixes over raw digests.
+fn reference_v2_root(leaves: &[String]) -> [u8; 32] {
+    if leaves.len() == 1 {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[0x00]);
+        hasher.update(&hex::decode(&leaves[0]).unwrap());
+        return *hasher.finalize().as_bytes();
+    }
+    let k = leaves.len().next_power_of_two() / 2;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[0x01]);
+    hasher.update(&reference_v2_root(&leaves[..k]));
+    hasher.update(&reference_v2_root(&leaves[k..]));
+    *hasher.finalize().as_bytes()
+}
+
+fn reference_root(version: TreeVersion, leaves: &[String]) -> String {
+    match version {
+        TreeVersion::V1 => reference_v1_root(leaves),
+        TreeVersion::V2 => hex::encode(reference_v2_root(leaves)),
+    }
+}
+
+#[test]
+fn incremental_root_matches_full_rebuild() {
+    for version in VERSIONS {
+        let mut frontier = MerkleFrontier::with_version(version);
+        let mut leaves = Vec::new();
+        for i in 0..70 {
+            leaves.push(leaf(i));
+            let (root, path) = frontier.append_with_path(leaf(i));
+            assert_eq!(
+                root,
+                reference_root(version, &leaves),
+                "{version:?} root mismatch at n={}",
+                i + 1
+            );
+            assert_eq!(root_from_path(version, &leaf(i), &path), root);
+        }
+    }
+}
+
+#[test]
+fn inclusion_path_for_every_index() {
+    for version in VERSIONS {
+        let leaves: Vec<String> = (0..37).map(leaf).collect();
+        let frontier = MerkleFrontier::from_leaves(version, leaves.clone());
+        let root = frontier.current_root();
+
+        for (i, l) in leaves.iter().enumerate() {
+            let path = frontier.inclusion_path(i).expect("path");
+            assert_eq!(
+                root_from_path(version, l, &path),
+                root,
+                "{version:?} path mismatch at index {i}"
+            );
+        }
+        assert!(frontier.inclusion_path(leaves.len()).is_none());
+    }
+}
+
+#[test]
+fn empty_and_single_leaf() {
+    let mut frontier = MerkleFrontier::with_version(TreeVersion::V1);
+    assert_eq!(frontier.current_root(), "0".repeat(64));
+
+    let (root, path) = frontier.append_with_path(leaf(0));
+    assert_eq!(root, leaf(0));
+    assert!(path.is_empty());
+
+    let mut frontier = MerkleFrontier::with_version(TreeVersion::V2);
+    let (root, path) = frontier.append_with_path(leaf(0));
+    assert_ne!(root, leaf(0), "v2 leaves are domain separated");
+    assert!(path.is_empty());
+}
+
+#[test]
+fn inclusion_path_at_historic_sizes() {
+    for version in VERSIONS {
+        let leaves: Vec<String> = (0..23).map(leaf).collect();
+        let frontier = MerkleFrontier::from_leaves(version, leaves.clone());
+
+        for size in 1..=leaves.len() {
+            let root = frontier.root_at(size).expect("root");
+            assert_eq!(root, reference_root(version, &leaves[..size]));
+            assert_eq!(frontier.size_of_root(&root), Some(size));
+            for (i, l) in leaves[..size].iter().enumerate() {
+                let path = frontier.inclusion_path_at(i, size).expect("path");
+                assert_eq!(
+                    root_from_path(version, l, &path),
+                    root,
+                    "{version:?} index {i} size {size}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn consistency_proofs_between_all_sizes() {
+    for version in VERSIONS {
+        let leaves: Vec<String> = (0..33).map(leaf).collect();
+        let frontier = MerkleFrontier::from_leaves(version, leaves.clone());
+
+        for new_size in 1..=leaves.len() {
+            let new_root = frontier.root_at(new_size).unwrap();
+            for old_size in 1..=new_size {
+                let old_root = frontier.root_at(old_size).unwrap();
+                let proof = frontier.consistency_proof(old_size, new_size).unwrap();
+                assert!(
+                    verify_consistency(version, old_size, new_size, &old_root, &new_root, &proof),
+                    "{version:?} consistency {old_size} -> {new_size}"
+                );
+                if old_size < new_size {
+                    assert!(!verify_consistency(
+                        version, old_size, new_size, &new_root, &new_root, &proof
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn truncate_matches_shorter_tree() {
+    for version in VERSIONS {
+        let leaves: Vec<String> = (0..19).map(leaf).collect();
+        let full_root = reference_root(version, &leaves);
+        for size in 0..=leaves.len() {
+            let mut frontier = MerkleFrontier::from_leaves(version, leaves.clone());
+            frontier.truncate(size);
+            assert_eq!(frontier.len(), size);
+            if size < leaves.len() {
+                assert_eq!(frontier.size_of_root(&full_root), None);
+            }
+            if size > 0 {
+                assert_eq!(frontier.size_of_root(&frontier.current_root()), Some(size));
+            }
+            assert_eq!(
+                frontier.current_root(),
+                MerkleFrontier::from_leaves(version, leaves[..size].to_vec()).current_root(),
+                "{version:?} truncate to {size}"
+            );
+            let (root, _) = frontier.append_with_path(leaf(100));
+            let mut expected = leaves[..size].to_vec();
+            expected.push(leaf(100));
+            assert_eq!(root, reference_root(version, &expected));
+        }
+    }
+}
+
+#[test]
+fn repeated_root_resolves_to_newest_size() {
+    // A v1 tree pairs an odd node with itself, so appending a copy of the
+    // last leaf of a 3-leaf tree leaves the root unchanged.
+    let leaves = vec![leaf(0), leaf(1), leaf(2), leaf(2)];
+    let mut frontier = MerkleFrontier::from_leaves(TreeVersion::V1, leaves);
+    let root = frontier.root_at(3).expect("root");
+    assert_eq!(frontier.root_at(4), Some(root.clone()));
+    assert_eq!(frontier.size_of_root(&root), Some(4));
+    assert_eq!(frontier.size_of_root_within(&root, 4), Some(4));
+    assert_eq!(frontier.size_of_root_within(&root, 3), Some(3));
+    assert_eq!(frontier.size_of_root_within(&root, 2), None);
+
+    frontier.truncate(3);
+    assert_eq!(frontier.size_of_root(&root), Some(3));
+}
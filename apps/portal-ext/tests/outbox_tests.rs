@@ -0,0 +1,239 @@
+mod common;
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::http::{HeaderMap, StatusCode};
+use common::{apply, config, get, post, send, token};
+use portal_ext::{
+    build_state,
+    config::DispatchConfig,
+    outbox::{self, OutboxStatus, IDEMPOTENCY_HEADER},
+    AppState,
+};
+use serde_json::{json, Value};
+
+/// Nothing listens here.
+const UNREACHABLE: &str = "http://127.0.0.1:1";
+
+/// A Guardian action server that answers 503 to its first `failures`
+/// deliveries, recording the idempotency key of every delivery it sees.
+async fn flaky_guardian(failures: usize) -> (String, Arc<Mutex<Vec<String>>>) {
+    let keys = Arc::new(Mutex::new(Vec::new()));
+    let seen = keys.clone();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let app = axum::Router::new().route(
+        "/actions/apply",
+        axum::routing::post(move |headers: HeaderMap| async move {
+            let mut seen = seen.lock().unwrap();
+            seen.push(
+                headers
+                    .get(IDEMPOTENCY_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string(),
+            );
+            if seen.len() <= failures {
+                (StatusCode::SERVICE_UNAVAILABLE, axum::Json(json!({})))
+            } else {
+                (StatusCode::OK, axum::Json(json!({ "status": "accepted" })))
+            }
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, keys)
+}
+
+/// Retries are due as soon as an attempt fails.
+async fn state(dir: &Path, guardian_url: &str, max_attempts: u32) -> AppState {
+    let mut config = config(dir);
+    config.guardian_url = Some(guardian_url.to_string());
+    config.dispatch.max_attempts = max_attempts;
+    config.dispatch.base_delay_ms = 0;
+    config.dispatch.timeout_ms = 2000;
+    build_state(config).await.expect("state")
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap() {
+    let config = DispatchConfig {
+        max_attempts: 8,
+        base_delay_ms: 1000,
+        max_delay_ms: 5000,
+        timeout_ms: 1000,
+    };
+    assert_eq!(outbox::backoff(&config, 1), Duration::from_millis(1000));
+    assert_eq!(outbox::backoff(&config, 2), Duration::from_millis(2000));
+    assert_eq!(outbox::backoff(&config, 3), Duration::from_millis(4000));
+    assert_eq!(outbox::backoff(&config, 4), Duration::from_millis(5000));
+    assert_eq!(outbox::backoff(&config, 200), Duration::from_millis(5000));
+
+    for _ in 0..100 {
+        let delay = outbox::jitter(Duration::from_millis(1000));
+        assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_millis(1000));
+    }
+    assert_eq!(outbox::jitter(Duration::ZERO), Duration::ZERO);
+
+    assert_eq!(
+        outbox::idempotency_key("act-1"),
+        outbox::idempotency_key("act-1")
+    );
+    assert_ne!(
+        outbox::idempotency_key("act-1"),
+        outbox::idempotency_key("act-2")
+    );
+}
+
+#[tokio::test]
+async fn retries_carry_the_same_idempotency_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, keys) = flaky_guardian(2).await;
+    let state = state(dir.path(), &url, 5).await;
+
+    let (status, body) = apply(&state, "act-1", "block_ip").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["state"], "dispatched");
+    let entry = state.outbox.get("act-1").await.unwrap().unwrap();
+    assert_eq!(entry.status, OutboxStatus::Pending);
+    assert_eq!(entry.attempts, 1);
+    assert!(entry.last_error.unwrap().contains("503"));
+
+    assert_eq!(outbox::process_due(&state).await, 1);
+    assert_eq!(outbox::process_due(&state).await, 1);
+    // Nothing is left to retry.
+    assert_eq!(outbox::process_due(&state).await, 0);
+
+    let entry = state.outbox.get("act-1").await.unwrap().unwrap();
+    assert_eq!(entry.status, OutboxStatus::Delivered);
+    assert_eq!(entry.attempts, 3);
+    let keys = keys.lock().unwrap().clone();
+    assert_eq!(keys.len(), 3);
+    assert!(keys.iter().all(|k| *k == outbox::idempotency_key("act-1")));
+
+    // Only the delivery is receipted, not the failed attempts.
+    let (_, record) = send(&state, get("/offsec/actions/act-1")).await;
+    assert_eq!(record["state"], "dispatched");
+    assert_eq!(record["receipt_ids"].as_array().unwrap().len(), 2);
+    let receipt = &state.store.list(1, None).await.unwrap()[0];
+    assert_eq!(receipt.event_type, "offsec.action.dispatch");
+    assert_eq!(receipt.payload.as_ref().unwrap()["outcome"], "delivered");
+    assert_eq!(receipt.payload.as_ref().unwrap()["attempts"], 3);
+    assert_eq!(record["receipt_ids"][1], json!(receipt.id));
+
+    let (status, delivered) = send(&state, get("/offsec/dispatch/outbox?status=delivered")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(delivered[0]["action_id"], "act-1");
+    let (_, dead) = send(&state, get("/offsec/dispatch/outbox?status=dead")).await;
+    assert_eq!(dead, json!([]));
+    let (status, _) = send(&state, get("/offsec/dispatch/outbox?status=bogus")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn exhausted_retries_dead_letter_the_action() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), UNREACHABLE, 2).await;
+    let mut frames = state.ws.subscribe();
+
+    let (_, body) = apply(&state, "act-1", "block_ip").await;
+    assert_eq!(body["state"], "dispatched");
+    assert_eq!(outbox::process_due(&state).await, 1);
+
+    let (_, record) = send(&state, get("/offsec/actions/act-1")).await;
+    assert_eq!(record["state"], "failed");
+    let last = record["history"]
+        .as_array()
+        .unwrap()
+        .last()
+        .unwrap()
+        .clone();
+    assert_eq!(last["actor"], "portal");
+    assert_eq!(last["details"]["attempts"], 2);
+    assert!(last["details"]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("dispatch failed"));
+    let receipt = &state.store.list(1, None).await.unwrap()[0];
+    assert_eq!(last["receipt_id"], json!(receipt.id));
+    assert_eq!(receipt.payload.as_ref().unwrap()["outcome"], "dead_letter");
+
+    let (_, dead) = send(&state, get("/offsec/dispatch/outbox?status=dead")).await;
+    assert_eq!(dead[0]["action_id"], "act-1");
+    assert_eq!(dead[0]["attempts"], 2);
+    assert!(dead[0]["last_error"].is_string());
+
+    let outcomes: Vec<Value> = std::iter::from_fn(|| frames.try_recv().ok())
+        .map(|f| serde_json::from_str::<Value>(&f).unwrap())
+        .filter(|f| f["type"] == "offsec.action.dispatch")
+        .map(|f| f["data"]["outcome"].clone())
+        .collect();
+    assert_eq!(outcomes, vec![json!("retry"), json!("dead_letter")]);
+
+    let (status, _) = send(
+        &state,
+        post(
+            "/offsec/action/update",
+            Some(&token("guardian-a", vec!["action:report"])),
+            json!({
+                "action_id": "act-1",
+                "action_type": "block_ip",
+                "status": "applied",
+                "details": {},
+                "ts": "2025-11-23T01:34:00Z",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn pending_dispatches_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let state = state(dir.path(), UNREACHABLE, 5).await;
+        let (_, body) = apply(&state, "act-1", "block_ip").await;
+        assert_eq!(body["state"], "dispatched");
+    }
+
+    let (url, keys) = flaky_guardian(0).await;
+    let state = state(dir.path(), &url, 5).await;
+    assert_eq!(outbox::process_due(&state).await, 1);
+    let entry = state.outbox.get("act-1").await.unwrap().unwrap();
+    assert_eq!(entry.status, OutboxStatus::Delivered);
+    assert_eq!(entry.attempts, 2);
+    assert_eq!(entry.payload["target"]["ip"], "10.1.2.3");
+    assert_eq!(keys.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn actions_are_queued_and_dispatched_together() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path(), UNREACHABLE, 5).await;
+    let new = portal_ext::actions::NewAction {
+        action_id: "act-1".to_string(),
+        action_type: "block_ip".to_string(),
+        target: json!({ "ip": "10.1.2.3" }),
+        guardian_id: None,
+        requested_by: None,
+        approval: None,
+    };
+    state.actions.create(&new, "operator").await.unwrap();
+    let req = serde_json::from_value(json!({
+        "action_id": "act-1",
+        "action_type": "block_ip",
+        "target": { "ip": "10.1.2.3" },
+        "ts": "2025-11-23T01:33:22Z",
+    }))
+    .unwrap();
+
+    // Still `requested`: the transition fails and nothing is queued.
+    assert!(outbox::dispatch(&state, &req).await.is_err());
+    assert!(state.outbox.get("act-1").await.unwrap().is_none());
+    let record = state.actions.get("act-1").await.unwrap().unwrap();
+    assert_eq!(record.state, portal_ext::actions::ActionState::Requested);
+}
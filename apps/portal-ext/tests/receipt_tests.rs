@@ -1,4 +1,6 @@
-use portal_ext::receipts::{read_receipts, write_receipt};
+use portal_ext::merkle::MerkleFrontier;
+use portal_ext::receipts::write_receipt;
+use portal_ext::store::{JsonImport, ReceiptStore};
 use portal_ext::{build_state, OffsecConfig};
 use serde_json::json;
 use std::env;
@@ -11,11 +13,12 @@ async fn writes_receipt_and_updates_root() {
 
     let mut config = OffsecConfig::from_env();
     config.data_dir = dir.path().to_string_lossy().into_owned();
-    let state = build_state(config);
+    let state = build_state(config).await.expect("state");
     let payload = json!({ "id": "evt-123", "foo": "bar" });
     let tags: Vec<String> = vec![];
 
     let receipt = write_receipt(&state, "test", Some("guardian-alpha"), &tags, &payload)
+        .await
         .expect("receipt write");
 
     assert!(receipt.hash.len() == 64);
@@ -26,12 +29,10 @@ async fn writes_receipt_and_updates_root() {
     let root_txt = std::fs::read_to_string(dir.path().join("ROOT.txt")).unwrap();
     assert!(root_txt.trim().len() >= 64);
 
-    let receipts_dir = dir.path().join("receipts/offsec");
-    assert!(receipts_dir.exists());
-    let file_count = std::fs::read_dir(&receipts_dir).unwrap().count();
-    assert_eq!(file_count, 1);
+    assert!(dir.path().join("receipts.db").exists());
+    assert_eq!(state.store.count().await.unwrap(), 1);
 
-    let receipts = read_receipts(dir.path().to_str().unwrap(), 10);
+    let receipts = state.store.list(10, None).await.unwrap();
     assert_eq!(receipts.len(), 1);
 }
 
@@ -42,15 +43,638 @@ async fn receipt_includes_merkle_path_on_subsequent_entries() {
 
     let mut config = OffsecConfig::from_env();
     config.data_dir = dir.path().to_string_lossy().into_owned();
-    let state = build_state(config);
+    let state = build_state(config).await.expect("state");
     let payload = json!({ "id": "evt-123", "foo": "bar" });
     let tags: Vec<String> = vec![];
 
     let _ = write_receipt(&state, "test", Some("guardian-alpha"), &tags, &payload)
+        .await
         .expect("receipt write");
     let second = write_receipt(&state, "test", Some("guardian-alpha"), &tags, &payload)
+        .await
         .expect("receipt write");
 
     assert!(!second.merkle_path.is_empty());
     assert!(second.merkle_root.len() == 64);
 }
+
+#[tokio::test]
+async fn frontier_survives_restart() {
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    let tags: Vec<String> = vec![];
+
+    let state = build_state(config.clone()).await.expect("state");
+    for i in 0..3 {
+        let payload = json!({ "id": format!("evt-{i}") });
+        write_receipt(&state, "test", None, &tags, &payload)
+            .await
+            .expect("receipt write");
+    }
+    let root_before = state.frontier.lock().unwrap().current_root();
+
+    let restarted = build_state(config).await.expect("restarted state");
+    let frontier = restarted.frontier.lock().unwrap();
+    assert_eq!(frontier.len(), 3);
+    assert_eq!(frontier.current_root(), root_before);
+}
+
+#[tokio::test]
+async fn refuses_to_start_on_root_mismatch() {
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    let state = build_state(config.clone()).await.expect("state");
+    let payload = json!({ "id": "evt-1" });
+    write_receipt(&state, "test", None, &[], &payload)
+        .await
+        .expect("receipt write");
+
+    std::fs::write(dir.path().join("ROOT.txt"), format!("{}\n", "f".repeat(64))).unwrap();
+
+    assert!(build_state(config).await.is_err());
+}
+
+#[tokio::test]
+async fn legacy_root_mismatch_is_migrated_with_a_receipt() {
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+
+    // Two receipts with the same timestamp, and a ROOT.txt that only covers
+    // the one written after the last pre-upgrade restart.
+    let legacy = dir.path().join("receipts/offsec");
+    std::fs::create_dir_all(&legacy).unwrap();
+    let hashes: Vec<String> = (0..2)
+        .map(|i| {
+            blake3::hash(format!("legacy-{i}").as_bytes())
+                .to_hex()
+                .to_string()
+        })
+        .collect();
+    for hash in &hashes {
+        let receipt = json!({
+            "id": format!("offsec-{hash}"),
+            "event_type": "offsec.ingest",
+            "timestamp": "2025-01-01T00:00:00+00:00",
+            "hash": hash,
+            "merkle_root": hash,
+        });
+        std::fs::write(
+            legacy.join(format!("offsec-{hash}.json")),
+            serde_json::to_vec(&receipt).unwrap(),
+        )
+        .unwrap();
+    }
+    std::fs::write(dir.path().join("ROOT.txt"), format!("{}\n", hashes[1])).unwrap();
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    let state = build_state(config.clone()).await.expect("state");
+
+    let mut sorted = hashes.clone();
+    sorted.sort();
+    let log = std::fs::read_to_string(dir.path().join("LEAVES.log")).unwrap();
+    let logged: Vec<&str> = log.lines().skip(1).collect();
+    assert_eq!(&logged[..2], &[sorted[0].as_str(), sorted[1].as_str()]);
+
+    let migrated = state.store.list(1, None).await.unwrap().remove(0);
+    assert_eq!(migrated.event_type, "offsec.merkle.migrated");
+    let payload = migrated.payload.unwrap();
+    assert_eq!(payload["leaves"], 2);
+    assert_eq!(payload["previous_root"], hashes[1].as_str());
+    let root_before = state.frontier.lock().unwrap().current_root();
+    drop(state);
+
+    let restarted = build_state(config).await.expect("restarted state");
+    assert_eq!(
+        restarted.frontier.lock().unwrap().current_root(),
+        root_before
+    );
+    assert_eq!(restarted.store.count().await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn fresh_inclusion_proof_tracks_current_root() {
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use tower::util::ServiceExt;
+
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    let state = build_state(config).await.expect("state");
+    let first = write_receipt(&state, "test", None, &[], &json!({ "id": "evt-0" }))
+        .await
+        .expect("receipt write");
+    for i in 1..5 {
+        write_receipt(
+            &state,
+            "test",
+            None,
+            &[],
+            &json!({ "id": format!("evt-{i}") }),
+        )
+        .await
+        .expect("receipt write");
+    }
+    let current_root = state.frontier.lock().unwrap().current_root();
+    assert_ne!(first.merkle_root, current_root);
+
+    let app = portal_ext::app_router(state);
+    let response = app
+        .clone()
+        .oneshot(
+            Request::get(format!("/offsec/proof/{}/inclusion", first.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value =
+        serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+    assert_eq!(body["root"], current_root);
+    assert_eq!(body["leafIndex"], 0);
+    assert_eq!(body["treeSize"], 5);
+
+    let response = app
+        .oneshot(
+            Request::get("/offsec/consistency?first=1&second=5")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value =
+        serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+    assert_eq!(body["firstRoot"], first.merkle_root);
+    assert_eq!(body["secondRoot"], current_root);
+}
+
+#[tokio::test]
+async fn headerless_leaf_log_is_loaded_as_v1() {
+    use portal_ext::merkle::{MerkleFrontier, TreeVersion};
+
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+
+    let leaves: Vec<String> = (0..3)
+        .map(|i| {
+            blake3::hash(format!("legacy-{i}").as_bytes())
+                .to_hex()
+                .to_string()
+        })
+        .collect();
+    let v1_root = MerkleFrontier::from_leaves(TreeVersion::V1, leaves.clone()).current_root();
+    std::fs::write(dir.path().join("LEAVES.log"), leaves.join("\n") + "\n").unwrap();
+    std::fs::write(dir.path().join("ROOT.txt"), format!("{v1_root}\n")).unwrap();
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    let state = build_state(config).await.expect("state");
+
+    let receipt = write_receipt(&state, "test", None, &[], &json!({ "id": "evt-4" }))
+        .await
+        .expect("receipt write");
+    assert_eq!(receipt.tree_version, 1);
+    assert_eq!(receipt.leaf_index, Some(3));
+}
+
+#[tokio::test]
+async fn receipt_leaf_commits_to_stored_payload() {
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    let state = build_state(config).await.expect("state");
+    let payload = json!({ "type": "threat_event", "data": { "id": "evt-1", "b": 2, "a": 1 } });
+    let receipt = write_receipt(&state, "test", None, &[], &payload)
+        .await
+        .expect("receipt write");
+
+    let stored = state.store.get(&receipt.id).await.unwrap().expect("stored");
+    let stored_payload = stored.payload.expect("payload stored");
+    assert_eq!(stored_payload, payload);
+    let bytes = portal_ext::mesh::util::canonical_json(&stored_payload).unwrap();
+    assert_eq!(blake3::hash(&bytes).to_hex().as_str(), receipt.hash);
+}
+
+#[tokio::test]
+async fn receipts_and_tree_heads_are_signed() {
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use portal_ext::mesh::util::{public_key_b64, verify_signature};
+    use portal_ext::receipts::{receipt_statement, SignedTreeHead};
+    use tower::util::ServiceExt;
+
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+    let key_path = dir.path().join("node.key");
+    std::fs::write(&key_path, [7u8; 32]).unwrap();
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().join("data").to_string_lossy().into_owned();
+    config.signing_key_file = Some(key_path.to_string_lossy().into_owned());
+    let state = build_state(config).await.expect("state");
+    let pubkey = public_key_b64(state.signer.as_deref().expect("signer"));
+
+    let tags = vec!["lab".to_string()];
+    write_receipt(&state, "test", None, &tags, &json!({ "n": 1 }))
+        .await
+        .expect("receipt write");
+    let receipt = write_receipt(
+        &state,
+        "test",
+        Some("guardian-a"),
+        &tags,
+        &json!({ "n": 2 }),
+    )
+    .await
+    .expect("receipt write");
+
+    let sig = receipt.signature.as_deref().expect("receipt signature");
+    verify_signature(&pubkey, sig, &receipt_statement(&receipt)).expect("receipt sig");
+
+    let mut forged = receipt.clone();
+    forged.event_type = "other".to_string();
+    assert!(verify_signature(&pubkey, sig, &receipt_statement(&forged)).is_err());
+    let mut forged = receipt.clone();
+    forged.guardian_tags = vec!["prod".to_string()];
+    assert!(verify_signature(&pubkey, sig, &receipt_statement(&forged)).is_err());
+    let mut forged = receipt.clone();
+    forged.guardian_id = Some("guardian-b".to_string());
+    assert!(verify_signature(&pubkey, sig, &receipt_statement(&forged)).is_err());
+
+    let sth: SignedTreeHead =
+        serde_json::from_slice(&std::fs::read(dir.path().join("data/STH.json")).unwrap()).unwrap();
+    assert_eq!(sth.tree_size, 2);
+    assert_eq!(sth.root, receipt.merkle_root);
+    let statement = json!({
+        "tree_version": sth.tree_version,
+        "tree_size": sth.tree_size,
+        "root": sth.root,
+        "timestamp": sth.timestamp,
+    });
+    verify_signature(&pubkey, &sth.signature, &statement).expect("sth sig");
+
+    // `/offsec/root` serves the tree head written at commit time.
+    let app = portal_ext::app_router(state);
+    for _ in 0..2 {
+        let response = app
+            .clone()
+            .oneshot(Request::get("/offsec/root").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap())
+                .unwrap();
+        assert_eq!(body["root"], json!(sth.root));
+        assert_eq!(body["signed_tree_head"]["timestamp"], json!(sth.timestamp));
+        assert_eq!(body["signed_tree_head"]["signature"], json!(sth.signature));
+        assert_eq!(body["pubkey"], json!(pubkey));
+    }
+}
+
+#[tokio::test]
+async fn json_receipts_are_imported_once() {
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+
+    let legacy = dir.path().join("receipts/offsec");
+    std::fs::create_dir_all(&legacy).unwrap();
+    for (i, guardian) in ["guardian-a", "guardian-b", "guardian-a"]
+        .iter()
+        .enumerate()
+    {
+        let hash = blake3::hash(format!("legacy-{i}").as_bytes())
+            .to_hex()
+            .to_string();
+        let receipt = json!({
+            "id": format!("offsec-{hash}"),
+            "event_type": "offsec.ingest",
+            "guardian_id": guardian,
+            "timestamp": format!("2025-01-0{}T00:00:00+00:00", i + 1),
+            "hash": hash,
+            "merkle_root": hash,
+        });
+        std::fs::write(
+            legacy.join(format!("offsec-{hash}.json")),
+            serde_json::to_vec(&receipt).unwrap(),
+        )
+        .unwrap();
+    }
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    let state = build_state(config.clone()).await.expect("state");
+    assert_eq!(state.store.count().await.unwrap(), 3);
+
+    let for_a = state.store.list(10, Some("guardian-a")).await.unwrap();
+    assert_eq!(for_a.len(), 2);
+    assert!(for_a[0].timestamp > for_a[1].timestamp);
+
+    write_receipt(&state, "test", None, &[], &json!({ "n": 1 }))
+        .await
+        .expect("receipt write");
+    drop(state);
+
+    let restarted = build_state(config).await.expect("restarted state");
+    assert_eq!(restarted.store.count().await.unwrap(), 4);
+    let in_leaf_order = restarted
+        .store
+        .page(
+            &Default::default(),
+            portal_ext::store::ReceiptOrder::Leaf,
+            10,
+            None,
+        )
+        .await
+        .unwrap()
+        .receipts;
+    let indexes: Vec<_> = in_leaf_order.iter().map(|r| r.leaf_index).collect();
+    assert_eq!(indexes, vec![Some(0), Some(1), Some(2), Some(3)]);
+    assert_eq!(
+        restarted.store.list(1, None).await.unwrap()[0].event_type,
+        "test"
+    );
+}
+
+#[tokio::test]
+async fn json_receipts_sharing_a_leaf_are_counted() {
+    let dir = tempdir().expect("tempdir");
+    let legacy = dir.path().join("receipts/offsec");
+    std::fs::create_dir_all(&legacy).unwrap();
+    let receipt = |n: usize| {
+        let hash = blake3::hash(format!("legacy-{n}").as_bytes())
+            .to_hex()
+            .to_string();
+        json!({
+            "id": format!("offsec-{hash}"),
+            "event_type": "offsec.ingest",
+            "timestamp": format!("2025-01-0{}T00:00:00+00:00", n + 1),
+            "hash": hash,
+            "merkle_root": hash,
+            "leaf_index": 0,
+        })
+    };
+    // The same receipt twice, and a second one claiming its leaf.
+    for (file, n) in [("a.json", 0), ("b.json", 0), ("c.json", 1)] {
+        std::fs::write(legacy.join(file), serde_json::to_vec(&receipt(n)).unwrap()).unwrap();
+    }
+
+    let data_dir = dir.path().to_string_lossy().into_owned();
+    let store = ReceiptStore::open(&data_dir).await.expect("store");
+    let outcome = store
+        .import_json_receipts(&data_dir, &MerkleFrontier::new())
+        .await
+        .expect("import");
+    assert_eq!(
+        outcome,
+        JsonImport {
+            imported: 1,
+            collisions: 1
+        }
+    );
+    let stored = store.list(10, None).await.unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].id, receipt(0)["id"]);
+}
+
+#[tokio::test]
+async fn receipts_page_with_cursor_and_filters() {
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use tower::util::ServiceExt;
+
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    let state = build_state(config).await.expect("state");
+    for i in 0..7 {
+        let (event_type, severity) = if i % 2 == 0 {
+            ("offsec.ingest", "high")
+        } else {
+            ("offsec.action.update", "low")
+        };
+        let tags = vec![format!("site-{}", i % 3)];
+        let payload = json!({ "type": "threat_event", "data": { "n": i, "severity": severity } });
+        write_receipt(&state, event_type, Some("guardian-alpha"), &tags, &payload)
+            .await
+            .expect("receipt write");
+    }
+
+    let pool = state.store.pool().clone();
+    let app = portal_ext::app_router(state);
+    let get = |uri: String| {
+        let app = app.clone();
+        async move {
+            let resp = app
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let status = resp.status();
+            let next = resp
+                .headers()
+                .get("x-next-cursor")
+                .map(|v| v.to_str().unwrap().to_string());
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, body, next)
+        }
+    };
+
+    // Walk the log in leaf order, three at a time.
+    let mut indexes = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let uri = match &cursor {
+            Some(c) => format!("/offsec/receipts?order=leaf&limit=3&cursor={c}"),
+            None => "/offsec/receipts?order=leaf&limit=3".to_string(),
+        };
+        let (status, body, next) = get(uri).await;
+        assert_eq!(status, StatusCode::OK);
+        indexes.extend(
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["leaf_index"].as_u64().unwrap()),
+        );
+        match next {
+            Some(n) => cursor = Some(n),
+            None => break,
+        }
+    }
+    assert_eq!(indexes, (0..7).collect::<Vec<u64>>());
+
+    let (_, newest, next) = get("/offsec/receipts?limit=4".to_string()).await;
+    let (_, older, last) = get(format!("/offsec/receipts?limit=4&cursor={}", next.unwrap())).await;
+    assert_eq!(newest.as_array().unwrap().len(), 4);
+    assert_eq!(older.as_array().unwrap().len(), 3);
+    assert!(last.is_none());
+    assert_eq!(newest[0]["leaf_index"], 6);
+
+    let (_, actions, _) = get("/offsec/receipts?event_type=offsec.action.*".to_string()).await;
+    assert_eq!(actions.as_array().unwrap().len(), 3);
+    let (_, high, _) = get("/offsec/receipts?severity=high&tag=site-0".to_string()).await;
+    let high: Vec<u64> = high
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["leaf_index"].as_u64().unwrap())
+        .collect();
+    assert_eq!(high, vec![6, 0]);
+
+    let (status, _, _) = get("/offsec/receipts?cursor=bm9wZQ".to_string()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _, _) = get("/offsec/receipts?since=yesterday".to_string()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body, _) = get("/offsec/receipts?order=time&cursor=bDox".to_string()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["details"], "cursor does not match requested order");
+
+    // A store failure is the server's fault, not the query's.
+    pool.close().await;
+    let (status, body, _) = get("/offsec/receipts".to_string()).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"], "receipt_store_unavailable");
+}
+
+#[tokio::test]
+async fn interrupted_commit_is_rolled_back_at_startup() {
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().to_string_lossy().into_owned();
+    let state = build_state(config.clone()).await.expect("state");
+    let mut last = None;
+    for i in 0..3 {
+        last = Some(
+            write_receipt(&state, "test", None, &[], &json!({ "n": i }))
+                .await
+                .expect("receipt write"),
+        );
+    }
+    let committed_root = last.unwrap().merkle_root;
+
+    // Crash mid-commit of leaf 3: write-ahead record written, leaf line torn,
+    // ROOT.txt truncated, receipt row already inserted.
+    let orphan = write_receipt(&state, "test", None, &[], &json!({ "n": 3 }))
+        .await
+        .expect("receipt write");
+    drop(state);
+    let pending = json!({ "leaf_index": 3, "leaf": orphan.hash, "root": orphan.merkle_root });
+    std::fs::write(dir.path().join("PENDING.json"), pending.to_string()).unwrap();
+    let leaves = std::fs::read_to_string(dir.path().join("LEAVES.log")).unwrap();
+    let torn = &leaves[..leaves.len() - 40];
+    std::fs::write(dir.path().join("LEAVES.log"), torn).unwrap();
+    std::fs::write(dir.path().join("ROOT.txt"), "").unwrap();
+
+    let restarted = build_state(config).await.expect("restarted state");
+    assert!(!dir.path().join("PENDING.json").exists());
+    assert_eq!(restarted.frontier.lock().unwrap().len(), 3);
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("ROOT.txt"))
+            .unwrap()
+            .trim(),
+        committed_root
+    );
+    assert_eq!(restarted.store.count().await.unwrap(), 3);
+    assert!(restarted.store.get(&orphan.id).await.unwrap().is_none());
+
+    let next = write_receipt(&restarted, "test", None, &[], &json!({ "n": 4 }))
+        .await
+        .expect("receipt write");
+    assert_eq!(next.leaf_index, Some(3));
+}
+
+#[tokio::test]
+async fn tree_heads_are_signed_only_for_committed_roots() {
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use tower::util::ServiceExt;
+
+    let dir = tempdir().expect("tempdir");
+    env::set_var("OFFSEC_JWT_HS256_SECRET", "test-secret");
+    let key_path = dir.path().join("node.key");
+    std::fs::write(&key_path, [7u8; 32]).unwrap();
+
+    let mut config = OffsecConfig::from_env();
+    config.data_dir = dir.path().join("data").to_string_lossy().into_owned();
+    config.signing_key_file = Some(key_path.to_string_lossy().into_owned());
+    let state = build_state(config).await.expect("state");
+    write_receipt(&state, "test", None, &[], &json!({ "n": 1 }))
+        .await
+        .expect("receipt write");
+    let receipt = write_receipt(&state, "test", None, &[], &json!({ "n": 2 }))
+        .await
+        .expect("receipt write");
+
+    // A stored receipt whose root the log does not reproduce.
+    let mut forged = receipt.clone();
+    forged.id = "offsec-forged".to_string();
+    forged.leaf_index = Some(2);
+    forged.merkle_root = "ab".repeat(32);
+    state.store.insert(&forged).await.unwrap();
+    // A leaf appended but not yet committed.
+    state
+        .frontier
+        .lock()
+        .unwrap()
+        .append(blake3::hash(b"in flight").to_hex().to_string());
+
+    let app = portal_ext::app_router(state);
+    let get = |path: String| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(Request::get(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let status = response.status();
+            let body: serde_json::Value =
+                serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap())
+                    .unwrap();
+            (status, body)
+        }
+    };
+
+    let (status, body) = get(format!("/offsec/proof/{}", receipt.id)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["sth"]["root"], json!(receipt.merkle_root));
+    let (status, body) = get("/offsec/proof/offsec-forged".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.get("sth").is_none());
+
+    let (status, body) = get(format!("/offsec/proof/{}/inclusion", receipt.id)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["treeSize"], 2);
+    assert_eq!(body["sth"]["tree_size"], 2);
+    let (status, _) = get(format!(
+        "/offsec/proof/{}/inclusion?tree_size=3",
+        receipt.id
+    ))
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = get("/offsec/consistency?first=1".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["second"], 2);
+    let (status, _) = get("/offsec/consistency?first=1&second=3".to_string()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, body) = get("/offsec/root".to_string()).await;
+    assert_eq!(body["root"], json!(receipt.merkle_root));
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use std::path::Path;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{post, send, SECRET};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use portal_ext::{auth::Principal, build_state, capabilities::Claims, OffsecConfig};
+use serde_json::{json, Value};
+
+fn config(dir: &Path) -> OffsecConfig {
+    let mut config = common::config(dir);
+    // Nothing listens here; forwarding is best-effort.
+    config.guardian_url = Some("http://127.0.0.1:1".to_string());
+    config
+}
+
+fn token(actions: Vec<&str>, id: Option<Value>, exp_in: i64) -> String {
+    let now = chrono::Utc::now().timestamp();
+    let mut claims = json!({
+        "sub": "guardian-1",
+        "aud": "offsec-portal",
+        "exp": now + exp_in,
+        "iat": now,
+        "actions": actions,
+    });
+    if let Some(Value::Object(id)) = id {
+        claims.as_object_mut().unwrap().extend(id);
+    }
+    encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+fn apply_request(token: &str, action_id: &str, action_type: &str) -> Request<Body> {
+    let payload = json!({
+        "action_id": action_id,
+        "action_type": action_type,
+        "target": { "ip": "203.0.113.7" },
+        "ts": "2025-11-23T01:33:22Z",
+    });
+    post("/offsec/action/apply", Some(token), payload)
+}
+
+#[tokio::test]
+async fn replayed_destructive_token_is_denied_and_receipted() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let token = token(vec!["block_ip"], Some(json!({ "nonce": "n-1" })), 600);
+
+    let (status, _) = send(&state, apply_request(&token, "act-1", "block_ip")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut frames = state.ws.subscribe();
+    let (status, body) = send(&state, apply_request(&token, "act-2", "block_ip")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "replayed_capability_token");
+
+    let frame: Value = serde_json::from_str(&frames.try_recv().unwrap()).unwrap();
+    assert_eq!(frame["type"], "capability_denied");
+    assert_eq!(frame["data"]["event_type"], "capability_replay");
+    assert_eq!(frame["data"]["metadata"]["token_id"], "n-1");
+    assert_eq!(frame["data"]["metadata"]["first_action"], "block_ip");
+    let frame: Value = serde_json::from_str(&frames.try_recv().unwrap()).unwrap();
+    assert_eq!(frame["type"], "receipt");
+    assert_eq!(frame["data"]["event_type"], "offsec.capability.replay");
+    assert_eq!(frame["data"]["guardian_id"], "guardian-1");
+
+    // Only the first request got as far as an action receipt.
+    let receipts = state.store.list(10, None).await.unwrap();
+    let kinds: Vec<_> = receipts.iter().map(|r| r.event_type.as_str()).collect();
+    assert_eq!(
+        kinds,
+        vec!["offsec.capability.replay", "offsec.action.request"]
+    );
+}
+
+#[tokio::test]
+async fn refused_requests_do_not_spend_the_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let alert = token(vec!["alert_human"], None, 600);
+    let (status, _) = send(&state, apply_request(&alert, "act-1", "alert_human")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The id is taken, so the destructive request is refused before it is
+    // receipted and the token stays unspent.
+    let block = token(vec!["block_ip"], Some(json!({ "nonce": "n-1" })), 600);
+    let (status, body) = send(&state, apply_request(&block, "act-1", "block_ip")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "duplicate_action");
+    assert_eq!(state.replay.len().await.unwrap(), 0);
+
+    let (status, _) = send(&state, apply_request(&block, "act-2", "block_ip")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(state.replay.len().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn used_tokens_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let token = token(vec!["quarantine"], Some(json!({ "jti": "j-1" })), 600);
+
+    let state = build_state(config(dir.path())).await.expect("state");
+    let (status, _) = send(&state, apply_request(&token, "act-1", "quarantine")).await;
+    assert_eq!(status, StatusCode::OK);
+    drop(state);
+
+    let state = build_state(config(dir.path())).await.expect("state");
+    let (status, body) = send(&state, apply_request(&token, "act-1", "quarantine")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "replayed_capability_token");
+}
+
+#[tokio::test]
+async fn non_destructive_actions_allow_reuse() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let token = token(vec!["alert_human"], None, 600);
+
+    for id in ["act-1", "act-2"] {
+        let (status, _) = send(&state, apply_request(&token, id, "alert_human")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    assert_eq!(state.replay.len().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn destructive_actions_need_a_token_id() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let mut frames = state.ws.subscribe();
+
+    let (status, body) = send(
+        &state,
+        apply_request(&token(vec!["block_ip"], None, 600), "act-1", "block_ip"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(
+        body["details"].as_str().unwrap().contains("single-use"),
+        "{body}"
+    );
+    let frame: Value = serde_json::from_str(&frames.try_recv().unwrap()).unwrap();
+    assert_eq!(frame["data"]["event_type"], "capability_denied");
+    assert_eq!(state.store.count().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_pruned() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let now = chrono::Utc::now().timestamp() as usize;
+    let claims = |id: &str, exp: usize| -> Principal {
+        serde_json::from_value::<Claims>(json!({
+            "sub": "guardian-1",
+            "aud": "offsec-portal",
+            "exp": exp,
+            "iat": now,
+            "actions": ["block_ip"],
+            "jti": id,
+        }))
+        .unwrap()
+        .into()
+    };
+
+    state
+        .replay
+        .consume(&claims("stale", now - 10), "block_ip")
+        .await
+        .unwrap();
+    assert_eq!(state.replay.len().await.unwrap(), 1);
+    assert_eq!(state.replay.prune().await.unwrap(), 1);
+
+    // Every consume prunes first, so the table only holds live tokens.
+    state
+        .replay
+        .consume(&claims("stale", now - 10), "block_ip")
+        .await
+        .unwrap();
+    state
+        .replay
+        .consume(&claims("fresh", now + 600), "block_ip")
+        .await
+        .unwrap();
+    assert_eq!(state.replay.len().await.unwrap(), 1);
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use std::path::Path;
+
+use axum::http::StatusCode;
+use common::{config, post, send, token};
+use portal_ext::{build_state, AppState};
+use serde_json::{json, Value};
+
+/// A Guardian action server that accepts every forwarded action.
+async fn guardian() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let app = axum::Router::new().route(
+        "/actions/apply",
+        axum::routing::post(|| async { axum::Json(json!({ "status": "accepted" })) }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    url
+}
+
+async fn state(dir: &Path) -> AppState {
+    let mut config = config(dir);
+    config.guardian_url = Some(guardian().await);
+    build_state(config).await.expect("state")
+}
+
+fn update_body(action_id: &str, action_type: &str) -> Value {
+    json!({
+        "action_id": action_id,
+        "action_type": action_type,
+        "status": "applied",
+        "details": { "ip": "10.1.2.3", "ok": true },
+        "ts": "2025-11-23T01:34:00Z",
+    })
+}
+
+/// An operator asks `guardian-a` to block an IP. The request and its
+/// delivery each write a receipt.
+async fn request_block(state: &AppState) {
+    let (status, _) = send(
+        state,
+        post(
+            "/offsec/action/apply",
+            Some(&token("operator", vec!["block_ip"])),
+            json!({
+                "action_id": "act-1",
+                "action_type": "block_ip",
+                "target": { "ip": "10.1.2.3" },
+                "ts": "2025-11-23T01:33:22Z",
+                "guardian_id": "guardian-a",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn updates_need_the_report_scope() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    request_block(&state).await;
+
+    let (status, _) = send(
+        &state,
+        post(
+            "/offsec/action/update",
+            None,
+            update_body("act-1", "block_ip"),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(
+        &state,
+        post(
+            "/offsec/action/update",
+            Some(&token("guardian-a", vec!["block_ip"])),
+            update_body("act-1", "block_ip"),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(state.store.count().await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn only_the_originating_guardian_may_report() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    request_block(&state).await;
+    let reporter = token("guardian-a", vec!["action:report"]);
+
+    let (status, body) = send(
+        &state,
+        post(
+            "/offsec/action/update",
+            Some(&token("guardian-b", vec!["action:report"])),
+            update_body("act-1", "block_ip"),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "not_originating_guardian");
+
+    // Claiming to be someone else in the body does not help either.
+    let mut spoofed = update_body("act-1", "block_ip");
+    spoofed["guardian_id"] = json!("guardian-b");
+    let (status, _) = send(
+        &state,
+        post("/offsec/action/update", Some(&reporter), spoofed),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = send(
+        &state,
+        post(
+            "/offsec/action/update",
+            Some(&reporter),
+            update_body("act-404", "block_ip"),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], "unknown_action");
+
+    let (status, body) = send(
+        &state,
+        post(
+            "/offsec/action/update",
+            Some(&reporter),
+            update_body("act-1", "quarantine"),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "action_type_mismatch");
+
+    let (status, _) = send(
+        &state,
+        post(
+            "/offsec/action/update",
+            Some(&reporter),
+            update_body("act-1", "block_ip"),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let receipt = &state.store.list(1, None).await.unwrap()[0];
+    assert_eq!(receipt.event_type, "offsec.action.result");
+    assert_eq!(receipt.guardian_id.as_deref(), Some("guardian-a"));
+}
+
+#[tokio::test]
+async fn guardian_submitted_actions_can_be_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    let (status, _) = send(
+        &state,
+        post(
+            "/offsec/action",
+            Some(&token("guardian-a", vec!["alert_human"])),
+            json!({
+                "id": "act-7",
+                "event_id": "evt-1",
+                "action": "alert_human",
+                "target": "10.1.2.3",
+                "reason": "test",
+                "created_at": "2025-11-23T01:33:22Z",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &state,
+        post(
+            "/offsec/action/update",
+            Some(&token("guardian-a", vec!["action:report"])),
+            update_body("act-7", "alert_human"),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn anchors_must_name_a_local_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(dir.path()).await;
+    request_block(&state).await;
+    let root = state.frontier.lock().unwrap().current_root();
+    let anchor = |root: &str| {
+        json!({
+            "root": root,
+            "ts": "2025-11-23T02:00:00Z",
+            "chain": "dev",
+            "txid": "tx-1",
+            "status": "anchored",
+        })
+    };
+    let writer = token("root-watcher", vec!["anchor:write"]);
+
+    let (status, _) = send(&state, post("/offsec/anchor", None, anchor(&root))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = send(
+        &state,
+        post("/offsec/anchor", Some(&writer), anchor(&"ab".repeat(32))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "unknown_root");
+    assert_eq!(state.store.count().await.unwrap(), 2);
+
+    // A root over a leaf that is not committed yet cannot be anchored.
+    let in_flight = {
+        let mut frontier = state.frontier.lock().unwrap();
+        frontier.append("cd".repeat(32));
+        frontier.current_root()
+    };
+    let (status, _) = send(
+        &state,
+        post("/offsec/anchor", Some(&writer), anchor(&in_flight)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    state.frontier.lock().unwrap().truncate(2);
+
+    let (status, _) = send(&state, post("/offsec/anchor", Some(&writer), anchor(&root))).await;
+    assert_eq!(status, StatusCode::OK);
+    let receipt = &state.store.list(1, None).await.unwrap()[0];
+    assert_eq!(receipt.event_type, "offsec.anchor");
+    let payload = receipt.payload.as_ref().unwrap();
+    assert_eq!(payload["tree_size"], 2);
+    assert_eq!(payload["anchored_by"], "root-watcher");
+}
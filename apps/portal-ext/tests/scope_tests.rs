@@ -0,0 +1,335 @@
+mod common;
+
+use std::path::Path;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{apply_as, post, send, SECRET};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use portal_ext::{
+    build_state,
+    scope::{cidr_contains, glob_match, ActionScope, ScopeTarget},
+    OffsecConfig,
+};
+use serde_json::{json, Value};
+
+fn config(dir: &Path) -> OffsecConfig {
+    let mut config = common::config(dir);
+    config.guardian_url = Some("http://127.0.0.1:1".to_string());
+    config
+}
+
+fn token(actions: Vec<&str>, scopes: Value) -> String {
+    tagged_token(actions, scopes, &[])
+}
+
+fn tagged_token(actions: Vec<&str>, scopes: Value, tags: &[&str]) -> String {
+    let now = chrono::Utc::now().timestamp();
+    let claims = json!({
+        "sub": "guardian-1",
+        "aud": "offsec-portal",
+        "exp": now + 600,
+        "iat": now,
+        "actions": actions,
+        "scopes": scopes,
+        "tags": tags,
+        "jti": uuid::Uuid::new_v4().to_string(),
+    });
+    encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+fn apply_request(token: &str, action_type: &str, target: Value, tags: &[&str]) -> Request<Body> {
+    let payload = json!({
+        "action_id": uuid::Uuid::new_v4().to_string(),
+        "action_type": action_type,
+        "target": target,
+        "ts": "2025-11-23T01:33:22Z",
+        "guardian_tags": tags,
+    });
+    post("/offsec/action/apply", Some(token), payload)
+}
+
+fn action_request(token: &str, action: &str, target: &str) -> Request<Body> {
+    let payload = json!({
+        "id": "action-1",
+        "event_id": "evt-1",
+        "action": action,
+        "target": target,
+        "reason": "test",
+        "created_at": "2025-11-23T01:33:22Z",
+    });
+    post("/offsec/action", Some(token), payload)
+}
+
+#[test]
+fn cidrs_and_globs_match() {
+    let ip = |s: &str| s.parse().unwrap();
+    assert!(cidr_contains("10.0.0.0/8", ip("10.200.1.1")).unwrap());
+    assert!(!cidr_contains("10.0.0.0/8", ip("11.0.0.1")).unwrap());
+    assert!(cidr_contains("0.0.0.0/0", ip("203.0.113.7")).unwrap());
+    assert!(cidr_contains("203.0.113.7", ip("203.0.113.7")).unwrap());
+    assert!(cidr_contains("2001:db8::/32", ip("2001:db8::1")).unwrap());
+    assert!(!cidr_contains("2001:db8::/32", ip("10.0.0.1")).unwrap());
+    assert!(cidr_contains("10.0.0.0/33", ip("10.0.0.1")).is_err());
+    assert!(cidr_contains("lab", ip("10.0.0.1")).is_err());
+
+    assert!(glob_match("*.lab.internal", "web-1.LAB.internal"));
+    assert!(glob_match("db-??", "db-01"));
+    assert!(glob_match("*", "anything"));
+    assert!(!glob_match("*.lab.internal", "web-1.prod.internal"));
+    assert!(!glob_match("db-??", "db-001"));
+}
+
+#[test]
+fn violations_name_the_constraint() {
+    let scope = ActionScope {
+        cidrs: vec!["10.0.0.0/8".to_string()],
+        hosts: vec![],
+        guardian_tags: vec!["lab".to_string()],
+        max_count: None,
+    };
+    let lab = vec!["lab".to_string()];
+    assert!(scope
+        .check("block_ip", &ScopeTarget::parse("10.1.2.3", &lab))
+        .is_ok());
+
+    let err = scope
+        .check("block_ip", &ScopeTarget::parse("192.168.1.5", &lab))
+        .unwrap_err();
+    assert_eq!(err.constraint, "cidrs");
+    assert!(err.to_string().contains("192.168.1.5"), "{err}");
+
+    let err = scope
+        .check("block_ip", &ScopeTarget::parse("web-1", &lab))
+        .unwrap_err();
+    assert_eq!(err.constraint, "cidrs");
+
+    let err = scope
+        .check("block_ip", &ScopeTarget::parse("10.1.2.3", &[]))
+        .unwrap_err();
+    assert_eq!(err.constraint, "guardian_tags");
+}
+
+#[tokio::test]
+async fn apply_enforces_cidrs_and_tags() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let scopes = json!({ "block_ip": { "cidrs": ["10.0.0.0/8"], "guardian_tags": ["lab"] } });
+    let lab_token = || tagged_token(vec!["block_ip"], scopes.clone(), &["lab"]);
+
+    let (status, _) = send(
+        &state,
+        apply_request(
+            &lab_token(),
+            "block_ip",
+            json!({ "ip": "10.1.2.3" }),
+            &["lab"],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut frames = state.ws.subscribe();
+    let (status, body) = send(
+        &state,
+        apply_request(
+            &lab_token(),
+            "block_ip",
+            json!({ "ip": "192.168.1.5" }),
+            &[],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "action_out_of_scope");
+    assert!(
+        body["details"].as_str().unwrap().contains("`cidrs`"),
+        "{body}"
+    );
+    let frame: Value = serde_json::from_str(&frames.try_recv().unwrap()).unwrap();
+    assert_eq!(frame["type"], "capability_denied");
+    assert_eq!(frame["data"]["metadata"]["constraint"], "cidrs");
+
+    let (status, body) = send(
+        &state,
+        apply_request(
+            &tagged_token(vec!["block_ip"], scopes.clone(), &["prod"]),
+            "block_ip",
+            json!({ "ip": "10.1.2.3" }),
+            &[],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(
+        body["details"]
+            .as_str()
+            .unwrap()
+            .contains("`guardian_tags`"),
+        "{body}"
+    );
+    // Only the in-scope request was receipted.
+    assert_eq!(state.store.count().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn body_tags_cannot_satisfy_tag_constraints() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let scopes = json!({ "block_ip": { "guardian_tags": ["lab"] } });
+
+    // An untagged token cannot borrow the tag from the body.
+    let (status, body) = send(
+        &state,
+        apply_request(
+            &token(vec!["block_ip"], scopes.clone()),
+            "block_ip",
+            json!({ "ip": "10.1.2.3" }),
+            &["lab"],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(
+        body["details"].as_str().unwrap().contains("[lab]"),
+        "{body}"
+    );
+
+    // Nor can a tagged one add tags it does not carry, scoped or not.
+    let (status, _) = send(
+        &state,
+        apply_request(
+            &tagged_token(vec!["alert_human"], json!({}), &["lab"]),
+            "alert_human",
+            json!({ "ip": "10.1.2.3" }),
+            &["lab", "prod"],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(state.store.count().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn action_enforces_host_globs() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let scopes = json!({ "quarantine": { "hosts": ["*.lab.internal"] } });
+
+    let (status, _) = send(
+        &state,
+        action_request(
+            &token(vec!["quarantine"], scopes.clone()),
+            "quarantine",
+            "web-1.lab.internal",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send(
+        &state,
+        action_request(
+            &token(vec!["quarantine"], scopes),
+            "quarantine",
+            "web-1.prod.internal",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(
+        body["details"].as_str().unwrap().contains("`hosts`"),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn max_count_limits_uses_per_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let token = token(
+        vec!["alert_human"],
+        json!({ "alert_human": { "max_count": 2 } }),
+    );
+
+    for _ in 0..2 {
+        let (status, _) = send(
+            &state,
+            apply_request(&token, "alert_human", json!({ "ip": "10.1.2.3" }), &[]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, body) = send(
+        &state,
+        apply_request(&token, "alert_human", json!({ "ip": "10.1.2.3" }), &[]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(
+        body["details"].as_str().unwrap().contains("`max_count`"),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn refused_and_replayed_requests_do_not_count_towards_max_count() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+    let alert = token(
+        vec!["alert_human"],
+        json!({ "alert_human": { "max_count": 2 } }),
+    );
+
+    let (status, _) = apply_as(&state, &alert, "act-1", "alert_human").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = apply_as(&state, &alert, "act-1", "alert_human").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = apply_as(&state, &alert, "act-2", "alert_human").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A replayed single-use token is refused before its use is counted.
+    let block = token(vec!["block_ip"], json!({ "block_ip": { "max_count": 1 } }));
+    let (status, _) = send(
+        &state,
+        apply_request(&block, "block_ip", json!({ "ip": "10.1.2.3" }), &[]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(
+        &state,
+        apply_request(&block, "block_ip", json!({ "ip": "10.1.2.3" }), &[]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "replayed_capability_token");
+}
+
+#[tokio::test]
+async fn unknown_scope_constraints_reject_the_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = build_state(config(dir.path())).await.expect("state");
+
+    let (status, body) = send(
+        &state,
+        apply_request(
+            &token(
+                vec!["block_ip"],
+                json!({ "block_ip": { "cidr": "10.0.0.0/8" } }),
+            ),
+            "block_ip",
+            json!({ "ip": "192.168.1.5" }),
+            &[],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "invalid_capability_token");
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use std::path::Path;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use common::{send, token, SECRET};
+use ed25519_dalek::SigningKey;
+use portal_ext::{build_state, trust, AppState, OffsecConfig};
+
+fn pubkey(seed: u8) -> String {
+    BASE64.encode(
+        SigningKey::from_bytes(&[seed; 32])
+            .verifying_key()
+            .to_bytes(),
+    )
+}
+
+fn write_config(dir: &Path, peers: &[(&str, String)]) {
+    let mut toml = format!(
+        "[portal]\ndata_dir = \"{}\"\njwt_hs256_secret = \"{SECRET}\"\n\n[mesh]\nnode_id = \"shield-self\"\nprivkey_file = \"{}\"\n",
+        dir.join("data").display(),
+        dir.join("node.key").display(),
+    );
+    for (id, key) in peers {
+        toml.push_str(&format!(
+            "\n[[mesh.peers]]\nid = \"{id}\"\nurl = \"http://{id}:9115\"\npubkey = \"{key}\"\n"
+        ));
+    }
+    std::fs::write(dir.join("offsec.toml"), toml).unwrap();
+}
+
+async fn state_with_peer(dir: &Path) -> AppState {
+    std::fs::write(dir.join("node.key"), [7u8; 32]).unwrap();
+    write_config(dir, &[("shield-a", pubkey(1))]);
+    let config = OffsecConfig::from_file(&dir.join("offsec.toml")).unwrap();
+    config.validate().unwrap();
+    build_state(config).await.expect("state")
+}
+
+fn write_issuers(dir: &Path, issuers: &[(&str, u8)]) {
+    let map: serde_json::Map<_, _> = issuers
+        .iter()
+        .map(|(did, seed)| {
+            let key = SigningKey::from_bytes(&[*seed; 32]).verifying_key();
+            (did.to_string(), hex::encode(key.to_bytes()).into())
+        })
+        .collect();
+    std::fs::write(
+        dir.join("data").join(trust::TRUSTED_ISSUERS_FILE),
+        serde_json::to_string(&map).unwrap(),
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn reload_swaps_peers_and_issuers_and_writes_receipt() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state_with_peer(dir.path()).await;
+    assert_eq!(state.trust.peer("shield-a").unwrap().pubkey, pubkey(1));
+    assert!(state.trust.snapshot().issuers.is_empty());
+
+    write_config(
+        dir.path(),
+        &[("shield-a", pubkey(2)), ("shield-b", pubkey(3))],
+    );
+    write_issuers(dir.path(), &[("did:vm:node:alice", 4)]);
+
+    let result = trust::reload(&state, "test", None).await.unwrap();
+    assert_eq!(result.peers.added.get("shield-b"), Some(&pubkey(3)));
+    assert_eq!(result.peers.changed.get("shield-a"), Some(&pubkey(2)));
+    assert!(result.peers.removed.is_empty());
+    assert!(result.issuers.added.contains_key("did:vm:node:alice"));
+
+    assert_eq!(state.trust.peer("shield-a").unwrap().pubkey, pubkey(2));
+    assert!(state.trust.peer("shield-b").is_some());
+    assert!(state
+        .trust
+        .snapshot()
+        .issuers
+        .contains_key("did:vm:node:alice"));
+
+    let receipt_id = result.receipt_id.expect("receipt");
+    let receipt = state.store.get(&receipt_id).await.unwrap().unwrap();
+    assert_eq!(receipt.event_type, "offsec.trust.reload");
+    let data = &receipt.payload.as_ref().unwrap()["data"];
+    assert_eq!(data["trigger"], "test");
+    assert_eq!(data["peers"]["added"]["shield-b"], pubkey(3));
+
+    // Removing a peer is recorded too.
+    write_config(dir.path(), &[("shield-a", pubkey(2))]);
+    let result = trust::reload(&state, "test", None).await.unwrap();
+    assert_eq!(result.peers.removed, vec!["shield-b".to_string()]);
+    assert!(state.trust.peer("shield-b").is_none());
+
+    // Reloading unchanged files swaps nothing and writes no receipt.
+    let receipts = state.store.count().await.unwrap();
+    let result = trust::reload(&state, "test", None).await.unwrap();
+    assert!(result.receipt_id.is_none());
+    assert!(result.peers.is_empty() && result.issuers.is_empty());
+    assert_eq!(state.store.count().await.unwrap(), receipts);
+}
+
+#[tokio::test]
+async fn invalid_reload_keeps_current_stores() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state_with_peer(dir.path()).await;
+
+    write_config(
+        dir.path(),
+        &[("shield-a", "SHIELD_A_PUBKEY_HERE".to_string())],
+    );
+    let err = trust::reload(&state, "test", None).await.unwrap_err();
+    assert!(matches!(err, trust::ReloadError::Invalid(_)), "{err}");
+    assert!(err.to_string().contains("shield-a"), "{err}");
+
+    write_config(dir.path(), &[("shield-a", pubkey(1))]);
+    std::fs::write(
+        dir.path().join("data").join(trust::TRUSTED_ISSUERS_FILE),
+        r#"{"did:vm:node:bob": "zz"}"#,
+    )
+    .unwrap();
+    assert!(trust::reload(&state, "test", None).await.is_err());
+
+    assert_eq!(state.trust.peer("shield-a").unwrap().pubkey, pubkey(1));
+    assert!(state.trust.snapshot().issuers.is_empty());
+    assert_eq!(state.store.count().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn admin_reload_requires_capability() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state_with_peer(dir.path()).await;
+    write_config(dir.path(), &[("shield-c", pubkey(5))]);
+
+    let (status, _) = send(&state, reload_request(&token("ops-admin", vec!["ingest"]))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(state.trust.peer("shield-c").is_none());
+
+    let (status, result) = send(
+        &state,
+        reload_request(&token("ops-admin", vec![trust::RELOAD_ACTION])),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result["trigger"], "admin");
+    assert_eq!(result["peers"]["removed"][0], "shield-a");
+    assert!(state.trust.peer("shield-c").is_some());
+
+    let receipt = state
+        .store
+        .get(result["receipt_id"].as_str().unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(receipt.guardian_id.as_deref(), Some("ops-admin"));
+}
+
+fn reload_request(token: &str) -> Request<Body> {
+    Request::post("/offsec/admin/reload")
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap()
+}
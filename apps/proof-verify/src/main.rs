@@ -1,18 +1,45 @@
 use anyhow::{anyhow, Context, Result};
-use blake3;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use clap::Parser;
+use ed25519_dalek::{Signature, VerifyingKey};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
 
 /// OffSec Shield proof bundle verifier
 #[derive(Parser, Debug)]
 #[command(name = "offsec-proof-verify")]
-#[command(about = "Verify OffSec Shield proof bundles (leaf/path/root/anchor).")]
+#[command(
+    about = "Verify OffSec Shield proof bundles (leaf/path/root/anchor) and exported incident chains."
+)]
 struct Args {
     /// Path to the proof bundle JSON file. Use '-' for stdin.
     #[arg(value_name = "FILE")]
     file: String,
+
+    /// Treat FILE as a consistency proof (GET /offsec/consistency) instead of
+    /// an inclusion proof bundle.
+    #[arg(long, conflicts_with = "incident")]
+    consistency: bool,
+
+    /// Treat FILE as an exported incident chain (GET
+    /// /api/offsec/incidents/:id/proof): check receipt hashes, prev_id links,
+    /// receipt seals and the proof signature. Requires --pubkey.
+    #[arg(long)]
+    incident: bool,
+
+    /// Fail if the bundle does not embed the receipt payload, so the leaf
+    /// cannot be bound to what actually happened.
+    #[arg(long)]
+    require_payload: bool,
+
+    /// Node Ed25519 public key (base64, as served by GET /offsec/root, or hex,
+    /// as served by GET /api/offsec/did). When set, the bundle's signed tree
+    /// heads and receipt signature must verify.
+    #[arg(long, value_name = "BASE64")]
+    pubkey: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,6 +57,15 @@ struct Anchor {
     status: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+struct SignedTreeHead {
+    tree_version: u8,
+    tree_size: usize,
+    root: String,
+    timestamp: String,
+    signature: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct ProofBundle {
     leaf: String,
@@ -42,9 +78,77 @@ struct ProofBundle {
     #[serde(rename = "eventType")]
     event_type: Option<String>,
     ts: Option<String>,
+    #[serde(rename = "guardianId", default)]
+    guardian_id: Option<String>,
+    #[serde(rename = "guardianTags", default)]
+    guardian_tags: Vec<String>,
+    #[serde(rename = "agentId", default)]
+    agent_id: Option<String>,
+    /// Root the receipt was issued under (`root` for stored proofs).
+    #[serde(rename = "receiptRoot", default)]
+    receipt_root: Option<String>,
+    #[serde(rename = "leafIndex", default)]
+    leaf_index: Option<usize>,
+    #[serde(rename = "treeSize", default)]
+    tree_size: Option<usize>,
+    #[serde(
+        rename = "treeVersion",
+        alias = "tree_version",
+        default = "legacy_tree_version"
+    )]
+    tree_version: u8,
+    /// Receipt payload the leaf commits to.
+    #[serde(default)]
+    payload: Option<serde_json::Value>,
+    #[serde(rename = "receiptSignature", default)]
+    receipt_signature: Option<String>,
+    #[serde(default)]
+    sth: Option<SignedTreeHead>,
 }
 
-fn read_bundle(path: &str) -> Result<ProofBundle> {
+#[derive(Debug, Deserialize)]
+struct ConsistencyBundle {
+    #[serde(
+        rename = "treeVersion",
+        alias = "tree_version",
+        default = "legacy_tree_version"
+    )]
+    tree_version: u8,
+    first: usize,
+    second: usize,
+    #[serde(rename = "firstRoot")]
+    first_root: String,
+    #[serde(rename = "secondRoot")]
+    second_root: String,
+    proof: Vec<String>,
+    #[serde(rename = "firstSth", default)]
+    first_sth: Option<SignedTreeHead>,
+    #[serde(rename = "secondSth", default)]
+    second_sth: Option<SignedTreeHead>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChainEntry {
+    receipt: serde_json::Value,
+    hash: String,
+    #[serde(default)]
+    signature: Option<String>,
+}
+
+/// Exported incident chain (see portal-ext `offsec_ledger::IncidentProof`).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IncidentProof {
+    proof_version: u8,
+    incident_id: String,
+    issuer: String,
+    entries: Vec<ChainEntry>,
+    head: String,
+    built_at: String,
+    signature: String,
+}
+
+fn read_input(path: &str) -> Result<String> {
     let data = if path == "-" {
         use std::io::Read;
         let mut buf = String::new();
@@ -53,18 +157,126 @@ fn read_bundle(path: &str) -> Result<ProofBundle> {
             .context("reading from stdin")?;
         buf
     } else {
-        fs::read_to_string(PathBuf::from(path))
-            .with_context(|| format!("reading file: {path}"))?
+        fs::read_to_string(PathBuf::from(path)).with_context(|| format!("reading file: {path}"))?
     };
+    Ok(data)
+}
 
+fn read_bundle(path: &str) -> Result<ProofBundle> {
+    let data = read_input(path)?;
     let bundle: ProofBundle = serde_json::from_str(&data).context("parsing JSON proof bundle")?;
     Ok(bundle)
 }
 
+fn read_consistency(path: &str) -> Result<ConsistencyBundle> {
+    let data = read_input(path)?;
+    let bundle: ConsistencyBundle =
+        serde_json::from_str(&data).context("parsing JSON consistency proof")?;
+    Ok(bundle)
+}
+
+fn read_incident(path: &str) -> Result<IncidentProof> {
+    let data = read_input(path)?;
+    let proof: IncidentProof =
+        serde_json::from_str(&data).context("parsing JSON incident proof")?;
+    Ok(proof)
+}
+
+/// Bundles written before `treeVersion` existed use the v1 tree.
+fn legacy_tree_version() -> u8 {
+    1
+}
+
+/// Merkle tree hashing format (see portal-ext `merkle::TreeVersion`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TreeVersion {
+    /// Hex strings concatenated as text; odd end node paired with itself.
+    V1,
+    /// RFC 6962: `H(0x00 || leaf)`, `H(0x01 || left || right)` over raw
+    /// 32-byte digests; odd end node promoted.
+    V2,
+}
+
+impl TreeVersion {
+    fn from_u8(v: u8) -> Result<Self> {
+        match v {
+            1 => Ok(TreeVersion::V1),
+            2 => Ok(TreeVersion::V2),
+            other => Err(anyhow!("unsupported tree version {other}")),
+        }
+    }
+
+    fn leaf_node(self, leaf: &str) -> Result<String> {
+        match self {
+            TreeVersion::V1 => Ok(leaf.to_string()),
+            TreeVersion::V2 => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&[0x00]);
+                hasher.update(&digest_bytes(leaf)?);
+                Ok(hasher.finalize().to_hex().to_string())
+            }
+        }
+    }
+
+    fn hash_pair(self, left: &str, right: &str) -> Result<String> {
+        match self {
+            TreeVersion::V1 => Ok(blake3::hash(format!("{}{}", left, right).as_bytes())
+                .to_hex()
+                .to_string()),
+            TreeVersion::V2 => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&[0x01]);
+                hasher.update(&digest_bytes(left)?);
+                hasher.update(&digest_bytes(right)?);
+                Ok(hasher.finalize().to_hex().to_string())
+            }
+        }
+    }
+}
+
+fn digest_bytes(h: &str) -> Result<[u8; 32]> {
+    let bytes = hex::decode(h).with_context(|| format!("decoding digest {h}"))?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow!("digest {h} is not 32 bytes"))
+}
+
 fn is_hex(s: &str) -> bool {
     !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit())
 }
 
+/// Serialize JSON with object keys sorted recursively and no whitespace,
+/// matching portal-ext `canonical_json`.
+fn canonical_json(value: &serde_json::Value) -> Result<Vec<u8>> {
+    fn sort_value(v: &serde_json::Value) -> serde_json::Value {
+        match v {
+            serde_json::Value::Object(map) => {
+                let sorted: BTreeMap<_, _> = map
+                    .iter()
+                    .map(|(k, v)| (k.clone(), sort_value(v)))
+                    .collect();
+                serde_json::Value::Object(sorted.into_iter().collect())
+            }
+            serde_json::Value::Array(arr) => {
+                serde_json::Value::Array(arr.iter().map(sort_value).collect())
+            }
+            _ => v.clone(),
+        }
+    }
+
+    Ok(serde_json::to_vec(&sort_value(value))?)
+}
+
+/// Step 1 of docs/PROOF_BUNDLE.md: `leaf == BLAKE3(canonical_json(payload))`.
+fn verify_leaf(bundle: &ProofBundle) -> Result<Option<bool>> {
+    let Some(payload) = &bundle.payload else {
+        return Ok(None);
+    };
+    let bytes = canonical_json(payload).context("canonicalizing payload")?;
+    let computed = blake3::hash(&bytes).to_hex().to_string();
+    Ok(Some(computed == bundle.leaf))
+}
+
 fn verify_merkle(bundle: &ProofBundle) -> Result<bool> {
     if !is_hex(&bundle.leaf) {
         return Err(anyhow!("leaf is not valid hex"));
@@ -73,8 +285,10 @@ fn verify_merkle(bundle: &ProofBundle) -> Result<bool> {
         return Err(anyhow!("root is not valid hex"));
     }
 
+    let version = TreeVersion::from_u8(bundle.tree_version)?;
+
     // Reconstruction: start at leaf, walk through path using BLAKE3
-    let mut h = bundle.leaf.clone();
+    let mut h = version.leaf_node(&bundle.leaf)?;
 
     for (i, step) in bundle.path.iter().enumerate() {
         if !is_hex(&step.sibling) {
@@ -85,19 +299,143 @@ fn verify_merkle(bundle: &ProofBundle) -> Result<bool> {
             ));
         }
 
-        let combined = match step.position.as_str() {
-            "left" => format!("{}{}", step.sibling, h),
-            "right" => format!("{}{}", h, step.sibling),
+        h = match step.position.as_str() {
+            "left" => version.hash_pair(&step.sibling, &h)?,
+            "right" => version.hash_pair(&h, &step.sibling)?,
             other => return Err(anyhow!("invalid position {:?} at path[{}]", other, i)),
         };
-
-        let digest = blake3::hash(combined.as_bytes()).to_hex().to_string();
-        h = digest;
     }
 
     Ok(h == bundle.root)
 }
 
+/// Replays a consistency proof: the seed is the smallest complete subtree on
+/// the old tree's right edge (or the old root when `first` is a power of two).
+/// Left siblings rebuild both roots and right siblings only extend the new
+/// one. On the ragged edge v1 pairs a node with itself, v2 promotes it.
+fn verify_consistency(bundle: &ConsistencyBundle) -> Result<bool> {
+    let version = TreeVersion::from_u8(bundle.tree_version)?;
+    let (m, n) = (bundle.first, bundle.second);
+    if m == 0 || m > n {
+        return Err(anyhow!("invalid tree sizes first={m} second={n}"));
+    }
+    for (i, h) in bundle.proof.iter().enumerate() {
+        if !is_hex(h) {
+            return Err(anyhow!("proof[{}] is not valid hex: {}", i, h));
+        }
+    }
+    if m == n {
+        return Ok(bundle.proof.is_empty() && bundle.first_root == bundle.second_root);
+    }
+
+    let level_count = |size: usize, level: u32| size.div_ceil(1 << level);
+    let mut level = m.trailing_zeros();
+    let mut idx = (m >> level) - 1;
+    let mut nodes = bundle.proof.iter();
+    let seed = if m.is_power_of_two() {
+        bundle.first_root.clone()
+    } else {
+        match nodes.next() {
+            Some(h) => h.clone(),
+            None => return Ok(false),
+        }
+    };
+
+    let mut old_hash = seed.clone();
+    let mut new_hash = seed;
+    while level_count(n, level) > 1 {
+        let has_sibling =
+            idx % 2 == 1 || version == TreeVersion::V1 || idx + 1 < level_count(n, level);
+        if has_sibling {
+            let Some(sibling) = nodes.next() else {
+                return Ok(false);
+            };
+            if idx % 2 == 1 {
+                new_hash = version.hash_pair(sibling, &new_hash)?;
+                old_hash = version.hash_pair(sibling, &old_hash)?;
+            } else {
+                new_hash = version.hash_pair(&new_hash, sibling)?;
+                if version == TreeVersion::V1 && level_count(m, level) > 1 {
+                    old_hash = version.hash_pair(&old_hash, &old_hash)?;
+                }
+            }
+        }
+        idx /= 2;
+        level += 1;
+    }
+
+    Ok(nodes.next().is_none() && old_hash == bundle.first_root && new_hash == bundle.second_root)
+}
+
+fn parse_pubkey(key: &str) -> Result<VerifyingKey> {
+    let bytes = if key.len() == 64 && is_hex(key) {
+        hex::decode(key).context("decoding --pubkey")?
+    } else {
+        BASE64.decode(key).context("decoding --pubkey")?
+    };
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| anyhow!("--pubkey is not a 32-byte Ed25519 key"))?;
+    VerifyingKey::from_bytes(&bytes).context("invalid --pubkey")
+}
+
+/// Ed25519 signature over BLAKE3(canonical_json(statement)), as produced by
+/// portal-ext `sign_payload`.
+fn verify_signature(key: &VerifyingKey, sig_b64: &str, statement: &serde_json::Value) -> bool {
+    let Ok(sig) = BASE64.decode(sig_b64) else {
+        return false;
+    };
+    let Ok(sig) = Signature::from_slice(&sig) else {
+        return false;
+    };
+    let Ok(bytes) = canonical_json(statement) else {
+        return false;
+    };
+    key.verify_strict(blake3::hash(&bytes).as_bytes(), &sig)
+        .is_ok()
+}
+
+/// The head must be signed and describe `root` at `size` in tree `version`.
+fn verify_sth(
+    key: &VerifyingKey,
+    sth: &SignedTreeHead,
+    version: u8,
+    size: Option<usize>,
+    root: &str,
+) -> bool {
+    let statement = serde_json::json!({
+        "tree_version": sth.tree_version,
+        "tree_size": sth.tree_size,
+        "root": sth.root,
+        "timestamp": sth.timestamp,
+    });
+    sth.root == root
+        && sth.tree_version == version
+        && size.is_none_or(|s| s == sth.tree_size)
+        && verify_signature(key, &sth.signature, &statement)
+}
+
+/// Rebuilds portal-ext `receipt_statement` from the bundle fields.
+fn verify_receipt_signature(key: &VerifyingKey, bundle: &ProofBundle) -> Result<bool> {
+    let sig = bundle
+        .receipt_signature
+        .as_deref()
+        .ok_or_else(|| anyhow!("bundle has no receiptSignature (--pubkey)"))?;
+    let statement = serde_json::json!({
+        "id": bundle.receipt_id,
+        "event_type": bundle.event_type,
+        "hash": bundle.leaf,
+        "leaf_index": bundle.leaf_index,
+        "tree_version": bundle.tree_version,
+        "ts": bundle.ts,
+        "guardian_id": bundle.guardian_id,
+        "guardian_tags": bundle.guardian_tags,
+        "agent_id": bundle.agent_id,
+        "merkle_root": bundle.receipt_root.as_deref().unwrap_or(&bundle.root),
+    });
+    Ok(verify_signature(key, sig, &statement))
+}
+
 fn verify_anchor(bundle: &ProofBundle) -> Result<Option<bool>> {
     if let Some(anchor) = &bundle.anchor {
         if let Some(anchor_root) = &anchor.root {
@@ -113,10 +451,162 @@ fn verify_anchor(bundle: &ProofBundle) -> Result<Option<bool>> {
     }
 }
 
+fn run_consistency(bundle: &ConsistencyBundle, pubkey: Option<&VerifyingKey>) -> Result<()> {
+    println!("== OffSec Shield Consistency Verification ==");
+    println!("Tree:    v{}", bundle.tree_version);
+    println!("First:   size={} root={}", bundle.first, bundle.first_root);
+    println!(
+        "Second:  size={} root={}",
+        bundle.second, bundle.second_root
+    );
+    println!("Proof elements: {}", bundle.proof.len());
+
+    let ok = verify_consistency(bundle)?;
+    println!(
+        "Consistency proof: {}",
+        if ok { "VALID" } else { "INVALID" }
+    );
+    if !ok {
+        return Err(anyhow!("consistency proof failed"));
+    }
+
+    if let Some(key) = pubkey {
+        let heads = [
+            (&bundle.first_sth, bundle.first, &bundle.first_root),
+            (&bundle.second_sth, bundle.second, &bundle.second_root),
+        ];
+        for (sth, size, root) in heads {
+            let sth = sth
+                .as_ref()
+                .ok_or_else(|| anyhow!("bundle has no signed tree head for size {size}"))?;
+            if !verify_sth(key, sth, bundle.tree_version, Some(size), root) {
+                return Err(anyhow!("signed tree head for size {size} is invalid"));
+            }
+        }
+        println!("Signed tree heads: VALID");
+    }
+
+    println!("✅ Consistency proof verified successfully.");
+    Ok(())
+}
+
+/// Mirrors portal-ext `IncidentProof::statement`.
+fn incident_statement(proof: &IncidentProof) -> serde_json::Value {
+    serde_json::json!({
+        "proof_version": proof.proof_version,
+        "incident_id": proof.incident_id,
+        "issuer": proof.issuer,
+        "hashes": proof.entries.iter().map(|e| &e.hash).collect::<Vec<_>>(),
+        "built_at": proof.built_at,
+    })
+}
+
+/// Checks each receipt's hash, `prev_id` link, incident and seal, then the
+/// proof signature. Mirrors portal-ext `verify_incident_proof`.
+fn run_incident(proof: &IncidentProof, pubkey: &VerifyingKey) -> Result<()> {
+    println!("== OffSec Shield Incident Chain Verification ==");
+    println!("Incident: {}", proof.incident_id);
+    println!("Issuer:   {}", proof.issuer);
+    println!("Built:    {}", proof.built_at);
+    println!("Receipts: {}", proof.entries.len());
+
+    if proof.proof_version != 1 {
+        return Err(anyhow!(
+            "unsupported incident proof version {}",
+            proof.proof_version
+        ));
+    }
+    if proof.entries.is_empty() {
+        return Err(anyhow!("chain has no receipts"));
+    }
+
+    let mut failures = 0;
+    let mut prev: Option<&str> = None;
+    for (i, entry) in proof.entries.iter().enumerate() {
+        let receipt = &entry.receipt;
+        let id = receipt["id"].as_str().unwrap_or("?");
+        let bytes = canonical_json(receipt).context("canonicalizing receipt")?;
+        let hash_ok = blake3::hash(&bytes).to_hex().as_str() == entry.hash;
+        let link_ok = receipt["prev_id"].as_str() == prev;
+        let ref_id = receipt["body"]["ref_id"]
+            .as_str()
+            .or_else(|| receipt["body"]["extra"]["incident_id"].as_str());
+        let incident_ok = ref_id == Some(proof.incident_id.as_str());
+        let seal_ok = entry
+            .signature
+            .as_deref()
+            .is_some_and(|sig| verify_signature(pubkey, sig, receipt));
+        println!(
+            "  [{i}] {id}: hash {} link {} incident {} seal {}",
+            if hash_ok { "OK" } else { "MISMATCH" },
+            if link_ok { "OK" } else { "BROKEN" },
+            if incident_ok { "OK" } else { "OTHER" },
+            match (seal_ok, &entry.signature) {
+                (true, _) => "VALID",
+                (false, None) => "MISSING",
+                (false, Some(_)) => "INVALID",
+            }
+        );
+        if !(hash_ok && link_ok && incident_ok && seal_ok) {
+            failures += 1;
+        }
+        prev = Some(id);
+    }
+
+    let head_ok = proof.entries.last().map(|e| e.hash.as_str()) == Some(proof.head.as_str());
+    println!(
+        "Head:     {}",
+        if head_ok {
+            "MATCHES newest receipt"
+        } else {
+            "DOES NOT MATCH"
+        }
+    );
+    let signature_ok = verify_signature(pubkey, &proof.signature, &incident_statement(proof));
+    println!(
+        "Proof signature: {}",
+        if signature_ok { "VALID" } else { "INVALID" }
+    );
+
+    if failures > 0 {
+        return Err(anyhow!("{failures} receipt(s) failed verification"));
+    }
+    if !head_ok {
+        return Err(anyhow!("head does not match the newest receipt"));
+    }
+    if !signature_ok {
+        return Err(anyhow!("proof signature does not verify"));
+    }
+
+    println!("✅ Incident chain verified successfully.");
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
-    let bundle = read_bundle(&args.file)?;
+    let pubkey = args.pubkey.as_deref().map(parse_pubkey).transpose()?;
+    if args.consistency {
+        return run_consistency(&read_consistency(&args.file)?, pubkey.as_ref());
+    }
+    if args.incident {
+        // Without the node key anyone could rebuild a self-consistent chain.
+        let pubkey = pubkey.ok_or_else(|| {
+            anyhow!("--incident requires --pubkey; an incident chain cannot be verified unauthenticated")
+        })?;
+        return run_incident(&read_incident(&args.file)?, &pubkey);
+    }
+    run_bundle(
+        &read_bundle(&args.file)?,
+        pubkey.as_ref(),
+        args.require_payload,
+    )
+}
 
+fn run_bundle(
+    bundle: &ProofBundle,
+    pubkey: Option<&VerifyingKey>,
+    require_payload: bool,
+) -> Result<()> {
     println!("== OffSec Shield Proof Verification ==");
     if let Some(id) = &bundle.receipt_id {
         println!("Receipt: {id}");
@@ -129,7 +619,11 @@ fn main() -> Result<()> {
     }
     println!("Leaf:    {}", bundle.leaf);
     println!("Root:    {}", bundle.root);
+    println!("Tree:    v{}", bundle.tree_version);
     println!("Path elements: {}", bundle.path.len());
+    if let (Some(index), Some(size)) = (bundle.leaf_index, bundle.tree_size) {
+        println!("Position: leaf {index} of {size}");
+    }
     if let Some(anchor) = &bundle.anchor {
         println!(
             "Anchor data: root={} chain={} txid={} status={} ts={}",
@@ -141,13 +635,20 @@ fn main() -> Result<()> {
         );
     }
 
-    let merkle_ok = verify_merkle(&bundle)?;
+    let leaf_ok = verify_leaf(bundle)?;
+    match leaf_ok {
+        Some(true) => println!("Payload: MATCHES leaf"),
+        Some(false) => println!("Payload: DOES NOT MATCH leaf"),
+        None => println!("Payload: (no payload present; leaf not bound to receipt)"),
+    }
+
+    let merkle_ok = verify_merkle(bundle)?;
     println!(
         "Merkle proof: {}",
         if merkle_ok { "VALID" } else { "INVALID" }
     );
 
-    let anchor_ok = verify_anchor(&bundle)?;
+    let anchor_ok = verify_anchor(bundle)?;
     match anchor_ok {
         Some(true) => {
             println!("Anchor:  MATCHES root");
@@ -160,6 +661,39 @@ fn main() -> Result<()> {
         }
     }
 
+    let signatures_ok = match pubkey {
+        Some(key) => {
+            let sth_ok = match &bundle.sth {
+                Some(sth) => verify_sth(
+                    key,
+                    sth,
+                    bundle.tree_version,
+                    bundle.tree_size,
+                    &bundle.root,
+                ),
+                None => return Err(anyhow!("bundle has no signed tree head (--pubkey)")),
+            };
+            let receipt_ok = verify_receipt_signature(key, bundle)?;
+            println!(
+                "Signed tree head: {}",
+                if sth_ok { "VALID" } else { "INVALID" }
+            );
+            println!(
+                "Receipt signature: {}",
+                if receipt_ok { "VALID" } else { "INVALID" }
+            );
+            Some((sth_ok, receipt_ok))
+        }
+        None => None,
+    };
+
+    if let Some(false) = leaf_ok {
+        return Err(anyhow!("leaf does not commit to payload"));
+    }
+    if leaf_ok.is_none() && require_payload {
+        return Err(anyhow!("bundle has no payload (--require-payload)"));
+    }
+
     if !merkle_ok {
         return Err(anyhow!("merkle proof failed"));
     }
@@ -168,6 +702,201 @@ fn main() -> Result<()> {
         return Err(anyhow!("anchor.root does not match root"));
     }
 
+    match signatures_ok {
+        Some((false, _)) => return Err(anyhow!("signed tree head does not verify")),
+        Some((_, false)) => return Err(anyhow!("receipt signature does not verify")),
+        _ => {}
+    }
+
     println!("✅ Proof bundle verified successfully.");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    //! Fixtures in `tests/fixtures` were produced by portal-ext: its
+    //! `MerkleFrontier`, node signer (`keys.json` `node`), ledger seals
+    //! (`keys.json` `ledger`) and `canonical_json`.
+
+    use super::*;
+    use serde::de::DeserializeOwned;
+
+    fn fixture<T: DeserializeOwned>(name: &str) -> T {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+    }
+
+    fn key(name: &str) -> VerifyingKey {
+        let keys: serde_json::Value = fixture("keys.json");
+        parse_pubkey(keys[name].as_str().unwrap()).unwrap()
+    }
+
+    /// A hex digest that differs from `h` in its first nibble.
+    fn flip(h: &str) -> String {
+        let first = if h.starts_with('0') { "1" } else { "0" };
+        format!("{first}{}", &h[1..])
+    }
+
+    #[test]
+    fn canonical_json_matches_portal_ext() {
+        let case: serde_json::Value = fixture("canonical.json");
+        let bytes = canonical_json(&case["value"]).unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            case["canonical"].as_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn portal_bundles_verify() {
+        let node = key("node");
+        for name in ["bundle_v2.json", "inclusion_v2.json", "inclusion_v1.json"] {
+            let bundle: ProofBundle = fixture(name);
+            assert_eq!(verify_leaf(&bundle).unwrap(), Some(true), "{name}");
+            assert!(verify_merkle(&bundle).unwrap(), "{name}");
+            run_bundle(&bundle, Some(&node), true).unwrap();
+        }
+        let fresh: ProofBundle = fixture("inclusion_v2.json");
+        assert_ne!(fresh.receipt_root.as_deref(), Some(fresh.root.as_str()));
+    }
+
+    #[test]
+    fn tampered_leaf_fails() {
+        let mut bundle: ProofBundle = fixture("bundle_v2.json");
+        bundle.payload.as_mut().unwrap()["id"] = serde_json::json!("evt-9");
+        assert_eq!(verify_leaf(&bundle).unwrap(), Some(false));
+        assert!(run_bundle(&bundle, None, false).is_err());
+
+        let mut bundle: ProofBundle = fixture("bundle_v2.json");
+        bundle.leaf = flip(&bundle.leaf);
+        assert!(!verify_merkle(&bundle).unwrap());
+
+        let mut bundle: ProofBundle = fixture("bundle_v2.json");
+        bundle.payload = None;
+        run_bundle(&bundle, None, false).unwrap();
+        assert!(run_bundle(&bundle, None, true).is_err());
+    }
+
+    #[test]
+    fn tampered_root_or_path_fails() {
+        for name in ["inclusion_v2.json", "inclusion_v1.json"] {
+            let mut bundle: ProofBundle = fixture(name);
+            bundle.root = flip(&bundle.root);
+            assert!(!verify_merkle(&bundle).unwrap(), "{name}");
+
+            let mut bundle: ProofBundle = fixture(name);
+            bundle.path[0].sibling = flip(&bundle.path[0].sibling);
+            assert!(!verify_merkle(&bundle).unwrap(), "{name}");
+
+            let mut bundle: ProofBundle = fixture(name);
+            let step = &mut bundle.path[0];
+            step.position = if step.position == "left" {
+                "right"
+            } else {
+                "left"
+            }
+            .to_string();
+            assert!(!verify_merkle(&bundle).unwrap(), "{name}");
+
+            let mut bundle: ProofBundle = fixture(name);
+            bundle.path.pop();
+            assert!(!verify_merkle(&bundle).unwrap(), "{name}");
+
+            // The same path hashed as the other tree format.
+            let mut bundle: ProofBundle = fixture(name);
+            bundle.tree_version = 3 - bundle.tree_version;
+            assert!(!verify_merkle(&bundle).unwrap(), "{name}");
+        }
+    }
+
+    #[test]
+    fn signatures_are_checked() {
+        let node = key("node");
+        let tampered: [fn(&mut ProofBundle); 6] = [
+            |b| b.sth.as_mut().unwrap().tree_size += 1,
+            |b| b.sth.as_mut().unwrap().timestamp.push('Z'),
+            |b| b.guardian_tags.push("prod".to_string()),
+            |b| b.guardian_id = Some("guardian-b".to_string()),
+            |b| b.receipt_root = Some(flip(b.receipt_root.as_deref().unwrap())),
+            |b| b.sth = None,
+        ];
+        for (i, tamper) in tampered.iter().enumerate() {
+            let mut bundle: ProofBundle = fixture("bundle_v2.json");
+            tamper(&mut bundle);
+            assert!(run_bundle(&bundle, Some(&node), false).is_err(), "case {i}");
+        }
+
+        let bundle: ProofBundle = fixture("bundle_v2.json");
+        assert!(run_bundle(&bundle, Some(&key("ledger")), false).is_err());
+        let mut bundle: ProofBundle = fixture("bundle_v2.json");
+        bundle.receipt_signature = bundle.sth.as_ref().map(|s| s.signature.clone());
+        assert!(!verify_receipt_signature(&node, &bundle).unwrap());
+    }
+
+    #[test]
+    fn consistency_proofs_verify() {
+        let node = key("node");
+        for name in ["consistency_v2.json", "consistency_v1.json"] {
+            let bundle: ConsistencyBundle = fixture(name);
+            assert!(verify_consistency(&bundle).unwrap(), "{name}");
+            run_consistency(&bundle, Some(&node)).unwrap();
+
+            let mut bundle: ConsistencyBundle = fixture(name);
+            bundle.proof[0] = flip(&bundle.proof[0]);
+            assert!(!verify_consistency(&bundle).unwrap(), "{name}");
+
+            let mut bundle: ConsistencyBundle = fixture(name);
+            bundle.first_root = flip(&bundle.first_root);
+            assert!(!verify_consistency(&bundle).unwrap(), "{name}");
+
+            let mut bundle: ConsistencyBundle = fixture(name);
+            bundle.proof.pop();
+            assert!(!verify_consistency(&bundle).unwrap(), "{name}");
+
+            // Sizes are bound by the signed tree heads.
+            let mut bundle: ConsistencyBundle = fixture(name);
+            bundle.second += 1;
+            assert!(run_consistency(&bundle, Some(&node)).is_err(), "{name}");
+
+            let mut bundle: ConsistencyBundle = fixture(name);
+            bundle.second_sth = None;
+            assert!(run_consistency(&bundle, Some(&node)).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn incident_chains_verify() {
+        let ledger = key("ledger");
+        let proof: IncidentProof = fixture("incident.json");
+        run_incident(&proof, &ledger).unwrap();
+        assert!(run_incident(&proof, &key("node")).is_err());
+
+        // An edited receipt fails its hash and, re-hashed, its seal.
+        let mut edited: IncidentProof = fixture("incident.json");
+        edited.entries[1].receipt["body"]["extra"]["n"] = serde_json::json!(99);
+        assert!(run_incident(&edited, &ledger).is_err());
+        let rehashed = canonical_json(&edited.entries[1].receipt).unwrap();
+        edited.entries[1].hash = blake3::hash(&rehashed).to_hex().to_string();
+        assert!(run_incident(&edited, &ledger).is_err());
+
+        // A broken prev_id link.
+        let mut relinked: IncidentProof = fixture("incident.json");
+        relinked.entries[2].receipt["prev_id"] = relinked.entries[0].receipt["id"].clone();
+        assert!(run_incident(&relinked, &ledger).is_err());
+        let mut dropped: IncidentProof = fixture("incident.json");
+        dropped.entries.remove(1);
+        assert!(run_incident(&dropped, &ledger).is_err());
+
+        let mut other: IncidentProof = fixture("incident.json");
+        other.incident_id = "inc-2".to_string();
+        assert!(run_incident(&other, &ledger).is_err());
+        let mut head: IncidentProof = fixture("incident.json");
+        head.head = head.entries[0].hash.clone();
+        assert!(run_incident(&head, &ledger).is_err());
+        let mut unsealed: IncidentProof = fixture("incident.json");
+        unsealed.entries[0].signature = None;
+        assert!(run_incident(&unsealed, &ledger).is_err());
+    }
+}
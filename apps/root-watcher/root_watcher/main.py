@@ -85,8 +85,11 @@ def write_anchor_files(data_dir: str, anchor: Dict) -> None:
 
 def notify_portal(portal_url: str, anchor: Dict) -> None:
     url = portal_url.rstrip("/") + "/offsec/anchor"
+    # portal-ext only accepts anchors from tokens granting `anchor:write`.
+    token = env("OFFSEC_ANCHOR_TOKEN", "")
+    headers = {"Authorization": f"Bearer {token}"} if token else {}
     try:
-        resp = requests.post(url, json=anchor, timeout=5)
+        resp = requests.post(url, json=anchor, headers=headers, timeout=5)
         resp.raise_for_status()
     except Exception as exc:
         print(f"[root-watcher] Failed to notify portal-ext: {exc}")
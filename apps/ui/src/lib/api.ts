@@ -25,6 +25,7 @@ export async function getReceipts(guardianId?: string): Promise<Receipt[]> {
 
 type ActionTarget = {
   ip?: string;
+  host?: string;
 };
 
 export type ActionRequestPayload = {
@@ -105,8 +105,8 @@ if [ -d "$DATA_DIR" ]; then
   else
     echo "  ROOT.txt missing"
   fi
-  echo "Receipts dir preview:"
-  ls -1 "$DATA_DIR/receipts/offsec" 2>/dev/null | tail -n 5 || echo "  no receipts or path missing"
+  echo "Receipt store:"
+  ls -l "$DATA_DIR/receipts.db" 2>/dev/null || echo "  receipts.db missing"
   echo "Data dir perms:"
   ls -ld "$DATA_DIR"
 else